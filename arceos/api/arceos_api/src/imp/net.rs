@@ -129,3 +129,7 @@ pub fn ax_poll_interfaces() -> AxResult {
     axnet::poll_interfaces();
     Ok(())
 }
+
+pub fn ax_register_poll_waker(waker: &core::task::Waker) {
+    axnet::register_poll_waker(waker)
+}
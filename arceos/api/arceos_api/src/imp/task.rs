@@ -26,6 +26,39 @@ pub fn ax_exit(_exit_code: i32) -> ! {
 cfg_task! {
     use core::time::Duration;
 
+    pub use axtask::CpuMask as AxCpuMask;
+    pub use axtask::{TaskStat as AxTaskStat, TaskState as AxTaskState, WaitReason as AxWaitReason};
+
+    /// The state and statistics of a task at some time.
+    #[derive(Debug, Clone)]
+    pub struct AxTaskInfo {
+        /// The task ID.
+        pub id: u64,
+        /// The task name.
+        pub name: alloc::string::String,
+        /// The state of the task.
+        pub state: AxTaskState,
+        /// Why the task is blocked, if it is.
+        pub wait_reason: Option<AxWaitReason>,
+        /// The time since boot when the task was created.
+        pub start_time: crate::time::AxTimeValue,
+        /// The CPU time and context switches of the task.
+        pub stat: AxTaskStat,
+    }
+
+    impl AxTaskInfo {
+        fn of(task: &axtask::TaskInner) -> Self {
+            Self {
+                id: task.id().as_u64(),
+                name: task.name().into(),
+                state: task.state(),
+                wait_reason: task.wait_reason(),
+                start_time: task.start_time(),
+                stat: task.stat(),
+            }
+        }
+    }
+
     /// A handle to a task.
     pub struct AxTaskHandle {
         inner: axtask::AxTaskRef,
@@ -71,6 +104,14 @@ cfg_task! {
         task.inner.join()
     }
 
+    pub fn ax_kill_task(task: &AxTaskHandle) -> crate::AxResult {
+        if axtask::kill(&task.inner) {
+            Ok(())
+        } else {
+            axerrno::ax_err!(BadState, "ax_kill_task: the task cannot be killed")
+        }
+    }
+
     pub fn ax_set_current_priority(prio: isize) -> crate::AxResult {
         if axtask::set_priority(prio) {
             Ok(())
@@ -82,6 +123,29 @@ cfg_task! {
         }
     }
 
+    pub fn ax_current_task_info() -> AxTaskInfo {
+        AxTaskInfo::of(&axtask::current())
+    }
+
+    pub fn ax_task_list() -> alloc::vec::Vec<AxTaskInfo> {
+        axtask::tasks().map(|task| AxTaskInfo::of(&task)).collect()
+    }
+
+    pub fn ax_total_task_stat() -> AxTaskStat {
+        axtask::total_stat()
+    }
+
+    pub fn ax_set_current_affinity(cpumask: AxCpuMask) -> crate::AxResult {
+        if axtask::set_current_affinity(cpumask) {
+            Ok(())
+        } else {
+            axerrno::ax_err!(
+                InvalidInput,
+                "ax_set_current_affinity: empty CPU mask"
+            )
+        }
+    }
+
     pub fn ax_wait_queue_wait(
         wq: &AxWaitQueueHandle,
         until_condition: impl Fn() -> bool,
@@ -121,6 +121,11 @@ pub mod task {
         @cfg "multitask";
         pub type AxTaskHandle;
         pub type AxWaitQueueHandle;
+        pub type AxCpuMask;
+        pub type AxTaskInfo;
+        pub type AxTaskState;
+        pub type AxTaskStat;
+        pub type AxWaitReason;
     }
 
     define_api! {
@@ -153,8 +158,23 @@ pub mod task {
         /// Waits for the given task to exit, and returns its exit code (the
         /// argument of [`ax_exit`]).
         pub fn ax_wait_for_exit(task: AxTaskHandle) -> Option<i32>;
+        /// Requests the given task to exit. It is woken up from killable
+        /// waits, and exits with [`axtask::KILLED_EXIT_CODE`] when it calls
+        /// [`axtask::exit_if_killed`].
+        pub fn ax_kill_task(task: &AxTaskHandle) -> crate::AxResult;
         /// Sets the priority of the current task.
         pub fn ax_set_current_priority(prio: isize) -> crate::AxResult;
+        /// Returns the state and statistics of the current task.
+        pub fn ax_current_task_info() -> AxTaskInfo;
+        /// Returns the state and statistics of all the live tasks, in the
+        /// order of their IDs.
+        pub fn ax_task_list() -> alloc::vec::Vec<AxTaskInfo>;
+        /// Returns the CPU time and context switches of all the tasks, live
+        /// or exited, summed up.
+        pub fn ax_total_task_stat() -> AxTaskStat;
+        /// Sets the CPUs the current task is allowed to run on, and moves it
+        /// to one of them if necessary.
+        pub fn ax_set_current_affinity(cpumask: AxCpuMask) -> crate::AxResult;
 
         /// Blocks the current task and put it into the wait queue, until the
         /// given condition becomes true, or the the given duration has elapsed
@@ -349,6 +369,9 @@ pub mod net {
         /// It may receive packets from the NIC and process them, and transmit queued
         /// packets to the NIC.
         pub fn ax_poll_interfaces() -> AxResult;
+        /// Wakes up the waker the next time polling the network stack, by
+        /// any task, may have changed the states of the sockets.
+        pub fn ax_register_poll_waker(waker: &core::task::Waker);
     }
 }
 
@@ -4,10 +4,13 @@ fn main() {
     fn gen_pthread_mutex(out_file: &str) -> std::io::Result<()> {
         // TODO: generate size and initial content automatically.
         let (mutex_size, mutex_init) = if cfg!(feature = "multitask") {
-            if cfg!(feature = "smp") {
-                (6, "{0, 0, 8, 0, 0, 0}") // core::mem::transmute::<_, [usize; 6]>(axsync::Mutex::new(()))
-            } else {
-                (5, "{0, 8, 0, 0, 0}") // core::mem::transmute::<_, [usize; 5]>(axsync::Mutex::new(()))
+            // With `lockdep`, one more word of the lock class, which is zeroed
+            // for a class on its own.
+            match (cfg!(feature = "smp"), cfg!(feature = "lockdep")) {
+                (true, true) => (10, "{0, 0, 8, 0, 0, 0, 0, 0, 0, 0}"),
+                (true, false) => (9, "{0, 0, 8, 0, 0, 0, 0, 0, 0}"), // core::mem::transmute::<_, [usize; 9]>(axsync::Mutex::new(()))
+                (false, true) => (8, "{0, 8, 0, 0, 0, 0, 0, 0}"),
+                (false, false) => (7, "{0, 8, 0, 0, 0, 0, 0}"), // core::mem::transmute::<_, [usize; 7]>(axsync::Mutex::new(()))
             }
         } else {
             (1, "{0}")
@@ -51,7 +54,9 @@ typedef struct {{
             "epoll_event",
             "iovec",
             "clockid_t",
+            "cpu_set_t",
             "rlimit",
+            "rusage",
             "aibuf",
         ];
         let allow_vars = [
@@ -66,6 +71,7 @@ typedef struct {{
             "EPOLL_CTL_.*",
             "EPOLL.*",
             "RLIMIT_.*",
+            "RUSAGE_.*",
             "EAI_.*",
             "MAXADDRS",
         ];
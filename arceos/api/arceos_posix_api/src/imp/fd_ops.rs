@@ -1,4 +1,5 @@
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::ffi::c_int;
 
 use axerrno::{LinuxError, LinuxResult};
@@ -11,6 +12,8 @@ use crate::ctypes;
 
 pub const AX_FILE_LIMIT: usize = 1024;
 
+/// An object that can be opened as a file descriptor, e.g., a regular file,
+/// a pipe or a socket.
 #[allow(dead_code)]
 pub trait FileLike: Send + Sync {
     fn read(&self, buf: &mut [u8]) -> LinuxResult<usize>;
@@ -21,33 +24,199 @@ pub trait FileLike: Send + Sync {
     fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult;
 }
 
+/// An open file descriptor.
+#[derive(Clone)]
+struct FileDesc {
+    file: Arc<dyn FileLike>,
+    /// Whether to close the descriptor on `execve` (`FD_CLOEXEC`).
+    cloexec: bool,
+}
+
+/// A file descriptor table.
+///
+/// In monolithic kernels, each user process has its own table, which is
+/// shared by the processes created with `CLONE_FILES` (e.g., threads).
+pub struct FdTable {
+    files: RwLock<FlattenObjects<FileDesc, AX_FILE_LIMIT>>,
+}
+
+impl FdTable {
+    /// Creates an empty table.
+    pub fn new() -> Self {
+        Self {
+            files: RwLock::new(FlattenObjects::new()),
+        }
+    }
+
+    /// Creates a table with stdin, stdout and stderr opened.
+    pub fn with_stdio() -> Self {
+        let table = Self::new();
+        table.add(Arc::new(stdin()), false).unwrap(); // stdin
+        table.add(Arc::new(stdout()), false).unwrap(); // stdout
+        table.add(Arc::new(stdout()), false).unwrap(); // stderr
+        table
+    }
+
+    /// Creates a copy of the table for a new process, the descriptors refer
+    /// to the same open files.
+    pub fn duplicate(&self) -> Self {
+        let files = self.files.read();
+        let mut new_files = FlattenObjects::new();
+        for fd in 0..AX_FILE_LIMIT {
+            if let Some(desc) = files.get(fd) {
+                new_files.add_at(fd, desc.clone()).unwrap();
+            }
+        }
+        Self {
+            files: RwLock::new(new_files),
+        }
+    }
+
+    /// Closes the descriptors with `FD_CLOEXEC` set, on `execve`.
+    pub fn close_on_exec(&self) {
+        let mut files = self.files.write();
+        let closed: Vec<_> = (0..AX_FILE_LIMIT)
+            .filter(|&fd| files.get(fd).is_some_and(|desc| desc.cloexec))
+            .filter_map(|fd| files.remove(fd))
+            .collect();
+        // The files are released without the lock held.
+        drop(files);
+        drop(closed);
+    }
+
+    /// Returns the file of the descriptor `fd`.
+    pub fn get(&self, fd: c_int) -> LinuxResult<Arc<dyn FileLike>> {
+        self.get_desc(fd).map(|desc| desc.file)
+    }
+
+    fn get_desc(&self, fd: c_int) -> LinuxResult<FileDesc> {
+        if fd < 0 {
+            return Err(LinuxError::EBADF);
+        }
+        self.files
+            .read()
+            .get(fd as usize)
+            .cloned()
+            .ok_or(LinuxError::EBADF)
+    }
+
+    /// Adds the file with the lowest available descriptor, and returns the
+    /// descriptor.
+    pub fn add(&self, file: Arc<dyn FileLike>, cloexec: bool) -> LinuxResult<c_int> {
+        let fd = self
+            .files
+            .write()
+            .add(FileDesc { file, cloexec })
+            .ok_or(LinuxError::EMFILE)?;
+        Ok(fd as c_int)
+    }
+
+    /// Adds the file with the lowest available descriptor not less than
+    /// `min_fd`, and returns the descriptor.
+    fn add_from(
+        &self,
+        min_fd: c_int,
+        file: Arc<dyn FileLike>,
+        cloexec: bool,
+    ) -> LinuxResult<c_int> {
+        if min_fd < 0 || min_fd as usize >= AX_FILE_LIMIT {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut files = self.files.write();
+        let fd = (min_fd as usize..AX_FILE_LIMIT)
+            .find(|&fd| files.get(fd).is_none())
+            .ok_or(LinuxError::EMFILE)?;
+        files.add_at(fd, FileDesc { file, cloexec }).unwrap();
+        Ok(fd as c_int)
+    }
+
+    /// Sets the descriptor `fd` to the file, closing the file it referred
+    /// to if any.
+    pub fn replace(&self, fd: c_int, file: Arc<dyn FileLike>, cloexec: bool) -> LinuxResult {
+        if fd < 0 || fd as usize >= AX_FILE_LIMIT {
+            return Err(LinuxError::EBADF);
+        }
+        let mut files = self.files.write();
+        let old = files.remove(fd as usize);
+        files
+            .add_at(fd as usize, FileDesc { file, cloexec })
+            .unwrap();
+        drop(files);
+        drop(old);
+        Ok(())
+    }
+
+    /// Removes the descriptor `fd`, and returns the file it referred to.
+    pub fn remove(&self, fd: c_int) -> LinuxResult<Arc<dyn FileLike>> {
+        if fd < 0 {
+            return Err(LinuxError::EBADF);
+        }
+        let desc = self
+            .files
+            .write()
+            .remove(fd as usize)
+            .ok_or(LinuxError::EBADF)?;
+        Ok(desc.file)
+    }
+
+    /// Returns whether `FD_CLOEXEC` is set on the descriptor `fd`.
+    pub fn cloexec(&self, fd: c_int) -> LinuxResult<bool> {
+        self.get_desc(fd).map(|desc| desc.cloexec)
+    }
+
+    /// Sets or clears `FD_CLOEXEC` on the descriptor `fd`.
+    pub fn set_cloexec(&self, fd: c_int, cloexec: bool) -> LinuxResult {
+        if fd < 0 {
+            return Err(LinuxError::EBADF);
+        }
+        let mut files = self.files.write();
+        let desc = files.get_mut(fd as usize).ok_or(LinuxError::EBADF)?;
+        desc.cloexec = cloexec;
+        Ok(())
+    }
+}
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Finds the file descriptor table of the current process.
+///
+/// It is implemented by the process management of monolithic kernels.
+#[cfg(feature = "uspace")]
+#[crate_interface::def_interface]
+pub trait FdTableIf {
+    /// Returns the table of the current process, or `None` to use the global
+    /// table (e.g., in kernel tasks).
+    fn current_fd_table() -> Option<Arc<FdTable>>;
+}
+
 lazy_static::lazy_static! {
-    static ref FD_TABLE: RwLock<FlattenObjects<Arc<dyn FileLike>, AX_FILE_LIMIT>> = {
-        let mut fd_table = FlattenObjects::new();
-        fd_table.add_at(0, Arc::new(stdin()) as _).unwrap(); // stdin
-        fd_table.add_at(1, Arc::new(stdout()) as _).unwrap(); // stdout
-        fd_table.add_at(2, Arc::new(stdout()) as _).unwrap(); // stderr
-        RwLock::new(fd_table)
-    };
+    static ref FD_TABLE: Arc<FdTable> = Arc::new(FdTable::with_stdio());
+}
+
+/// Returns the file descriptor table of the current process, or the global
+/// table shared by all tasks.
+pub fn current_fd_table() -> Arc<FdTable> {
+    #[cfg(feature = "uspace")]
+    if let Some(table) = crate_interface::call_interface!(FdTableIf::current_fd_table) {
+        return table;
+    }
+    FD_TABLE.clone()
 }
 
 pub fn get_file_like(fd: c_int) -> LinuxResult<Arc<dyn FileLike>> {
-    FD_TABLE
-        .read()
-        .get(fd as usize)
-        .cloned()
-        .ok_or(LinuxError::EBADF)
+    current_fd_table().get(fd)
 }
 
 pub fn add_file_like(f: Arc<dyn FileLike>) -> LinuxResult<c_int> {
-    Ok(FD_TABLE.write().add(f).ok_or(LinuxError::EMFILE)? as c_int)
+    current_fd_table().add(f, false)
 }
 
 pub fn close_file_like(fd: c_int) -> LinuxResult {
-    let f = FD_TABLE
-        .write()
-        .remove(fd as usize)
-        .ok_or(LinuxError::EBADF)?;
+    let f = current_fd_table().remove(fd)?;
     drop(f);
     Ok(())
 }
@@ -55,63 +224,81 @@ pub fn close_file_like(fd: c_int) -> LinuxResult {
 /// Close a file by `fd`.
 pub fn sys_close(fd: c_int) -> c_int {
     debug!("sys_close <= {}", fd);
-    if (0..=2).contains(&fd) {
+    // The stdio of the global table is never closed, since it is shared by
+    // all tasks.
+    if !cfg!(feature = "uspace") && (0..=2).contains(&fd) {
         return 0; // stdin, stdout, stderr
     }
     syscall_body!(sys_close, close_file_like(fd).map(|_| 0))
 }
 
-fn dup_fd(old_fd: c_int) -> LinuxResult<c_int> {
-    let f = get_file_like(old_fd)?;
-    let new_fd = add_file_like(f)?;
-    Ok(new_fd)
+fn dup_fd(old_fd: c_int, min_fd: c_int, cloexec: bool) -> LinuxResult<c_int> {
+    let table = current_fd_table();
+    let f = table.get(old_fd)?;
+    table.add_from(min_fd, f, cloexec)
 }
 
 /// Duplicate a file descriptor.
 pub fn sys_dup(old_fd: c_int) -> c_int {
     debug!("sys_dup <= {}", old_fd);
-    syscall_body!(sys_dup, dup_fd(old_fd))
+    syscall_body!(sys_dup, dup_fd(old_fd, 0, false))
 }
 
 /// Duplicate a file descriptor, but it uses the file descriptor number specified in `new_fd`.
 ///
-/// TODO: `dup2` should forcibly close new_fd if it is already opened.
+/// The file `new_fd` referred to is closed first.
 pub fn sys_dup2(old_fd: c_int, new_fd: c_int) -> c_int {
     debug!("sys_dup2 <= old_fd: {}, new_fd: {}", old_fd, new_fd);
     syscall_body!(sys_dup2, {
         if old_fd == new_fd {
-            let r = sys_fcntl(old_fd, ctypes::F_GETFD as _, 0);
-            if r >= 0 {
-                return Ok(old_fd);
-            } else {
-                return Ok(r);
-            }
-        }
-        if new_fd as usize >= AX_FILE_LIMIT {
-            return Err(LinuxError::EBADF);
+            current_fd_table().get(old_fd)?;
+            return Ok(new_fd);
         }
+        let table = current_fd_table();
+        table.replace(new_fd, table.get(old_fd)?, false)?;
+        Ok(new_fd)
+    })
+}
 
-        let f = get_file_like(old_fd)?;
-        FD_TABLE
-            .write()
-            .add_at(new_fd as usize, f)
-            .ok_or(LinuxError::EMFILE)?;
-
+/// Like [`sys_dup2`], but `FD_CLOEXEC` can be set on `new_fd` with
+/// `O_CLOEXEC` in `flags`, and `old_fd` must differ from `new_fd`.
+pub fn sys_dup3(old_fd: c_int, new_fd: c_int, flags: c_int) -> c_int {
+    debug!(
+        "sys_dup3 <= old_fd: {}, new_fd: {}, flags: {:#x}",
+        old_fd, new_fd, flags
+    );
+    syscall_body!(sys_dup3, {
+        if old_fd == new_fd || flags as u32 & !ctypes::O_CLOEXEC != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let cloexec = flags as u32 & ctypes::O_CLOEXEC != 0;
+        let table = current_fd_table();
+        table.replace(new_fd, table.get(old_fd)?, cloexec)?;
         Ok(new_fd)
     })
 }
 
 /// Manipulate file descriptor.
 ///
-/// TODO: `SET/GET` command is ignored, hard-code stdin/stdout
+/// TODO: `F_GETFL` is not supported, hard-code stdin/stdout for `F_SETFL`
 pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> c_int {
     debug!("sys_fcntl <= fd: {} cmd: {} arg: {}", fd, cmd, arg);
     syscall_body!(sys_fcntl, {
         match cmd as u32 {
-            ctypes::F_DUPFD => dup_fd(fd),
-            ctypes::F_DUPFD_CLOEXEC => {
-                // TODO: Change fd flags
-                dup_fd(fd)
+            ctypes::F_DUPFD => dup_fd(fd, arg as c_int, false),
+            ctypes::F_DUPFD_CLOEXEC => dup_fd(fd, arg as c_int, true),
+            ctypes::F_GETFD => {
+                let cloexec = current_fd_table().cloexec(fd)?;
+                Ok(if cloexec {
+                    ctypes::FD_CLOEXEC as c_int
+                } else {
+                    0
+                })
+            }
+            ctypes::F_SETFD => {
+                let cloexec = arg & ctypes::FD_CLOEXEC as usize != 0;
+                current_fd_table().set_cloexec(fd, cloexec)?;
+                Ok(0)
             }
             ctypes::F_SETFL => {
                 if fd == 0 || fd == 1 || fd == 2 {
@@ -20,8 +20,8 @@ impl File {
         }
     }
 
-    fn add_to_fd_table(self) -> LinuxResult<c_int> {
-        super::fd_ops::add_file_like(Arc::new(self))
+    fn add_to_fd_table(self, cloexec: bool) -> LinuxResult<c_int> {
+        super::fd_ops::current_fd_table().add(Arc::new(self), cloexec)
     }
 
     fn from_fd(fd: c_int) -> LinuxResult<Arc<Self>> {
@@ -112,10 +112,19 @@ pub fn sys_open(filename: *const c_char, flags: c_int, mode: ctypes::mode_t) ->
     syscall_body!(sys_open, {
         let options = flags_to_options(flags, mode);
         let file = axfs::fops::File::open(filename?, &options)?;
-        File::new(file).add_to_fd_table()
+        File::new(file).add_to_fd_table(flags as u32 & ctypes::O_CLOEXEC != 0)
     })
 }
 
+/// Get an independent handle of the regular file indicated by `fd`.
+///
+/// The returned handle refers to the same file with the same permissions, but
+/// has its own cursor. It is used by subsystems that need to keep the file
+/// open on their own, e.g., file-backed memory mappings.
+pub fn dup_file_handle(fd: c_int) -> LinuxResult<axfs::fops::File> {
+    Ok(File::from_fd(fd)?.inner.lock().try_clone()?)
+}
+
 /// Set the position of the file indicated by `fd`.
 ///
 /// Return its position after seek.
@@ -10,6 +10,9 @@ use crate::ctypes;
 
 pub mod mutex;
 
+/// The return value of the canceled threads, `PTHREAD_CANCELED` in C.
+const PTHREAD_CANCELED: *mut c_void = -1isize as *mut c_void;
+
 lazy_static::lazy_static! {
     static ref TID_TO_PTHREAD: RwLock<BTreeMap<u64, ForceSendSync<ctypes::pthread_t>>> = {
         let mut map = BTreeMap::new();
@@ -94,13 +97,26 @@ impl Pthread {
         }
 
         let thread = unsafe { Box::from_raw(ptr as *mut Pthread) };
-        thread.inner.join();
+        let exit_code = thread.inner.join();
         let tid = thread.inner.id().as_u64();
-        let retval = unsafe { *thread.retval.result.get() };
+        let retval = if exit_code == Some(axtask::KILLED_EXIT_CODE) {
+            PTHREAD_CANCELED
+        } else {
+            unsafe { *thread.retval.result.get() }
+        };
         TID_TO_PTHREAD.write().remove(&tid);
         drop(thread);
         Ok(retval)
     }
+
+    fn cancel(ptr: ctypes::pthread_t) -> LinuxResult {
+        let thread = unsafe { &*(ptr as *const Pthread) };
+        if axtask::kill(&thread.inner) {
+            Ok(())
+        } else {
+            Err(LinuxError::ESRCH)
+        }
+    }
 }
 
 /// Returns the `pthread` struct of current thread.
@@ -138,6 +154,7 @@ pub fn sys_pthread_exit(retval: *mut c_void) -> ! {
 /// Waits for the given thread to exit, and stores the return value in `retval`.
 pub unsafe fn sys_pthread_join(thread: ctypes::pthread_t, retval: *mut *mut c_void) -> c_int {
     debug!("sys_pthread_join <= {:#x}", retval as usize);
+    axtask::exit_if_killed();
     syscall_body!(sys_pthread_join, {
         let ret = Pthread::join(thread)?;
         if !retval.is_null() {
@@ -147,6 +164,26 @@ pub unsafe fn sys_pthread_join(thread: ctypes::pthread_t, retval: *mut *mut c_vo
     })
 }
 
+/// Requests the given thread to be canceled.
+///
+/// The thread exits at the next cancellation point, i.e., when it calls
+/// [`sys_pthread_testcancel`], enters [`sys_pthread_join`], or enters or
+/// returns from [`sys_nanosleep`](crate::sys_nanosleep), and the joiner gets
+/// `PTHREAD_CANCELED`. Blocking calls are not interrupted, and no cleanup
+/// handlers are run.
+pub fn sys_pthread_cancel(thread: ctypes::pthread_t) -> c_int {
+    debug!("sys_pthread_cancel <= {:#x}", thread as usize);
+    syscall_body!(sys_pthread_cancel, {
+        Pthread::cancel(thread)?;
+        Ok(0)
+    })
+}
+
+/// Exits the current thread if it has been canceled.
+pub fn sys_pthread_testcancel() {
+    axtask::exit_if_killed();
+}
+
 #[derive(Clone, Copy)]
 struct ForceSendSync<T>(T);
 
@@ -14,9 +14,16 @@ static_assertions::const_assert_eq!(
 #[repr(C)]
 pub struct PthreadMutex(Mutex<()>);
 
+/// The bit of `pthread_mutexattr_t` for `PTHREAD_PRIO_INHERIT`.
+const MUTEXATTR_PRIO_INHERIT: u32 = 8;
+
 impl PthreadMutex {
-    const fn new() -> Self {
-        Self(Mutex::new(()))
+    const fn new(prio_inherit: bool) -> Self {
+        if prio_inherit {
+            Self(Mutex::new_pi(()))
+        } else {
+            Self(Mutex::new(()))
+        }
     }
 
     fn lock(&self) -> LinuxResult {
@@ -31,15 +38,22 @@ impl PthreadMutex {
 }
 
 /// Initialize a mutex.
+///
+/// The mutex uses priority inheritance if the protocol in `attr` is
+/// `PTHREAD_PRIO_INHERIT`.
 pub fn sys_pthread_mutex_init(
     mutex: *mut ctypes::pthread_mutex_t,
-    _attr: *const ctypes::pthread_mutexattr_t,
+    attr: *const ctypes::pthread_mutexattr_t,
 ) -> c_int {
     debug!("sys_pthread_mutex_init <= {:#x}", mutex as usize);
     syscall_body!(sys_pthread_mutex_init, {
         check_null_mut_ptr(mutex)?;
+        let prio_inherit =
+            !attr.is_null() && unsafe { (*attr).__attr } & MUTEXATTR_PRIO_INHERIT != 0;
         unsafe {
-            mutex.cast::<PthreadMutex>().write(PthreadMutex::new());
+            mutex
+                .cast::<PthreadMutex>()
+                .write(PthreadMutex::new(prio_inherit));
         }
         Ok(0)
     })
@@ -49,3 +49,44 @@ pub unsafe fn sys_setrlimit(resource: c_int, rlimits: *mut crate::ctypes::rlimit
         Ok(0)
     })
 }
+
+/// Get resource usage
+///
+/// All the tasks are in the same process, so `RUSAGE_SELF` sums up all the
+/// tasks, live or exited, and `RUSAGE_THREAD` is of the current task. There
+/// are no child processes, so `RUSAGE_CHILDREN` gets all zeros. Only the CPU
+/// time and context switches are filled.
+pub unsafe fn sys_getrusage(who: c_int, usage: *mut ctypes::rusage) -> c_int {
+    debug!("sys_getrusage <= {} {:#x}", who, usage as usize);
+    syscall_body!(sys_getrusage, {
+        if usage.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let mut ru: ctypes::rusage = unsafe { core::mem::zeroed() };
+        #[cfg(feature = "multitask")]
+        {
+            let stat = if who == ctypes::RUSAGE_SELF as c_int {
+                axtask::total_stat()
+            } else if who == ctypes::RUSAGE_THREAD as c_int {
+                axtask::current().stat()
+            } else if who == ctypes::RUSAGE_CHILDREN {
+                axtask::TaskStat::default()
+            } else {
+                return Err(LinuxError::EINVAL);
+            };
+            ru.ru_utime = stat.utime.into();
+            ru.ru_stime = stat.stime.into();
+            ru.ru_nvcsw = stat.nvcsw as _;
+            ru.ru_nivcsw = stat.nivcsw as _;
+        }
+        #[cfg(not(feature = "multitask"))]
+        if who == ctypes::RUSAGE_SELF as c_int || who == ctypes::RUSAGE_THREAD as c_int {
+            // The only task has been running in the kernel since boot.
+            ru.ru_stime = axhal::time::monotonic_time().into();
+        } else if who != ctypes::RUSAGE_CHILDREN {
+            return Err(LinuxError::EINVAL);
+        }
+        unsafe { *usage = ru };
+        Ok(0)
+    })
+}
@@ -1,5 +1,9 @@
 use core::ffi::c_int;
 
+use axerrno::LinuxError;
+
+use crate::ctypes;
+
 /// Relinquish the CPU, and switches to another task.
 ///
 /// For single-threaded configuration (`multitask` feature is disabled), we just
@@ -30,6 +34,59 @@ pub fn sys_getpid() -> c_int {
     )
 }
 
+/// Set the CPUs a thread is allowed to run on.
+///
+/// Only the calling thread is supported, i.e., `pid` must be 0 or its thread
+/// ID. CPU `i` is in the set if bit `i` of `mask` (`cpusetsize` bytes) is set,
+/// the bytes beyond [`cpu_set_t`](ctypes::cpu_set_t) are ignored.
+///
+/// For single-threaded configuration, the task never leaves the primary CPU,
+/// so the set must contain it.
+pub unsafe fn sys_sched_setaffinity(
+    pid: c_int,
+    cpusetsize: usize,
+    mask: *const ctypes::cpu_set_t,
+) -> c_int {
+    debug!(
+        "sys_sched_setaffinity <= {} {} {:#x}",
+        pid, cpusetsize, mask as usize
+    );
+    syscall_body!(sys_sched_setaffinity, {
+        if mask.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        if pid != 0 && pid != sys_getpid() {
+            return Err(LinuxError::ESRCH);
+        }
+        if cpusetsize == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let len = cpusetsize.min(core::mem::size_of::<ctypes::cpu_set_t>());
+        let bytes = core::slice::from_raw_parts(mask as *const u8, len);
+        let cpu_in_set = |cpu_id: usize| {
+            bytes
+                .get(cpu_id / 8)
+                .is_some_and(|b| b & (1 << (cpu_id % 8)) != 0)
+        };
+
+        #[cfg(feature = "multitask")]
+        {
+            let mut cpumask = axtask::CpuMask::new();
+            for cpu_id in 0..axconfig::SMP {
+                cpumask.set(cpu_id, cpu_in_set(cpu_id));
+            }
+            if !axtask::set_current_affinity(cpumask) {
+                return Err(LinuxError::EINVAL);
+            }
+        }
+        #[cfg(not(feature = "multitask"))]
+        if !cpu_in_set(axhal::cpu::this_cpu_id()) {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(0)
+    })
+}
+
 /// Exit current task
 pub fn sys_exit(exit_code: c_int) -> ! {
     debug!("sys_exit <= {}", exit_code);
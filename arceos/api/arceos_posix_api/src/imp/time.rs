@@ -59,7 +59,10 @@ pub unsafe fn sys_clock_gettime(clk: ctypes::clockid_t, ts: *mut ctypes::timespe
 ///
 /// TODO: should be woken by signals, and set errno
 pub unsafe fn sys_nanosleep(req: *const ctypes::timespec, rem: *mut ctypes::timespec) -> c_int {
-    syscall_body!(sys_nanosleep, {
+    // A cancellation point of `pthread_cancel`.
+    #[cfg(feature = "multitask")]
+    axtask::exit_if_killed();
+    let ret = syscall_body!(sys_nanosleep, {
         unsafe {
             if req.is_null() || (*req).tv_nsec < 0 || (*req).tv_nsec > 999999999 {
                 return Err(LinuxError::EINVAL);
@@ -88,5 +91,8 @@ pub unsafe fn sys_nanosleep(req: *const ctypes::timespec, rem: *mut ctypes::time
             return Err(LinuxError::EINTR);
         }
         Ok(0)
-    })
+    });
+    #[cfg(feature = "multitask")]
+    axtask::exit_if_killed();
+    ret
 }
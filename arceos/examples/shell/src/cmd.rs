@@ -26,6 +26,7 @@ const CMD_TABLE: &[(&str, CmdHandler)] = &[
     ("mkdir", do_mkdir),
     ("pwd", do_pwd),
     ("rm", do_rm),
+    ("top", do_top),
     ("uname", do_uname),
 ];
 
@@ -260,6 +261,81 @@ fn do_uname(_args: &str) {
     );
 }
 
+fn do_top(_args: &str) {
+    /// The clock ticks per second of the times in `/proc/<pid>/stat`.
+    const USER_HZ: u64 = 100;
+
+    struct TaskTimes {
+        pid: u64,
+        comm: String,
+        state: String,
+        ticks: u64,
+    }
+
+    // pid (comm) state ... utime stime ...
+    fn parse_stat(stat: &str) -> Option<TaskTimes> {
+        let (pid, rest) = stat.split_once(" (")?;
+        let (comm, rest) = rest.rsplit_once(") ")?;
+        let fields = rest.split_whitespace().collect::<Vec<_>>();
+        let utime: u64 = fields.get(11)?.parse().ok()?;
+        let stime: u64 = fields.get(12)?.parse().ok()?;
+        Some(TaskTimes {
+            pid: pid.parse().ok()?,
+            comm: String::from(comm),
+            state: String::from(fields[0]),
+            ticks: utime + stime,
+        })
+    }
+
+    fn read_all() -> io::Result<Vec<TaskTimes>> {
+        let mut tasks = Vec::new();
+        for entry in fs::read_dir("/proc")? {
+            let entry = entry?.file_name();
+            let pid = path_to_str!(entry);
+            if !pid.bytes().all(|b| b.is_ascii_digit()) {
+                continue;
+            }
+            // The task may exit at any time.
+            let path = String::from("/proc/") + pid + "/stat";
+            if let Some(times) = fs::read_to_string(&path).ok().and_then(|s| parse_stat(&s)) {
+                tasks.push(times);
+            }
+        }
+        tasks.sort_by_key(|t| t.pid);
+        Ok(tasks)
+    }
+
+    let interval = std::time::Duration::from_secs(1);
+    let result = read_all().and_then(|before| {
+        std::thread::sleep(interval);
+        Ok((before, read_all()?))
+    });
+    let (before, after) = match result {
+        Ok(res) => res,
+        Err(e) => {
+            print_err!("top", "/proc", e);
+            return;
+        }
+    };
+
+    println!("{:>6} S  %CPU     TIME COMMAND", "PID");
+    for task in after {
+        let last = before.iter().find(|t| t.pid == task.pid);
+        let delta = task.ticks - last.map_or(0, |t| t.ticks.min(task.ticks));
+        let cpu = delta as f64 * 100.0 / (USER_HZ as f64 * interval.as_secs_f64());
+        let secs = task.ticks / USER_HZ;
+        println!(
+            "{:>6} {}  {:>4.1} {:>5}:{:02} {}",
+            task.pid,
+            task.state,
+            cpu,
+            secs / 60,
+            secs % 60,
+            task.comm
+        );
+    }
+}
+
 fn do_help(_args: &str) {
     println!("Available commands:");
     for (name, _) in CMD_TABLE {
@@ -8,42 +8,43 @@ extern crate alloc;
 #[macro_use]
 extern crate axlog;
 
-mod task;
 mod syscall;
-mod loader;
 
-use axstd::io;
-use axhal::paging::MappingFlags;
+use alloc::string::String;
+use alloc::sync::Arc;
 use axhal::arch::UspaceContext;
 use axhal::mem::VirtAddr;
+use axhal::paging::MappingFlags;
+use axhal::trap::{register_trap_handler, PAGE_FAULT};
+use axprocess::{load_user_app, UserSpace};
+use axprocess::signal::SIGSEGV;
 use axsync::Mutex;
-use alloc::sync::Arc;
-use alloc::string::String;
-use alloc::collections::BTreeMap;
-use axmm::AddrSpace;
-use loader::load_user_app;
-
-const USER_STACK_SIZE: usize = 0x10000;
-const KERNEL_STACK_SIZE: usize = 0x40000; // 256 KiB
+use axtask::TaskExtRef;
 
 #[cfg_attr(feature = "axstd", no_mangle)]
 fn main() {
     // A new address space for user app.
-    let mut uspace = axmm::new_user_aspace().unwrap();
+    let mut uspace = UserSpace::new().unwrap();
 
-    // Load user app binary file into address space.
-    let entry = match load_user_app("/sbin/mapfile", &mut uspace) {
+    // Load user app binary file into address space, and init user stack.
+    let path = "/sbin/mapfile";
+    let (entry, ustack_top) = match load_user_app(&mut uspace, path, &[String::from(path)], &[]) {
         Ok(e) => e,
         Err(err) => panic!("Cannot load app! {:?}", err),
     };
     ax_println!("entry: {:#x}", entry);
-
-    // Init user stack.
-    let ustack_top = init_user_stack(&mut uspace, true).unwrap();
     ax_println!("New user address space: {:#x?}", uspace);
 
+    // Trace the syscalls of the user process with the `strace` feature, they
+    // are logged at info level.
+    #[cfg(feature = "strace")]
+    {
+        axsyscall::trace::enable();
+        axsyscall::trace::set_log(true);
+    }
+
     // Let's kick off the user process.
-    let user_task = task::spawn_user_task(
+    let user_task = axprocess::spawn_user_task(
         Arc::new(Mutex::new(uspace)),
         UspaceContext::new(entry, ustack_top),
     );
@@ -53,30 +54,28 @@ fn main() {
     ax_println!("monolithic kernel exit [{:?}] normally!", exit_code);
 }
 
-fn init_user_stack(uspace: &mut AddrSpace, populating: bool) -> io::Result<VirtAddr> {
-    let ustack_top = uspace.end();
-    let ustack_vaddr = ustack_top - crate::USER_STACK_SIZE;
-    ax_println!(
-        "Mapping user stack: {:#x?} -> {:#x?}",
-        ustack_vaddr, ustack_top
-    );
-    uspace.map_alloc(
-        ustack_vaddr,
-        crate::USER_STACK_SIZE,
-        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER,
-        populating,
-    ).unwrap();
-
-    let app_name = "hello";
-    let av = BTreeMap::new();
-    let (stack_data, ustack_pointer) = kernel_elf_parser::get_app_stack_region(
-        &[String::from(app_name)],
-        &[],
-        &av,
-        ustack_vaddr,
-        crate::USER_STACK_SIZE,
-    );
-    uspace.write(VirtAddr::from_usize(ustack_pointer), stack_data.as_slice())?;
-
-    Ok(ustack_pointer.into())
+#[register_trap_handler(PAGE_FAULT)]
+fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags, is_user: bool) -> bool {
+    let curr = axtask::current();
+    // Kernel tasks have no user address space.
+    if unsafe { curr.task_ext_ptr() }.is_null() {
+        return false;
+    }
+    // Faults from the kernel are raised when accessing lazily mapped user
+    // buffers in syscalls.
+    if curr
+        .task_ext()
+        .aspace
+        .lock()
+        .handle_page_fault(vaddr, access_flags)
+    {
+        return true;
+    }
+    if is_user {
+        ax_println!("{}: segmentation fault at {:#x}", curr.id_name(), vaddr);
+        // Delivered before returning to user space.
+        axprocess::signal::force_signal(SIGSEGV);
+        return true;
+    }
+    false
 }
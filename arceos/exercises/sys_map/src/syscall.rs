@@ -1,30 +1,45 @@
 #![allow(dead_code)]
 
-use alloc::vec;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use arceos_posix_api as api;
-use arceos_posix_api::get_file_like;
-use axerrno::{LinuxError, LinuxResult};
-use axhal::arch::TrapFrame;
+use axerrno::{AxError, LinuxError, LinuxResult};
+use axhal::arch::{TrapFrame, UspaceContext};
 use axhal::paging::MappingFlags;
-use axhal::trap::{register_trap_handler, SYSCALL};
+use axmm::{UserCStr, UserPtr, UserSlice};
+use axprocess::futex;
+use axprocess::shm::{self, MemFd};
+use axprocess::signal::{self, SigAction, SignalSet};
+use axprocess::{CloneFlags, MremapFlags, Pid};
+use axsyscall::register_syscall;
 use axtask::current;
 use axtask::TaskExtRef;
 use core::ffi::{c_char, c_int, c_void};
-use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange};
-
-const SYS_IOCTL: usize = 29;
-const SYS_OPENAT: usize = 56;
-const SYS_CLOSE: usize = 57;
-const SYS_READ: usize = 63;
-const SYS_WRITE: usize = 64;
-const SYS_WRITEV: usize = 66;
-const SYS_EXIT: usize = 93;
-const SYS_EXIT_GROUP: usize = 94;
-const SYS_SET_TID_ADDRESS: usize = 96;
-const SYS_MMAP: usize = 222;
+use core::mem::size_of;
+use core::time::Duration;
+use memory_addr::{MemoryAddr, VirtAddr};
 
 const AT_FDCWD: i32 = -100;
 
+/// Return immediately if no child has exited, for `wait4`.
+const WNOHANG: i32 = 1;
+
+/// The maximum length of a path, including the terminating NUL.
+const PATH_MAX: usize = 4096;
+/// The maximum length of an argument or environment string of `execve`.
+const ARG_MAX: usize = 0x2_0000;
+/// The size of the kernel buffer for copying data of `read` and `write`.
+const IO_BUF_SIZE: usize = 0x1_0000;
+
+/// Close the memfd on `execve`.
+const MFD_CLOEXEC: u32 = 1;
+/// Allow sealing the memfd, seals are not supported yet though.
+const MFD_ALLOW_SEALING: u32 = 2;
+/// The maximum length of memfd names, without the `memfd:` prefix.
+const MFD_NAME_MAX: usize = 249;
+
 /// Macro to generate syscall body
 ///
 /// It will receive a function which return Result<_, LinuxError> and convert it to
@@ -99,107 +114,799 @@ bitflags::bitflags! {
     }
 }
 
-#[register_trap_handler(SYSCALL)]
-fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
-    ax_println!("handle_syscall [{}] ...", syscall_num);
-    let ret = match syscall_num {
-        SYS_IOCTL => sys_ioctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
-        SYS_SET_TID_ADDRESS => sys_set_tid_address(tf.arg0() as _),
-        SYS_OPENAT => sys_openat(
-            tf.arg0() as _,
-            tf.arg1() as _,
-            tf.arg2() as _,
-            tf.arg3() as _,
-        ),
-        SYS_CLOSE => sys_close(tf.arg0() as _),
-        SYS_READ => sys_read(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
-        SYS_WRITE => sys_write(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
-        SYS_WRITEV => sys_writev(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
-        SYS_EXIT_GROUP => {
-            ax_println!("[SYS_EXIT_GROUP]: system is exiting ..");
-            axtask::exit(tf.arg0() as _)
-        }
-        SYS_EXIT => {
-            ax_println!("[SYS_EXIT]: system is exiting ..");
-            axtask::exit(tf.arg0() as _)
-        }
-        SYS_MMAP => sys_mmap(
-            tf.arg0() as _,
-            tf.arg1() as _,
-            tf.arg2() as _,
-            tf.arg3() as _,
-            tf.arg4() as _,
-            tf.arg5() as _,
-        ),
-        _ => {
-            ax_println!("Unimplemented syscall: {}", syscall_num);
-            -LinuxError::ENOSYS.code() as _
-        }
-    };
-    ret
-}
+register_syscall!(Ioctl => sys_ioctl(i32, usize, *mut c_void));
+register_syscall!(SetTidAddress => sys_set_tid_address(*const i32));
+register_syscall!(Openat => sys_openat(c_int, *const c_char, c_int, api::ctypes::mode_t));
+register_syscall!(Close => sys_close(i32));
+register_syscall!(Dup => sys_dup(i32));
+register_syscall!(Dup3 => sys_dup3(i32, i32, i32));
+register_syscall!(Fcntl => sys_fcntl(i32, i32, usize));
+register_syscall!(Read => sys_read(i32, *mut c_void, usize));
+register_syscall!(Write => sys_write(i32, *const c_void, usize));
+register_syscall!(Writev => sys_writev(i32, *const api::ctypes::iovec, i32));
+register_syscall!(ExitGroup => sys_exit_group(i32));
+register_syscall!(Exit => sys_exit(i32));
+register_syscall!(Futex => sys_futex(usize, u32, u32, *const api::ctypes::timespec, usize, u32));
+register_syscall!(SchedYield => sys_sched_yield());
+register_syscall!(SchedSetaffinity => sys_sched_setaffinity(i32, usize, *const c_void));
+register_syscall!(Getpid => sys_getpid());
+register_syscall!(Getppid => sys_getppid());
+register_syscall!(Gettid => sys_gettid());
+register_syscall!(Kill => sys_kill(i32, u32));
+register_syscall!(Tkill => sys_tkill(i32, u32));
+register_syscall!(Tgkill => sys_tgkill(i32, i32, u32));
+register_syscall!(RtSigaction => sys_rt_sigaction(u32, *const SigAction, *mut SigAction, usize));
+register_syscall!(
+    RtSigprocmask => sys_rt_sigprocmask(i32, *const SignalSet, *mut SignalSet, usize)
+);
+register_syscall!(RtSigpending => sys_rt_sigpending(*mut SignalSet, usize));
+register_syscall!(RtSigreturn => sys_rt_sigreturn(tf));
+// The order of `tls` and `ctid` is swapped on x86_64.
+#[cfg(not(target_arch = "x86_64"))]
+register_syscall!(Clone => sys_clone(tf, u32, usize, usize, usize, usize));
+#[cfg(target_arch = "x86_64")]
+register_syscall!(Clone => sys_clone_x86_64(tf, u32, usize, usize, usize, usize));
+register_syscall!(Execve => sys_execve(*const c_char, *const *const c_char, *const *const c_char));
+register_syscall!(Wait4 => sys_wait4(i32, *mut i32, i32, *mut c_void));
+register_syscall!(Mmap => sys_mmap(*mut usize, usize, i32, i32, i32, isize));
+register_syscall!(Munmap => sys_munmap(*mut c_void, usize));
+register_syscall!(Mprotect => sys_mprotect(*mut c_void, usize, i32));
+register_syscall!(Mremap => sys_mremap(*mut c_void, usize, usize, u32, *mut c_void));
+register_syscall!(Brk => sys_brk(usize));
+register_syscall!(Msync => sys_msync(*mut c_void, usize, i32));
+register_syscall!(Shmget => sys_shmget(i32, usize, i32));
+register_syscall!(Shmat => sys_shmat(i32, *const c_void, i32));
+register_syscall!(Shmdt => sys_shmdt(*const c_void));
+register_syscall!(Shmctl => sys_shmctl(i32, i32, *mut c_void));
+register_syscall!(MemfdCreate => sys_memfd_create(*const c_char, u32));
+register_syscall!(Ftruncate => sys_ftruncate(i32, isize));
+register_syscall!(Readlinkat => sys_readlinkat(c_int, *const c_char, *mut c_char, usize));
 
-#[allow(unused_variables)]
 fn sys_mmap(
     addr: *mut usize,
     length: usize,
     prot: i32,
     flags: i32,
     fd: i32,
-    _offset: isize,
+    offset: isize,
 ) -> isize {
     debug!(
-        "sys_mmap <= addr={:p}, length={:#x}, prot={:#x}, flags={:#x}, fd={:#x}, offset={:#x}",
-        addr, length, prot, flags, fd, _offset
+        "sys_mmap <= addr={:p}, length={:#x}, prot={:#x}, flags={:#x}, fd={}, offset={:#x}",
+        addr, length, prot, flags, fd, offset
     );
-    syscall_body!(sys_mmap, mmap(addr, length, prot, flags, fd, _offset))
+    syscall_body!(sys_mmap, mmap(addr, length, prot, flags, fd, offset))
 }
+
 fn mmap(
     addr: *mut usize,
     length: usize,
     prot: i32,
-    _flags: i32,
+    flags: i32,
     fd: i32,
-    _offset: isize,
-) -> LinuxResult<c_int> {
-    let cur = current();
+    offset: isize,
+) -> LinuxResult<usize> {
+    let prot = MmapProt::from_bits_truncate(prot);
+    let flags = MmapFlags::from_bits_truncate(flags);
+    if length == 0 || offset < 0 || !(offset as usize).is_aligned_4k() {
+        return Err(LinuxError::EINVAL);
+    }
     let length = length.align_up_4k();
+    let start = VirtAddr::from(addr as usize);
+
+    let cur = current();
     let mut aspace = cur.task_ext().aspace.lock();
-    let Some(vaddr) = aspace.find_free_area(
-        VirtAddr::from(addr as usize),
-        length,
-        VirtAddrRange::from_start_size(aspace.base(), aspace.size()),
-    ) else {
-        return Err(LinuxError::ENOMEM);
+    let vaddr = if flags.contains(MmapFlags::MAP_FIXED) {
+        if !start.is_aligned_4k() {
+            return Err(LinuxError::EINVAL);
+        }
+        aspace.unmap(start, length)?;
+        start
+    } else {
+        aspace
+            .find_mmap_area(start, length)
+            .ok_or(LinuxError::ENOMEM)?
     };
-    let flags = MmapProt::from_bits_truncate(prot);
-    aspace.map_alloc(vaddr, length, flags.into(), true)?;
-    let file = get_file_like(fd)?;
-    let mut buf = vec![0; length];
-    file.read(&mut buf)?;
-    aspace.write(vaddr, &buf)?;
 
-    Ok(vaddr.as_usize() as c_int)
+    if flags.contains(MmapFlags::MAP_ANONYMOUS) {
+        aspace.map_alloc(vaddr, length, prot.into(), false)?;
+    } else if let Some(memfd) = get_memfd(fd)? {
+        // Pages of a memfd are always shared, private copies are not
+        // supported.
+        if !flags.contains(MmapFlags::MAP_SHARED) {
+            return Err(LinuxError::EINVAL);
+        }
+        let pages = memfd.pages().clone();
+        aspace.map_shared(vaddr, length, prot.into(), pages, offset as usize)?;
+    } else {
+        let file = api::dup_file_handle(fd)?;
+        aspace.map_file(
+            vaddr,
+            length,
+            prot.into(),
+            Arc::new(file),
+            offset as u64,
+            flags.contains(MmapFlags::MAP_SHARED),
+        )?;
+    }
+    Ok(vaddr.as_usize())
+}
+
+/// Returns the memfd opened as `fd`, or `None` if `fd` is another file.
+fn get_memfd(fd: i32) -> LinuxResult<Option<Arc<MemFd>>> {
+    Ok(api::get_file_like(fd)?.into_any().downcast::<MemFd>().ok())
+}
+
+fn sys_shmget(key: i32, size: usize, shmflg: i32) -> isize {
+    debug!(
+        "sys_shmget <= key={}, size={:#x}, shmflg={:#o}",
+        key, size, shmflg
+    );
+    syscall_body!(sys_shmget, Ok(shm::shmget(key, size, shmflg)?))
+}
+
+fn sys_shmat(shmid: i32, addr: *const c_void, shmflg: i32) -> isize {
+    debug!(
+        "sys_shmat <= shmid={}, addr={:p}, shmflg={:#o}",
+        shmid, addr, shmflg
+    );
+    syscall_body!(sys_shmat, {
+        let cur = current();
+        let mut aspace = cur.task_ext().aspace.lock();
+        let addr = VirtAddr::from(addr as usize);
+        let start = shm::shmat(&mut aspace, shmid, addr, shmflg)?;
+        Ok(start.as_usize())
+    })
+}
+
+fn sys_shmdt(addr: *const c_void) -> isize {
+    debug!("sys_shmdt <= addr={:p}", addr);
+    syscall_body!(sys_shmdt, {
+        let cur = current();
+        let mut aspace = cur.task_ext().aspace.lock();
+        shm::shmdt(&mut aspace, VirtAddr::from(addr as usize))?;
+        Ok(0)
+    })
+}
+
+fn sys_shmctl(shmid: i32, cmd: i32, _buf: *mut c_void) -> isize {
+    debug!("sys_shmctl <= shmid={}, cmd={}", shmid, cmd);
+    syscall_body!(sys_shmctl, {
+        shm::shmctl(shmid, cmd).map_err(|e| match e {
+            AxError::Unsupported => LinuxError::EINVAL,
+            e => e.into(),
+        })?;
+        Ok(0)
+    })
+}
+
+fn sys_memfd_create(name: *const c_char, flags: u32) -> isize {
+    syscall_body!(sys_memfd_create, {
+        let name = read_path(name as usize)?;
+        debug!("sys_memfd_create <= name={:?}, flags={:#x}", name, flags);
+        if name.len() > MFD_NAME_MAX || flags & !(MFD_CLOEXEC | MFD_ALLOW_SEALING) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let fd_table = api::current_fd_table();
+        fd_table.add(Arc::new(MemFd::new(&name)), flags & MFD_CLOEXEC != 0)
+    })
+}
+
+fn sys_ftruncate(fd: i32, length: isize) -> isize {
+    debug!("sys_ftruncate <= fd={}, length={:#x}", fd, length);
+    syscall_body!(sys_ftruncate, {
+        if length < 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        match get_memfd(fd)? {
+            Some(memfd) => memfd.truncate(length as usize)?,
+            None => api::dup_file_handle(fd)?.truncate(length as u64)?,
+        }
+        Ok(0)
+    })
 }
+
+/// Reads the target of the link at `path`, only `/proc/self/fd/<fd>` of
+/// memfds is supported, as there are no symbolic links in the file system.
+fn sys_readlinkat(dfd: c_int, path: *const c_char, buf: *mut c_char, bufsiz: usize) -> isize {
+    syscall_body!(sys_readlinkat, {
+        let path = read_path(path as usize)?;
+        debug!(
+            "sys_readlinkat <= dfd={}, path={:?}, buf={:p}, bufsiz={:#x}",
+            dfd, path, buf, bufsiz
+        );
+        if dfd != AT_FDCWD && !path.starts_with('/') {
+            return Err(LinuxError::ENOSYS);
+        }
+        if bufsiz == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let fd = path
+            .strip_prefix("/proc/self/fd/")
+            .ok_or(LinuxError::EINVAL)?
+            .parse::<i32>()
+            .map_err(|_| LinuxError::ENOENT)?;
+        let memfd = get_memfd(fd)?.ok_or(LinuxError::ENOSYS)?;
+        // A memfd has no directory entry, so it shows as deleted like on Linux.
+        let target = format!("/{} (deleted)", memfd.name());
+        // The target is truncated to `bufsiz` bytes without a NUL.
+        let len = target.len().min(bufsiz);
+        let cur = current();
+        UserSlice::new(buf as usize, len)
+            .copy_from_slice(&mut cur.task_ext().aspace.lock(), &target.as_bytes()[..len])?;
+        Ok(len)
+    })
+}
+
+fn sys_munmap(addr: *mut c_void, length: usize) -> isize {
+    debug!("sys_munmap <= addr={:p}, length={:#x}", addr, length);
+    syscall_body!(sys_munmap, {
+        let start = VirtAddr::from(addr as usize);
+        if !start.is_aligned_4k() || length == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let cur = current();
+        cur.task_ext()
+            .aspace
+            .lock()
+            .unmap(start, length.align_up_4k())?;
+        Ok(0)
+    })
+}
+
+fn sys_msync(addr: *mut c_void, length: usize, flags: i32) -> isize {
+    debug!(
+        "sys_msync <= addr={:p}, length={:#x}, flags={:#x}",
+        addr, length, flags
+    );
+    syscall_body!(sys_msync, {
+        let start = VirtAddr::from(addr as usize);
+        if !start.is_aligned_4k() {
+            return Err(LinuxError::EINVAL);
+        }
+        let cur = current();
+        cur.task_ext()
+            .aspace
+            .lock()
+            .sync(start, length.align_up_4k())?;
+        Ok(0)
+    })
+}
+
+fn sys_mprotect(addr: *mut c_void, length: usize, prot: i32) -> isize {
+    debug!(
+        "sys_mprotect <= addr={:p}, length={:#x}, prot={:#x}",
+        addr, length, prot
+    );
+    syscall_body!(sys_mprotect, {
+        let start = VirtAddr::from(addr as usize);
+        if !start.is_aligned_4k() {
+            return Err(LinuxError::EINVAL);
+        }
+        let prot = MmapProt::from_bits(prot).ok_or(LinuxError::EINVAL)?;
+        if length == 0 {
+            return Ok(0);
+        }
+        let cur = current();
+        cur.task_ext()
+            .aspace
+            .lock()
+            .protect(start, length.align_up_4k(), prot.into())?;
+        Ok(0)
+    })
+}
+
+fn sys_mremap(
+    old_addr: *mut c_void,
+    old_size: usize,
+    new_size: usize,
+    flags: u32,
+    new_addr: *mut c_void,
+) -> isize {
+    debug!(
+        "sys_mremap <= old_addr={:p}, old_size={:#x}, new_size={:#x}, flags={:#x}, new_addr={:p}",
+        old_addr, old_size, new_size, flags, new_addr
+    );
+    syscall_body!(sys_mremap, {
+        let flags = MremapFlags::from_bits(flags).ok_or(LinuxError::EINVAL)?;
+        let cur = current();
+        let new_start = cur.task_ext().aspace.lock().remap(
+            VirtAddr::from(old_addr as usize),
+            old_size,
+            new_size,
+            flags,
+            VirtAddr::from(new_addr as usize),
+        )?;
+        Ok(new_start.as_usize())
+    })
+}
+
+fn sys_brk(addr: usize) -> isize {
+    debug!("sys_brk <= addr={:#x}", addr);
+    syscall_body!(sys_brk, {
+        let cur = current();
+        let mut aspace = cur.task_ext().aspace.lock();
+        // `brk(0)` queries the current program break.
+        let brk = if addr == 0 {
+            aspace.brk()
+        } else {
+            aspace.set_brk(VirtAddr::from(addr))
+        };
+        Ok(brk.as_usize())
+    })
+}
+
+fn sys_clone(
+    tf: &TrapFrame,
+    flags: u32,
+    stack: usize,
+    ptid: usize,
+    tls: usize,
+    ctid: usize,
+) -> isize {
+    debug!(
+        "sys_clone <= flags={:#x}, stack={:#x}, ptid={:#x}, tls={:#x}, ctid={:#x}",
+        flags, stack, ptid, tls, ctid
+    );
+    syscall_body!(sys_clone, {
+        let flags = CloneFlags::from_bits_truncate(flags);
+        Ok(axprocess::clone_task(tf, flags, stack, ptid, tls, ctid)?)
+    })
+}
+
+#[cfg(target_arch = "x86_64")]
+fn sys_clone_x86_64(
+    tf: &TrapFrame,
+    flags: u32,
+    stack: usize,
+    ptid: usize,
+    ctid: usize,
+    tls: usize,
+) -> isize {
+    sys_clone(tf, flags, stack, ptid, tls, ctid)
+}
+
+/// Reads a NULL-terminated array of C strings from user space.
+fn read_cstr_array(addr: usize) -> LinuxResult<Vec<String>> {
+    let mut strs = Vec::new();
+    if addr == 0 {
+        return Ok(strs);
+    }
+    let cur = current();
+    let mut aspace = cur.task_ext().aspace.lock();
+    let mut ptr = UserPtr::<usize>::new(addr);
+    loop {
+        let addr = ptr.read(&mut aspace)?;
+        if addr == 0 {
+            break;
+        }
+        let s = UserCStr::new(addr).read(&mut aspace, ARG_MAX);
+        strs.push(s.map_err(cstr_err)?);
+        ptr = ptr.add(1);
+    }
+    Ok(strs)
+}
+
+/// Reads a path from user space.
+fn read_path(addr: usize) -> LinuxResult<String> {
+    if addr == 0 {
+        return Err(LinuxError::EFAULT);
+    }
+    let cur = current();
+    let mut aspace = cur.task_ext().aspace.lock();
+    UserCStr::new(addr)
+        .read(&mut aspace, PATH_MAX - 1)
+        .map_err(|e| match e {
+            AxError::InvalidInput => LinuxError::ENAMETOOLONG,
+            e => cstr_err(e),
+        })
+}
+
+fn cstr_err(e: AxError) -> LinuxError {
+    match e {
+        AxError::InvalidData => LinuxError::EINVAL,
+        e => e.into(),
+    }
+}
+
+fn sys_execve(
+    path: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> isize {
+    let res = (|| -> LinuxResult<UspaceContext> {
+        let path = read_path(path as usize)?;
+        let args = read_cstr_array(argv as usize)?;
+        let envs = read_cstr_array(envp as usize)?;
+        debug!(
+            "sys_execve <= path={:?}, args={:?}, envs={:?}",
+            path, args, envs
+        );
+        Ok(axprocess::exec(&path, &args, &envs)?)
+    })();
+    // It only returns on failure.
+    let uctx = match res {
+        Ok(uctx) => uctx,
+        Err(e) => {
+            info!("sys_execve => {:?}", e);
+            return -e.code() as _;
+        }
+    };
+    // Everything on the kernel stack is discarded from here.
+    let kstack_top = current().kernel_stack_top().unwrap();
+    unsafe { uctx.enter_uspace(kstack_top) }
+}
+
+fn sys_wait4(pid: i32, wstatus: *mut i32, options: i32, _rusage: *mut c_void) -> isize {
+    debug!(
+        "sys_wait4 <= pid={}, wstatus={:p}, options={:#x}",
+        pid, wstatus, options
+    );
+    syscall_body!(sys_wait4, {
+        // Process groups are not supported, so `pid <= 0` means any child.
+        let pid = (pid > 0).then_some(pid as Pid);
+        let cur = current();
+        let proc = cur.task_ext().proc.clone();
+        match proc.wait_child(pid, options & WNOHANG != 0) {
+            Ok(Some((pid, status))) => {
+                let wstatus = UserPtr::from(wstatus);
+                if !wstatus.is_null() {
+                    wstatus.write(&mut cur.task_ext().aspace.lock(), status)?;
+                }
+                Ok(pid as isize)
+            }
+            Ok(None) => Ok(0),
+            Err(AxError::NotFound) => Err(LinuxError::ECHILD),
+            Err(e) => Err(e.into()),
+        }
+    })
+}
+
+fn sys_futex(
+    uaddr: usize,
+    op: u32,
+    val: u32,
+    timeout: *const api::ctypes::timespec,
+    uaddr2: usize,
+    val3: u32,
+) -> isize {
+    debug!(
+        "sys_futex <= uaddr={:#x}, op={:#x}, val={:#x}, uaddr2={:#x}, val3={:#x}",
+        uaddr, op, val, uaddr2, val3
+    );
+    syscall_body!(sys_futex, {
+        let uaddr = VirtAddr::from(uaddr);
+        let cmd = op & !(futex::FUTEX_PRIVATE_FLAG | futex::FUTEX_CLOCK_REALTIME);
+        // For requeue operations, the timeout argument is the number of
+        // waiters to requeue.
+        let val2 = timeout as usize;
+        let res = match cmd {
+            futex::FUTEX_WAIT | futex::FUTEX_WAIT_BITSET => {
+                let timeout = UserPtr::from(timeout);
+                let timeout = if timeout.is_null() {
+                    None
+                } else {
+                    let cur = current();
+                    Some(timeout.read(&mut cur.task_ext().aspace.lock())?)
+                };
+                let timeout = match timeout {
+                    Some(ts) => {
+                        if ts.tv_sec < 0 || !(0..1_000_000_000).contains(&ts.tv_nsec) {
+                            return Err(LinuxError::EINVAL);
+                        }
+                        let dur = Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32);
+                        if cmd == futex::FUTEX_WAIT {
+                            Some(dur)
+                        } else {
+                            // The timeout is absolute for `FUTEX_WAIT_BITSET`.
+                            let now = if op & futex::FUTEX_CLOCK_REALTIME != 0 {
+                                axhal::time::wall_time()
+                            } else {
+                                axhal::time::monotonic_time()
+                            };
+                            Some(dur.saturating_sub(now))
+                        }
+                    }
+                    None => None,
+                };
+                let bitset = if cmd == futex::FUTEX_WAIT {
+                    futex::FUTEX_BITSET_MATCH_ANY
+                } else {
+                    val3
+                };
+                futex::futex_wait(uaddr, val, timeout, bitset).map(|_| 0)
+            }
+            futex::FUTEX_WAKE => Ok(futex::futex_wake(
+                uaddr,
+                val as usize,
+                futex::FUTEX_BITSET_MATCH_ANY,
+            )),
+            futex::FUTEX_WAKE_BITSET => {
+                if val3 == 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                Ok(futex::futex_wake(uaddr, val as usize, val3))
+            }
+            futex::FUTEX_REQUEUE => {
+                futex::futex_requeue(uaddr, val as usize, uaddr2.into(), val2, None)
+            }
+            futex::FUTEX_CMP_REQUEUE => {
+                futex::futex_requeue(uaddr, val as usize, uaddr2.into(), val2, Some(val3))
+            }
+            _ => return Err(LinuxError::ENOSYS),
+        };
+        Ok(res?)
+    })
+}
+
+fn sys_kill(pid: i32, sig: u32) -> isize {
+    debug!("sys_kill <= pid={}, sig={}", pid, sig);
+    syscall_body!(sys_kill, {
+        // Process groups are not supported, each process is in its own group.
+        let pid = match pid {
+            0 => current().task_ext().proc_id(),
+            -1 => return Err(LinuxError::EINVAL),
+            pid => pid.unsigned_abs() as Pid,
+        };
+        signal::send_signal_to_process(pid, sig).map_err(signal_err)?;
+        Ok(0)
+    })
+}
+
+fn sys_tkill(tid: i32, sig: u32) -> isize {
+    sys_tgkill(current().task_ext().proc_id() as _, tid, sig)
+}
+
+fn sys_tgkill(tgid: i32, tid: i32, sig: u32) -> isize {
+    debug!("sys_tgkill <= tgid={}, tid={}, sig={}", tgid, tid, sig);
+    syscall_body!(sys_tgkill, {
+        if tgid <= 0 || tid <= 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        signal::send_signal_to_thread(tgid as Pid, tid as Pid, sig).map_err(signal_err)?;
+        Ok(0)
+    })
+}
+
+fn signal_err(e: AxError) -> LinuxError {
+    match e {
+        AxError::NotFound => LinuxError::ESRCH,
+        e => e.into(),
+    }
+}
+
+fn sys_rt_sigaction(
+    sig: u32,
+    act: *const SigAction,
+    oldact: *mut SigAction,
+    sigsetsize: usize,
+) -> isize {
+    debug!(
+        "sys_rt_sigaction <= sig={}, act={:p}, oldact={:p}",
+        sig, act, oldact
+    );
+    syscall_body!(sys_rt_sigaction, {
+        if sigsetsize != size_of::<SignalSet>() {
+            return Err(LinuxError::EINVAL);
+        }
+        let cur = current();
+        let (act, oldact) = (UserPtr::from(act), UserPtr::from(oldact));
+        let act = if act.is_null() {
+            None
+        } else {
+            Some(act.read(&mut cur.task_ext().aspace.lock())?)
+        };
+        let old = signal::sigaction(sig, act)?;
+        if !oldact.is_null() {
+            oldact.write(&mut cur.task_ext().aspace.lock(), old)?;
+        }
+        Ok(0)
+    })
+}
+
+fn sys_rt_sigprocmask(
+    how: i32,
+    set: *const SignalSet,
+    oldset: *mut SignalSet,
+    sigsetsize: usize,
+) -> isize {
+    debug!(
+        "sys_rt_sigprocmask <= how={}, set={:p}, oldset={:p}",
+        how, set, oldset
+    );
+    syscall_body!(sys_rt_sigprocmask, {
+        if sigsetsize != size_of::<SignalSet>() {
+            return Err(LinuxError::EINVAL);
+        }
+        let cur = current();
+        let (set, oldset) = (UserPtr::from(set), UserPtr::from(oldset));
+        let set = if set.is_null() {
+            None
+        } else {
+            Some(set.read(&mut cur.task_ext().aspace.lock())?)
+        };
+        let old = signal::sigprocmask(how, set)?;
+        if !oldset.is_null() {
+            oldset.write(&mut cur.task_ext().aspace.lock(), old)?;
+        }
+        Ok(0)
+    })
+}
+
+fn sys_rt_sigpending(set: *mut SignalSet, sigsetsize: usize) -> isize {
+    syscall_body!(sys_rt_sigpending, {
+        if sigsetsize != size_of::<SignalSet>() {
+            return Err(LinuxError::EINVAL);
+        }
+        let cur = current();
+        UserPtr::from(set).write(&mut cur.task_ext().aspace.lock(), signal::sigpending())?;
+        Ok(0)
+    })
+}
+
+fn sys_rt_sigreturn(tf: &TrapFrame) -> isize {
+    // The whole user context is restored before returning to user space, so
+    // the return value is discarded.
+    syscall_body!(sys_rt_sigreturn, {
+        if let Err(e) = signal::sigreturn(tf) {
+            // The signal frame is corrupted, there is no context to return to.
+            signal::force_signal(signal::SIGSEGV);
+            return Err(e.into());
+        }
+        Ok(0)
+    })
+}
+
 fn sys_openat(dfd: c_int, fname: *const c_char, flags: c_int, mode: api::ctypes::mode_t) -> isize {
-    assert_eq!(dfd, AT_FDCWD);
-    api::sys_open(fname, flags, mode) as isize
+    let path = match read_path(fname as usize) {
+        Ok(path) => path,
+        Err(e) => return -e.code() as _,
+    };
+    // Directory file descriptors are not supported, they only matter for
+    // relative paths.
+    if dfd != AT_FDCWD && !path.starts_with('/') {
+        return -LinuxError::ENOSYS.code() as _;
+    }
+    // Appends the NUL terminator for the C API.
+    let mut path = path.into_bytes();
+    path.push(0);
+    api::sys_open(path.as_ptr() as _, flags, mode) as isize
 }
 
 fn sys_close(fd: i32) -> isize {
     api::sys_close(fd) as isize
 }
 
+fn sys_dup(fd: i32) -> isize {
+    api::sys_dup(fd) as isize
+}
+
+fn sys_dup3(old_fd: i32, new_fd: i32, flags: i32) -> isize {
+    api::sys_dup3(old_fd, new_fd, flags) as isize
+}
+
+fn sys_fcntl(fd: i32, cmd: i32, arg: usize) -> isize {
+    api::sys_fcntl(fd, cmd, arg) as isize
+}
+
 fn sys_read(fd: i32, buf: *mut c_void, count: usize) -> isize {
-    api::sys_read(fd, buf, count)
+    syscall_body!(sys_read, {
+        // Short reads are allowed, so at most one kernel buffer is read.
+        let mut kbuf = alloc::vec![0u8; count.min(IO_BUF_SIZE)];
+        let n = api::sys_read(fd, kbuf.as_mut_ptr() as _, kbuf.len());
+        if n <= 0 {
+            return Ok(n);
+        }
+        let cur = current();
+        UserSlice::new(buf as usize, count)
+            .copy_from_slice(&mut cur.task_ext().aspace.lock(), &kbuf[..n as usize])?;
+        Ok(n)
+    })
 }
 
 fn sys_write(fd: i32, buf: *const c_void, count: usize) -> isize {
-    api::sys_write(fd, buf, count)
+    syscall_body!(sys_write, write_user(fd, buf as usize, count))
+}
+
+/// Writes `count` bytes from the user buffer at `buf` to `fd` chunk by chunk,
+/// stops at the first short write.
+fn write_user(fd: i32, buf: usize, count: usize) -> LinuxResult<usize> {
+    let cur = current();
+    let mut written = 0;
+    while written < count {
+        let len = (count - written).min(IO_BUF_SIZE);
+        let kbuf = UserSlice::<u8>::new(buf + written, len)
+            .read_to_vec(&mut cur.task_ext().aspace.lock())?;
+        let n = api::sys_write(fd, kbuf.as_ptr() as _, len);
+        if n < 0 {
+            // Errors are only reported if nothing has been written.
+            if written > 0 {
+                break;
+            }
+            return Err(LinuxError::try_from(-n as i32).unwrap_or(LinuxError::EIO));
+        }
+        written += n as usize;
+        if (n as usize) < len {
+            break;
+        }
+    }
+    Ok(written)
 }
 
 fn sys_writev(fd: i32, iov: *const api::ctypes::iovec, iocnt: i32) -> isize {
-    unsafe { api::sys_writev(fd, iov, iocnt) }
+    syscall_body!(sys_writev, {
+        if !(0..=1024).contains(&iocnt) {
+            return Err(LinuxError::EINVAL);
+        }
+        let cur = current();
+        let iovs = UserSlice::<api::ctypes::iovec>::new(iov as usize, iocnt as usize)
+            .read_to_vec(&mut cur.task_ext().aspace.lock())?;
+        let mut ret = 0;
+        for iov in iovs {
+            let len = iov.iov_len as usize;
+            match write_user(fd, iov.iov_base as usize, len) {
+                Ok(n) => {
+                    ret += n;
+                    if n < len {
+                        break;
+                    }
+                }
+                Err(e) if ret == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        Ok(ret)
+    })
+}
+
+fn sys_exit(code: i32) -> isize {
+    ax_println!("[SYS_EXIT]: system is exiting ..");
+    axprocess::exit_current(code)
+}
+
+fn sys_exit_group(code: i32) -> isize {
+    ax_println!("[SYS_EXIT_GROUP]: system is exiting ..");
+    axprocess::exit_group_current(code)
+}
+
+fn sys_sched_yield() -> isize {
+    axtask::yield_now();
+    0
+}
+
+/// Sets the CPUs the calling thread may run on, other threads are not
+/// supported.
+fn sys_sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const c_void) -> isize {
+    debug!(
+        "sys_sched_setaffinity <= pid={}, cpusetsize={}, mask={:p}",
+        pid, cpusetsize, mask
+    );
+    syscall_body!(sys_sched_setaffinity, {
+        if cpusetsize == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        // Only the bytes the kernel knows of are copied, like on Linux.
+        let len = cpusetsize.min(size_of::<api::ctypes::cpu_set_t>());
+        let cur = current();
+        let bytes = UserSlice::<u8>::new(mask as usize, len)
+            .read_to_vec(&mut cur.task_ext().aspace.lock())?;
+        let ret = unsafe { api::sys_sched_setaffinity(pid, len, bytes.as_ptr() as _) };
+        if ret < 0 {
+            return Err(LinuxError::try_from(-ret).unwrap_or(LinuxError::EINVAL));
+        }
+        Ok(0)
+    })
+}
+
+fn sys_getpid() -> isize {
+    current().task_ext().proc_id() as _
+}
+
+fn sys_getppid() -> isize {
+    current().task_ext().proc.ppid() as _
+}
+
+fn sys_gettid() -> isize {
+    current().id().as_u64() as _
 }
 
 fn sys_set_tid_address(tid_ptd: *const i32) -> isize {
@@ -208,7 +915,7 @@ fn sys_set_tid_address(tid_ptd: *const i32) -> isize {
     curr.id().as_u64() as isize
 }
 
-fn sys_ioctl(_fd: i32, _op: usize, _argp: *mut c_void) -> i32 {
+fn sys_ioctl(_fd: i32, _op: usize, _argp: *mut c_void) -> isize {
     ax_println!("Ignore SYS_IOCTL");
     0
 }
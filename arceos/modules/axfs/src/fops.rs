@@ -169,6 +169,21 @@ impl File {
         Self::_open_at(None, path, opts)
     }
 
+    /// Creates a new handle to the same file, with the same access
+    /// capabilities but an independent cursor (starting at 0).
+    ///
+    /// It is useful when the file needs to be held by other subsystems, e.g.,
+    /// file-backed memory mappings.
+    pub fn try_clone(&self) -> AxResult<Self> {
+        let node = self.access_node(Cap::empty())?.clone();
+        node.open()?; // balanced by `release()` on drop
+        Ok(Self {
+            node: WithCap::new(node, self.node.cap()),
+            is_append: self.is_append,
+            offset: 0,
+        })
+    }
+
     /// Truncates the file to the specified size.
     pub fn truncate(&self, size: u64) -> AxResult {
         self.access_node(Cap::WRITE)?.truncate(size)?;
@@ -246,6 +261,16 @@ impl File {
     pub fn get_attr(&self) -> AxResult<FileAttr> {
         self.access_node(Cap::empty())?.get_attr()
     }
+
+    /// Returns an identifier of the underlying file node.
+    ///
+    /// Handles created by [`try_clone`](Self::try_clone) have the same
+    /// identifier. Files opened separately have the same identifier if the
+    /// filesystem returns the same node for them.
+    pub fn node_id(&self) -> usize {
+        let node = unsafe { self.node.access_unchecked() };
+        alloc::sync::Arc::as_ptr(node) as *const () as usize
+    }
 }
 
 impl Directory {
@@ -11,3 +11,6 @@ pub use axfs_devfs as devfs;
 
 #[cfg(feature = "ramfs")]
 pub use axfs_ramfs as ramfs;
+
+#[cfg(all(feature = "procfs", feature = "multitask"))]
+pub mod procfs;
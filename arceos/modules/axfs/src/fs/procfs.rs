@@ -0,0 +1,269 @@
+//! The `/proc` filesystem with task information.
+//!
+//! Besides the static files kept in a RAM filesystem, there is a directory
+//! for each live task named by its ID, and `self` for the current task. Each
+//! of them has these files generated when read:
+//!
+//! - `stat`: the first 22 fields of Linux `/proc/<pid>/stat` in one line,
+//!   times are in clock ticks of [`USER_HZ`].
+//! - `status`: human-readable lines like Linux `/proc/<pid>/status`.
+//! - `wchan`: why the task is blocked, or `0` if it is not.
+
+use alloc::{format, string::String, sync::Arc};
+use core::fmt::Write;
+use core::time::Duration;
+
+use axfs_ramfs::{DirNode, RamFileSystem};
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps};
+use axfs_vfs::{VfsError, VfsResult};
+use axtask::{AxTaskRef, TaskState, WaitReason};
+
+/// The clock ticks per second of the times in `stat`.
+pub const USER_HZ: u64 = 100;
+
+/// The files in each task directory.
+const TASK_FILES: [&str; 3] = ["stat", "status", "wchan"];
+
+/// The procfs, with the static files in a [`RamFileSystem`].
+pub struct ProcFileSystem {
+    static_fs: RamFileSystem,
+    root: Arc<ProcRootDir>,
+}
+
+impl ProcFileSystem {
+    /// Creates a procfs from the RAM filesystem of the static files.
+    pub fn new(static_fs: RamFileSystem) -> Self {
+        let root = Arc::new(ProcRootDir {
+            static_root: static_fs.root_dir_node(),
+        });
+        Self { static_fs, root }
+    }
+}
+
+impl VfsOps for ProcFileSystem {
+    fn mount(&self, path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        self.static_fs.mount(path, mount_point)
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.root.clone()
+    }
+}
+
+fn split_path(path: &str) -> (&str, Option<&str>) {
+    let trimmed_path = path.trim_start_matches('/');
+    trimmed_path.find('/').map_or((trimmed_path, None), |n| {
+        (&trimmed_path[..n], Some(&trimmed_path[n + 1..]))
+    })
+}
+
+fn find_task(id: u64) -> VfsResult<AxTaskRef> {
+    axtask::tasks()
+        .find(|task| task.id().as_u64() == id)
+        .ok_or(VfsError::NotFound)
+}
+
+/// The root directory, listing the static files, then the tasks.
+struct ProcRootDir {
+    static_root: Arc<DirNode>,
+}
+
+impl ProcRootDir {
+    /// Returns the task ID of the directory `name`.
+    fn task_id(name: &str) -> Option<u64> {
+        match name {
+            "self" => Some(axtask::current().id().as_u64()),
+            _ => name.parse().ok(),
+        }
+    }
+}
+
+impl VfsNodeOps for ProcRootDir {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        self.static_root.get_attr()
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        self.static_root.parent()
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let (name, rest) = split_path(path);
+        let Some(id) = Self::task_id(name) else {
+            return self.static_root.clone().lookup(path);
+        };
+        find_task(id)?;
+        let node: VfsNodeRef = Arc::new(TaskDir { id, root: self });
+        match rest {
+            Some(rest) => node.lookup(rest),
+            None => Ok(node),
+        }
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        self.static_root.create(path, ty)
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        self.static_root.remove(path)
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        // `.`, `..` and the static files come first.
+        let num_static = self.static_root.get_entries().len() + 2;
+        let mut count = 0;
+        if start_idx < num_static {
+            count = self.static_root.read_dir(start_idx, dirents)?;
+        }
+        let tasks = axtask::tasks().skip((start_idx + count).saturating_sub(num_static));
+        for (ent, task) in dirents[count..].iter_mut().zip(tasks) {
+            *ent = VfsDirEntry::new(&format!("{}", task.id().as_u64()), VfsNodeType::Dir);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    axfs_vfs::impl_vfs_dir_default! {}
+}
+
+/// The directory of a task.
+struct TaskDir {
+    id: u64,
+    root: Arc<ProcRootDir>,
+}
+
+impl VfsNodeOps for TaskDir {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(VfsNodeAttr::new_dir(4096, 0))
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        Some(self.root.clone())
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let (name, rest) = split_path(path);
+        let node: VfsNodeRef = match name {
+            "" | "." => self.clone(),
+            ".." => self.root.clone(),
+            _ => {
+                let name = TASK_FILES
+                    .into_iter()
+                    .find(|&f| f == name)
+                    .ok_or(VfsError::NotFound)?;
+                Arc::new(TaskFile { id: self.id, name })
+            }
+        };
+        match rest {
+            Some(rest) => node.lookup(rest),
+            None => Ok(node),
+        }
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let entries = [(".", VfsNodeType::Dir), ("..", VfsNodeType::Dir)]
+            .into_iter()
+            .chain(TASK_FILES.map(|f| (f, VfsNodeType::File)))
+            .skip(start_idx);
+        let mut count = 0;
+        for (ent, (name, ty)) in dirents.iter_mut().zip(entries) {
+            *ent = VfsDirEntry::new(name, ty);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    axfs_vfs::impl_vfs_dir_default! {}
+}
+
+/// A file in a task directory, generated when read.
+struct TaskFile {
+    id: u64,
+    name: &'static str,
+}
+
+fn clock_ticks(time: Duration) -> u64 {
+    (time.as_nanos() * USER_HZ as u128 / 1_000_000_000) as u64
+}
+
+impl TaskFile {
+    fn content(&self) -> VfsResult<String> {
+        let task = find_task(self.id)?;
+        let stat = task.stat();
+        let mut buf = String::new();
+        match self.name {
+            "stat" => {
+                let state = match task.state() {
+                    TaskState::Running | TaskState::Ready => 'R',
+                    TaskState::Blocked => 'S',
+                    TaskState::Exited => 'Z',
+                };
+                // pid (comm) state ppid pgrp session tty_nr tpgid flags minflt
+                // cminflt majflt cmajflt utime stime cutime cstime priority nice
+                // num_threads itrealvalue starttime
+                writeln!(
+                    buf,
+                    "{} ({}) {} 0 0 0 0 -1 0 0 0 0 0 {} {} 0 0 20 0 1 0 {}",
+                    self.id,
+                    task.name(),
+                    state,
+                    clock_ticks(stat.utime),
+                    clock_ticks(stat.stime),
+                    clock_ticks(task.start_time()),
+                )
+            }
+            "status" => {
+                let state = match task.state() {
+                    TaskState::Running | TaskState::Ready => "R (running)",
+                    TaskState::Blocked => "S (sleeping)",
+                    TaskState::Exited => "Z (zombie)",
+                };
+                let cpus = task
+                    .cpumask()
+                    .iter()
+                    .map(|cpu| format!("{cpu}"))
+                    .collect::<alloc::vec::Vec<_>>()
+                    .join(",");
+                write!(
+                    buf,
+                    "Name:\t{}\nState:\t{}\nPid:\t{}\nCpus_allowed_list:\t{}\n\
+                     voluntary_ctxt_switches:\t{}\nnonvoluntary_ctxt_switches:\t{}\n",
+                    task.name(),
+                    state,
+                    self.id,
+                    cpus,
+                    stat.nvcsw,
+                    stat.nivcsw,
+                )
+            }
+            _ => {
+                let wchan = match task.wait_reason() {
+                    Some(WaitReason::Sleep) => "sleep",
+                    Some(WaitReason::WaitQueue) => "wait_queue",
+                    Some(WaitReason::WaitQueueTimeout) => "wait_queue_timeout",
+                    None => "0",
+                };
+                writeln!(buf, "{wchan}")
+            }
+        }
+        .unwrap();
+        Ok(buf)
+    }
+}
+
+impl VfsNodeOps for TaskFile {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(VfsNodeAttr::new_file(self.content()?.len() as _, 0))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let content = self.content()?;
+        let start = content.len().min(offset as usize);
+        let end = content.len().min(start + buf.len());
+        let src = &content.as_bytes()[start..end];
+        buf[..src.len()].copy_from_slice(src);
+        Ok(src.len())
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
@@ -22,7 +22,7 @@ pub(crate) fn ramfs() -> Arc<fs::ramfs::RamFileSystem> {
 }
 
 #[cfg(feature = "procfs")]
-pub(crate) fn procfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
+pub(crate) fn procfs() -> VfsResult<Arc<dyn VfsOps>> {
     let procfs = fs::ramfs::RamFileSystem::new();
     let proc_root = procfs.root_dir();
 
@@ -40,11 +40,17 @@ pub(crate) fn procfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
     let file_over = proc_root.clone().lookup("./sys/vm/overcommit_memory")?;
     file_over.write_at(0, b"0\n")?;
 
-    // Create /proc/self/stat
-    proc_root.create("self", VfsNodeType::Dir)?;
-    proc_root.create("self/stat", VfsNodeType::File)?;
+    // With tasks, /proc/self and /proc/<id> are generated by procfs.
+    #[cfg(feature = "multitask")]
+    return Ok(Arc::new(fs::procfs::ProcFileSystem::new(procfs)));
 
-    Ok(Arc::new(procfs))
+    // Create /proc/self/stat
+    #[cfg(not(feature = "multitask"))]
+    {
+        proc_root.create("self", VfsNodeType::Dir)?;
+        proc_root.create("self/stat", VfsNodeType::File)?;
+        Ok(Arc::new(procfs))
+    }
 }
 
 #[cfg(feature = "sysfs")]
@@ -135,3 +135,19 @@ pub fn read_thread_pointer() -> usize {
 pub unsafe fn write_thread_pointer(tpidr_el0: usize) {
     TPIDR_EL0.set(tpidr_el0 as _)
 }
+
+/// Allows the kernel to access user memory, returns whether it was allowed
+/// before.
+///
+/// PAN (Privileged Access Never) is not enabled, so the kernel can always
+/// access user memory.
+#[inline]
+pub fn enable_user_access() -> bool {
+    true
+}
+
+/// Forbids the kernel to access user memory.
+///
+/// It does nothing as PAN is not enabled.
+#[inline]
+pub fn disable_user_access() {}
@@ -36,9 +36,27 @@ fn invalid_exception(tf: &TrapFrame, kind: TrapKind, source: TrapSource) {
     );
 }
 
+/// Whether the trap is taken from EL0, i.e., user space.
+#[cfg(feature = "uspace")]
+const fn is_from_user(tf: &TrapFrame) -> bool {
+    // `SPSR_EL1.M[3:0]` is 0 (EL0t).
+    tf.spsr & 0b1111 == 0
+}
+
 #[no_mangle]
-fn handle_irq_exception(_tf: &TrapFrame) {
+#[cfg_attr(not(feature = "uspace"), allow(unused_variables))]
+fn handle_irq_exception(tf: &mut TrapFrame) {
+    #[cfg(feature = "uspace")]
+    let from_user = is_from_user(tf);
+    #[cfg(feature = "uspace")]
+    if from_user {
+        crate::trap::handle_enter_from_user(tf);
+    }
     handle_trap!(IRQ, 0);
+    #[cfg(feature = "uspace")]
+    if from_user {
+        crate::trap::handle_return_to_user(tf);
+    }
 }
 
 fn handle_instruction_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
@@ -76,6 +94,10 @@ fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
         access_flags |= MappingFlags::USER;
     }
     let vaddr = va!(FAR_EL1.get() as usize);
+    #[cfg(feature = "paging")]
+    if !is_user && crate::mem::kernel_stack_region().contains(vaddr) {
+        crate::trap::handle_stack_overflow(vaddr, tf.elr as usize, tf);
+    }
 
     // Only handle Translation fault and Permission fault
     if !matches!(iss & 0b111100, 0b0100 | 0b1100) // IFSC or DFSC bits
@@ -95,9 +117,21 @@ fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
 
 #[no_mangle]
 fn handle_sync_exception(tf: &mut TrapFrame) {
+    #[cfg(feature = "uspace")]
+    let from_user = is_from_user(tf);
+    #[cfg(feature = "uspace")]
+    if from_user {
+        crate::trap::handle_enter_from_user(tf);
+    }
     let esr = ESR_EL1.extract();
     let iss = esr.read(ESR_EL1::ISS);
     match esr.read_as_enum(ESR_EL1::EC) {
+        // `ELR_EL1` already points to the instruction after `svc`.
+        #[cfg(feature = "uspace")]
+        Some(ESR_EL1::EC::Value::SVC64) => {
+            tf.r[0] = crate::trap::handle_syscall(tf, tf.r[8] as usize) as u64;
+        }
+        #[cfg(not(feature = "uspace"))]
         Some(ESR_EL1::EC::Value::SVC64) => {
             warn!("No syscall is supported currently!");
         }
@@ -119,4 +153,8 @@ fn handle_sync_exception(tf: &mut TrapFrame) {
             );
         }
     }
+    #[cfg(feature = "uspace")]
+    if from_user {
+        crate::trap::handle_return_to_user(tf);
+    }
 }
@@ -85,6 +85,11 @@ impl TrapFrame {
     pub const fn arg5(&self) -> usize {
         self.regs.a5
     }
+
+    /// Gets the syscall number.
+    pub const fn sysno(&self) -> usize {
+        self.regs.a7
+    }
 }
 
 /// Saved hardware states of a task.
@@ -199,14 +204,13 @@ impl UspaceContext {
     /// and the argument.
     pub fn new(entry: usize, ustack_top: VirtAddr) -> Self {
         const SPIE: usize = 1 << 5;
-        const SUM: usize = 1 << 18;
         Self(TrapFrame {
             regs: GeneralRegisters {
                 sp: ustack_top.as_usize(),
                 ..Default::default()
             },
             sepc: entry,
-            sstatus: SPIE | SUM,
+            sstatus: SPIE,
         })
     }
 
@@ -240,6 +244,11 @@ impl UspaceContext {
         self.0.regs.a0 = a0;
     }
 
+    /// Sets the user thread pointer (`tp` register), i.e., the TLS area.
+    pub const fn set_tls(&mut self, tls: usize) {
+        self.0.regs.tp = tls;
+    }
+
     /// Enters user space.
     ///
     /// It restores the user registers and jumps to the user entry point
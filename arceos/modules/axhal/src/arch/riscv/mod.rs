@@ -123,6 +123,24 @@ pub fn cpu_init() {
 /// Bit 1: Supervisor Interrupt Enable
 const SIE_BIT: usize = 1 << 1;
 
+/// Bit 18: Permit Supervisor User Memory access
+const SUM_BIT: usize = 1 << 18;
+
+/// Allows the kernel to access user memory by setting the `SUM` bit, returns
+/// whether it was allowed before.
+#[inline]
+pub fn enable_user_access() -> bool {
+    let flags: usize;
+    unsafe { core::arch::asm!("csrrs {}, sstatus, {}", out(reg) flags, in(reg) SUM_BIT) };
+    flags & SUM_BIT != 0
+}
+
+/// Forbids the kernel to access user memory by clearing the `SUM` bit.
+#[inline]
+pub fn disable_user_access() {
+    unsafe { core::arch::asm!("csrc sstatus, {}", in(reg) SUM_BIT) };
+}
+
 #[inline]
 pub fn local_irq_save_and_disable() -> usize {
     let flags: usize;
@@ -6,9 +6,21 @@ use super::TrapFrame;
 
 include_asm_marcos!();
 
+/// The size of the stack to handle kernel stack overflows on, shared by all
+/// CPUs since the kernel panics anyway.
+#[cfg(feature = "paging")]
+const OVERFLOW_STACK_SIZE: usize = 0x8000;
+#[cfg(not(feature = "paging"))]
+const OVERFLOW_STACK_SIZE: usize = 0;
+
 core::arch::global_asm!(
     include_str!("trap.S"),
     trapframe_size = const core::mem::size_of::<TrapFrame>(),
+    stack_guard = const cfg!(feature = "paging") as usize,
+    overflow_stack_size = const OVERFLOW_STACK_SIZE,
+    kstack_region_base = const crate::mem::KERNEL_STACK_REGION_BASE as isize,
+    kstack_region_shift = const crate::mem::KERNEL_STACK_REGION_SHIFT,
+    kstack_guard_bit = const crate::mem::KERNEL_STACK_SLOT_SHIFT - 1,
 );
 
 fn handle_breakpoint(sepc: &mut usize) {
@@ -21,6 +33,10 @@ fn handle_page_fault(tf: &TrapFrame, mut access_flags: MappingFlags, is_user: bo
         access_flags |= MappingFlags::USER;
     }
     let vaddr = va!(stval::read());
+    #[cfg(feature = "paging")]
+    if !is_user && crate::mem::kernel_stack_region().contains(vaddr) {
+        crate::trap::handle_stack_overflow(vaddr, tf.sepc, tf);
+    }
     if !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user) {
         panic!(
             "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}",
@@ -33,14 +49,28 @@ fn handle_page_fault(tf: &TrapFrame, mut access_flags: MappingFlags, is_user: bo
     }
 }
 
+/// Called on the overflow stack by the trap entry, if the trap frame would be
+/// pushed into the guard pages of a kernel stack.
+#[cfg(feature = "paging")]
+#[no_mangle]
+fn riscv_stack_overflow_handler(tf: &TrapFrame) -> ! {
+    crate::trap::handle_stack_overflow(va!(stval::read()), tf.sepc, tf)
+}
+
 #[no_mangle]
 fn riscv_trap_handler(tf: &mut TrapFrame, from_user: bool) {
     let scause = scause::read();
+    #[cfg(feature = "uspace")]
+    if from_user {
+        crate::trap::handle_enter_from_user(tf);
+    }
     match scause.cause() {
         #[cfg(feature = "uspace")]
         Trap::Exception(E::UserEnvCall) => {
-            tf.regs.a0 = crate::trap::handle_syscall(tf, tf.regs.a7) as usize;
+            // Skip the `ecall` first, so that syscall handlers see the
+            // address to return to, as on other architectures.
             tf.sepc += 4;
+            tf.regs.a0 = crate::trap::handle_syscall(tf, tf.regs.a7) as usize;
         }
         Trap::Exception(E::LoadPageFault) => handle_page_fault(tf, MappingFlags::READ, from_user),
         Trap::Exception(E::StorePageFault) => handle_page_fault(tf, MappingFlags::WRITE, from_user),
@@ -60,4 +90,8 @@ fn riscv_trap_handler(tf: &mut TrapFrame, from_user: bool) {
             );
         }
     }
+    #[cfg(feature = "uspace")]
+    if from_user {
+        crate::trap::handle_return_to_user(tf);
+    }
 }
@@ -108,3 +108,28 @@ pub fn read_thread_pointer() -> usize {
 pub unsafe fn write_thread_pointer(fs_base: usize) {
     unsafe { msr::wrmsr(msr::IA32_FS_BASE, fs_base as u64) }
 }
+
+/// Allows the kernel to access user memory by setting `RFLAGS.AC` if SMAP is
+/// enabled, returns whether it was allowed before.
+#[inline]
+pub fn enable_user_access() -> bool {
+    if !smap_enabled() {
+        return true;
+    }
+    let rflags: u64;
+    unsafe { asm!("pushfq; pop {}; stac", out(reg) rflags) };
+    rflags & (1 << 18) != 0 // AC
+}
+
+/// Forbids the kernel to access user memory by clearing `RFLAGS.AC` if SMAP is
+/// enabled.
+#[inline]
+pub fn disable_user_access() {
+    if smap_enabled() {
+        unsafe { asm!("clac") };
+    }
+}
+
+fn smap_enabled() -> bool {
+    unsafe { controlregs::cr4() }.contains(controlregs::Cr4::CR4_ENABLE_SMAP)
+}
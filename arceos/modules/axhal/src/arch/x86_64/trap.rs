@@ -13,6 +13,10 @@ fn handle_page_fault(tf: &TrapFrame) {
     let access_flags = err_code_to_flags(tf.error_code)
         .unwrap_or_else(|e| panic!("Invalid #PF error code: {:#x}", e));
     let vaddr = va!(unsafe { cr2() });
+    #[cfg(feature = "paging")]
+    if !tf.is_user() && crate::mem::kernel_stack_region().contains(vaddr) {
+        crate::trap::handle_stack_overflow(vaddr, tf.rip as usize, tf);
+    }
     if !handle_trap!(PAGE_FAULT, vaddr, access_flags, tf.is_user()) {
         panic!(
             "Unhandled {} #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}",
@@ -27,7 +31,13 @@ fn handle_page_fault(tf: &TrapFrame) {
 }
 
 #[no_mangle]
-fn x86_trap_handler(tf: &TrapFrame) {
+fn x86_trap_handler(tf: &mut TrapFrame) {
+    #[cfg(feature = "uspace")]
+    let from_user = tf.is_user();
+    #[cfg(feature = "uspace")]
+    if from_user {
+        crate::trap::handle_enter_from_user(tf);
+    }
     match tf.vector as u8 {
         PAGE_FAULT_VECTOR => handle_page_fault(tf),
         BREAKPOINT_VECTOR => debug!("#BP @ {:#x} ", tf.rip),
@@ -51,6 +61,10 @@ fn x86_trap_handler(tf: &TrapFrame) {
             );
         }
     }
+    #[cfg(feature = "uspace")]
+    if from_user {
+        crate::trap::handle_return_to_user(tf);
+    }
 }
 
 fn vec_to_str(vec: u64) -> &'static str {
@@ -12,6 +12,16 @@ pub type IrqHandler = handler_table::Handler;
 
 static IRQ_HANDLER_TABLE: HandlerTable<MAX_IRQ_COUNT> = HandlerTable::new();
 
+/// The nesting depth of IRQ handlers on this CPU.
+#[percpu::def_percpu]
+static IRQ_DEPTH: usize = 0;
+
+/// Returns whether the current CPU is running an IRQ handler.
+#[inline]
+pub fn in_irq() -> bool {
+    IRQ_DEPTH.read_current() != 0
+}
+
 /// Platform-independent IRQ dispatching.
 #[allow(dead_code)]
 pub(crate) fn dispatch_irq_common(irq_num: usize) {
@@ -38,7 +48,10 @@ pub(crate) fn register_handler_common(irq_num: usize, handler: IrqHandler) -> bo
 #[register_trap_handler(IRQ)]
 fn handler_irq(irq_num: usize) -> bool {
     let guard = kernel_guard::NoPreempt::new();
+    // The guard disables preemption, so we stay on this CPU.
+    unsafe { IRQ_DEPTH.write_current_raw(IRQ_DEPTH.read_current_raw() + 1) };
     dispatch_irq(irq_num);
+    unsafe { IRQ_DEPTH.write_current_raw(IRQ_DEPTH.read_current_raw() - 1) };
     drop(guard); // rescheduling may occur when preemption is re-enabled.
     true
 }
@@ -2,6 +2,8 @@
 
 use core::fmt;
 
+use memory_addr::{align_up, VirtAddrRange};
+
 #[doc(no_inline)]
 pub use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, PAGE_SIZE_4K};
 
@@ -70,6 +72,34 @@ pub const fn phys_to_virt(paddr: PhysAddr) -> VirtAddr {
     va!(paddr.as_usize() + axconfig::PHYS_VIRT_OFFSET)
 }
 
+pub(crate) const KERNEL_STACK_REGION_SHIFT: usize = 34;
+pub(crate) const KERNEL_STACK_SLOT_SHIFT: usize = 22;
+
+/// The base address of the virtual region for kernel stacks, in the upper
+/// half of the kernel address space.
+///
+/// The region is divided into slots of [`KERNEL_STACK_SLOT_SIZE`] bytes,
+/// each holding one stack at its top. The lower half of every slot is never
+/// mapped, so it works as the guard pages of the stack above, and a stack
+/// overflow is detected by the page fault it causes. The region is only used
+/// with the `paging` feature.
+pub const KERNEL_STACK_REGION_BASE: usize = align_up(
+    axconfig::KERNEL_ASPACE_BASE + axconfig::KERNEL_ASPACE_SIZE / 2,
+    KERNEL_STACK_REGION_SIZE,
+);
+/// The size of the virtual region for kernel stacks.
+pub const KERNEL_STACK_REGION_SIZE: usize = 1 << KERNEL_STACK_REGION_SHIFT;
+/// The size of a kernel stack slot, a stack can be at most half of it.
+pub const KERNEL_STACK_SLOT_SIZE: usize = 1 << KERNEL_STACK_SLOT_SHIFT;
+
+/// Returns the virtual region for kernel stacks.
+pub fn kernel_stack_region() -> VirtAddrRange {
+    VirtAddrRange::from_start_size(
+        VirtAddr::from(KERNEL_STACK_REGION_BASE),
+        KERNEL_STACK_REGION_SIZE,
+    )
+}
+
 /// Returns an iterator over all physical memory regions.
 pub fn memory_regions() -> impl Iterator<Item = MemRegion> {
     kernel_image_regions().chain(crate::platform::mem::platform_regions())
@@ -4,7 +4,7 @@ use linkme::distributed_slice as def_trap_handler;
 use memory_addr::VirtAddr;
 use page_table_entry::MappingFlags;
 
-#[cfg(feature = "uspace")]
+#[cfg(feature = "paging")]
 use crate::arch::TrapFrame;
 
 pub use linkme::distributed_slice as register_trap_handler;
@@ -22,6 +22,27 @@ pub static PAGE_FAULT: [fn(VirtAddr, MappingFlags, bool) -> bool];
 #[def_trap_handler]
 pub static SYSCALL: [fn(&TrapFrame, usize) -> isize];
 
+/// A slice of handler functions called when trapping from user space, before
+/// the trap is handled.
+#[cfg(feature = "uspace")]
+#[def_trap_handler]
+pub static ENTER_FROM_USER: [fn(&TrapFrame)];
+
+/// A slice of handler functions called before returning to user space.
+///
+/// They can modify the user context, e.g., to deliver signals.
+#[cfg(feature = "uspace")]
+#[def_trap_handler]
+pub static RETURN_TO_USER: [fn(&mut TrapFrame)];
+
+/// A slice of handler functions called when a kernel stack overflows into its
+/// guard pages, with the faulting address.
+///
+/// They can report the overflowing task, the kernel panics afterwards.
+#[cfg(feature = "paging")]
+#[def_trap_handler]
+pub static STACK_OVERFLOW: [fn(VirtAddr)];
+
 #[allow(unused_macros)]
 macro_rules! handle_trap {
     ($trap:ident, $($args:tt)*) => {{
@@ -43,3 +64,32 @@ macro_rules! handle_trap {
 pub(crate) fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
     SYSCALL[0](tf, syscall_num)
 }
+
+/// Call all the external handlers after trapping from user space.
+#[cfg(feature = "uspace")]
+pub(crate) fn handle_enter_from_user(tf: &TrapFrame) {
+    for func in ENTER_FROM_USER {
+        func(tf);
+    }
+}
+
+/// Call all the external handlers before returning to user space.
+#[cfg(feature = "uspace")]
+pub(crate) fn handle_return_to_user(tf: &mut TrapFrame) {
+    for func in RETURN_TO_USER {
+        func(tf);
+    }
+}
+
+/// Calls all the external handlers of a kernel stack overflow that faulted at
+/// `vaddr`, and panics.
+#[cfg(feature = "paging")]
+pub(crate) fn handle_stack_overflow(vaddr: VirtAddr, pc: usize, tf: &TrapFrame) -> ! {
+    for func in STACK_OVERFLOW {
+        func(vaddr);
+    }
+    panic!(
+        "Kernel stack overflow @ {:#x}, fault_vaddr={:#x}:\n{:#x?}",
+        pc, vaddr, tf
+    );
+}
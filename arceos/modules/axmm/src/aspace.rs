@@ -3,13 +3,14 @@ use core::fmt;
 use axerrno::{ax_err, AxError, AxResult};
 use axhal::{
     mem::phys_to_virt,
-    paging::{MappingFlags, PageTable},
+    paging::{MappingFlags, PageSize, PageTable},
 };
 use memory_addr::{
     is_aligned_4k, pa, MemoryAddr, PageIter4K, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K,
 };
 use memory_set::{MemoryArea, MemorySet};
-use crate::backend::Backend;
+use crate::backend::{split_huge_pages, Backend, SharedPages};
+use crate::frame::{frame_ref_dec, frame_ref_inc};
 use crate::paging_err_to_ax_err;
 use crate::mapping_err_to_ax_err;
 use alloc::vec::Vec;
@@ -19,6 +20,8 @@ pub struct AddrSpace {
     va_range: VirtAddrRange,
     areas: MemorySet<Backend>,
     pt: PageTable,
+    #[cfg(feature = "swap")]
+    swap: crate::swap::SwapSpace,
 }
 
 impl AddrSpace {
@@ -59,6 +62,8 @@ impl AddrSpace {
             va_range: VirtAddrRange::from_start_size(base, size),
             areas: MemorySet::new(),
             pt: PageTable::try_new().map_err(|_| AxError::NoMemory)?,
+            #[cfg(feature = "swap")]
+            swap: crate::swap::SwapSpace::new(),
         })
     }
 
@@ -77,6 +82,107 @@ impl AddrSpace {
         Ok(())
     }
 
+    /// Creates the intermediate page tables covering `[start, start + size)`
+    /// without mapping anything.
+    ///
+    /// Address spaces copying the mappings of this one with
+    /// [`copy_mappings_from`](Self::copy_mappings_from) then also see the
+    /// pages mapped in the range later, since the top-level entries are
+    /// shared.
+    ///
+    /// Returns an error if the address range is out of the address space or
+    /// not aligned to 1G.
+    pub fn populate_page_tables(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        let step = usize::from(PageSize::Size1G);
+        if start.as_usize() % step != 0 || size % step != 0 {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+        for addr in (start.as_usize()..start.as_usize() + size).step_by(step) {
+            // Tables are not freed on unmapping, so mapping a 2M page creates
+            // the tables above it for good.
+            let vaddr = VirtAddr::from(addr);
+            self.pt
+                .map(vaddr, pa!(0), PageSize::Size2M, MappingFlags::READ)
+                .map_err(paging_err_to_ax_err)?
+                .ignore();
+            self.pt
+                .unmap(vaddr)
+                .map_err(paging_err_to_ax_err)?
+                .2
+                .flush();
+        }
+        Ok(())
+    }
+
+    /// Duplicates all memory areas of this address space into `child` with
+    /// copy-on-write semantics.
+    ///
+    /// Physical frames already present in allocation areas are shared by both
+    /// address spaces and mapped read-only in each of them. The first write
+    /// from either side triggers a page fault, which is resolved in
+    /// [`handle_page_fault`](Self::handle_page_fault) by giving the writer a
+    /// private copy of the frame. Pages not yet populated stay lazy in the
+    /// child.
+    ///
+    /// Linear mappings created by [`map_linear`](Self::map_linear) are not
+    /// tracked as areas, so they are not duplicated.
+    ///
+    /// Returns an error if the two address spaces have different ranges, or
+    /// `child` already has overlapping areas.
+    pub fn fork_into(&mut self, child: &mut AddrSpace) -> AxResult {
+        if self.va_range != child.va_range {
+            return ax_err!(InvalidInput, "address space range mismatch");
+        }
+        #[cfg(feature = "swap")]
+        {
+            self.swap.restore(self.base(), self.end(), &mut self.pt);
+            child.swap = self.swap.fork();
+        }
+        for area in self.areas.iter() {
+            // Shared file mappings and shared memory stay shared in the child,
+            // other pages are copy-on-write.
+            let (backend, cow) = match area.backend() {
+                Backend::Alloc { .. } => (Backend::new_alloc(false), true),
+                Backend::Huge { page_size, .. } => (Backend::new_huge(*page_size, false), true),
+                #[cfg(feature = "fs")]
+                Backend::File { cache, .. } => (area.backend().clone(), cache.is_none()),
+                Backend::Shared { .. } => (area.backend().clone(), false),
+                Backend::Linear { .. } => continue,
+            };
+            let new_area = MemoryArea::new(area.start(), area.size(), area.flags(), backend);
+            child
+                .areas
+                .map(new_area, &mut child.pt, false)
+                .map_err(mapping_err_to_ax_err)?;
+
+            let mut vaddr = area.start();
+            while vaddr < area.end() {
+                let Ok((frame, flags, page_size)) = self.pt.query(vaddr) else {
+                    vaddr += PAGE_SIZE_4K;
+                    continue; // not populated yet
+                };
+                frame_ref_inc(frame);
+                let child_flags = if cow && flags.contains(MappingFlags::WRITE) {
+                    let cow_flags = flags - MappingFlags::WRITE;
+                    self.pt
+                        .protect(vaddr, cow_flags)
+                        .map_err(paging_err_to_ax_err)?
+                        .1
+                        .flush();
+                    cow_flags
+                } else {
+                    flags
+                };
+                map_frame(&mut child.pt, vaddr, frame, page_size, child_flags)?;
+                vaddr += usize::from(page_size);
+            }
+        }
+        Ok(())
+    }
+
     /// Finds a free area that can accommodate the given size.
     ///
     /// The search starts from the given hint address, and the area should be within the given limit range.
@@ -129,6 +235,45 @@ impl AddrSpace {
         Ok(())
     }
 
+    /// Add a new linear mapping with huge pages.
+    ///
+    /// Like [`map_linear`](Self::map_linear), but 2M or 1G pages are used
+    /// where the addresses and the size are aligned to them. A huge page is
+    /// split into smaller ones when only part of it is unmapped or protected,
+    /// during which the whole huge page is briefly unmapped, so this must not
+    /// be used for the memory in use by the kernel itself.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    pub fn map_linear_huge(
+        &mut self,
+        start_vaddr: VirtAddr,
+        start_paddr: PhysAddr,
+        size: usize,
+        flags: MappingFlags,
+    ) -> AxResult {
+        if !self.contains_range(start_vaddr, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start_vaddr.is_aligned_4k() || !start_paddr.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let offset = start_vaddr.as_usize() - start_paddr.as_usize();
+        self.pt
+            .map_region(
+                start_vaddr,
+                |va| pa!(va.as_usize() - offset),
+                size,
+                flags,
+                true,  // allow_huge
+                false, // flush_tlb_by_page
+            )
+            .map_err(paging_err_to_ax_err)?
+            .flush_all();
+        Ok(())
+    }
+
     /// Add a new allocation mapping.
     ///
     /// See [`Backend`] for more details about the mapping backends.
@@ -158,8 +303,111 @@ impl AddrSpace {
         Ok(())
     }
 
+    /// Add a new allocation mapping with huge pages.
+    ///
+    /// Like [`map_alloc`](Self::map_alloc), but the memory is allocated and
+    /// mapped in huge pages up to `page_size` where possible, which reduces
+    /// the TLB pressure of large mappings. The parts of the range not
+    /// covering a whole huge page are mapped with 4K pages.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned, or `page_size` is not a huge page size.
+    pub fn map_huge(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        page_size: PageSize,
+        populate: bool,
+    ) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+        if !page_size.is_huge() {
+            return ax_err!(InvalidInput, "not a huge page size");
+        }
+
+        let area = MemoryArea::new(start, size, flags, Backend::new_huge(page_size, populate));
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        Ok(())
+    }
+
+    /// Add a new file mapping.
+    ///
+    /// The pages are filled with the contents of `file` starting at `offset`
+    /// on demand. If `shared` is `true`, modifications are written back to the
+    /// file when the pages are unmapped or [`sync`](Self::sync)ed.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    #[cfg(feature = "fs")]
+    pub fn map_file(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        file: alloc::sync::Arc<axfs::fops::File>,
+        offset: u64,
+        shared: bool,
+    ) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) || !is_aligned_4k(offset as usize) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let backend = Backend::new_file(start, file, offset, shared);
+        let area = MemoryArea::new(start, size, flags, backend);
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        Ok(())
+    }
+
+    /// Add a new shared mapping.
+    ///
+    /// The pages are taken from `pages` starting at the byte `offset` on
+    /// demand, and are shared with all other mappings of `pages`, including
+    /// those in the address spaces forked from this one.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    pub fn map_shared(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        pages: alloc::sync::Arc<SharedPages>,
+        offset: usize,
+    ) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) || !is_aligned_4k(offset) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let backend = Backend::new_shared(start, pages, offset);
+        let area = MemoryArea::new(start, size, flags, backend);
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        Ok(())
+    }
+
     /// Removes mappings within the specified virtual address range.
     ///
+    /// Memory areas in the range are released through their backends (e.g.,
+    /// frames are freed, and dirty pages of shared file mappings are written
+    /// back), and may be split if partially covered. Linear mappings in the
+    /// rest of the range are removed, and unmapped pages are skipped.
+    ///
     /// Returns an error if the address range is out of the address space or not
     /// aligned.
     pub fn unmap(&mut self, start: VirtAddr, size: usize) -> AxResult {
@@ -170,13 +418,68 @@ impl AddrSpace {
             return ax_err!(InvalidInput, "address not aligned");
         }
 
-        self.pt
-            .unmap_region(start, size, true)
-            .map_err(paging_err_to_ax_err)?
-            .ignore();
+        let range = VirtAddrRange::from_start_size(start, size);
+        if self.areas.overlaps(range) {
+            #[cfg(feature = "swap")]
+            self.swap.release(start, start + size, &mut self.pt);
+            self.areas
+                .unmap(start, size, &mut self.pt)
+                .map_err(mapping_err_to_ax_err)?;
+        }
+        // Linear mappings are not tracked by memory areas.
+        for (hole_start, hole_end) in self.holes(range) {
+            if !split_huge_pages(hole_start, hole_end - hole_start, &mut self.pt) {
+                return ax_err!(NoMemory, "failed to split huge pages");
+            }
+            let mut vaddr = hole_start;
+            while vaddr < hole_end {
+                match self.pt.unmap(vaddr) {
+                    Ok((_, page_size, tlb)) => {
+                        tlb.flush();
+                        vaddr += usize::from(page_size);
+                    }
+                    Err(_) => vaddr += PAGE_SIZE_4K, // not mapped
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes modified pages within the specified virtual address range back
+    /// to their backing store (i.e., shared file mappings), like `msync`.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    pub fn sync(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let end = start + size;
+        for area in self.areas.iter() {
+            let sync_start = area.start().max(start);
+            let sync_end = area.end().min(end);
+            if sync_start < sync_end
+                && !area
+                    .backend()
+                    .sync(sync_start, sync_end - sync_start, &mut self.pt)
+            {
+                return ax_err!(BadState, "failed to sync memory area");
+            }
+        }
         Ok(())
     }
 
+    /// Removes all memory areas and releases their physical frames.
+    pub fn clear(&mut self) {
+        #[cfg(feature = "swap")]
+        self.swap.release(self.base(), self.end(), &mut self.pt);
+        self.areas.clear(&mut self.pt).unwrap();
+    }
+
     /// To process data in this area with the given function.
     ///
     /// Now it supports reading and writing data in the given interval.
@@ -237,6 +540,10 @@ impl AddrSpace {
 
     /// Updates mapping within the specified virtual address range.
     ///
+    /// Memory areas in the range get the new permissions, and may be split if
+    /// partially covered. Linear mappings in the rest of the range are also
+    /// updated, and unmapped pages are skipped.
+    ///
     /// Returns an error if the address range is out of the address space or not
     /// aligned.
     pub fn protect(&mut self, start: VirtAddr, size: usize, flags: MappingFlags) -> AxResult {
@@ -247,10 +554,182 @@ impl AddrSpace {
             return ax_err!(InvalidInput, "address not aligned");
         }
 
-        self.pt
-            .protect_region(start, size, flags, true)
-            .map_err(paging_err_to_ax_err)?
-            .ignore();
+        let range = VirtAddrRange::from_start_size(start, size);
+        if self.areas.overlaps(range) {
+            #[cfg(feature = "swap")]
+            self.swap.restore(start, start + size, &mut self.pt);
+            self.areas
+                .protect(start, size, |_| Some(flags), &mut self.pt)
+                .map_err(mapping_err_to_ax_err)?;
+        }
+        // Linear mappings are not tracked by memory areas.
+        for (hole_start, hole_end) in self.holes(range) {
+            if !split_huge_pages(hole_start, hole_end - hole_start, &mut self.pt) {
+                return ax_err!(NoMemory, "failed to split huge pages");
+            }
+            let mut vaddr = hole_start;
+            while vaddr < hole_end {
+                match self.pt.protect(vaddr, flags) {
+                    Ok((page_size, tlb)) => {
+                        tlb.flush();
+                        vaddr += usize::from(page_size);
+                    }
+                    Err(_) => vaddr += PAGE_SIZE_4K, // not mapped
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the parts of `range` not covered by any memory area, in
+    /// ascending order.
+    fn holes(&self, range: VirtAddrRange) -> Vec<(VirtAddr, VirtAddr)> {
+        let mut holes = Vec::new();
+        let mut next = range.start;
+        for area in self.areas.iter() {
+            if area.end() <= next {
+                continue;
+            }
+            if area.start() >= range.end {
+                break;
+            }
+            if area.start() > next {
+                holes.push((next, area.start()));
+            }
+            next = area.end();
+        }
+        if next < range.end {
+            holes.push((next, range.end));
+        }
+        holes
+    }
+
+    /// Moves the mappings in `[start, start + size)` to `new_start`, like
+    /// `mremap`.
+    ///
+    /// Populated pages are moved without copying, so the contents are
+    /// preserved. File mappings keep their file offsets.
+    ///
+    /// Returns an error if the ranges are out of the address space or not
+    /// aligned, the source range is not fully covered by memory areas, or
+    /// the destination range is not free.
+    pub fn move_range(&mut self, start: VirtAddr, size: usize, new_start: VirtAddr) -> AxResult {
+        if !self.contains_range(start, size) || !self.contains_range(new_start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !new_start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+        if self
+            .areas
+            .overlaps(VirtAddrRange::from_start_size(new_start, size))
+        {
+            return ax_err!(AlreadyExists, "destination range is not free");
+        }
+
+        // Collect the parts of the areas covering the source range first.
+        let end = start + size;
+        #[cfg(feature = "swap")]
+        self.swap.restore(start, end, &mut self.pt);
+        let mut parts = Vec::new();
+        let mut next = start;
+        for area in self.areas.iter() {
+            let part_start = area.start().max(start);
+            let part_end = area.end().min(end);
+            if part_start >= part_end {
+                continue;
+            }
+            if part_start != next {
+                break;
+            }
+            let backend = match area.backend() {
+                Backend::Alloc { .. } => Backend::new_alloc(false),
+                Backend::Huge { page_size, .. } => Backend::new_huge(*page_size, false),
+                #[cfg(feature = "fs")]
+                Backend::File {
+                    file,
+                    start: file_start,
+                    offset,
+                    cache,
+                } => Backend::new_file(
+                    new_start + (part_start - start),
+                    file.clone(),
+                    offset + (part_start - *file_start) as u64,
+                    cache.is_some(),
+                ),
+                Backend::Shared {
+                    pages,
+                    start: shared_start,
+                    offset,
+                } => Backend::new_shared(
+                    new_start + (part_start - start),
+                    pages.clone(),
+                    offset + (part_start - *shared_start),
+                ),
+                Backend::Linear { .. } => {
+                    return ax_err!(Unsupported, "cannot move linear mappings");
+                }
+            };
+            parts.push((part_start, part_end, area.flags(), backend));
+            next = part_end;
+        }
+        if next != end {
+            return ax_err!(BadAddress, "source range is not fully mapped");
+        }
+        for (part_start, part_end, flags, backend) in parts {
+            let new_part_start = new_start + (part_start - start);
+            if let Err(err) = self.move_part(part_start, part_end, new_part_start, flags, backend) {
+                // Undo the parts moved so far. The source range is untouched,
+                // and unmapping drops the references taken on the frames.
+                let _ = self.areas.unmap(new_start, size, &mut self.pt);
+                return Err(err);
+            }
+        }
+        // Swapped out pages move with their mappings.
+        #[cfg(feature = "swap")]
+        self.swap.move_range(start, end, new_start);
+        self.unmap(start, size)
+    }
+
+    /// Maps a new area at `new_part_start` for the part `[part_start,
+    /// part_end)` of the source range of [`move_range`](Self::move_range),
+    /// and maps the populated pages of the part into it.
+    fn move_part(
+        &mut self,
+        part_start: VirtAddr,
+        part_end: VirtAddr,
+        new_part_start: VirtAddr,
+        flags: MappingFlags,
+        backend: Backend,
+    ) -> AxResult {
+        let part_size = part_end - part_start;
+        let area = MemoryArea::new(new_part_start, part_size, flags, backend);
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        let mut vaddr = part_start;
+        while vaddr < part_end {
+            let Ok((frame, flags, page_size)) = self.pt.query(vaddr) else {
+                vaddr += PAGE_SIZE_4K;
+                continue; // not populated yet
+            };
+            let new_vaddr = new_part_start + (vaddr - part_start);
+            let page_bytes = usize::from(page_size);
+            if page_size.is_huge()
+                && (vaddr.as_usize() % page_bytes != 0
+                    || new_vaddr.as_usize() % page_bytes != 0
+                    || vaddr + page_bytes > part_end)
+            {
+                return ax_err!(Unsupported, "cannot move part of a huge page");
+            }
+            // The old mapping drops its reference when unmapped.
+            frame_ref_inc(frame);
+            if let Err(err) = map_frame(&mut self.pt, new_vaddr, frame, page_size, flags) {
+                frame_ref_dec(frame); // still owned by the old mapping
+                return Err(err);
+            }
+            vaddr += page_bytes;
+        }
         Ok(())
     }
 
@@ -261,20 +740,103 @@ impl AddrSpace {
     /// Returns `true` if the page fault is handled successfully (not a real
     /// fault).
     pub fn handle_page_fault(&mut self, vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
+        #[cfg(feature = "swap")]
+        self.reclaim_if_low(vaddr);
+        self.handle_page_fault_inner(vaddr, access_flags)
+    }
+
+    fn handle_page_fault_inner(&mut self, vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
         if !self.va_range.contains(vaddr) {
             return false;
         }
         if let Some(area) = self.areas.find(vaddr) {
             let orig_flags = area.flags();
             if orig_flags.contains(access_flags) {
-                return area
-                    .backend()
-                    .handle_page_fault(vaddr, orig_flags, &mut self.pt);
+                #[cfg(feature = "swap")]
+                if let Some(handled) =
+                    self.swap
+                        .handle_page_fault(vaddr, orig_flags, access_flags, &mut self.pt)
+                {
+                    return handled;
+                }
+                return area.backend().handle_page_fault(
+                    vaddr,
+                    area.va_range(),
+                    orig_flags,
+                    access_flags,
+                    &mut self.pt,
+                );
             }
         }
         false
     }
 
+    /// Checks whether user programs can access `[start, start + size)` with
+    /// `access_flags`, and populates the pages in the range, so that the
+    /// kernel can access the range without page faults.
+    ///
+    /// Write access breaks copy-on-write sharing of the pages in advance.
+    ///
+    /// Returns [`BadAddress`](AxError::BadAddress) if any page in the range is
+    /// not accessible.
+    pub fn check_user_range(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        access_flags: MappingFlags,
+    ) -> AxResult {
+        if size == 0 {
+            return Ok(());
+        }
+        let Some(end) = start.as_usize().checked_add(size) else {
+            return ax_err!(BadAddress, "address overflow");
+        };
+        if !self.contains_range(start, size) {
+            return ax_err!(BadAddress, "address out of range");
+        }
+        // Reclaim before the check, so that no checked page is swapped out.
+        #[cfg(feature = "swap")]
+        self.reclaim_if_low(start);
+        for vaddr in PageIter4K::new(start.align_down_4k(), VirtAddr::from(end).align_up_4k())
+            .expect("Failed to create page iterator")
+        {
+            match self.areas.find(vaddr) {
+                Some(area) if area.flags().contains(access_flags | MappingFlags::USER) => {}
+                _ => return ax_err!(BadAddress, "page not accessible"),
+            }
+            if let Ok((_, flags, _)) = self.pt.query(vaddr) {
+                if flags.contains(access_flags) {
+                    continue;
+                }
+            }
+            if !self.handle_page_fault_inner(vaddr, access_flags) {
+                return ax_err!(BadAddress, "failed to populate page");
+            }
+        }
+        Ok(())
+    }
+
+    /// Swaps out up to `count` cold pages of allocation mappings, see
+    /// [`swap`](crate::swap). Returns the number of pages swapped out.
+    ///
+    /// It may take two calls to swap out pages, since pages are only aged
+    /// the first time they are visited.
+    #[cfg(feature = "swap")]
+    pub fn reclaim(&mut self, count: usize) -> usize {
+        self.swap
+            .reclaim(&self.areas, &mut self.pt, count, VirtAddr::from(0))
+    }
+
+    /// Reclaims pages if the free memory is low, except the page at `vaddr`
+    /// which is about to be accessed.
+    #[cfg(feature = "swap")]
+    fn reclaim_if_low(&mut self, vaddr: VirtAddr) {
+        if crate::swap::should_reclaim() {
+            self.swap
+                .reclaim(&self.areas, &mut self.pt, crate::swap::RECLAIM_BATCH, vaddr);
+        }
+    }
+
     pub fn translated_byte_buffer(
         &self,
         vaddr: VirtAddr,
@@ -324,6 +886,28 @@ impl AddrSpace {
     }
 }
 
+/// Maps `frame` at `vaddr` in a newly created memory area, which is either
+/// lazily mapped to empty entries, or has no entries at all for huge page
+/// mappings.
+fn map_frame(
+    pt: &mut PageTable,
+    vaddr: VirtAddr,
+    frame: PhysAddr,
+    page_size: PageSize,
+    flags: MappingFlags,
+) -> AxResult {
+    if !page_size.is_huge() {
+        if let Ok((_, tlb)) = pt.remap(vaddr, frame, flags) {
+            tlb.ignore();
+            return Ok(());
+        }
+    }
+    pt.map(vaddr, frame, page_size, flags)
+        .map_err(paging_err_to_ax_err)?
+        .ignore();
+    Ok(())
+}
+
 impl fmt::Debug for AddrSpace {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("AddrSpace")
@@ -332,3 +916,9 @@ impl fmt::Debug for AddrSpace {
             .finish()
     }
 }
+
+impl Drop for AddrSpace {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
@@ -1,11 +1,12 @@
 use axalloc::global_allocator;
 use axhal::mem::{phys_to_virt, virt_to_phys};
 use axhal::paging::{MappingFlags, PageSize, PageTable};
-use memory_addr::{PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+use memory_addr::{MemoryAddr, PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
 
 use super::Backend;
+use crate::frame::{frame_ref_count, frame_ref_dec};
 
-fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
+pub(crate) fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
     let vaddr = VirtAddr::from(global_allocator().alloc_pages(1, PAGE_SIZE_4K).ok()?);
     if zeroed {
         unsafe { core::ptr::write_bytes(vaddr.as_mut_ptr(), 0, PAGE_SIZE_4K) };
@@ -14,9 +15,13 @@ fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
     Some(paddr)
 }
 
-fn dealloc_frame(frame: PhysAddr) {
-    let vaddr = phys_to_virt(frame);
-    global_allocator().dealloc_pages(vaddr.as_usize(), 1);
+pub(crate) fn dealloc_frame(frame: PhysAddr) {
+    // The frame may still be shared by other address spaces after a
+    // copy-on-write fork, only free it when the last owner goes away.
+    if frame_ref_dec(frame) {
+        let vaddr = phys_to_virt(frame);
+        global_allocator().dealloc_pages(vaddr.as_usize(), 1);
+    }
 }
 
 impl Backend {
@@ -43,12 +48,18 @@ impl Backend {
         if populate {
             // allocate all possible physical frames for populated mapping.
             for addr in PageIter4K::new(start, start + size).unwrap() {
-                if let Some(frame) = alloc_frame(true) {
-                    if let Ok(tlb) = pt.map(addr, frame, PageSize::Size4K, flags) {
-                        tlb.ignore(); // TLB flush on map is unnecessary, as there are no outdated mappings.
-                    } else {
-                        return false;
-                    }
+                let Some(frame) = alloc_frame(true) else {
+                    // Leave no partial mapping behind, as the area is not
+                    // added on failure.
+                    self.unmap_alloc(start, addr - start, pt, populate);
+                    return false;
+                };
+                if let Ok(tlb) = pt.map(addr, frame, PageSize::Size4K, flags) {
+                    tlb.ignore(); // TLB flush on map is unnecessary, as there are no outdated mappings.
+                } else {
+                    dealloc_frame(frame);
+                    self.unmap_alloc(start, addr - start, pt, populate);
+                    return false;
                 }
             }
             true
@@ -89,10 +100,21 @@ impl Backend {
         &self,
         vaddr: VirtAddr,
         orig_flags: MappingFlags,
+        access_flags: MappingFlags,
         pt: &mut PageTable,
         populate: bool,
     ) -> bool {
-        if populate {
+        if let Ok((frame, flags, page_size)) = pt.query(vaddr.align_down_4k()) {
+            // The page is present, so it must be a write to a copy-on-write
+            // page shared with other address spaces.
+            if page_size.is_huge()
+                || !access_flags.contains(MappingFlags::WRITE)
+                || flags.contains(MappingFlags::WRITE)
+            {
+                return false;
+            }
+            Self::break_cow(vaddr, frame, orig_flags, pt)
+        } else if populate {
             false // Populated mappings should not trigger page faults.
         } else if let Some(frame) = alloc_frame(true) {
             // Allocate a physical frame lazily and map it to the fault address.
@@ -105,4 +127,37 @@ impl Backend {
             false
         }
     }
+
+    /// Gives the faulting task a private copy of a copy-on-write frame.
+    ///
+    /// If the task is already the only owner of the frame (e.g., all other
+    /// sharers have exited or copied it), the original write permission is
+    /// simply restored without copying.
+    pub(super) fn break_cow(
+        vaddr: VirtAddr,
+        frame: PhysAddr,
+        orig_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        if frame_ref_count(frame) == 1 {
+            return pt
+                .protect(vaddr, orig_flags)
+                .map(|(_, tlb)| tlb.flush())
+                .is_ok();
+        }
+        let Some(new_frame) = alloc_frame(false) else {
+            return false;
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                phys_to_virt(frame).as_ptr(),
+                phys_to_virt(new_frame).as_mut_ptr(),
+                PAGE_SIZE_4K,
+            )
+        };
+        dealloc_frame(frame);
+        pt.remap(vaddr, new_frame, orig_flags)
+            .map(|(_, tlb)| tlb.flush())
+            .is_ok()
+    }
 }
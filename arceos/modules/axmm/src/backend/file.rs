@@ -0,0 +1,281 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use axfs::fops::File;
+use axhal::mem::phys_to_virt;
+use axhal::paging::{MappingFlags, PageTable};
+use axsync::spin::SpinNoIrq;
+use memory_addr::{MemoryAddr, PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+use super::alloc::{alloc_frame, dealloc_frame};
+use super::Backend;
+use crate::frame::frame_ref_inc;
+
+/// The page caches of the files with shared mappings, by file node.
+static FILE_CACHES: SpinNoIrq<BTreeMap<usize, Weak<FileCache>>> = SpinNoIrq::new(BTreeMap::new());
+
+/// The pages of a file shared by all its shared mappings, so that they see
+/// each other's stores before the pages are written back.
+///
+/// Like [`SharedPages`](super::SharedPages), the cache holds one reference to
+/// each frame, and each mapping of it holds another. It is dropped with the
+/// last shared mapping of the file.
+///
+/// It is not coherent with `read`/`write` on the file, which see the stores
+/// only after they are written back.
+pub struct FileCache {
+    node_id: usize,
+    /// The frames by page index in the file.
+    frames: SpinNoIrq<BTreeMap<u64, PhysAddr>>,
+}
+
+impl FileCache {
+    /// Returns the cache of the file, creating it if necessary.
+    pub(crate) fn get(file: &File) -> Arc<Self> {
+        let node_id = file.node_id();
+        let mut caches = FILE_CACHES.lock();
+        if let Some(cache) = caches.get(&node_id).and_then(Weak::upgrade) {
+            return cache;
+        }
+        let cache = Arc::new(Self {
+            node_id,
+            frames: SpinNoIrq::new(BTreeMap::new()),
+        });
+        caches.insert(node_id, Arc::downgrade(&cache));
+        cache
+    }
+
+    /// Returns the frame of the page at `offset` with a new reference for
+    /// the caller, reading it from the file if necessary.
+    pub(crate) fn frame(&self, file: &File, offset: u64) -> Option<PhysAddr> {
+        let index = offset / PAGE_SIZE_4K as u64;
+        if let Some(&frame) = self.frames.lock().get(&index) {
+            frame_ref_inc(frame);
+            return Some(frame);
+        }
+        // Read without holding the lock, another mapping may have read the
+        // page in the meantime.
+        let frame = alloc_frame(true)?;
+        if !read_page(file, offset, frame) {
+            dealloc_frame(frame);
+            return None;
+        }
+        let cached = *self.frames.lock().entry(index).or_insert(frame);
+        if cached != frame {
+            dealloc_frame(frame);
+        }
+        frame_ref_inc(cached);
+        Some(cached)
+    }
+}
+
+impl Drop for FileCache {
+    fn drop(&mut self) {
+        let mut caches = FILE_CACHES.lock();
+        // A new cache may have been created for the file after the last
+        // reference to this one was dropped.
+        if caches
+            .get(&self.node_id)
+            .is_some_and(|cache| cache.strong_count() == 0)
+        {
+            caches.remove(&self.node_id);
+        }
+        drop(caches);
+        let frames = core::mem::take(self.frames.get_mut());
+        frames.into_values().for_each(dealloc_frame);
+    }
+}
+
+fn frame_slice<'a>(frame: PhysAddr) -> &'a mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K) }
+}
+
+/// Fills the frame with file contents starting at `offset`, the part beyond
+/// the end of file is left zeroed.
+fn read_page(file: &File, offset: u64, frame: PhysAddr) -> bool {
+    let buf = frame_slice(frame);
+    let mut read = 0;
+    while read < PAGE_SIZE_4K {
+        match file.read_at(offset + read as u64, &mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) => {
+                warn!("failed to read file page at {:#x}: {:?}", offset, e);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Writes the frame back to the file at `offset`, without extending the file.
+fn write_page(file: &File, offset: u64, frame: PhysAddr) {
+    let file_size = match file.get_attr() {
+        Ok(attr) => attr.size(),
+        Err(e) => {
+            warn!("failed to get file size: {:?}", e);
+            return;
+        }
+    };
+    if offset >= file_size {
+        return;
+    }
+    let len = PAGE_SIZE_4K.min((file_size - offset) as usize);
+    if let Err(e) = file.write_at(offset, &frame_slice(frame)[..len]) {
+        warn!("failed to write back file page at {:#x}: {:?}", offset, e);
+    }
+}
+
+impl Backend {
+    /// Creates a new file mapping backend.
+    ///
+    /// The mapping starting at `start` is backed by `file` from the file
+    /// offset `offset`. Shared mappings of the same file share its page
+    /// cache.
+    pub fn new_file(start: VirtAddr, file: Arc<File>, offset: u64, shared: bool) -> Self {
+        let cache = shared.then(|| FileCache::get(&file));
+        Self::File {
+            file,
+            start,
+            offset,
+            cache,
+        }
+    }
+
+    fn file_offset(&self, vaddr: VirtAddr) -> u64 {
+        match self {
+            Self::File { start, offset, .. } => offset + (vaddr - *start) as u64,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn map_file(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        debug!(
+            "map_file: [{:#x}, {:#x}) {:?} (offset={:#x})",
+            start,
+            start + size,
+            flags,
+            self.file_offset(start)
+        );
+        // Map to a empty entry for on-demand mapping.
+        pt.map_region(
+            start,
+            |_| 0.into(),
+            size,
+            MappingFlags::empty(),
+            false,
+            false,
+        )
+        .map(|tlb| tlb.ignore())
+        .is_ok()
+    }
+
+    pub(crate) fn unmap_file(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
+        debug!("unmap_file: [{:#x}, {:#x})", start, start + size);
+        let Self::File { file, cache, .. } = self else {
+            unreachable!()
+        };
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            if let Ok((frame, flags, page_size)) = pt.query(addr) {
+                if page_size.is_huge() {
+                    return false;
+                }
+                // Only pages written since the last sync are writable.
+                if cache.is_some() && flags.contains(MappingFlags::WRITE) {
+                    write_page(file, self.file_offset(addr), frame);
+                }
+            }
+            if let Ok((frame, _, tlb)) = pt.unmap(addr) {
+                tlb.flush();
+                // Cached frames stay alive as long as the cache owns them.
+                dealloc_frame(frame);
+            }
+        }
+        true
+    }
+
+    /// Writes dirty pages in the given range back to the file, and marks them
+    /// clean again. It does nothing for private mappings.
+    pub(crate) fn sync_file(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
+        let Self::File { file, cache, .. } = self else {
+            unreachable!()
+        };
+        if cache.is_none() {
+            return true;
+        }
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            if let Ok((frame, flags, _)) = pt.query(addr) {
+                if flags.contains(MappingFlags::WRITE) {
+                    write_page(file, self.file_offset(addr), frame);
+                    match pt.protect(addr, flags - MappingFlags::WRITE) {
+                        Ok((_, tlb)) => tlb.flush(),
+                        Err(_) => return false,
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    pub(crate) fn handle_page_fault_file(
+        &self,
+        vaddr: VirtAddr,
+        orig_flags: MappingFlags,
+        access_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        let Self::File { file, cache, .. } = self else {
+            unreachable!()
+        };
+        let vaddr = vaddr.align_down_4k();
+        let is_write = access_flags.contains(MappingFlags::WRITE);
+        if let Ok((frame, flags, _)) = pt.query(vaddr) {
+            if !is_write || flags.contains(MappingFlags::WRITE) {
+                return false;
+            }
+            return if cache.is_some() {
+                // First write to a clean page, mark it dirty.
+                pt.protect(vaddr, orig_flags)
+                    .map(|(_, tlb)| tlb.flush())
+                    .is_ok()
+            } else {
+                Self::break_cow(vaddr, frame, orig_flags, pt)
+            };
+        }
+
+        let offset = self.file_offset(vaddr);
+        let frame = match cache {
+            Some(cache) => cache.frame(file, offset),
+            None => alloc_frame(true),
+        };
+        let Some(frame) = frame else {
+            return false;
+        };
+        if cache.is_none() && !read_page(file, offset, frame) {
+            dealloc_frame(frame);
+            return false;
+        }
+        // Shared pages are mapped read-only until they are written, so that
+        // only dirty pages need to be written back.
+        let flags = if cache.is_some() && !is_write {
+            orig_flags - MappingFlags::WRITE
+        } else {
+            orig_flags
+        };
+        if pt
+            .remap(vaddr, frame, flags)
+            .map(|(_, tlb)| tlb.flush())
+            .is_ok()
+        {
+            true
+        } else {
+            dealloc_frame(frame);
+            false
+        }
+    }
+}
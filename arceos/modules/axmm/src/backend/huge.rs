@@ -0,0 +1,271 @@
+use axalloc::global_allocator;
+use axhal::mem::{phys_to_virt, virt_to_phys};
+use axhal::paging::{MappingFlags, PageSize, PageTable};
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use super::Backend;
+use crate::frame::{frame_ref_count, frame_ref_dec};
+
+/// Allocates a physically contiguous block of `page_size`, aligned to its
+/// size.
+pub(crate) fn alloc_block(page_size: PageSize, zeroed: bool) -> Option<PhysAddr> {
+    let size = usize::from(page_size);
+    // The linear mapping offset is aligned to 1G, so the physical address is
+    // aligned as well.
+    let vaddr = VirtAddr::from(
+        global_allocator()
+            .alloc_pages(size / PAGE_SIZE_4K, size)
+            .ok()?,
+    );
+    if zeroed {
+        unsafe { core::ptr::write_bytes(vaddr.as_mut_ptr(), 0, size) };
+    }
+    Some(virt_to_phys(vaddr))
+}
+
+/// Drops an owner of the block of `page_size` at `frame`, and frees it if it
+/// was the last owner.
+pub(crate) fn dealloc_block(frame: PhysAddr, page_size: PageSize) {
+    if frame_ref_dec(frame) {
+        let vaddr = phys_to_virt(frame);
+        global_allocator().dealloc_pages(vaddr.as_usize(), usize::from(page_size) / PAGE_SIZE_4K);
+    }
+}
+
+/// Splits the huge page containing `vaddr` (if any), so that `vaddr` becomes
+/// a page boundary.
+///
+/// The pieces map the same frames with the same flags, each as a page as
+/// large as its alignment allows. Returns `false` if the pieces cannot be
+/// mapped.
+fn split_huge_page(vaddr: VirtAddr, pt: &mut PageTable) -> bool {
+    let Ok((paddr, flags, page_size)) = pt.query(vaddr) else {
+        return true;
+    };
+    let size = usize::from(page_size);
+    let offset = vaddr.as_usize() % size;
+    if !page_size.is_huge() || offset == 0 {
+        return true;
+    }
+    let start = vaddr - offset;
+    let base = paddr - offset;
+    match pt.unmap(start) {
+        Ok((_, _, tlb)) => tlb.flush(),
+        Err(_) => return false,
+    }
+    let va_to_pa = |va: VirtAddr| base + (va - start);
+    pt.map_region(start, va_to_pa, offset, flags, true, false)
+        .and_then(|tlb| {
+            tlb.ignore();
+            pt.map_region(vaddr, va_to_pa, size - offset, flags, true, false)
+        })
+        .map(|tlb| tlb.ignore())
+        .is_ok()
+}
+
+/// Splits the huge pages crossing the boundaries of `[start, start + size)`,
+/// so that every page in the range lies entirely within it.
+pub(crate) fn split_huge_pages(start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
+    split_huge_page(start, pt) && split_huge_page(start + size, pt)
+}
+
+impl Backend {
+    /// Creates a new huge page allocation mapping backend.
+    pub const fn new_huge(page_size: PageSize, populate: bool) -> Self {
+        Self::Huge {
+            page_size,
+            populate,
+        }
+    }
+
+    /// Allocates and maps the largest page up to `max_size` that contains
+    /// `vaddr` and lies within `area`.
+    ///
+    /// Smaller pages are tried if the block cannot be allocated, or part of
+    /// it is already mapped. Returns the size of the mapped page, or `None`
+    /// if out of memory.
+    fn map_new_page(
+        vaddr: VirtAddr,
+        area: VirtAddrRange,
+        max_size: PageSize,
+        flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> Option<PageSize> {
+        for page_size in [PageSize::Size1G, PageSize::Size2M, PageSize::Size4K] {
+            let size = usize::from(page_size);
+            if size > usize::from(max_size) {
+                continue;
+            }
+            let start = vaddr.align_down(page_size);
+            if start < area.start || start + size > area.end {
+                continue;
+            }
+            let Some(frame) = alloc_block(page_size, true) else {
+                continue;
+            };
+            match pt.map(start, frame, page_size, flags) {
+                Ok(tlb) => {
+                    tlb.flush();
+                    return Some(page_size);
+                }
+                Err(_) => dealloc_block(frame, page_size),
+            }
+        }
+        None
+    }
+
+    /// Gives this mapping a private copy of the huge page containing `vaddr`
+    /// if it is shared copy-on-write and about to be split at `vaddr`, since
+    /// the pieces of a split page are owned separately.
+    fn unshare_huge_page(vaddr: VirtAddr, pt: &mut PageTable) -> bool {
+        let Ok((paddr, flags, page_size)) = pt.query(vaddr) else {
+            return true;
+        };
+        let size = usize::from(page_size);
+        let offset = vaddr.as_usize() % size;
+        if !page_size.is_huge() || offset == 0 || frame_ref_count(paddr - offset) == 1 {
+            return true;
+        }
+        let frame = paddr - offset;
+        let Some(new_frame) = alloc_block(page_size, false) else {
+            return false;
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                phys_to_virt(frame).as_ptr(),
+                phys_to_virt(new_frame).as_mut_ptr(),
+                size,
+            )
+        };
+        dealloc_block(frame, page_size);
+        pt.remap(vaddr, new_frame, flags)
+            .map(|(_, tlb)| tlb.flush())
+            .is_ok()
+    }
+
+    /// Splits the huge pages crossing the boundaries of `[start, start + size)`
+    /// like [`split_huge_pages`], copying shared ones first.
+    pub(crate) fn split_huge(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
+        [start, start + size]
+            .into_iter()
+            .all(|vaddr| Self::unshare_huge_page(vaddr, pt) && split_huge_page(vaddr, pt))
+    }
+
+    pub(crate) fn map_huge(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        pt: &mut PageTable,
+        page_size: PageSize,
+        populate: bool,
+    ) -> bool {
+        debug!(
+            "map_huge: [{:#x}, {:#x}) {:?} (page_size={:?}, populate={})",
+            start,
+            start + size,
+            flags,
+            page_size,
+            populate
+        );
+        if !populate {
+            // No entries are created until the first access, since the page
+            // size is not decided yet.
+            return true;
+        }
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
+            // Only pages starting at `addr` are mapped, as the ones before are
+            // populated already.
+            match Self::map_new_page(addr, VirtAddrRange::new(addr, end), page_size, flags, pt) {
+                Some(mapped) => addr += usize::from(mapped),
+                None => return false,
+            }
+        }
+        true
+    }
+
+    pub(crate) fn unmap_huge(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
+        debug!("unmap_huge: [{:#x}, {:#x})", start, start + size);
+        if !self.split_huge(start, size, pt) {
+            return false;
+        }
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
+            if let Ok((frame, page_size, tlb)) = pt.unmap(addr) {
+                tlb.flush();
+                dealloc_block(frame, page_size);
+                addr += usize::from(page_size);
+            } else {
+                addr += PAGE_SIZE_4K;
+            }
+        }
+        true
+    }
+
+    pub(crate) fn handle_page_fault_huge(
+        &self,
+        vaddr: VirtAddr,
+        area: VirtAddrRange,
+        orig_flags: MappingFlags,
+        access_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        let Self::Huge {
+            page_size,
+            populate,
+        } = *self
+        else {
+            unreachable!()
+        };
+        if let Ok((paddr, flags, mapped)) = pt.query(vaddr.align_down_4k()) {
+            // The page is present, so it must be a write to a copy-on-write
+            // page shared with other address spaces.
+            if !access_flags.contains(MappingFlags::WRITE) || flags.contains(MappingFlags::WRITE) {
+                return false;
+            }
+            if !mapped.is_huge() {
+                return Self::break_cow(vaddr, paddr, orig_flags, pt);
+            }
+            let frame = paddr - vaddr.as_usize() % usize::from(mapped);
+            Self::break_cow_huge(vaddr, frame, mapped, orig_flags, pt)
+        } else if populate {
+            false // Populated mappings should not trigger page faults.
+        } else {
+            Self::map_new_page(vaddr, area, page_size, orig_flags, pt).is_some()
+        }
+    }
+
+    /// Gives the faulting task a private copy of a copy-on-write huge page,
+    /// like [`break_cow`](Self::break_cow).
+    fn break_cow_huge(
+        vaddr: VirtAddr,
+        frame: PhysAddr,
+        page_size: PageSize,
+        orig_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        if frame_ref_count(frame) == 1 {
+            return pt
+                .protect(vaddr, orig_flags)
+                .map(|(_, tlb)| tlb.flush())
+                .is_ok();
+        }
+        let Some(new_frame) = alloc_block(page_size, false) else {
+            return false;
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                phys_to_virt(frame).as_ptr(),
+                phys_to_virt(new_frame).as_mut_ptr(),
+                usize::from(page_size),
+            )
+        };
+        dealloc_block(frame, page_size);
+        pt.remap(vaddr, new_frame, orig_flags)
+            .map(|(_, tlb)| tlb.flush())
+            .is_ok()
+    }
+}
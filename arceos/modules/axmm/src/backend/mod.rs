@@ -1,21 +1,52 @@
 //! Memory mapping backends.
 #![allow(dead_code)]
 
-use axhal::paging::{MappingFlags, PageTable};
-use memory_addr::VirtAddr;
+use axhal::paging::{MappingFlags, PageSize, PageTable};
+use memory_addr::{VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
 use memory_set::MappingBackend;
 
+use ::alloc::sync::Arc;
+
 mod alloc;
+mod huge;
 mod linear;
+mod shared;
+
+#[cfg(feature = "fs")]
+mod file;
+
+#[cfg(feature = "fs")]
+pub use self::file::FileCache;
+
+pub use self::shared::SharedPages;
+
+pub(crate) use self::huge::split_huge_pages;
+
+#[cfg(test)]
+pub(crate) use self::huge::{alloc_block, dealloc_block};
+
+#[cfg(any(test, feature = "swap"))]
+pub(crate) use self::alloc::{alloc_frame, dealloc_frame};
 
 /// A unified enum type for different memory mapping backends.
 ///
-/// Currently, two backends are implemented:
+/// Currently, the following backends are implemented:
 ///
 /// - **Linear**: used for linear mappings. The target physical frames are
 ///   contiguous and their addresses should be known when creating the mapping.
 /// - **Allocation**: used in general, or for lazy mappings. The target physical
-///   frames are obtained from the global allocator.
+///   frames are obtained from the global allocator. Frames of this backend can
+///   be shared copy-on-write between address spaces (see
+///   [`AddrSpace::fork_into`](crate::AddrSpace::fork_into)).
+/// - **Huge**: used for large anonymous mappings. Like **Allocation**, but
+///   the target physical frames are allocated in contiguous blocks and mapped
+///   as huge pages (2M or 1G) where possible.
+/// - **File**: used for file-backed mappings (requires the `fs` feature). The
+///   target physical frames are allocated and filled with the file contents
+///   on demand.
+/// - **Shared**: used for memory shared between address spaces, such as SysV
+///   shared memory and `memfd`. The target physical frames are taken from a
+///   [`SharedPages`] on demand, and are never copied on fork.
 #[derive(Clone)]
 pub enum Backend {
     /// Linear mapping backend.
@@ -23,6 +54,10 @@ pub enum Backend {
     /// The offset between the virtual address and the physical address is
     /// constant, which is specified by `pa_va_offset`. For example, the virtual
     /// address `vaddr` is mapped to the physical address `vaddr - pa_va_offset`.
+    ///
+    /// Huge pages are used where the addresses and the size are aligned to
+    /// them. A huge page is split into smaller ones when only part of it is
+    /// unmapped or protected.
     Linear {
         /// `vaddr - paddr`.
         pa_va_offset: usize,
@@ -33,10 +68,73 @@ pub enum Backend {
     /// mapping is created, and no page faults are triggered during the memory
     /// access. Otherwise, the physical frames are allocated on demand (by
     /// handling page faults).
+    ///
+    /// Write faults on present but read-only pages are treated as
+    /// copy-on-write faults, which give the faulting address space a private
+    /// copy of the shared frame.
     Alloc {
         /// Whether to populate the physical frames when creating the mapping.
         populate: bool,
     },
+    /// Huge page allocation mapping backend.
+    ///
+    /// Each block of `page_size` within the area is allocated contiguously
+    /// and mapped as a huge page, or as smaller pages if the contiguous
+    /// memory is not available. The parts of the area not covering a whole
+    /// block are mapped with 4K pages. Frames are populated and shared
+    /// copy-on-write like [`Alloc`](Self::Alloc), where a huge page is
+    /// copied as a whole.
+    ///
+    /// A huge page is split into smaller ones when only part of it is
+    /// unmapped or protected. Pages not populated yet have no page table
+    /// entries, since their sizes are only decided on the first access.
+    Huge {
+        /// The largest page size used, [`PageSize::Size2M`] or
+        /// [`PageSize::Size1G`].
+        page_size: PageSize,
+        /// Whether to populate the physical frames when creating the mapping.
+        populate: bool,
+    },
+    /// File mapping backend.
+    ///
+    /// Pages are read from `file` lazily on the first access. The page at
+    /// `vaddr` contains the file contents at `offset + (vaddr - start)`.
+    ///
+    /// For shared mappings, pages are taken from the [`FileCache`] of the
+    /// file, so all the shared mappings of a file see the same contents.
+    /// They are mapped read-only until they are written, so the writable
+    /// pages are exactly the dirty ones. They are written back to the file on
+    /// unmapping or [`AddrSpace::sync`]. Private mappings are never written
+    /// back.
+    ///
+    /// [`AddrSpace::sync`]: crate::AddrSpace::sync
+    #[cfg(feature = "fs")]
+    File {
+        /// The backing file.
+        file: Arc<axfs::fops::File>,
+        /// The start virtual address of the whole mapping.
+        start: VirtAddr,
+        /// The file offset corresponding to `start`.
+        offset: u64,
+        /// The page cache of the file for shared mappings (`MAP_SHARED`),
+        /// whose modifications are visible in the file. `None` for private
+        /// mappings.
+        cache: Option<Arc<FileCache>>,
+    },
+    /// Shared mapping backend.
+    ///
+    /// The page at `vaddr` is the page of `pages` at the byte offset
+    /// `offset + (vaddr - start)`, which is allocated on the first access
+    /// through any mapping. All the mappings of the same pages see the same
+    /// contents.
+    Shared {
+        /// The shared pages.
+        pages: Arc<SharedPages>,
+        /// The start virtual address of the whole mapping.
+        start: VirtAddr,
+        /// The byte offset in `pages` corresponding to `start`.
+        offset: usize,
+    },
 }
 
 impl MappingBackend for Backend {
@@ -47,6 +145,13 @@ impl MappingBackend for Backend {
         match *self {
             Self::Linear { pa_va_offset } => self.map_linear(start, size, flags, pt, pa_va_offset),
             Self::Alloc { populate } => self.map_alloc(start, size, flags, pt, populate),
+            Self::Huge {
+                page_size,
+                populate,
+            } => self.map_huge(start, size, flags, pt, page_size, populate),
+            #[cfg(feature = "fs")]
+            Self::File { .. } => self.map_file(start, size, flags, pt),
+            Self::Shared { .. } => self.map_shared(start, size, flags, pt),
         }
     }
 
@@ -54,6 +159,10 @@ impl MappingBackend for Backend {
         match *self {
             Self::Linear { pa_va_offset } => self.unmap_linear(start, size, pt, pa_va_offset),
             Self::Alloc { populate } => self.unmap_alloc(start, size, pt, populate),
+            Self::Huge { .. } => self.unmap_huge(start, size, pt),
+            #[cfg(feature = "fs")]
+            Self::File { .. } => self.unmap_file(start, size, pt),
+            Self::Shared { .. } => self.unmap_shared(start, size, pt),
         }
     }
 
@@ -64,25 +173,114 @@ impl MappingBackend for Backend {
         new_flags: Self::Flags,
         page_table: &mut Self::PageTable,
     ) -> bool {
-        page_table
-            .protect_region(start, size, new_flags, true)
-            .map(|tlb| tlb.ignore())
-            .is_ok()
+        match *self {
+            Self::Linear { .. } => {
+                split_huge_pages(start, size, page_table)
+                    && page_table
+                        .protect_region(start, size, new_flags, true)
+                        .map(|tlb| tlb.ignore())
+                        .is_ok()
+            }
+            Self::Huge { .. } => {
+                self.split_huge(start, size, page_table)
+                    && self.protect_present(start, size, new_flags, page_table)
+            }
+            _ => self.protect_present(start, size, new_flags, page_table),
+        }
     }
 }
 
 impl Backend {
+    /// Handles a page fault at `vaddr` in the memory area `area` of this
+    /// backend.
     pub(crate) fn handle_page_fault(
         &self,
         vaddr: VirtAddr,
+        area: VirtAddrRange,
         orig_flags: MappingFlags,
+        access_flags: MappingFlags,
         page_table: &mut PageTable,
     ) -> bool {
         match *self {
             Self::Linear { .. } => false, // Linear mappings should not trigger page faults.
-            Self::Alloc { populate } => {
-                self.handle_page_fault_alloc(vaddr, orig_flags, page_table, populate)
+            Self::Alloc { populate } => self.handle_page_fault_alloc(
+                vaddr,
+                orig_flags,
+                access_flags,
+                page_table,
+                populate,
+            ),
+            Self::Huge { .. } => {
+                self.handle_page_fault_huge(vaddr, area, orig_flags, access_flags, page_table)
+            }
+            #[cfg(feature = "fs")]
+            Self::File { .. } => {
+                self.handle_page_fault_file(vaddr, orig_flags, access_flags, page_table)
+            }
+            Self::Shared { .. } => {
+                self.handle_page_fault_shared(vaddr, orig_flags, access_flags, page_table)
+            }
+        }
+    }
+
+    /// Updates the permissions of the present pages in the given range.
+    ///
+    /// Pages not populated yet are left untouched, they get `new_flags` on the
+    /// first access. Present pages without write permission stay read-only
+    /// even if `new_flags` allows writing, since they may be copy-on-write
+    /// or clean shared file pages. The write permission is restored in the
+    /// page fault handler.
+    ///
+    /// Present pages that become inaccessible are released like unmapped
+    /// ones, as page table entries cannot express that.
+    ///
+    /// Huge pages must not cross the boundaries of the range.
+    fn protect_present(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        new_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
+            let Ok((_, flags, page_size)) = pt.query(addr) else {
+                addr += PAGE_SIZE_4K;
+                continue; // not populated yet
+            };
+            let page_size = usize::from(page_size);
+            if !new_flags
+                .intersects(MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE)
+            {
+                if !self.unmap(addr, page_size, pt) {
+                    return false;
+                }
+                addr += page_size;
+                continue;
             }
+            let flags = if flags.contains(MappingFlags::WRITE) {
+                new_flags
+            } else {
+                new_flags - MappingFlags::WRITE
+            };
+            match pt.protect(addr, flags) {
+                Ok((_, tlb)) => tlb.flush(),
+                Err(_) => return false,
+            }
+            addr += page_size;
+        }
+        true
+    }
+
+    /// Writes back the modified contents in the given range, if the backend
+    /// has a backing store.
+    #[cfg_attr(not(feature = "fs"), allow(unused_variables))]
+    pub(crate) fn sync(&self, start: VirtAddr, size: usize, page_table: &mut PageTable) -> bool {
+        match *self {
+            #[cfg(feature = "fs")]
+            Self::File { .. } => self.sync_file(start, size, page_table),
+            _ => true,
         }
     }
 }
@@ -0,0 +1,245 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use axhal::mem::phys_to_virt;
+use axhal::paging::{MappingFlags, PageTable};
+use axsync::spin::SpinNoIrq;
+use memory_addr::{MemoryAddr, PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+use super::alloc::{alloc_frame, dealloc_frame};
+use super::Backend;
+use crate::frame::frame_ref_inc;
+
+/// A set of physical pages that can be mapped into multiple address spaces,
+/// e.g., a SysV shared memory segment or a `memfd`.
+///
+/// Frames are allocated (zeroed) on the first access, either through a
+/// mapping or [`read_at`](Self::read_at)/[`write_at`](Self::write_at). The
+/// set holds one reference to each frame, and each mapping of it holds
+/// another, so the frames are freed when the set and all the mappings are
+/// gone.
+pub struct SharedPages {
+    frames: SpinNoIrq<Vec<Option<PhysAddr>>>,
+}
+
+impl SharedPages {
+    /// Creates a set of `num_pages` pages, no frames are allocated yet.
+    pub fn new(num_pages: usize) -> Self {
+        let mut frames = Vec::new();
+        frames.resize(num_pages, None);
+        Self {
+            frames: SpinNoIrq::new(frames),
+        }
+    }
+
+    /// Returns the number of pages.
+    pub fn num_pages(&self) -> usize {
+        self.frames.lock().len()
+    }
+
+    /// Returns the size in bytes.
+    pub fn size(&self) -> usize {
+        self.num_pages() * PAGE_SIZE_4K
+    }
+
+    /// Changes the number of pages.
+    ///
+    /// Pages beyond the new size are dropped from the set, but they stay
+    /// accessible in existing mappings until unmapped.
+    pub fn resize(&self, num_pages: usize) {
+        let dropped = {
+            let mut frames = self.frames.lock();
+            if num_pages < frames.len() {
+                frames.split_off(num_pages)
+            } else {
+                frames.resize(num_pages, None);
+                Vec::new()
+            }
+        };
+        dropped.into_iter().flatten().for_each(dealloc_frame);
+    }
+
+    /// Returns the frame of the page at `index` with a new reference taken
+    /// for the caller, allocating the frame first if `alloc` is true.
+    ///
+    /// The reference keeps the frame alive if the page is dropped from the
+    /// set by a concurrent [`resize`](Self::resize), the caller releases it
+    /// with [`dealloc_frame`] or hands it to a mapping.
+    ///
+    /// Returns `None` if `index` is out of range, out of memory, or the page
+    /// is not allocated and `alloc` is false.
+    fn frame(&self, index: usize, alloc: bool) -> Option<PhysAddr> {
+        let mut frames = self.frames.lock();
+        let slot = frames.get_mut(index)?;
+        if slot.is_none() && alloc {
+            *slot = Some(alloc_frame(true)?);
+        }
+        let frame = (*slot)?;
+        frame_ref_inc(frame);
+        Some(frame)
+    }
+
+    /// Reads the contents at `offset` into `buf`, returns the number of bytes
+    /// read, which is short at the end of the pages.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.size().saturating_sub(offset));
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let page_off = pos % PAGE_SIZE_4K;
+            let n = (PAGE_SIZE_4K - page_off).min(len - done);
+            let dst = &mut buf[done..done + n];
+            match self.frame(pos / PAGE_SIZE_4K, false) {
+                Some(frame) => {
+                    unsafe {
+                        let src = phys_to_virt(frame).as_ptr().add(page_off);
+                        core::ptr::copy_nonoverlapping(src, dst.as_mut_ptr(), n);
+                    }
+                    dealloc_frame(frame);
+                }
+                // Never touched, read as zeros.
+                None => dst.fill(0),
+            }
+            done += n;
+        }
+        len
+    }
+
+    /// Writes `buf` at `offset`, returns the number of bytes written, which
+    /// is short at the end of the pages or out of memory.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let len = buf.len().min(self.size().saturating_sub(offset));
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let page_off = pos % PAGE_SIZE_4K;
+            let n = (PAGE_SIZE_4K - page_off).min(len - done);
+            let Some(frame) = self.frame(pos / PAGE_SIZE_4K, true) else {
+                break;
+            };
+            unsafe {
+                let dst = phys_to_virt(frame).as_mut_ptr().add(page_off);
+                core::ptr::copy_nonoverlapping(buf[done..].as_ptr(), dst, n);
+            }
+            dealloc_frame(frame);
+            done += n;
+        }
+        done
+    }
+}
+
+impl Drop for SharedPages {
+    fn drop(&mut self) {
+        self.frames
+            .get_mut()
+            .drain(..)
+            .flatten()
+            .for_each(dealloc_frame);
+    }
+}
+
+impl Backend {
+    /// Creates a new shared mapping backend.
+    ///
+    /// The mapping starting at `start` is backed by `pages` from the byte
+    /// offset `offset`.
+    pub fn new_shared(start: VirtAddr, pages: Arc<SharedPages>, offset: usize) -> Self {
+        Self::Shared {
+            pages,
+            start,
+            offset,
+        }
+    }
+
+    fn shared_page_index(&self, vaddr: VirtAddr) -> usize {
+        match self {
+            Self::Shared { start, offset, .. } => {
+                (offset + (vaddr.align_down_4k() - *start)) / PAGE_SIZE_4K
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn map_shared(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        debug!(
+            "map_shared: [{:#x}, {:#x}) {:?} (page={})",
+            start,
+            start + size,
+            flags,
+            self.shared_page_index(start)
+        );
+        // Map to a empty entry for on-demand mapping.
+        pt.map_region(
+            start,
+            |_| 0.into(),
+            size,
+            MappingFlags::empty(),
+            false,
+            false,
+        )
+        .map(|tlb| tlb.ignore())
+        .is_ok()
+    }
+
+    pub(crate) fn unmap_shared(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
+        debug!("unmap_shared: [{:#x}, {:#x})", start, start + size);
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            if let Ok((frame, page_size, tlb)) = pt.unmap(addr) {
+                if page_size.is_huge() {
+                    return false;
+                }
+                tlb.flush();
+                // Drops the reference of this mapping, the frame stays alive
+                // as long as the pages or other mappings own it.
+                dealloc_frame(frame);
+            }
+        }
+        true
+    }
+
+    pub(crate) fn handle_page_fault_shared(
+        &self,
+        vaddr: VirtAddr,
+        orig_flags: MappingFlags,
+        access_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        let Self::Shared { pages, .. } = self else {
+            unreachable!()
+        };
+        if let Ok((_, flags, page_size)) = pt.query(vaddr.align_down_4k()) {
+            // The page is present but read-only after `mprotect`, restore the
+            // write permission since shared pages are never copied.
+            if page_size.is_huge()
+                || !access_flags.contains(MappingFlags::WRITE)
+                || flags.contains(MappingFlags::WRITE)
+            {
+                return false;
+            }
+            return pt
+                .protect(vaddr, orig_flags)
+                .map(|(_, tlb)| tlb.flush())
+                .is_ok();
+        }
+        // Faults beyond the end of the pages are not handled.
+        // The reference taken is owned by the new mapping.
+        let Some(frame) = pages.frame(self.shared_page_index(vaddr), true) else {
+            return false;
+        };
+        if pt
+            .remap(vaddr, frame, orig_flags)
+            .map(|(_, tlb)| tlb.flush())
+            .is_ok()
+        {
+            true
+        } else {
+            dealloc_frame(frame);
+            false
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! Reference counting of physical frames shared by multiple mappings.
+//!
+//! Only frames with more than one owner are recorded in the table, so frames
+//! that are never shared (the common case) cost nothing.
+
+use alloc::collections::BTreeMap;
+use axsync::spin::SpinNoIrq;
+use memory_addr::PhysAddr;
+
+/// The number of *extra* references of each shared frame.
+static FRAME_REFS: SpinNoIrq<BTreeMap<PhysAddr, usize>> = SpinNoIrq::new(BTreeMap::new());
+
+/// Returns the number of owners of the given frame.
+pub(crate) fn frame_ref_count(frame: PhysAddr) -> usize {
+    FRAME_REFS.lock().get(&frame).map_or(1, |extra| extra + 1)
+}
+
+/// Adds a new owner to the given frame.
+pub(crate) fn frame_ref_inc(frame: PhysAddr) {
+    *FRAME_REFS.lock().entry(frame).or_insert(0) += 1;
+}
+
+/// Drops an owner of the given frame.
+///
+/// Returns `true` if the caller was the last owner, in which case the frame
+/// should be deallocated by the caller.
+pub(crate) fn frame_ref_dec(frame: PhysAddr) -> bool {
+    let mut refs = FRAME_REFS.lock();
+    match refs.get_mut(&frame) {
+        Some(extra) if *extra > 1 => {
+            *extra -= 1;
+            false
+        }
+        Some(_) => {
+            refs.remove(&frame);
+            false
+        }
+        None => true,
+    }
+}
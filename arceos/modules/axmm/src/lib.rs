@@ -1,6 +1,6 @@
 //! [ArceOS](https://github.com/arceos-org/arceos) memory management module.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
@@ -8,13 +8,23 @@ extern crate alloc;
 
 mod aspace;
 mod backend;
+mod frame;
+mod uaccess;
+
+#[cfg(test)]
+mod tests;
+
+#[cfg(feature = "swap")]
+pub mod swap;
 
 pub use self::aspace::AddrSpace;
+pub use self::backend::SharedPages;
+pub use self::uaccess::{UserCStr, UserPtr, UserSlice};
 
 use axerrno::{AxError, AxResult};
 use axhal::mem::phys_to_virt;
 use axhal::paging::PagingError;
-use kspin::SpinNoIrq;
+use axsync::spin::SpinNoIrq;
 use lazyinit::LazyInit;
 use memory_addr::{va, PhysAddr, VirtAddr};
 use memory_set::MappingError;
@@ -51,6 +61,16 @@ pub fn new_user_aspace() -> AxResult<AddrSpace> {
     Ok(aspace)
 }
 
+/// Creates a copy-on-write duplicate of the given user address space, e.g.,
+/// for `fork`.
+///
+/// See [`AddrSpace::fork_into`] for more details.
+pub fn fork_user_aspace(parent: &mut AddrSpace) -> AxResult<AddrSpace> {
+    let mut aspace = new_user_aspace()?;
+    parent.fork_into(&mut aspace)?;
+    Ok(aspace)
+}
+
 /// Creates a new address space for kernel itself.
 pub fn new_kernel_aspace() -> AxResult<AddrSpace> {
     let mut aspace = AddrSpace::new_empty(
@@ -60,6 +80,11 @@ pub fn new_kernel_aspace() -> AxResult<AddrSpace> {
     for r in axhal::mem::memory_regions() {
         aspace.map_linear(phys_to_virt(r.paddr), r.paddr, r.size, r.flags.into())?;
     }
+    // Kernel stacks are mapped after user address spaces are created.
+    aspace.populate_page_tables(
+        va!(axhal::mem::KERNEL_STACK_REGION_BASE),
+        axhal::mem::KERNEL_STACK_REGION_SIZE,
+    )?;
     Ok(aspace)
 }
 
@@ -0,0 +1,443 @@
+//! Page reclaim by swapping anonymous pages out to a block device.
+//!
+//! Once a swap device is set up with [`init_swap`], an address space
+//! reclaims its own cold pages when the free memory drops below
+//! [`low_watermark`], before handling each page fault. Only private pages
+//! of allocation mappings (see [`Backend::Alloc`]) are swapped, frames
+//! shared copy-on-write are skipped.
+//!
+//! Page tables are not required to expose the hardware accessed and dirty
+//! bits, so they are tracked in software:
+//!
+//! - **Accessed**: reclaim works like a clock. The first time the hand
+//!   passes a resident page, the page is *aged*, i.e., unmapped while its
+//!   frame is kept. Accessing it again faults and maps it back. Pages that
+//!   are still aged the next time the hand passes are evicted.
+//! - **Dirty**: pages swapped in keep their slot and are mapped read-only,
+//!   like clean pages of shared file mappings. Evicting a page that is still
+//!   clean needs no I/O. The first write drops the slot and restores the
+//!   write permission.
+//!
+//! Non-resident pages are mapped to empty entries like lazy pages, and come
+//! back in through [`AddrSpace::handle_page_fault`].
+//!
+//! [`Backend::Alloc`]: crate::backend::Backend::Alloc
+//! [`AddrSpace::handle_page_fault`]: crate::AddrSpace::handle_page_fault
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axalloc::global_allocator;
+use axdriver::prelude::*;
+use axhal::mem::phys_to_virt;
+use axhal::paging::{MappingFlags, PageSize, PageTable};
+use axsync::spin::SpinNoIrq;
+use lazyinit::LazyInit;
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+use memory_set::MemorySet;
+
+use crate::backend::{alloc_frame, dealloc_frame, Backend};
+use crate::frame::frame_ref_count;
+
+/// The number of pages reclaimed at a time.
+pub(crate) const RECLAIM_BATCH: usize = 32;
+
+static LOW_WATERMARK: AtomicUsize = AtomicUsize::new(256); // 1 MiB
+
+static SWAP_AREA: LazyInit<SpinNoIrq<SwapArea>> = LazyInit::new();
+
+/// A page-sized slot in the swap area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SwapSlot(u32);
+
+/// The swap area on a block device.
+struct SwapArea {
+    dev: AxBlockDevice,
+    blocks_per_slot: usize,
+    /// The number of page table entries referring to each slot.
+    refs: Vec<u16>,
+    free: Vec<u32>,
+}
+
+impl SwapArea {
+    fn page_io(&mut self, slot: SwapSlot, frame: PhysAddr, write: bool) -> bool {
+        let block_size = self.dev.block_size();
+        let page: &mut [u8] = unsafe {
+            core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K)
+        };
+        let first = slot.0 as u64 * self.blocks_per_slot as u64;
+        for (i, buf) in page.chunks_mut(block_size).enumerate() {
+            let block_id = first + i as u64;
+            let res = if write {
+                self.dev.write_block(block_id, buf)
+            } else {
+                self.dev.read_block(block_id, buf)
+            };
+            if let Err(e) = res {
+                warn!("swap I/O error at block {}: {:?}", block_id, e);
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Usage of the swap area.
+#[derive(Debug, Clone, Copy)]
+pub struct SwapInfo {
+    /// The number of pages the swap area can hold.
+    pub total_pages: usize,
+    /// The number of free pages in the swap area.
+    pub free_pages: usize,
+}
+
+/// Sets up the swap area on the given block device, which is used as a
+/// whole.
+pub fn init_swap(dev: AxBlockDevice) {
+    let block_size = dev.block_size();
+    assert!(
+        block_size > 0 && PAGE_SIZE_4K % block_size == 0,
+        "unsupported swap block size {}",
+        block_size
+    );
+    let blocks_per_slot = PAGE_SIZE_4K / block_size;
+    let num_slots = (dev.num_blocks() / blocks_per_slot as u64).min(u32::MAX as u64) as u32;
+    info!(
+        "Initialize swap area on {:?}: {} pages",
+        dev.device_name(),
+        num_slots
+    );
+    SWAP_AREA.init_once(SpinNoIrq::new(SwapArea {
+        dev,
+        blocks_per_slot,
+        refs: alloc::vec![0; num_slots as usize],
+        free: (0..num_slots).rev().collect(),
+    }));
+}
+
+/// Whether a swap area has been set up.
+pub fn is_swap_enabled() -> bool {
+    SWAP_AREA.is_inited()
+}
+
+/// Returns the usage of the swap area, or `None` if there is none.
+pub fn swap_info() -> Option<SwapInfo> {
+    let area = SWAP_AREA.get()?.lock();
+    Some(SwapInfo {
+        total_pages: area.refs.len(),
+        free_pages: area.free.len(),
+    })
+}
+
+/// Returns the number of free pages below which pages are reclaimed.
+pub fn low_watermark() -> usize {
+    LOW_WATERMARK.load(Ordering::Relaxed)
+}
+
+/// Sets the number of free pages below which pages are reclaimed.
+pub fn set_low_watermark(pages: usize) {
+    LOW_WATERMARK.store(pages, Ordering::Relaxed);
+}
+
+/// Whether pages should be reclaimed now.
+pub(crate) fn should_reclaim() -> bool {
+    is_swap_enabled() && global_allocator().available_pages() < low_watermark()
+}
+
+/// Writes the frame to a new slot, returns `None` if the swap area is full
+/// or on I/O errors.
+pub(crate) fn swap_out(frame: PhysAddr) -> Option<SwapSlot> {
+    let mut area = SWAP_AREA.get()?.lock();
+    let slot = SwapSlot(area.free.pop()?);
+    if !area.page_io(slot, frame, true) {
+        area.free.push(slot.0);
+        return None;
+    }
+    area.refs[slot.0 as usize] = 1;
+    Some(slot)
+}
+
+/// Reads the slot into the frame.
+pub(crate) fn swap_in(slot: SwapSlot, frame: PhysAddr) -> bool {
+    SWAP_AREA.lock().page_io(slot, frame, false)
+}
+
+pub(crate) fn slot_dup(slot: SwapSlot) {
+    SWAP_AREA.lock().refs[slot.0 as usize] += 1;
+}
+
+pub(crate) fn slot_free(slot: SwapSlot) {
+    let mut area = SWAP_AREA.lock();
+    let refs = &mut area.refs[slot.0 as usize];
+    *refs -= 1;
+    if *refs == 0 {
+        area.free.push(slot.0);
+    }
+}
+
+/// The swap state of a page of an allocation mapping.
+#[derive(Clone, Copy)]
+enum PageSwap {
+    /// Resident and mapped read-only, the slot holds the same contents.
+    Clean(SwapSlot),
+    /// Resident but unmapped, to find out whether it is accessed again.
+    Aged {
+        frame: PhysAddr,
+        flags: MappingFlags,
+        /// The slot if the page is clean.
+        slot: Option<SwapSlot>,
+    },
+    /// Swapped out to the slot.
+    Out(SwapSlot),
+}
+
+impl PageSwap {
+    fn slot(&self) -> Option<SwapSlot> {
+        match *self {
+            Self::Clean(slot) | Self::Out(slot) => Some(slot),
+            Self::Aged { slot, .. } => slot,
+        }
+    }
+}
+
+/// The swap state of the pages of an address space.
+pub(crate) struct SwapSpace {
+    pages: BTreeMap<VirtAddr, PageSwap>,
+    /// Where the reclaim clock continues.
+    hand: VirtAddr,
+}
+
+impl SwapSpace {
+    pub const fn new() -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            hand: VirtAddr::from_usize(0),
+        }
+    }
+
+    /// Maps the aged pages in `[start, end)` back, so that the page table
+    /// reflects all resident pages.
+    pub fn restore(&mut self, start: VirtAddr, end: VirtAddr, pt: &mut PageTable) {
+        let aged: Vec<_> = self
+            .pages
+            .range(start..end)
+            .filter(|(_, page)| matches!(page, PageSwap::Aged { .. }))
+            .map(|(&vaddr, _)| vaddr)
+            .collect();
+        for vaddr in aged {
+            let Some(PageSwap::Aged { frame, flags, slot }) = self.pages.remove(&vaddr) else {
+                unreachable!()
+            };
+            if let Ok((_, tlb)) = pt.remap(vaddr, frame, flags) {
+                tlb.flush();
+            }
+            if let Some(slot) = slot {
+                self.pages.insert(vaddr, PageSwap::Clean(slot));
+            }
+        }
+    }
+
+    /// Forgets the pages in `[start, end)` and frees their slots, before the
+    /// range is unmapped.
+    ///
+    /// Aged pages are mapped back first so that their frames are freed by
+    /// the backend.
+    pub fn release(&mut self, start: VirtAddr, end: VirtAddr, pt: &mut PageTable) {
+        self.restore(start, end, pt);
+        let released: Vec<_> = self
+            .pages
+            .range(start..end)
+            .map(|(&vaddr, _)| vaddr)
+            .collect();
+        for vaddr in released {
+            if let Some(slot) = self.pages.remove(&vaddr).and_then(|p| p.slot()) {
+                slot_free(slot);
+            }
+        }
+    }
+
+    /// Moves the state of the pages in `[start, end)` to `new_start`, for
+    /// moving mappings. Aged pages must have been restored.
+    pub fn move_range(&mut self, start: VirtAddr, end: VirtAddr, new_start: VirtAddr) {
+        let moved: Vec<_> = self
+            .pages
+            .range(start..end)
+            .map(|(&vaddr, _)| vaddr)
+            .collect();
+        for vaddr in moved {
+            let page = self.pages.remove(&vaddr).unwrap();
+            self.pages.insert(new_start + (vaddr - start), page);
+        }
+    }
+
+    /// Duplicates the state for a forked address space, whose pages refer to
+    /// the same slots. Aged pages must have been restored.
+    pub fn fork(&self) -> Self {
+        let pages = self
+            .pages
+            .iter()
+            .filter_map(|(&vaddr, page)| {
+                let slot = page.slot()?;
+                slot_dup(slot);
+                Some((
+                    vaddr,
+                    match page {
+                        PageSwap::Clean(_) => PageSwap::Clean(slot),
+                        _ => PageSwap::Out(slot),
+                    },
+                ))
+            })
+            .collect();
+        Self {
+            pages,
+            hand: self.hand,
+        }
+    }
+
+    /// Handles a page fault on a page that is aged, swapped out, or clean.
+    ///
+    /// Returns `None` if the fault should be handled by the backend, e.g.,
+    /// for pages unknown to swapping, or to restore the write permission of
+    /// a page that is no longer clean.
+    pub fn handle_page_fault(
+        &mut self,
+        vaddr: VirtAddr,
+        orig_flags: MappingFlags,
+        access_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> Option<bool> {
+        let vaddr = vaddr.align_down_4k();
+        let page = match *self.pages.get(&vaddr)? {
+            // Clean pages may have been dropped by `mprotect(PROT_NONE)`,
+            // their contents are still in the slot.
+            PageSwap::Clean(slot) if pt.query(vaddr).is_err() => PageSwap::Out(slot),
+            page => page,
+        };
+        let flags = match page {
+            PageSwap::Aged { frame, flags, slot } => {
+                if pt
+                    .remap(vaddr, frame, flags)
+                    .map(|(_, tlb)| tlb.flush())
+                    .is_err()
+                {
+                    return Some(false);
+                }
+                match slot {
+                    Some(slot) => self.pages.insert(vaddr, PageSwap::Clean(slot)),
+                    None => self.pages.remove(&vaddr),
+                };
+                flags
+            }
+            PageSwap::Out(slot) => {
+                let Some(frame) = alloc_frame(false) else {
+                    return Some(false);
+                };
+                // Keep the slot until the page is written.
+                let flags = orig_flags - MappingFlags::WRITE;
+                if !swap_in(slot, frame)
+                    || pt
+                        .remap(vaddr, frame, flags)
+                        .map(|(_, tlb)| tlb.flush())
+                        .is_err()
+                {
+                    dealloc_frame(frame);
+                    return Some(false);
+                }
+                self.pages.insert(vaddr, PageSwap::Clean(slot));
+                flags
+            }
+            PageSwap::Clean(_) => pt.query(vaddr).map_or(orig_flags, |(_, flags, _)| flags),
+        };
+        if access_flags.contains(MappingFlags::WRITE) {
+            // The page is being dirtied, the write permission is restored by
+            // the backend.
+            if let Some(PageSwap::Clean(slot)) = self.pages.remove(&vaddr) {
+                slot_free(slot);
+            }
+        }
+        if flags.contains(access_flags) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// Ages or evicts up to `count` pages of the allocation mappings in
+    /// `areas`, except the page at `skip`. Returns the number of evicted
+    /// pages.
+    pub fn reclaim(
+        &mut self,
+        areas: &MemorySet<Backend>,
+        pt: &mut PageTable,
+        count: usize,
+        skip: VirtAddr,
+    ) -> usize {
+        let ranges: Vec<_> = areas
+            .iter()
+            .filter(|area| matches!(area.backend(), Backend::Alloc { .. }))
+            .map(|area| (area.start(), area.end()))
+            .collect();
+        // Each page is aged in the first round and evicted in the second.
+        let mut budget = 2 * ranges
+            .iter()
+            .map(|(s, e)| (*e - *s) / PAGE_SIZE_4K)
+            .sum::<usize>();
+        let mut evicted = 0;
+        let mut vaddr = self.hand;
+        while evicted < count && budget > 0 {
+            // Moves the hand to the next page of the mappings.
+            vaddr = match ranges.iter().find(|(_, end)| vaddr < *end) {
+                Some(&(start, _)) => vaddr.max(start),
+                None => ranges[0].0,
+            };
+            budget -= 1;
+            if vaddr != skip.align_down_4k() && self.scan_page(vaddr, pt) {
+                evicted += 1;
+            }
+            vaddr += PAGE_SIZE_4K;
+        }
+        self.hand = vaddr;
+        if evicted > 0 {
+            debug!("reclaimed {} pages", evicted);
+        }
+        evicted
+    }
+
+    /// Ages the page if it is resident, or evicts it if it is still aged.
+    /// Returns whether it is evicted.
+    fn scan_page(&mut self, vaddr: VirtAddr, pt: &mut PageTable) -> bool {
+        match self.pages.get(&vaddr) {
+            Some(&PageSwap::Aged { frame, slot, .. }) => {
+                let Some(slot) = slot.or_else(|| swap_out(frame)) else {
+                    return false; // swap area is full
+                };
+                dealloc_frame(frame);
+                self.pages.insert(vaddr, PageSwap::Out(slot));
+                true
+            }
+            Some(PageSwap::Out(_)) => false,
+            page => {
+                let slot = page.and_then(PageSwap::slot);
+                let Ok((frame, flags, page_size)) = pt.query(vaddr) else {
+                    return false; // not populated yet
+                };
+                if page_size.is_huge() || frame_ref_count(frame) > 1 {
+                    return false;
+                }
+                // Replaces the entry with an empty one like lazy pages, the
+                // frame is still owned by the page.
+                match pt.unmap(vaddr) {
+                    Ok((_, _, tlb)) => tlb.flush(),
+                    Err(_) => return false,
+                }
+                if let Ok(tlb) = pt.map(vaddr, 0.into(), PageSize::Size4K, MappingFlags::empty()) {
+                    tlb.ignore();
+                }
+                self.pages
+                    .insert(vaddr, PageSwap::Aged { frame, flags, slot });
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::sync::{Arc, Mutex, Once};
+
+use axalloc::global_allocator;
+use axhal::mem::phys_to_virt;
+use axhal::paging::PageSize;
+use memory_addr::PAGE_SIZE_4K;
+
+use crate::backend::{alloc_block, alloc_frame, dealloc_block, dealloc_frame};
+use crate::frame::{frame_ref_count, frame_ref_inc};
+use crate::SharedPages;
+
+#[cfg(feature = "swap")]
+use crate::swap::{
+    init_swap, low_watermark, set_low_watermark, should_reclaim, slot_dup, slot_free, swap_in,
+    swap_info, swap_out,
+};
+#[cfg(any(feature = "fs", feature = "swap"))]
+use axdriver_block::ramdisk::RamDisk;
+#[cfg(feature = "fs")]
+use {
+    crate::backend::FileCache,
+    axdriver::AxDeviceContainer,
+    axfs::fops::{Disk, File, MyFileSystemIf, OpenOptions},
+    axfs_ramfs::RamFileSystem,
+    axfs_vfs::VfsOps,
+};
+
+static INIT: Once = Once::new();
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// The size of the memory managed by the global allocator, physical
+/// addresses are the same as virtual addresses on the host.
+const MEMORY_SIZE: usize = 0x80_0000; // 8 MiB
+
+fn init_allocator() {
+    INIT.call_once(|| {
+        // Aligned to 2M so that huge page blocks are aligned as well.
+        let layout = std::alloc::Layout::from_size_align(MEMORY_SIZE, 0x20_0000).unwrap();
+        let start = unsafe { std::alloc::alloc(layout) };
+        assert!(!start.is_null());
+        axalloc::global_init(start as usize, MEMORY_SIZE);
+    });
+}
+
+#[test]
+fn test_frame_refs() {
+    let _lock = SERIAL.lock();
+    init_allocator();
+
+    let free_pages = global_allocator().available_pages();
+    let frame = alloc_frame(true).unwrap();
+    assert_eq!(global_allocator().available_pages(), free_pages - 1);
+    assert_eq!(frame_ref_count(frame), 1);
+
+    // Shared by two more owners, e.g., after forking twice.
+    frame_ref_inc(frame);
+    frame_ref_inc(frame);
+    assert_eq!(frame_ref_count(frame), 3);
+
+    dealloc_frame(frame);
+    assert_eq!(frame_ref_count(frame), 2);
+    dealloc_frame(frame);
+    assert_eq!(frame_ref_count(frame), 1);
+    assert_eq!(global_allocator().available_pages(), free_pages - 1);
+
+    // The last owner frees the frame.
+    let page = unsafe { core::slice::from_raw_parts(phys_to_virt(frame).as_ptr(), PAGE_SIZE_4K) };
+    assert!(page.iter().all(|&b| b == 0));
+    dealloc_frame(frame);
+    assert_eq!(global_allocator().available_pages(), free_pages);
+}
+
+#[test]
+fn test_huge_block_refs() {
+    let _lock = SERIAL.lock();
+    init_allocator();
+
+    const SIZE_2M: usize = 0x20_0000;
+    let free_pages = global_allocator().available_pages();
+    let block = alloc_block(PageSize::Size2M, true).unwrap();
+    assert_eq!(block.as_usize() % SIZE_2M, 0);
+    assert_eq!(
+        global_allocator().available_pages(),
+        free_pages - SIZE_2M / PAGE_SIZE_4K
+    );
+    let data = unsafe { core::slice::from_raw_parts(phys_to_virt(block).as_ptr(), SIZE_2M) };
+    assert!(data.iter().all(|&b| b == 0));
+
+    // A huge page shared copy-on-write is freed as a whole by its last owner.
+    frame_ref_inc(block);
+    dealloc_block(block, PageSize::Size2M);
+    assert_eq!(frame_ref_count(block), 1);
+    assert_eq!(
+        global_allocator().available_pages(),
+        free_pages - SIZE_2M / PAGE_SIZE_4K
+    );
+    dealloc_block(block, PageSize::Size2M);
+    assert_eq!(global_allocator().available_pages(), free_pages);
+}
+
+#[test]
+fn test_shared_pages() {
+    let _lock = SERIAL.lock();
+    init_allocator();
+
+    let free_pages = global_allocator().available_pages();
+    let pages = SharedPages::new(2);
+    assert_eq!(pages.size(), 2 * PAGE_SIZE_4K);
+
+    // Untouched pages read as zeros without allocating frames.
+    let mut buf = [0xff; 16];
+    assert_eq!(pages.read_at(PAGE_SIZE_4K - 8, &mut buf), 16);
+    assert_eq!(buf, [0; 16]);
+    assert_eq!(global_allocator().available_pages(), free_pages);
+
+    // Writes across the page boundary allocate both frames.
+    assert_eq!(pages.write_at(PAGE_SIZE_4K - 8, b"Rust is cool!"), 13);
+    assert_eq!(global_allocator().available_pages(), free_pages - 2);
+    assert_eq!(pages.read_at(PAGE_SIZE_4K - 8, &mut buf), 16);
+    assert_eq!(&buf[..13], b"Rust is cool!");
+    assert_eq!(&buf[13..], [0; 3]);
+
+    // Accesses are short at the end of the pages.
+    assert_eq!(pages.write_at(2 * PAGE_SIZE_4K - 4, b"Rust"), 4);
+    assert_eq!(pages.write_at(2 * PAGE_SIZE_4K - 2, b"Rust"), 2);
+    assert_eq!(pages.read_at(2 * PAGE_SIZE_4K - 4, &mut buf), 4);
+    assert_eq!(&buf[..4], b"RuRu");
+    assert_eq!(pages.read_at(2 * PAGE_SIZE_4K, &mut buf), 0);
+
+    // Shrinking frees the dropped frames, and dropping frees the rest.
+    pages.resize(1);
+    assert_eq!(pages.size(), PAGE_SIZE_4K);
+    assert_eq!(global_allocator().available_pages(), free_pages - 1);
+    pages.resize(2);
+    assert_eq!(pages.read_at(PAGE_SIZE_4K, &mut buf), 16);
+    assert_eq!(buf, [0; 16]);
+    drop(pages);
+    assert_eq!(global_allocator().available_pages(), free_pages);
+}
+
+#[test]
+fn test_shared_pages_concurrent_resize() {
+    let _lock = SERIAL.lock();
+    init_allocator();
+
+    let free_pages = global_allocator().available_pages();
+    let pages = Arc::new(SharedPages::new(4));
+    let accessor = {
+        let pages = pages.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0; 64];
+            for i in 0..10000 {
+                let offset = (i % 4) * PAGE_SIZE_4K + PAGE_SIZE_4K - 32;
+                pages.write_at(offset, &[0x5a; 64]);
+                pages.read_at(offset, &mut buf);
+            }
+        })
+    };
+    // Frames dropped by shrinking stay alive until the accesses finish.
+    for i in 0..10000 {
+        pages.resize(if i % 2 == 0 { 1 } else { 4 });
+    }
+    accessor.join().unwrap();
+    drop(pages);
+    assert_eq!(global_allocator().available_pages(), free_pages);
+}
+
+#[cfg(feature = "swap")]
+#[test]
+fn test_swap_out_in() {
+    static SWAP_INIT: Once = Once::new();
+
+    let _lock = SERIAL.lock();
+    init_allocator();
+    SWAP_INIT.call_once(|| init_swap(RamDisk::new(16 * PAGE_SIZE_4K)));
+
+    let info = swap_info().unwrap();
+    assert_eq!(info.total_pages, 16);
+    let free_slots = info.free_pages;
+
+    let frame = alloc_frame(false).unwrap();
+    let page =
+        unsafe { core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K) };
+    page.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+
+    let slot = swap_out(frame).unwrap();
+    assert_eq!(swap_info().unwrap().free_pages, free_slots - 1);
+    page.fill(0);
+    assert!(swap_in(slot, frame));
+    assert!(page.iter().enumerate().all(|(i, &b)| b == i as u8));
+
+    // The slot is freed when the last page table entry referring to it,
+    // e.g., in a forked address space, goes away.
+    slot_dup(slot);
+    slot_free(slot);
+    assert_eq!(swap_info().unwrap().free_pages, free_slots - 1);
+    slot_free(slot);
+    assert_eq!(swap_info().unwrap().free_pages, free_slots);
+    dealloc_frame(frame);
+
+    let watermark = low_watermark();
+    set_low_watermark(global_allocator().available_pages() + 1);
+    assert!(should_reclaim());
+    set_low_watermark(0);
+    assert!(!should_reclaim());
+    set_low_watermark(watermark);
+}
+
+#[cfg(feature = "fs")]
+struct MyFileSystemIfImpl;
+
+#[cfg(feature = "fs")]
+#[crate_interface::impl_interface]
+impl MyFileSystemIf for MyFileSystemIfImpl {
+    fn new_myfs(_disk: Disk) -> Arc<dyn VfsOps> {
+        Arc::new(RamFileSystem::new())
+    }
+}
+
+#[cfg(feature = "fs")]
+fn init_filesystems() {
+    static FS_INIT: Once = Once::new();
+    FS_INIT.call_once(|| {
+        // dummy disk, actually not used.
+        axfs::init_filesystems(AxDeviceContainer::from_one(RamDisk::default()));
+    });
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_file_cache_shared() {
+    let _lock = SERIAL.lock();
+    init_allocator();
+    init_filesystems();
+
+    let mut opts = OpenOptions::new();
+    opts.read(true);
+    opts.write(true);
+    opts.create(true);
+    let file = File::open("/shared.txt", &opts).unwrap();
+    file.write_at(0, b"Rust is cool!").unwrap();
+
+    let free_pages = global_allocator().available_pages();
+    // Two processes mapping the same file with `MAP_SHARED`, which open it
+    // separately.
+    let other = File::open("/shared.txt", &opts).unwrap();
+    let cache1 = FileCache::get(&file);
+    let cache2 = FileCache::get(&other);
+    assert!(Arc::ptr_eq(&cache1, &cache2));
+
+    // Both mappings get the same frame, so they see each other's stores.
+    let frame1 = cache1.frame(&file, 0).unwrap();
+    let frame2 = cache2.frame(&other, 0).unwrap();
+    assert_eq!(frame1, frame2);
+    assert_eq!(frame_ref_count(frame1), 3); // the cache and two mappings
+    assert_eq!(global_allocator().available_pages(), free_pages - 1);
+
+    let page = unsafe { core::slice::from_raw_parts(phys_to_virt(frame1).as_ptr(), PAGE_SIZE_4K) };
+    assert_eq!(&page[..13], b"Rust is cool!");
+    assert!(page[13..].iter().all(|&b| b == 0));
+
+    // Unmapping drops the references of the mappings, and dropping the
+    // cache frees the frame.
+    dealloc_frame(frame1);
+    dealloc_frame(frame2);
+    assert_eq!(frame_ref_count(frame1), 1);
+    drop(cache1);
+    assert_eq!(global_allocator().available_pages(), free_pages - 1);
+    drop(cache2);
+    assert_eq!(global_allocator().available_pages(), free_pages);
+}
@@ -0,0 +1,254 @@
+//! Safe access to user memory from the kernel.
+//!
+//! User pointers passed to syscalls must not be dereferenced directly: they
+//! may point to unmapped or kernel memory, or to pages that are not populated
+//! yet. The types here check the pointed range against the user address space
+//! first, populate it by demand paging, and then copy the data with the user
+//! memory access enabled (the `SUM` bit on RISC-V, `RFLAGS.AC` with SMAP on
+//! x86_64). Bad pointers result in [`BadAddress`](AxError::BadAddress)
+//! (i.e., `EFAULT`) instead of kernel panics.
+//!
+//! The address space must be the current one, and it should stay locked
+//! during the access, so that the checked pages cannot be unmapped
+//! concurrently.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::size_of;
+
+use axerrno::{ax_err, AxError, AxResult};
+use axhal::paging::MappingFlags;
+use memory_addr::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
+
+use crate::AddrSpace;
+
+/// Enables the kernel to access user memory until dropped.
+struct UserAccessGuard(bool);
+
+impl UserAccessGuard {
+    fn new() -> Self {
+        Self(axhal::arch::enable_user_access())
+    }
+}
+
+impl Drop for UserAccessGuard {
+    fn drop(&mut self) {
+        if !self.0 {
+            axhal::arch::disable_user_access();
+        }
+    }
+}
+
+/// A pointer to a value of type `T` in user memory.
+pub struct UserPtr<T> {
+    addr: VirtAddr,
+    _phantom: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T> From<usize> for UserPtr<T> {
+    fn from(addr: usize) -> Self {
+        Self::new(addr)
+    }
+}
+
+impl<T> From<*mut T> for UserPtr<T> {
+    fn from(ptr: *mut T) -> Self {
+        Self::new(ptr as usize)
+    }
+}
+
+impl<T> From<*const T> for UserPtr<T> {
+    fn from(ptr: *const T) -> Self {
+        Self::new(ptr as usize)
+    }
+}
+
+impl<T> UserPtr<T> {
+    /// Creates a user pointer from the user virtual address.
+    pub const fn new(addr: usize) -> Self {
+        Self {
+            addr: VirtAddr::from_usize(addr),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the user virtual address.
+    pub const fn address(&self) -> VirtAddr {
+        self.addr
+    }
+
+    /// Whether the pointer is null.
+    pub fn is_null(&self) -> bool {
+        self.addr.as_usize() == 0
+    }
+
+    /// Returns the pointer to the `count`-th value after this one.
+    pub fn add(self, count: usize) -> Self {
+        Self::new(self.addr.as_usize().wrapping_add(count * size_of::<T>()))
+    }
+
+    /// Reads the value from user memory.
+    pub fn read(&self, aspace: &mut AddrSpace) -> AxResult<T> {
+        aspace.check_user_range(self.addr, size_of::<T>(), MappingFlags::READ)?;
+        let _guard = UserAccessGuard::new();
+        Ok(unsafe { (self.addr.as_usize() as *const T).read_unaligned() })
+    }
+
+    /// Writes the value to user memory.
+    pub fn write(&self, aspace: &mut AddrSpace, val: T) -> AxResult {
+        aspace.check_user_range(self.addr, size_of::<T>(), MappingFlags::WRITE)?;
+        let _guard = UserAccessGuard::new();
+        unsafe { (self.addr.as_usize() as *mut T).write_unaligned(val) };
+        Ok(())
+    }
+}
+
+/// A slice of values of type `T` in user memory.
+pub struct UserSlice<T> {
+    ptr: UserPtr<T>,
+    len: usize,
+}
+
+impl<T> UserSlice<T> {
+    /// Creates a user slice of `len` values starting at the user virtual
+    /// address.
+    pub const fn new(addr: usize, len: usize) -> Self {
+        Self {
+            ptr: UserPtr::new(addr),
+            len,
+        }
+    }
+
+    /// Returns the user virtual address of the first value.
+    pub const fn address(&self) -> VirtAddr {
+        self.ptr.addr
+    }
+
+    /// Returns the number of values.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the slice is empty.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn size(&self) -> AxResult<usize> {
+        self.len
+            .checked_mul(size_of::<T>())
+            .ok_or(AxError::BadAddress)
+    }
+
+    /// Copies the values from user memory to `dst`, whose length must be the
+    /// same as the slice.
+    pub fn copy_to_slice(&self, aspace: &mut AddrSpace, dst: &mut [T]) -> AxResult {
+        if dst.len() != self.len {
+            return ax_err!(InvalidInput, "slice length mismatch");
+        }
+        aspace.check_user_range(self.ptr.addr, self.size()?, MappingFlags::READ)?;
+        let _guard = UserAccessGuard::new();
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                self.ptr.addr.as_usize() as *const T,
+                dst.as_mut_ptr(),
+                self.len,
+            )
+        };
+        Ok(())
+    }
+
+    /// Copies `src` to the beginning of the slice in user memory, `src` must
+    /// not be longer than the slice.
+    pub fn copy_from_slice(&self, aspace: &mut AddrSpace, src: &[T]) -> AxResult {
+        if src.len() > self.len {
+            return ax_err!(InvalidInput, "slice length mismatch");
+        }
+        let size = src.len() * size_of::<T>();
+        aspace.check_user_range(self.ptr.addr, size, MappingFlags::WRITE)?;
+        let _guard = UserAccessGuard::new();
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                src.as_ptr(),
+                self.ptr.addr.as_usize() as *mut T,
+                src.len(),
+            )
+        };
+        Ok(())
+    }
+}
+
+impl<T: Copy + Default> UserSlice<T> {
+    /// Reads all values from user memory.
+    pub fn read_to_vec(&self, aspace: &mut AddrSpace) -> AxResult<Vec<T>> {
+        let mut buf = Vec::new();
+        buf.try_reserve_exact(self.len)
+            .map_err(|_| AxError::NoMemory)?;
+        buf.resize(self.len, T::default());
+        self.copy_to_slice(aspace, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// A NUL-terminated string in user memory.
+pub struct UserCStr {
+    ptr: UserPtr<u8>,
+}
+
+impl UserCStr {
+    /// Creates a user string starting at the user virtual address.
+    pub const fn new(addr: usize) -> Self {
+        Self {
+            ptr: UserPtr::new(addr),
+        }
+    }
+
+    /// Returns the user virtual address of the string.
+    pub const fn address(&self) -> VirtAddr {
+        self.ptr.addr
+    }
+
+    /// Whether the pointer is null.
+    pub fn is_null(&self) -> bool {
+        self.ptr.is_null()
+    }
+
+    /// Reads the string from user memory, which must be valid UTF-8 and at
+    /// most `max_len` bytes long (not including the NUL).
+    pub fn read(&self, aspace: &mut AddrSpace, max_len: usize) -> AxResult<String> {
+        let mut buf = Vec::new();
+        let mut addr = self.ptr.addr;
+        // Checks page by page, as the string may end right before an
+        // inaccessible page.
+        loop {
+            let chunk = (addr.align_down_4k() + PAGE_SIZE_4K) - addr;
+            aspace.check_user_range(addr, chunk, MappingFlags::READ)?;
+            let _guard = UserAccessGuard::new();
+            let bytes = unsafe { core::slice::from_raw_parts(addr.as_ptr(), chunk) };
+            match bytes.iter().position(|&b| b == 0) {
+                Some(len) => {
+                    buf.extend_from_slice(&bytes[..len]);
+                    break;
+                }
+                None => buf.extend_from_slice(bytes),
+            }
+            if buf.len() > max_len {
+                break;
+            }
+            addr += chunk;
+        }
+        if buf.len() > max_len {
+            return ax_err!(InvalidInput, "string too long");
+        }
+        String::from_utf8(buf).map_err(|_| AxError::InvalidData)
+    }
+}
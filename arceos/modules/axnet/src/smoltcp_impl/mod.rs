@@ -6,8 +6,10 @@ mod tcp;
 mod udp;
 
 use alloc::vec;
+use alloc::vec::Vec;
 use core::cell::RefCell;
 use core::ops::DerefMut;
+use core::task::Waker;
 
 use axdriver::prelude::*;
 use axdriver_net::{DevError, NetBufPtr};
@@ -54,6 +56,9 @@ static LISTEN_TABLE: LazyInit<ListenTable> = LazyInit::new();
 static SOCKET_SET: LazyInit<SocketSetWrapper> = LazyInit::new();
 static ETH0: LazyInit<InterfaceWrapper> = LazyInit::new();
 
+/// Woken up when polling the interfaces may have changed the socket states.
+static POLL_WAKERS: Mutex<Vec<Waker>> = Mutex::new(Vec::new());
+
 struct SocketSetWrapper<'a>(Mutex<SocketSet<'a>>);
 
 struct DeviceWrapper {
@@ -120,7 +125,10 @@ impl<'a> SocketSetWrapper<'a> {
     }
 
     pub fn poll_interfaces(&self) {
-        ETH0.poll(&self.0);
+        if ETH0.poll(&self.0) {
+            let wakers = core::mem::take(&mut *POLL_WAKERS.lock());
+            wakers.into_iter().for_each(Waker::wake);
+        }
     }
 
     pub fn remove(&self, handle: SocketHandle) {
@@ -170,12 +178,13 @@ impl InterfaceWrapper {
         };
     }
 
-    pub fn poll(&self, sockets: &Mutex<SocketSet>) {
+    /// Returns whether the states of some sockets may have changed.
+    pub fn poll(&self, sockets: &Mutex<SocketSet>) -> bool {
         let mut dev = self.dev.lock();
         let mut iface = self.iface.lock();
         let mut sockets = sockets.lock();
         let timestamp = Self::current_time();
-        iface.poll(timestamp, dev.deref_mut(), &mut sockets);
+        iface.poll(timestamp, dev.deref_mut(), &mut sockets)
     }
 }
 
@@ -300,6 +309,15 @@ pub fn poll_interfaces() {
     SOCKET_SET.poll_interfaces();
 }
 
+/// Wakes up `waker` the next time polling the network interfaces, by any
+/// task, may have changed the states of the sockets.
+pub fn register_poll_waker(waker: &Waker) {
+    let mut wakers = POLL_WAKERS.lock();
+    if !wakers.iter().any(|w| w.will_wake(waker)) {
+        wakers.push(waker.clone());
+    }
+}
+
 /// Benchmark raw socket transmit bandwidth.
 pub fn bench_transmit() {
     ETH0.dev.lock().bench_transmit_bandwidth();
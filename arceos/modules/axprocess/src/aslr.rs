@@ -0,0 +1,70 @@
+//! Address space layout randomization (ASLR) of user programs.
+//!
+//! The layout of a program is decided when it is loaded. Which parts of it
+//! are randomized depends on the [`AslrLevel`], like
+//! `/proc/sys/kernel/randomize_va_space` of Linux. The initial level is taken
+//! from the `AX_ASLR` environment variable at build time (e.g., `make
+//! ASLR=0` for reproducible runs), and defaults to [`AslrLevel::Full`].
+//!
+//! Random offsets are taken from [`axhal::misc::random`].
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use memory_addr::PAGE_SIZE_4K;
+
+/// What is randomized in the layout of user programs.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AslrLevel {
+    /// Nothing is randomized, every run gets the same layout.
+    Off = 0,
+    /// The stack, the mmap base, and the load bases of position-independent
+    /// executables and interpreters are randomized.
+    Conservative = 1,
+    /// The heap (i.e., the initial program break) is randomized as well.
+    Full = 2,
+}
+
+impl AslrLevel {
+    const fn from_u8(level: u8) -> Self {
+        match level {
+            0 => Self::Off,
+            1 => Self::Conservative,
+            _ => Self::Full,
+        }
+    }
+}
+
+const fn default_level() -> AslrLevel {
+    match option_env!("AX_ASLR") {
+        Some(level) => match level.as_bytes() {
+            [b'0'] => AslrLevel::Off,
+            [b'1'] => AslrLevel::Conservative,
+            _ => AslrLevel::Full,
+        },
+        None => AslrLevel::Full,
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(default_level() as u8);
+
+/// Returns the current ASLR level.
+pub fn level() -> AslrLevel {
+    AslrLevel::from_u8(LEVEL.load(Ordering::Relaxed))
+}
+
+/// Sets the ASLR level, which takes effect on the programs loaded
+/// afterwards.
+pub fn set_level(level: AslrLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns a random page-aligned offset in `[0, range)` if the current level
+/// is at least `min_level`, or 0 otherwise.
+pub(crate) fn random_offset(range: usize, min_level: AslrLevel) -> usize {
+    if level() < min_level {
+        return 0;
+    }
+    let pages = range / PAGE_SIZE_4K;
+    (axhal::misc::random() as usize % pages) * PAGE_SIZE_4K
+}
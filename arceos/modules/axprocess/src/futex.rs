@@ -0,0 +1,212 @@
+//! Fast user-space locking (futex).
+//!
+//! A futex is identified by the address space and the user virtual address
+//! of the futex word, so futexes are private to the processes sharing the
+//! address space. Each waiter sleeps on its own [`WaitQueue`], waiters of the
+//! same futex are queued in FIFO order in a global table, which makes it easy
+//! to move them between futexes on requeue.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use core::mem::align_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use axerrno::{ax_err, AxResult};
+use axhal::mem::VirtAddr;
+use axmm::UserPtr;
+use axsync::spin::SpinNoIrq;
+use axtask::{current, TaskExtRef, WaitQueue};
+
+use crate::signal::wait_interruptible;
+
+/// Wait until the futex word changes.
+pub const FUTEX_WAIT: u32 = 0;
+/// Wake up waiters.
+pub const FUTEX_WAKE: u32 = 1;
+/// Wake up waiters and move the remaining ones to another futex.
+pub const FUTEX_REQUEUE: u32 = 3;
+/// Like [`FUTEX_REQUEUE`], but checks the futex word first.
+pub const FUTEX_CMP_REQUEUE: u32 = 4;
+/// Like [`FUTEX_WAIT`], with an absolute timeout and a bitset.
+pub const FUTEX_WAIT_BITSET: u32 = 9;
+/// Like [`FUTEX_WAKE`], only wakes waiters matching a bitset.
+pub const FUTEX_WAKE_BITSET: u32 = 10;
+/// The futex is not shared with other processes.
+pub const FUTEX_PRIVATE_FLAG: u32 = 128;
+/// The timeout is measured against `CLOCK_REALTIME`.
+pub const FUTEX_CLOCK_REALTIME: u32 = 256;
+/// The bitset matching all waiters.
+pub const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
+
+/// The address space (the address of its `Arc`) and the virtual address.
+type FutexKey = (usize, usize);
+
+struct FutexWaiter {
+    /// The futex the waiter is queued on, protected by the table lock.
+    key: SpinNoIrq<FutexKey>,
+    bitset: u32,
+    woken: AtomicBool,
+    wq: WaitQueue,
+}
+
+impl FutexWaiter {
+    fn wake(&self) {
+        self.woken.store(true, Ordering::Release);
+        self.wq.notify_one(false);
+    }
+}
+
+static FUTEX_TABLE: SpinNoIrq<BTreeMap<FutexKey, VecDeque<Arc<FutexWaiter>>>> =
+    SpinNoIrq::new(BTreeMap::new());
+
+fn futex_key(uaddr: VirtAddr) -> FutexKey {
+    let curr = current();
+    let aspace = Arc::as_ptr(&curr.task_ext().aspace) as usize;
+    (aspace, uaddr.as_usize())
+}
+
+fn read_futex_word(uaddr: VirtAddr) -> AxResult<u32> {
+    if uaddr.as_usize() % align_of::<u32>() != 0 {
+        return ax_err!(InvalidInput, "misaligned futex word");
+    }
+    let curr = current();
+    let mut aspace = curr.task_ext().aspace.lock();
+    UserPtr::new(uaddr.as_usize()).read(&mut aspace)
+}
+
+/// Removes the waiter from the table, returns `false` if it has already been
+/// woken up.
+fn remove_waiter(waiter: &Arc<FutexWaiter>) -> bool {
+    let mut table = FUTEX_TABLE.lock();
+    let key = *waiter.key.lock();
+    let Some(queue) = table.get_mut(&key) else {
+        return false;
+    };
+    let Some(idx) = queue.iter().position(|w| Arc::ptr_eq(w, waiter)) else {
+        return false;
+    };
+    queue.remove(idx);
+    if queue.is_empty() {
+        table.remove(&key);
+    }
+    true
+}
+
+/// Takes up to `count` waiters matching `bitset` from the futex `key`.
+fn take_waiters(
+    table: &mut BTreeMap<FutexKey, VecDeque<Arc<FutexWaiter>>>,
+    key: FutexKey,
+    count: usize,
+    bitset: u32,
+) -> VecDeque<Arc<FutexWaiter>> {
+    let mut taken = VecDeque::new();
+    if let Some(queue) = table.get_mut(&key) {
+        queue.retain(|w| {
+            if taken.len() < count && w.bitset & bitset != 0 {
+                taken.push_back(w.clone());
+                false
+            } else {
+                true
+            }
+        });
+        if queue.is_empty() {
+            table.remove(&key);
+        }
+    }
+    taken
+}
+
+/// Blocks the current task on the futex at `uaddr` if it still contains
+/// `val`, until it is woken up by a waker with a matching `bitset`, or the
+/// `timeout` elapses.
+///
+/// Returns [`WouldBlock`](axerrno::AxError::WouldBlock) if the futex word is
+/// not `val`, [`TimedOut`](axerrno::AxError::TimedOut) on timeout, or
+/// [`Interrupted`](axerrno::AxError::Interrupted) if a signal arrives while
+/// waiting.
+pub fn futex_wait(uaddr: VirtAddr, val: u32, timeout: Option<Duration>, bitset: u32) -> AxResult {
+    if bitset == 0 {
+        return ax_err!(InvalidInput, "empty futex bitset");
+    }
+    let key = futex_key(uaddr);
+    let waiter = Arc::new(FutexWaiter {
+        key: SpinNoIrq::new(key),
+        bitset,
+        woken: AtomicBool::new(false),
+        wq: WaitQueue::new(),
+    });
+    // Queue the waiter before checking the futex word, so that a wake after
+    // the check is never lost. The word is read without the table lock held
+    // since reading it may sleep on the address space lock.
+    FUTEX_TABLE
+        .lock()
+        .entry(key)
+        .or_default()
+        .push_back(waiter.clone());
+    match read_futex_word(uaddr) {
+        Ok(cur) if cur == val => {}
+        Ok(_) if remove_waiter(&waiter) => return ax_err!(WouldBlock),
+        Err(e) if remove_waiter(&waiter) => return Err(e),
+        // Already woken up.
+        _ => return Ok(()),
+    }
+
+    let res = wait_interruptible(&waiter.wq, timeout, || waiter.woken.load(Ordering::Acquire));
+    // A wake racing with the timeout or the signal still counts.
+    if res.is_err() && !remove_waiter(&waiter) {
+        return Ok(());
+    }
+    res
+}
+
+/// Wakes up at most `count` waiters matching `bitset` on the futex at
+/// `uaddr`, returns the number of woken waiters.
+pub fn futex_wake(uaddr: VirtAddr, count: usize, bitset: u32) -> usize {
+    let key = futex_key(uaddr);
+    let waiters = take_waiters(&mut FUTEX_TABLE.lock(), key, count, bitset);
+    for waiter in &waiters {
+        waiter.wake();
+    }
+    waiters.len()
+}
+
+/// Wakes up at most `wake_count` waiters on the futex at `uaddr`, and moves
+/// at most `requeue_count` of the remaining waiters to the futex at `uaddr2`.
+///
+/// If `cmp` is given, the futex word at `uaddr` is checked first, and
+/// [`WouldBlock`](axerrno::AxError::WouldBlock) is returned if it mismatches.
+///
+/// Returns the number of woken and requeued waiters.
+pub fn futex_requeue(
+    uaddr: VirtAddr,
+    wake_count: usize,
+    uaddr2: VirtAddr,
+    requeue_count: usize,
+    cmp: Option<u32>,
+) -> AxResult<usize> {
+    if let Some(cmp) = cmp {
+        if read_futex_word(uaddr)? != cmp {
+            return ax_err!(WouldBlock);
+        }
+    }
+    let key = futex_key(uaddr);
+    let key2 = futex_key(uaddr2);
+
+    let mut table = FUTEX_TABLE.lock();
+    let woken = take_waiters(&mut table, key, wake_count, FUTEX_BITSET_MATCH_ANY);
+    let requeued = take_waiters(&mut table, key, requeue_count, FUTEX_BITSET_MATCH_ANY);
+    let count = woken.len() + requeued.len();
+    if !requeued.is_empty() {
+        for waiter in &requeued {
+            *waiter.key.lock() = key2;
+        }
+        table.entry(key2).or_default().extend(requeued);
+    }
+    drop(table);
+
+    for waiter in &woken {
+        waiter.wake();
+    }
+    Ok(count)
+}
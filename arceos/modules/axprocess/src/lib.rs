@@ -0,0 +1,46 @@
+//! [ArceOS](https://github.com/arceos-org/arceos) process management module
+//! for monolithic kernels.
+//!
+//! It builds user processes on top of [`axtask`] tasks: each user task carries
+//! a [`TaskExt`] with its user context, address space and the [`Process`] it
+//! belongs to. Processes keep track of their parent and children, and provide
+//! the lifecycle operations needed by the `clone`, `execve`, `exit` and
+//! `wait4` syscalls. The memory mappings of a process are tracked by
+//! [`UserSpace`], and the file descriptor tables of
+//! [`arceos_posix_api`] are attached to user tasks. POSIX signals, futexes
+//! and shared memory are implemented in [`signal`], [`futex`] and [`shm`]
+//! respectively. The layout of user programs is randomized as configured in
+//! [`aslr`].
+//!
+//! The crate defines the task extended data with [`axtask::def_task_ext`], so
+//! kernels using it must not define their own.
+
+#![no_std]
+
+#[macro_use]
+extern crate log;
+extern crate alloc;
+
+mod loader;
+mod mm;
+mod process;
+mod task;
+
+pub mod aslr;
+pub mod futex;
+pub mod shm;
+pub mod signal;
+
+pub use self::loader::load_user_app;
+pub use self::mm::{MremapFlags, UserSpace, Vma, VmaBacking};
+pub use self::process::{find_process, Pid, Process, Thread};
+pub use self::task::{
+    clone_task, current_process, exec, exit_current, exit_group_current, spawn_user_task,
+    CloneFlags, TaskExt,
+};
+
+/// The size of the user stack of a new program.
+pub const USER_STACK_SIZE: usize = 0x10000;
+
+/// The size of the kernel stack of user tasks.
+pub const KERNEL_STACK_SIZE: usize = 0x40000; // 256 KiB
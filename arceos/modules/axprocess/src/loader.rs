@@ -0,0 +1,378 @@
+//! Loading ELF executables into user address spaces.
+//!
+//! Both static executables and position-independent executables (PIE) are
+//! supported. If the executable requests an interpreter (`PT_INTERP`), i.e.,
+//! the dynamic linker, the interpreter is loaded as well and started first,
+//! with the auxiliary vector telling it where the executable is.
+//!
+//! The load bases of PIEs and interpreters, the stack, the heap and the mmap
+//! base are randomized according to the [`aslr`](crate::aslr) level.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use axerrno::{ax_err, AxResult};
+use axfs::fops::{File, OpenOptions};
+use axhal::mem::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
+use axhal::paging::MappingFlags;
+use memory_addr::align_down;
+
+use crate::aslr::{self, AslrLevel};
+use crate::mm::{UserSpace, MMAP_BASE};
+use crate::signal::map_signal_trampoline;
+
+use elf::abi::{ET_DYN, PF_R, PF_W, PF_X, PT_INTERP, PT_LOAD, PT_PHDR};
+use elf::endian::AnyEndian;
+use elf::parse::ParseAt;
+use elf::segment::ProgramHeader;
+use elf::segment::SegmentTable;
+use elf::ElfBytes;
+
+const ELF_HEAD_BUF_SIZE: usize = 256;
+
+/// The lowest load base of position-independent executables.
+const PIE_BASE: usize = 0x4000_0000;
+/// The lowest load base of interpreters.
+const INTERP_BASE: usize = 0x20_0000_0000;
+/// The size of the range the load bases are randomized in.
+const LOAD_BASE_RANGE: usize = 0x1000_0000;
+/// The size of the range the mmap base is randomized in.
+const MMAP_BASE_RANGE: usize = 0x1_0000_0000;
+/// The size of the range the stack top is randomized in.
+const STACK_TOP_RANGE: usize = 0x100_0000;
+/// The size of the range the heap start is randomized in.
+const HEAP_START_RANGE: usize = 0x200_0000;
+
+// Auxiliary vector entry types.
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_FLAGS: usize = 8;
+const AT_ENTRY: usize = 9;
+const AT_UID: usize = 11;
+const AT_EUID: usize = 12;
+const AT_GID: usize = 13;
+const AT_EGID: usize = 14;
+const AT_SECURE: usize = 23;
+const AT_RANDOM: usize = 25;
+const AT_EXECFN: usize = 31;
+
+/// An opened ELF executable whose program headers have been parsed.
+pub(crate) struct ElfImage {
+    file: File,
+    phdrs: Vec<ProgramHeader>,
+    entry: usize,
+    is_dyn: bool,
+    phoff: u64,
+    phentsize: usize,
+}
+
+/// Where an ELF image has been loaded.
+struct LoadedImage {
+    /// The load bias, 0 for non-PIE executables.
+    base: usize,
+    entry: usize,
+    /// The address of the program headers, or 0 if they are not loaded.
+    phdr: usize,
+    /// The end of the highest segment.
+    end: VirtAddr,
+}
+
+fn read_exact_at(file: &File, mut offset: u64, mut buf: &mut [u8]) -> AxResult {
+    while !buf.is_empty() {
+        match file.read_at(offset, buf)? {
+            0 => return ax_err!(InvalidData, "unexpected end of ELF file"),
+            n => {
+                offset += n as u64;
+                buf = &mut buf[n..];
+            }
+        }
+    }
+    Ok(())
+}
+
+fn segment_flags(phdr: &ProgramHeader) -> MappingFlags {
+    let mut flags = MappingFlags::USER;
+    if phdr.p_flags & PF_R != 0 {
+        flags |= MappingFlags::READ;
+    }
+    if phdr.p_flags & PF_W != 0 {
+        flags |= MappingFlags::WRITE;
+    }
+    if phdr.p_flags & PF_X != 0 {
+        flags |= MappingFlags::EXECUTE;
+    }
+    flags
+}
+
+/// Returns a random page-aligned address in `[base, base + LOAD_BASE_RANGE)`,
+/// or `base` if load bases are not randomized.
+fn random_base(base: usize) -> usize {
+    base + aslr::random_offset(LOAD_BASE_RANGE, AslrLevel::Conservative)
+}
+
+impl ElfImage {
+    /// Opens the ELF file at `path` and parses its program headers.
+    pub fn open(path: &str) -> AxResult<Self> {
+        let mut opts = OpenOptions::new();
+        opts.read(true);
+        let file = File::open(path, &opts)?;
+
+        let mut buf = [0u8; ELF_HEAD_BUF_SIZE];
+        let len = file.read_at(0, &mut buf)?;
+        let ehdr = match ElfBytes::<AnyEndian>::parse_elf_header(&buf[..len]) {
+            Ok(ehdr) => ehdr,
+            Err(_) => return ax_err!(InvalidData, "invalid ELF header"),
+        };
+        info!("e_entry: {:#X}", ehdr.e_entry);
+
+        let phnum = ehdr.e_phnum as usize;
+        // Validate phentsize before trying to read the table so that we can error early for corrupted files
+        let size = match ProgramHeader::validate_entsize(ehdr.class, ehdr.e_phentsize as usize) {
+            Ok(entsize) => entsize.saturating_mul(phnum),
+            Err(_) => return ax_err!(InvalidData, "invalid ELF program header size"),
+        };
+        if size == 0 || size > PAGE_SIZE_4K {
+            return ax_err!(InvalidData, "invalid ELF program header table");
+        }
+        let mut buf = vec![0u8; size];
+        read_exact_at(&file, ehdr.e_phoff, &mut buf)?;
+        let phdrs = SegmentTable::new(ehdr.endianness, ehdr.class, &buf[..])
+            .iter()
+            .collect();
+
+        Ok(Self {
+            file,
+            phdrs,
+            entry: ehdr.e_entry as usize,
+            is_dyn: ehdr.e_type == ET_DYN,
+            phoff: ehdr.e_phoff,
+            phentsize: ehdr.e_phentsize as usize,
+        })
+    }
+
+    /// Returns the path of the interpreter requested by `PT_INTERP`, if any.
+    fn interp_path(&self) -> AxResult<Option<String>> {
+        let Some(phdr) = self.phdrs.iter().find(|phdr| phdr.p_type == PT_INTERP) else {
+            return Ok(None);
+        };
+        if phdr.p_filesz == 0 || phdr.p_filesz as usize > PAGE_SIZE_4K {
+            return ax_err!(InvalidData, "invalid ELF interpreter path");
+        }
+        let mut buf = vec![0u8; phdr.p_filesz as usize];
+        read_exact_at(&self.file, phdr.p_offset, &mut buf)?;
+        let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+        buf.truncate(len);
+        match String::from_utf8(buf) {
+            Ok(path) => Ok(Some(path)),
+            Err(_) => ax_err!(InvalidData, "invalid ELF interpreter path"),
+        }
+    }
+
+    /// Maps the loadable segments into `uspace` with the permissions in their
+    /// program headers.
+    ///
+    /// A PIE is loaded with the bias `base`, other executables are loaded at
+    /// their linked addresses.
+    fn load(&self, uspace: &mut UserSpace, base: usize) -> AxResult<LoadedImage> {
+        let base = if self.is_dyn { base } else { 0 };
+        let loads: Vec<_> = self
+            .phdrs
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD && phdr.p_memsz > 0)
+            .collect();
+
+        // Adjacent segments may share a page, which then gets the permissions
+        // of both.
+        let mut areas: Vec<(VirtAddr, VirtAddr, MappingFlags)> = Vec::new();
+        let mut image_end = VirtAddr::from(base);
+        for phdr in &loads {
+            let start = VirtAddr::from(base + phdr.p_vaddr as usize).align_down_4k();
+            let end = VirtAddr::from(base + (phdr.p_vaddr + phdr.p_memsz) as usize).align_up_4k();
+            let flags = segment_flags(phdr);
+            image_end = image_end.max(end);
+            match areas.last_mut() {
+                Some(last) if start < last.1 => {
+                    last.1 = last.1.max(end);
+                    last.2 |= flags;
+                }
+                _ => areas.push((start, end, flags)),
+            }
+        }
+        for (start, end, flags) in areas {
+            debug!(
+                "Mapping ELF segment: [{:#x?}, {:#x?}) {:?}",
+                start, end, flags
+            );
+            uspace.map_alloc(start, end - start, flags, true)?;
+        }
+
+        for phdr in &loads {
+            debug!(
+                "phdr: offset: {:#X}=>{:#X} size: {:#X}=>{:#X}",
+                phdr.p_offset, phdr.p_vaddr, phdr.p_filesz, phdr.p_memsz
+            );
+            // The frames are zero-filled, only the file contents are copied.
+            let mut data = vec![0u8; phdr.p_filesz as usize];
+            read_exact_at(&self.file, phdr.p_offset, &mut data)?;
+            uspace.write(VirtAddr::from(base + phdr.p_vaddr as usize), &data)?;
+        }
+
+        let phdr = match self.phdrs.iter().find(|phdr| phdr.p_type == PT_PHDR) {
+            Some(phdr) => base + phdr.p_vaddr as usize,
+            // Look for the segment containing the program header table.
+            None => loads
+                .iter()
+                .find(|phdr| (phdr.p_offset..phdr.p_offset + phdr.p_filesz).contains(&self.phoff))
+                .map_or(0, |phdr| {
+                    base + (phdr.p_vaddr + self.phoff - phdr.p_offset) as usize
+                }),
+        };
+        Ok(LoadedImage {
+            base,
+            entry: base + self.entry,
+            phdr,
+            end: image_end,
+        })
+    }
+}
+
+/// Loads the ELF executable at `path` and its interpreter into `uspace`, and
+/// sets up the user stack with the arguments, environment variables and the
+/// auxiliary vector. The heap is placed after the executable.
+///
+/// Returns the entry point and the initial user stack pointer.
+pub fn load_user_app(
+    uspace: &mut UserSpace,
+    path: &str,
+    args: &[String],
+    envs: &[String],
+) -> AxResult<(usize, VirtAddr)> {
+    load_elf(uspace, &ElfImage::open(path)?, path, args, envs)
+}
+
+/// Like [`load_user_app`], but for an executable that has been opened.
+pub(crate) fn load_elf(
+    uspace: &mut UserSpace,
+    elf: &ElfImage,
+    path: &str,
+    args: &[String],
+    envs: &[String],
+) -> AxResult<(usize, VirtAddr)> {
+    let stack_top = uspace.end() - aslr::random_offset(STACK_TOP_RANGE, AslrLevel::Conservative);
+    let mmap_base = MMAP_BASE + aslr::random_offset(MMAP_BASE_RANGE, AslrLevel::Conservative);
+    uspace.init_layout(stack_top, VirtAddr::from(mmap_base));
+
+    let app = elf.load(uspace, random_base(PIE_BASE))?;
+    uspace.init_heap(app.end + aslr::random_offset(HEAP_START_RANGE, AslrLevel::Full));
+    let (entry, interp_base) = match elf.interp_path()? {
+        Some(interp_path) => {
+            debug!("Loading ELF interpreter: {}", interp_path);
+            let interp = ElfImage::open(&interp_path)?;
+            if interp.interp_path()?.is_some() {
+                return ax_err!(InvalidData, "ELF interpreter requests an interpreter");
+            }
+            let interp = interp.load(uspace, random_base(INTERP_BASE))?;
+            (interp.entry, interp.base)
+        }
+        None => (app.entry, 0),
+    };
+
+    let auxv = [
+        (AT_PHDR, app.phdr),
+        (AT_PHENT, elf.phentsize),
+        (AT_PHNUM, elf.phdrs.len()),
+        (AT_PAGESZ, PAGE_SIZE_4K),
+        (AT_BASE, interp_base),
+        (AT_FLAGS, 0),
+        (AT_ENTRY, app.entry),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_SECURE, 0),
+    ];
+    let ustack_pointer = init_user_stack(uspace, path, args, envs, &auxv)?;
+    Ok((entry, ustack_pointer))
+}
+
+/// Maps the user stack below the stack top of `uspace`, and pushes the
+/// arguments, environment variables and the auxiliary vector onto it. The
+/// signal trampoline is mapped as well.
+///
+/// Returns the initial user stack pointer.
+fn init_user_stack(
+    uspace: &mut UserSpace,
+    path: &str,
+    args: &[String],
+    envs: &[String],
+    auxv: &[(usize, usize)],
+) -> AxResult<VirtAddr> {
+    let ustack_top = uspace.stack_top();
+    let ustack_vaddr = ustack_top - crate::USER_STACK_SIZE;
+    debug!(
+        "Mapping user stack: {:#x?} -> {:#x?}",
+        ustack_vaddr, ustack_top
+    );
+    uspace.map_alloc(
+        ustack_vaddr,
+        crate::USER_STACK_SIZE,
+        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER,
+        true,
+    )?;
+
+    map_signal_trampoline(uspace)?;
+
+    // The strings go to the top of the stack.
+    let mut sp = ustack_top.as_usize();
+    let mut push_str = |s: &str| -> AxResult<usize> {
+        if sp - ustack_vaddr.as_usize() < s.len() + 1 {
+            return ax_err!(NoMemory, "arguments too long");
+        }
+        sp -= s.len() + 1;
+        uspace.write(VirtAddr::from(sp), s.as_bytes())?;
+        uspace.write(VirtAddr::from(sp + s.len()), &[0])?;
+        Ok(sp)
+    };
+    let execfn = push_str(path)?;
+    let argv = args
+        .iter()
+        .map(|arg| push_str(arg))
+        .collect::<AxResult<Vec<_>>>()?;
+    let envp = envs
+        .iter()
+        .map(|env| push_str(env))
+        .collect::<AxResult<Vec<_>>>()?;
+
+    // 16 random bytes pointed to by `AT_RANDOM`.
+    sp = align_down(sp - 16, 16);
+    let random = sp;
+    uspace.write(VirtAddr::from(random), &axhal::misc::random().to_ne_bytes())?;
+
+    // argc, argv, envp and auxv, in this order from low to high addresses.
+    let mut items = Vec::with_capacity(argv.len() + envp.len() + auxv.len() * 2 + 9);
+    items.push(argv.len());
+    items.extend(argv);
+    items.push(0);
+    items.extend(envp);
+    items.push(0);
+    for &(key, val) in auxv {
+        items.extend([key, val]);
+    }
+    items.extend([AT_RANDOM, random, AT_EXECFN, execfn, AT_NULL, 0]);
+
+    let size = items.len() * size_of::<usize>();
+    if sp - ustack_vaddr.as_usize() < size + 16 {
+        return ax_err!(NoMemory, "arguments too long");
+    }
+    sp = align_down(sp - size, 16);
+    let data: Vec<u8> = items.iter().flat_map(|item| item.to_ne_bytes()).collect();
+    uspace.write(VirtAddr::from(sp), &data)?;
+
+    Ok(VirtAddr::from(sp))
+}
@@ -0,0 +1,613 @@
+//! User address spaces with virtual memory area (VMA) tracking.
+//!
+//! [`UserSpace`] wraps an [`AddrSpace`] and keeps a list of the areas mapped
+//! by the user program, which is what `mmap`, `munmap`, `mprotect`, `mremap`
+//! and `brk` operate on. Adjacent areas with the same permissions and
+//! backing are merged, and areas are split when only part of them is
+//! unmapped or protected.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+
+use axerrno::{ax_err, AxResult};
+use axfs::fops::File;
+use axhal::mem::{MemoryAddr, VirtAddr};
+use axhal::paging::MappingFlags;
+use axmm::{AddrSpace, SharedPages};
+use memory_addr::VirtAddrRange;
+
+bitflags::bitflags! {
+    /// Flags for `mremap`.
+    ///
+    /// See <https://man7.org/linux/man-pages/man2/mremap.2.html>
+    #[derive(Debug, Clone, Copy)]
+    pub struct MremapFlags: u32 {
+        /// The mapping may be moved to a new address.
+        const MREMAP_MAYMOVE = 1;
+        /// The mapping is moved to the given new address.
+        const MREMAP_FIXED = 2;
+    }
+}
+
+/// What a virtual memory area is backed by.
+#[derive(Clone)]
+pub enum VmaBacking {
+    /// Anonymous memory, zero-filled.
+    Anonymous,
+    /// A file mapping.
+    File {
+        /// The backing file.
+        file: Arc<File>,
+        /// The file offset corresponding to the start of the area.
+        offset: u64,
+        /// Whether modifications are visible in the file (`MAP_SHARED`).
+        shared: bool,
+    },
+    /// Shared memory, e.g., a SysV shared memory segment or a `memfd`.
+    Shared {
+        /// The shared pages.
+        pages: Arc<SharedPages>,
+        /// The byte offset in `pages` corresponding to the start of the area.
+        offset: usize,
+    },
+}
+
+impl VmaBacking {
+    /// Returns the backing of the part of an area starting `off` bytes after
+    /// the area start.
+    fn offset_by(&self, off: usize) -> Self {
+        match self {
+            Self::Anonymous => Self::Anonymous,
+            Self::File {
+                file,
+                offset,
+                shared,
+            } => Self::File {
+                file: file.clone(),
+                offset: offset + off as u64,
+                shared: *shared,
+            },
+            Self::Shared { pages, offset } => Self::Shared {
+                pages: pages.clone(),
+                offset: offset + off,
+            },
+        }
+    }
+}
+
+/// A virtual memory area, i.e., a range of pages with the same permissions
+/// and backing.
+#[derive(Clone)]
+pub struct Vma {
+    start: VirtAddr,
+    end: VirtAddr,
+    flags: MappingFlags,
+    backing: VmaBacking,
+}
+
+impl Vma {
+    /// Returns the start address of the area.
+    pub const fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    /// Returns the end address of the area (exclusive).
+    pub const fn end(&self) -> VirtAddr {
+        self.end
+    }
+
+    /// Returns the size of the area.
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Returns the permissions of the area.
+    pub const fn flags(&self) -> MappingFlags {
+        self.flags
+    }
+
+    /// Returns what the area is backed by.
+    pub const fn backing(&self) -> &VmaBacking {
+        &self.backing
+    }
+
+    /// Whether `next` directly follows this area and can be merged into it.
+    fn can_merge(&self, next: &Vma) -> bool {
+        if self.end != next.start || self.flags != next.flags {
+            return false;
+        }
+        match (&self.backing, &next.backing) {
+            (VmaBacking::Anonymous, VmaBacking::Anonymous) => true,
+            (
+                VmaBacking::File {
+                    file,
+                    offset,
+                    shared,
+                },
+                VmaBacking::File {
+                    file: next_file,
+                    offset: next_offset,
+                    shared: next_shared,
+                },
+            ) => {
+                Arc::ptr_eq(file, next_file)
+                    && shared == next_shared
+                    && offset + self.size() as u64 == *next_offset
+            }
+            (
+                VmaBacking::Shared { pages, offset },
+                VmaBacking::Shared {
+                    pages: next_pages,
+                    offset: next_offset,
+                },
+            ) => Arc::ptr_eq(pages, next_pages) && offset + self.size() == *next_offset,
+            _ => false,
+        }
+    }
+
+    /// Splits the area at `at`, this area keeps the lower part and the upper
+    /// part is returned.
+    fn split(&mut self, at: VirtAddr) -> Vma {
+        let upper = Vma {
+            start: at,
+            end: self.end,
+            flags: self.flags,
+            backing: self.backing.offset_by(at - self.start),
+        };
+        self.end = at;
+        upper
+    }
+}
+
+/// The lowest address for mappings without an address hint, before it is
+/// randomized by the loader.
+pub(crate) const MMAP_BASE: usize = 0x10_0000_0000;
+
+/// A user address space with its virtual memory areas and the program break.
+///
+/// Methods of the inner [`AddrSpace`] are available through [`Deref`], e.g.,
+/// for accessing user memory, but mappings must be changed through the
+/// methods of this type so that the areas are kept in sync.
+pub struct UserSpace {
+    aspace: AddrSpace,
+    /// Mapped areas, indexed by their start addresses.
+    vmas: BTreeMap<VirtAddr, Vma>,
+    /// The start of the heap, i.e., the initial program break.
+    heap_start: VirtAddr,
+    /// The current program break.
+    brk: VirtAddr,
+    /// The top of the user stack.
+    stack_top: VirtAddr,
+    /// The lowest address for mappings without an address hint.
+    mmap_base: VirtAddr,
+    /// The number of live tasks running in the address space.
+    users: usize,
+}
+
+impl UserSpace {
+    /// Creates a new empty user address space.
+    pub fn new() -> AxResult<Self> {
+        let aspace = axmm::new_user_aspace()?;
+        Ok(Self {
+            stack_top: aspace.end(),
+            aspace,
+            vmas: BTreeMap::new(),
+            heap_start: VirtAddr::from(0),
+            brk: VirtAddr::from(0),
+            mmap_base: VirtAddr::from(MMAP_BASE),
+            users: 0,
+        })
+    }
+
+    /// Creates a copy-on-write duplicate of the address space, e.g., for
+    /// `fork`.
+    pub fn fork(&mut self) -> AxResult<Self> {
+        Ok(Self {
+            aspace: axmm::fork_user_aspace(&mut self.aspace)?,
+            vmas: self.vmas.clone(),
+            heap_start: self.heap_start,
+            brk: self.brk,
+            stack_top: self.stack_top,
+            mmap_base: self.mmap_base,
+            users: 0,
+        })
+    }
+
+    /// Returns the number of live tasks running in the address space.
+    pub fn users(&self) -> usize {
+        self.users
+    }
+
+    /// Records a new task running in the address space.
+    pub(crate) fn add_user(&mut self) {
+        self.users += 1;
+    }
+
+    /// Records the exit of a task running in the address space.
+    pub(crate) fn remove_user(&mut self) {
+        self.users -= 1;
+    }
+
+    /// Returns all areas in ascending order of addresses.
+    pub fn vmas(&self) -> impl Iterator<Item = &Vma> {
+        self.vmas.values()
+    }
+
+    /// Returns the area containing `vaddr`.
+    pub fn find_vma(&self, vaddr: VirtAddr) -> Option<&Vma> {
+        self.vmas
+            .range(..=vaddr)
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vaddr < vma.end)
+    }
+
+    /// Whether `[start, end)` is fully covered by areas.
+    fn is_mapped(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        let mut next = start;
+        while next < end {
+            match self.find_vma(next) {
+                Some(vma) => next = vma.end,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Splits the area containing `at` so that an area starts at `at`.
+    fn split_at(&mut self, at: VirtAddr) {
+        let Some((_, vma)) = self.vmas.range_mut(..at).next_back() else {
+            return;
+        };
+        if at < vma.end {
+            let upper = vma.split(at);
+            self.vmas.insert(at, upper);
+        }
+    }
+
+    /// Merges the areas in `[start, end]` with their neighbours if possible.
+    fn merge_range(&mut self, start: VirtAddr, end: VirtAddr) {
+        let starts: Vec<_> = self.vmas.range(start..=end).map(|(&k, _)| k).collect();
+        for at in starts {
+            let Some((_, prev)) = self.vmas.range(..at).next_back() else {
+                continue;
+            };
+            if prev.can_merge(&self.vmas[&at]) {
+                let next = self.vmas.remove(&at).unwrap();
+                self.vmas.range_mut(..at).next_back().unwrap().1.end = next.end;
+            }
+        }
+    }
+
+    /// Records a new area and merges it with its neighbours.
+    fn insert_vma(&mut self, vma: Vma) {
+        let (start, end) = (vma.start, vma.end);
+        self.vmas.insert(start, vma);
+        self.merge_range(start, end);
+    }
+
+    /// Removes the areas in `[start, end)`, splitting partially covered ones.
+    fn remove_vmas(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.split_at(start);
+        self.split_at(end);
+        self.vmas.retain(|&k, _| k < start || k >= end);
+    }
+
+    /// Maps `[start, start + size)` with the given backing.
+    fn map_backing(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        backing: VmaBacking,
+    ) -> AxResult {
+        match &backing {
+            VmaBacking::Anonymous => self.aspace.map_alloc(start, size, flags, false)?,
+            VmaBacking::File {
+                file,
+                offset,
+                shared,
+            } => self
+                .aspace
+                .map_file(start, size, flags, file.clone(), *offset, *shared)?,
+            VmaBacking::Shared { pages, offset } => {
+                self.aspace
+                    .map_shared(start, size, flags, pages.clone(), *offset)?
+            }
+        }
+        self.insert_vma(Vma {
+            start,
+            end: start + size,
+            flags,
+            backing,
+        });
+        Ok(())
+    }
+
+    /// Adds a new anonymous mapping, see [`AddrSpace::map_alloc`].
+    pub fn map_alloc(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        populate: bool,
+    ) -> AxResult {
+        self.aspace.map_alloc(start, size, flags, populate)?;
+        self.insert_vma(Vma {
+            start,
+            end: start + size,
+            flags,
+            backing: VmaBacking::Anonymous,
+        });
+        Ok(())
+    }
+
+    /// Adds a new file mapping, see [`AddrSpace::map_file`].
+    pub fn map_file(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        file: Arc<File>,
+        offset: u64,
+        shared: bool,
+    ) -> AxResult {
+        self.map_backing(
+            start,
+            size,
+            flags,
+            VmaBacking::File {
+                file,
+                offset,
+                shared,
+            },
+        )
+    }
+
+    /// Adds a new shared memory mapping, see [`AddrSpace::map_shared`].
+    pub fn map_shared(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        pages: Arc<SharedPages>,
+        offset: usize,
+    ) -> AxResult {
+        self.map_backing(start, size, flags, VmaBacking::Shared { pages, offset })
+    }
+
+    /// Removes the mappings in `[start, start + size)`, which need not be
+    /// mapped, see [`AddrSpace::unmap`].
+    pub fn unmap(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        self.aspace.unmap(start, size)?;
+        self.remove_vmas(start, start + size);
+        Ok(())
+    }
+
+    /// Changes the permissions of `[start, start + size)`, like `mprotect`.
+    ///
+    /// Returns [`NoMemory`](axerrno::AxError::NoMemory) if the range is not
+    /// fully mapped.
+    pub fn protect(&mut self, start: VirtAddr, size: usize, flags: MappingFlags) -> AxResult {
+        let end = start + size;
+        if !self.is_mapped(start, end) {
+            return ax_err!(NoMemory, "range not fully mapped");
+        }
+        self.aspace.protect(start, size, flags)?;
+        self.split_at(start);
+        self.split_at(end);
+        for (_, vma) in self.vmas.range_mut(start..end) {
+            vma.flags = flags;
+        }
+        self.merge_range(start, end);
+        Ok(())
+    }
+
+    /// Writes modified pages back to files, see [`AddrSpace::sync`].
+    pub fn sync(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        self.aspace.sync(start, size)
+    }
+
+    /// Handles a page fault, see [`AddrSpace::handle_page_fault`].
+    pub fn handle_page_fault(&mut self, vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
+        self.aspace.handle_page_fault(vaddr, access_flags)
+    }
+
+    /// Removes all mappings and resets the program break and the layout.
+    pub fn clear(&mut self) {
+        self.aspace.clear();
+        self.vmas.clear();
+        self.heap_start = VirtAddr::from(0);
+        self.brk = VirtAddr::from(0);
+        self.stack_top = self.aspace.end();
+        self.mmap_base = VirtAddr::from(MMAP_BASE);
+    }
+
+    /// Returns the top of the user stack.
+    pub const fn stack_top(&self) -> VirtAddr {
+        self.stack_top
+    }
+
+    /// Returns the lowest address for mappings without an address hint.
+    pub const fn mmap_base(&self) -> VirtAddr {
+        self.mmap_base
+    }
+
+    /// Sets the top of the user stack and the mmap base, which are decided
+    /// by the loader.
+    pub(crate) fn init_layout(&mut self, stack_top: VirtAddr, mmap_base: VirtAddr) {
+        self.stack_top = stack_top;
+        self.mmap_base = mmap_base;
+    }
+
+    /// Finds a free area of `size` bytes for a new mapping, like `mmap`
+    /// without `MAP_FIXED`.
+    ///
+    /// The search starts from `hint`, or from the [mmap base](Self::mmap_base)
+    /// if `hint` is below it.
+    pub fn find_mmap_area(&self, hint: VirtAddr, size: usize) -> Option<VirtAddr> {
+        let limit = VirtAddrRange::from_start_size(self.aspace.base(), self.aspace.size());
+        self.aspace
+            .find_free_area(hint.max(self.mmap_base), size, limit)
+    }
+
+    /// Returns the start of the heap.
+    pub const fn heap_start(&self) -> VirtAddr {
+        self.heap_start
+    }
+
+    /// Returns the current program break.
+    pub const fn brk(&self) -> VirtAddr {
+        self.brk
+    }
+
+    /// Places the empty heap at `start`, which should be above the program
+    /// image.
+    pub(crate) fn init_heap(&mut self, start: VirtAddr) {
+        self.heap_start = start;
+        self.brk = start;
+    }
+
+    /// Moves the program break to `brk`, growing or shrinking the heap, like
+    /// `brk`.
+    ///
+    /// Returns the new program break, or the old one if it cannot be moved.
+    pub fn set_brk(&mut self, brk: VirtAddr) -> VirtAddr {
+        if brk < self.heap_start {
+            return self.brk;
+        }
+        let old_end = self.brk.align_up_4k();
+        let new_end = brk.align_up_4k();
+        let res = if new_end > old_end {
+            self.map_alloc(
+                old_end,
+                new_end - old_end,
+                MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER,
+                false,
+            )
+        } else if new_end < old_end {
+            self.unmap(new_end, old_end - new_end)
+        } else {
+            Ok(())
+        };
+        if res.is_ok() {
+            self.brk = brk;
+        }
+        self.brk
+    }
+
+    /// Moves the pages in `[start, start + size)` of `vma` to `new_start`,
+    /// and expands the moved mapping to `new_size` bytes.
+    ///
+    /// The expansion is mapped before anything is moved, and is unmapped
+    /// again if the move fails, so that on error the mapping is unchanged.
+    fn move_vma(
+        &mut self,
+        vma: &Vma,
+        start: VirtAddr,
+        size: usize,
+        new_start: VirtAddr,
+        new_size: usize,
+    ) -> AxResult {
+        if new_size > size {
+            let backing = vma.backing.offset_by(start - vma.start + size);
+            self.map_backing(new_start + size, new_size - size, vma.flags, backing)?;
+        }
+        if let Err(err) = self.aspace.move_range(start, size, new_start) {
+            if new_size > size {
+                self.unmap(new_start + size, new_size - size)?;
+            }
+            return Err(err);
+        }
+        self.remove_vmas(start, start + size);
+        self.insert_vma(Vma {
+            start: new_start,
+            end: new_start + size,
+            flags: vma.flags,
+            backing: vma.backing.offset_by(start - vma.start),
+        });
+        Ok(())
+    }
+
+    /// Expands or shrinks the mapping in `[start, start + old_size)`, and
+    /// possibly moves it, like `mremap`.
+    ///
+    /// The old range must lie within a single area. `new_start` is only used
+    /// with [`MREMAP_FIXED`](MremapFlags::MREMAP_FIXED). Returns the new start
+    /// address of the mapping.
+    pub fn remap(
+        &mut self,
+        start: VirtAddr,
+        old_size: usize,
+        new_size: usize,
+        flags: MremapFlags,
+        new_start: VirtAddr,
+    ) -> AxResult<VirtAddr> {
+        let may_move = flags.contains(MremapFlags::MREMAP_MAYMOVE);
+        let fixed = flags.contains(MremapFlags::MREMAP_FIXED);
+        if !start.is_aligned_4k() || old_size == 0 || new_size == 0 || (fixed && !may_move) {
+            return ax_err!(InvalidInput);
+        }
+        let old_size = old_size.align_up_4k();
+        let new_size = new_size.align_up_4k();
+        let old_end = start + old_size;
+        let vma = match self.find_vma(start) {
+            Some(vma) if old_end <= vma.end => vma.clone(),
+            _ => return ax_err!(BadAddress, "old range is not a single mapping"),
+        };
+
+        if fixed {
+            let new_range = VirtAddrRange::from_start_size(new_start, new_size);
+            if !new_start.is_aligned_4k() || new_range.overlaps(VirtAddrRange::new(start, old_end))
+            {
+                return ax_err!(InvalidInput);
+            }
+            self.unmap(new_start, new_size)?;
+            let moved = old_size.min(new_size);
+            self.move_vma(&vma, start, moved, new_start, new_size)?;
+            if new_size < old_size {
+                self.unmap(start + new_size, old_size - new_size)?;
+            }
+            return Ok(new_start);
+        }
+
+        if new_size <= old_size {
+            if new_size < old_size {
+                self.unmap(start + new_size, old_size - new_size)?;
+            }
+            return Ok(start);
+        }
+
+        // Try to expand in place first.
+        let backing = vma.backing.offset_by(start - vma.start + old_size);
+        let grow = new_size - old_size;
+        if self.map_backing(old_end, grow, vma.flags, backing).is_ok() {
+            return Ok(start);
+        }
+        if !may_move {
+            return ax_err!(NoMemory, "cannot expand the mapping in place");
+        }
+        let limit = VirtAddrRange::from_start_size(self.aspace.base(), self.aspace.size());
+        let Some(new_start) = self.aspace.find_free_area(start, new_size, limit) else {
+            return ax_err!(NoMemory, "no free area for the mapping");
+        };
+        self.move_vma(&vma, start, old_size, new_start, new_size)?;
+        Ok(new_start)
+    }
+}
+
+impl Deref for UserSpace {
+    type Target = AddrSpace;
+
+    fn deref(&self) -> &AddrSpace {
+        &self.aspace
+    }
+}
+
+impl DerefMut for UserSpace {
+    fn deref_mut(&mut self) -> &mut AddrSpace {
+        &mut self.aspace
+    }
+}
@@ -0,0 +1,321 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, Ordering};
+
+use axerrno::{ax_err, AxResult};
+use axsync::spin::SpinNoIrq;
+use axtask::WaitQueue;
+use lazyinit::LazyInit;
+
+use crate::signal::{wait_interruptible, wait_killable, ProcessSignals, ThreadSignals};
+
+/// Process ID type.
+///
+/// A process shares its ID with its first thread (the *leader*), in the same
+/// way as Linux.
+pub type Pid = u64;
+
+/// All processes that have not been reaped, indexed by their PIDs.
+static PROCESS_TABLE: SpinNoIrq<BTreeMap<Pid, Weak<Process>>> = SpinNoIrq::new(BTreeMap::new());
+
+/// The first process, which adopts orphaned processes.
+static INIT_PROC: LazyInit<Arc<Process>> = LazyInit::new();
+
+/// A thread of a process, i.e., the per-task state shared with the process.
+pub struct Thread {
+    tid: Pid,
+    pub(crate) signal: ThreadSignals,
+}
+
+impl Thread {
+    /// Returns the thread ID.
+    pub fn tid(&self) -> Pid {
+        self.tid
+    }
+}
+
+/// A process, i.e., a group of user tasks sharing the same process ID.
+///
+/// Processes form a tree. When a process exits, it becomes a *zombie* and
+/// stays in its parent's children list until the parent reaps it by
+/// [`Process::wait_child`]. Its own children are handed over to the init
+/// process.
+pub struct Process {
+    pid: Pid,
+    parent: SpinNoIrq<Weak<Process>>,
+    children: SpinNoIrq<Vec<Arc<Process>>>,
+    /// Live threads, indexed by their thread IDs.
+    threads: SpinNoIrq<BTreeMap<Pid, Arc<Thread>>>,
+    exit_code: AtomicI32,
+    /// The signal that terminated the process, or 0 if it exited normally.
+    term_signal: AtomicU32,
+    /// The signal sent to the parent on exit, or 0 for none.
+    exit_signal: u32,
+    /// Set by `exit_group`, the exit code of the last thread is ignored.
+    group_exiting: AtomicBool,
+    zombie: AtomicBool,
+    /// The thread running `execve`, whose sibling threads are exiting, or 0
+    /// for none.
+    exec_tid: AtomicU64,
+    /// Wait queue for waiting for children to exit.
+    child_exit_wq: WaitQueue,
+    /// Wait queue for waiting for threads to exit.
+    thread_exit_wq: WaitQueue,
+    pub(crate) signal: ProcessSignals,
+}
+
+impl Process {
+    /// Creates a new process without threads, and adds it to the children
+    /// of `parent`.
+    ///
+    /// The first process created without a parent becomes the init process.
+    pub(crate) fn new(
+        pid: Pid,
+        parent: Option<&Arc<Process>>,
+        exit_signal: u32,
+        signal: ProcessSignals,
+    ) -> Arc<Self> {
+        let proc = Arc::new(Self {
+            pid,
+            parent: SpinNoIrq::new(parent.map_or(Weak::new(), Arc::downgrade)),
+            children: SpinNoIrq::new(Vec::new()),
+            threads: SpinNoIrq::new(BTreeMap::new()),
+            exit_code: AtomicI32::new(0),
+            term_signal: AtomicU32::new(0),
+            exit_signal,
+            group_exiting: AtomicBool::new(false),
+            zombie: AtomicBool::new(false),
+            exec_tid: AtomicU64::new(0),
+            child_exit_wq: WaitQueue::new(),
+            thread_exit_wq: WaitQueue::new(),
+            signal,
+        });
+        match parent {
+            Some(parent) => parent.children.lock().push(proc.clone()),
+            None => {
+                if !INIT_PROC.is_inited() {
+                    INIT_PROC.init_once(proc.clone());
+                }
+            }
+        }
+        PROCESS_TABLE.lock().insert(pid, Arc::downgrade(&proc));
+        proc
+    }
+
+    /// Returns the process ID.
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Returns the parent process, or `None` if it has no parent.
+    pub fn parent(&self) -> Option<Arc<Process>> {
+        self.parent.lock().upgrade()
+    }
+
+    /// Returns the parent process ID, or 0 if it has no parent.
+    pub fn ppid(&self) -> Pid {
+        self.parent().map_or(0, |p| p.pid)
+    }
+
+    /// Returns the children of the process, including zombies.
+    pub fn children(&self) -> Vec<Arc<Process>> {
+        self.children.lock().clone()
+    }
+
+    /// Whether the process has exited but not been reaped yet.
+    pub fn is_zombie(&self) -> bool {
+        self.zombie.load(Ordering::Acquire)
+    }
+
+    /// Whether the process is exiting as a whole, e.g., by `exit_group` or
+    /// a fatal signal.
+    pub fn is_group_exiting(&self) -> bool {
+        self.group_exiting.load(Ordering::Acquire)
+    }
+
+    /// Whether the thread `tid` has to exit because another thread of the
+    /// process is running `execve`.
+    pub(crate) fn is_killed_by_exec(&self, tid: Pid) -> bool {
+        let exec_tid = self.exec_tid.load(Ordering::Acquire);
+        exec_tid != 0 && exec_tid != tid
+    }
+
+    /// Returns the exit code, only meaningful for zombies.
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code.load(Ordering::Acquire)
+    }
+
+    /// Returns the exit status in the format of `wait4`.
+    ///
+    /// See <https://man7.org/linux/man-pages/man2/wait.2.html>
+    pub fn wait_status(&self) -> i32 {
+        match self.term_signal.load(Ordering::Acquire) {
+            0 => (self.exit_code() & 0xff) << 8,
+            sig => (sig & 0x7f) as i32,
+        }
+    }
+
+    /// Returns the number of live threads.
+    pub fn thread_count(&self) -> usize {
+        self.threads.lock().len()
+    }
+
+    /// Returns the live thread with the given ID.
+    pub fn thread(&self, tid: Pid) -> Option<Arc<Thread>> {
+        self.threads.lock().get(&tid).cloned()
+    }
+
+    /// Returns all live threads.
+    pub fn threads(&self) -> Vec<Arc<Thread>> {
+        self.threads.lock().values().cloned().collect()
+    }
+
+    pub(crate) fn add_thread(&self, tid: Pid, signal: ThreadSignals) -> Arc<Thread> {
+        let thread = Arc::new(Thread { tid, signal });
+        self.threads.lock().insert(tid, thread.clone());
+        thread
+    }
+
+    /// Records the exit of a thread.
+    ///
+    /// The process exits when its last thread exits.
+    pub(crate) fn exit_thread(&self, tid: Pid, exit_code: i32) {
+        let mut threads = self.threads.lock();
+        threads.remove(&tid);
+        if threads.is_empty() {
+            drop(threads);
+            if !self.is_group_exiting() {
+                self.exit_code.store(exit_code, Ordering::Release);
+            }
+            self.exit();
+        } else {
+            drop(threads);
+            self.thread_exit_wq.notify_all(false);
+        }
+    }
+
+    /// Makes the thread `tid` the only thread of the process, for `execve`.
+    ///
+    /// The other threads exit when they are about to return to user space,
+    /// those blocked in syscalls are interrupted, and this waits until all
+    /// of them have exited. Like Linux, it only fails with
+    /// [`Interrupted`](axerrno::AxError::Interrupted) if the process is
+    /// exiting as a whole, or another thread has started `execve` first and
+    /// this thread is exiting.
+    pub(crate) fn dethread(&self, tid: Pid) -> AxResult {
+        if self
+            .exec_tid
+            .compare_exchange(0, tid, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return ax_err!(Interrupted);
+        }
+        self.interrupt_threads();
+        let res = wait_killable(&self.thread_exit_wq, || self.thread_count() == 1);
+        // No other thread is left to be killed, new threads are not.
+        self.exec_tid.store(0, Ordering::Release);
+        res
+    }
+
+    /// Marks the whole process as exiting with `exit_code`.
+    ///
+    /// The exit code takes effect when the last thread exits. Other threads
+    /// exit when they are about to return to user space, those blocked in
+    /// syscalls are interrupted.
+    pub(crate) fn set_group_exit(&self, exit_code: i32) {
+        if !self.group_exiting.swap(true, Ordering::AcqRel) {
+            self.exit_code.store(exit_code, Ordering::Release);
+            self.interrupt_threads();
+        }
+    }
+
+    /// Marks the whole process as terminated by the signal `sig`.
+    pub(crate) fn set_group_exit_signal(&self, sig: u32) {
+        if !self.group_exiting.swap(true, Ordering::AcqRel) {
+            self.term_signal.store(sig, Ordering::Release);
+            self.interrupt_threads();
+        }
+    }
+
+    /// Turns the process into a zombie, reparents its children to the init
+    /// process, and notifies the parent.
+    fn exit(&self) {
+        debug!(
+            "process {} exited with status {:#x}",
+            self.pid,
+            self.wait_status()
+        );
+        let children = core::mem::take(&mut *self.children.lock());
+        let init = INIT_PROC.get().filter(|init| init.pid != self.pid);
+        for child in children {
+            *child.parent.lock() = init.map_or(Weak::new(), Arc::downgrade);
+            match init {
+                Some(init) => init.children.lock().push(child),
+                // No one will reap it.
+                None => {
+                    PROCESS_TABLE.lock().remove(&child.pid);
+                }
+            }
+        }
+        if let Some(init) = init {
+            init.child_exit_wq.notify_all(false);
+        }
+
+        self.zombie.store(true, Ordering::Release);
+        match self.parent() {
+            Some(parent) => {
+                if self.exit_signal != 0 {
+                    parent.send_signal(self.exit_signal);
+                }
+                parent.child_exit_wq.notify_all(false);
+            }
+            None => {
+                PROCESS_TABLE.lock().remove(&self.pid);
+            }
+        }
+    }
+
+    /// Waits for a child process to exit and reaps it.
+    ///
+    /// If `pid` is `None`, any child may be reaped. Returns the PID and the
+    /// [wait status](Process::wait_status) of the reaped child, or `None` if
+    /// `nohang` is set and no child has exited yet.
+    ///
+    /// Returns [`NotFound`](axerrno::AxError::NotFound) if there is no such
+    /// child, or [`Interrupted`](axerrno::AxError::Interrupted) if a signal
+    /// arrives while waiting.
+    pub fn wait_child(&self, pid: Option<Pid>, nohang: bool) -> AxResult<Option<(Pid, i32)>> {
+        let matches = |child: &Arc<Process>| pid.map_or(true, |pid| child.pid == pid);
+        loop {
+            {
+                let mut children = self.children.lock();
+                if !children.iter().any(matches) {
+                    return ax_err!(NotFound, "no such child process");
+                }
+                if let Some(idx) = children
+                    .iter()
+                    .position(|child| matches(child) && child.is_zombie())
+                {
+                    let child = children.remove(idx);
+                    PROCESS_TABLE.lock().remove(&child.pid);
+                    return Ok(Some((child.pid, child.wait_status())));
+                }
+            }
+            if nohang {
+                return Ok(None);
+            }
+            wait_interruptible(&self.child_exit_wq, None, || {
+                self.children
+                    .lock()
+                    .iter()
+                    .any(|child| matches(child) && child.is_zombie())
+            })?;
+        }
+    }
+}
+
+/// Finds a process that has not been reaped by its PID.
+pub fn find_process(pid: Pid) -> Option<Arc<Process>> {
+    PROCESS_TABLE.lock().get(&pid).and_then(Weak::upgrade)
+}
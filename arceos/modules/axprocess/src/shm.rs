@@ -0,0 +1,283 @@
+//! Shared memory between user address spaces.
+//!
+//! Both SysV shared memory segments and `memfd` files are backed by
+//! [`SharedPages`], whose frames are mapped into every address space
+//! attaching them, so processes can exchange data without copying. Mappings
+//! of shared pages stay shared across `fork`.
+//!
+//! A segment removed with [`IPC_RMID`] can no longer be found by its key or
+//! ID, but its pages are only freed when the last mapping goes away.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use arceos_posix_api::{ctypes, FileLike};
+use axerrno::{ax_err, AxResult, LinuxError, LinuxResult};
+use axhal::mem::{MemoryAddr, VirtAddr};
+use axhal::paging::MappingFlags;
+use axio::PollState;
+use axmm::SharedPages;
+use axsync::spin::SpinNoIrq;
+use memory_addr::PAGE_SIZE_4K;
+
+use crate::{UserSpace, VmaBacking};
+
+/// The key to always create a new segment.
+pub const IPC_PRIVATE: i32 = 0;
+/// Create the segment if the key does not exist.
+pub const IPC_CREAT: i32 = 0o1000;
+/// Fail if the key already exists, used with [`IPC_CREAT`].
+pub const IPC_EXCL: i32 = 0o2000;
+/// Remove the segment.
+pub const IPC_RMID: i32 = 0;
+/// Attach the segment read-only.
+pub const SHM_RDONLY: i32 = 0o10000;
+/// Round the attach address down to [`SHMLBA`].
+pub const SHM_RND: i32 = 0o20000;
+/// The alignment of attach addresses.
+pub const SHMLBA: usize = PAGE_SIZE_4K;
+/// The maximum size of a segment.
+pub const SHMMAX: usize = 0x1000_0000; // 256 MiB
+
+struct ShmSegment {
+    key: i32,
+    size: usize,
+    pages: Arc<SharedPages>,
+}
+
+struct ShmTable {
+    segments: BTreeMap<i32, ShmSegment>,
+    next_id: i32,
+}
+
+static SHM_TABLE: SpinNoIrq<ShmTable> = SpinNoIrq::new(ShmTable {
+    segments: BTreeMap::new(),
+    next_id: 1,
+});
+
+/// Returns the ID of the segment of `key`, creating it with `size` bytes if
+/// necessary, like `shmget`.
+///
+/// Returns [`NotFound`](axerrno::AxError::NotFound) if the key does not
+/// exist without [`IPC_CREAT`], or
+/// [`AlreadyExists`](axerrno::AxError::AlreadyExists) if it exists with
+/// [`IPC_CREAT`] and [`IPC_EXCL`].
+pub fn shmget(key: i32, size: usize, shmflg: i32) -> AxResult<i32> {
+    let mut table = SHM_TABLE.lock();
+    if key != IPC_PRIVATE {
+        if let Some((&id, seg)) = table.segments.iter().find(|(_, seg)| seg.key == key) {
+            if shmflg & IPC_CREAT != 0 && shmflg & IPC_EXCL != 0 {
+                return ax_err!(AlreadyExists);
+            }
+            if size > seg.size {
+                return ax_err!(InvalidInput, "segment too small");
+            }
+            return Ok(id);
+        }
+        if shmflg & IPC_CREAT == 0 {
+            return ax_err!(NotFound);
+        }
+    }
+    if size == 0 || size > SHMMAX {
+        return ax_err!(InvalidInput, "invalid segment size");
+    }
+    let id = table.next_id;
+    table.next_id = id.checked_add(1).unwrap_or(1);
+    let pages = Arc::new(SharedPages::new(size.div_ceil(PAGE_SIZE_4K)));
+    table.segments.insert(id, ShmSegment { key, size, pages });
+    Ok(id)
+}
+
+/// Attaches the segment `shmid` to `aspace`, like `shmat`.
+///
+/// The segment is attached at `addr`, or at a free address found from the
+/// [mmap base](UserSpace::mmap_base) if `addr` is null. Returns the attach
+/// address.
+pub fn shmat(
+    aspace: &mut UserSpace,
+    shmid: i32,
+    addr: VirtAddr,
+    shmflg: i32,
+) -> AxResult<VirtAddr> {
+    let pages = match SHM_TABLE.lock().segments.get(&shmid) {
+        Some(seg) => seg.pages.clone(),
+        None => return ax_err!(InvalidInput, "no such segment"),
+    };
+    let size = pages.size();
+    let start = if addr.as_usize() == 0 {
+        match aspace.find_mmap_area(addr, size) {
+            Some(start) => start,
+            None => return ax_err!(NoMemory, "no free area for the segment"),
+        }
+    } else if shmflg & SHM_RND != 0 {
+        VirtAddr::from(addr.as_usize() & !(SHMLBA - 1))
+    } else if addr.as_usize() % SHMLBA == 0 {
+        addr
+    } else {
+        return ax_err!(InvalidInput, "unaligned attach address");
+    };
+
+    let mut flags = MappingFlags::READ | MappingFlags::USER;
+    if shmflg & SHM_RDONLY == 0 {
+        flags |= MappingFlags::WRITE;
+    }
+    aspace.map_shared(start, size, flags, pages, 0)?;
+    Ok(start)
+}
+
+/// Detaches the segment attached at `addr` from `aspace`, like `shmdt`.
+///
+/// The attachment is found from the areas mapping shared pages from `addr`
+/// on, so it is also detached if it has been partly unmapped or protected.
+pub fn shmdt(aspace: &mut UserSpace, addr: VirtAddr) -> AxResult {
+    let (pages, mut end) = match aspace.find_vma(addr) {
+        Some(vma) if vma.start() == addr => match vma.backing() {
+            VmaBacking::Shared { pages, offset: 0 } => (pages.clone(), vma.end()),
+            _ => return ax_err!(InvalidInput, "no segment attached"),
+        },
+        _ => return ax_err!(InvalidInput, "no segment attached"),
+    };
+    // Areas of the same attachment map the pages at their distance from
+    // `addr`.
+    while let Some(vma) = aspace.find_vma(end) {
+        match vma.backing() {
+            VmaBacking::Shared { pages: p, offset }
+                if Arc::ptr_eq(p, &pages) && *offset == end - addr =>
+            {
+                end = vma.end();
+            }
+            _ => break,
+        }
+    }
+    aspace.unmap(addr, end - addr)
+}
+
+/// Removes the segment `shmid`, like `shmctl(IPC_RMID)`.
+///
+/// Other commands are not supported.
+pub fn shmctl(shmid: i32, cmd: i32) -> AxResult {
+    if cmd != IPC_RMID {
+        return ax_err!(Unsupported);
+    }
+    match SHM_TABLE.lock().segments.remove(&shmid) {
+        Some(_) => Ok(()),
+        None => ax_err!(InvalidInput, "no such segment"),
+    }
+}
+
+/// An anonymous file in memory created by `memfd_create`.
+///
+/// Its contents are [`SharedPages`], so all the mappings of the file (see
+/// [`MemFd::pages`]) and its `read`/`write` see the same data. The file
+/// size is kept in bytes, and is changed with [`MemFd::truncate`] or by
+/// writing beyond the end.
+pub struct MemFd {
+    /// The name given to `memfd_create`, with the `memfd:` prefix.
+    name: String,
+    pages: Arc<SharedPages>,
+    /// The file size and the current position.
+    state: SpinNoIrq<(usize, usize)>,
+}
+
+impl MemFd {
+    /// Creates an empty file named `name`.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: format!("memfd:{}", name),
+            pages: Arc::new(SharedPages::new(0)),
+            state: SpinNoIrq::new((0, 0)),
+        }
+    }
+
+    /// Returns the name of the file as shown in `/proc/self/fd`, e.g.
+    /// `memfd:foo`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the pages of the file, for mapping it.
+    pub fn pages(&self) -> &Arc<SharedPages> {
+        &self.pages
+    }
+
+    /// Returns the file size.
+    pub fn size(&self) -> usize {
+        self.state.lock().0
+    }
+
+    /// Sets the file size to `size`, the pages are grown or shrunk
+    /// accordingly.
+    pub fn truncate(&self, size: usize) -> AxResult {
+        if size > SHMMAX {
+            return ax_err!(InvalidInput, "file too large");
+        }
+        let mut state = self.state.lock();
+        self.pages.resize(size.div_ceil(PAGE_SIZE_4K));
+        if size < state.0 {
+            // Clear the tail of the last page, so growing again reads zeros.
+            let tail = size.align_up_4k().min(state.0) - size;
+            self.pages.write_at(size, &[0; PAGE_SIZE_4K][..tail]);
+        }
+        state.0 = size;
+        Ok(())
+    }
+}
+
+impl FileLike for MemFd {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        let mut state = self.state.lock();
+        let (size, pos) = *state;
+        let len = buf.len().min(size.saturating_sub(pos));
+        let n = self.pages.read_at(pos, &mut buf[..len]);
+        state.1 += n;
+        Ok(n)
+    }
+
+    fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        let mut state = self.state.lock();
+        let (size, pos) = *state;
+        let end = pos.checked_add(buf.len()).ok_or(LinuxError::EFBIG)?;
+        if end > SHMMAX {
+            return Err(LinuxError::EFBIG);
+        }
+        if end > self.pages.size() {
+            self.pages.resize(end.div_ceil(PAGE_SIZE_4K));
+        }
+        let n = self.pages.write_at(pos, buf);
+        if n == 0 && !buf.is_empty() {
+            return Err(LinuxError::ENOMEM);
+        }
+        *state = (size.max(pos + n), pos + n);
+        Ok(n)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        let size = self.size();
+        Ok(ctypes::stat {
+            st_ino: 1,
+            st_nlink: 1,
+            st_mode: 0o100000 | 0o777, // S_IFREG | rwxrwxrwx
+            st_size: size as _,
+            st_blksize: PAGE_SIZE_4K as _,
+            st_blocks: size.div_ceil(512) as _,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: true,
+            writable: true,
+        })
+    }
+
+    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+        Ok(())
+    }
+}
@@ -0,0 +1,677 @@
+//! POSIX signals.
+//!
+//! Signals are recorded as pending in the target thread or process, and are
+//! delivered when a thread of the process is about to return to user space
+//! (see [`axhal::trap::RETURN_TO_USER`]). A signal with a user handler is
+//! delivered by pushing a signal frame onto the user stack and redirecting
+//! the user context to the handler, which returns to the kernel through the
+//! signal trampoline and `rt_sigreturn`. The layout of the signal frame and
+//! the trampoline code are architecture-specific.
+
+use alloc::sync::Arc;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use axerrno::{ax_err, AxResult};
+use axhal::arch::TrapFrame;
+use axhal::mem::{VirtAddr, PAGE_SIZE_4K};
+use axhal::paging::MappingFlags;
+use axhal::trap::{register_trap_handler, RETURN_TO_USER};
+use axmm::UserPtr;
+use axsync::spin::SpinNoIrq;
+use axtask::{current, TaskExtRef, WaitQueue};
+
+use crate::mm::UserSpace;
+use crate::process::{find_process, Pid, Process, Thread};
+use crate::task::TaskExt;
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        mod x86_64;
+        use self::x86_64 as arch;
+    } else if #[cfg(target_arch = "aarch64")] {
+        mod aarch64;
+        use self::aarch64 as arch;
+    } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+        mod riscv;
+        use self::riscv as arch;
+    }
+}
+
+/// The number of supported signals, signal numbers are in `1..=SIGNAL_MAX`.
+pub const SIGNAL_MAX: u32 = 64;
+
+// Standard signal numbers.
+pub const SIGHUP: u32 = 1;
+pub const SIGINT: u32 = 2;
+pub const SIGQUIT: u32 = 3;
+pub const SIGILL: u32 = 4;
+pub const SIGTRAP: u32 = 5;
+pub const SIGABRT: u32 = 6;
+pub const SIGBUS: u32 = 7;
+pub const SIGFPE: u32 = 8;
+pub const SIGKILL: u32 = 9;
+pub const SIGUSR1: u32 = 10;
+pub const SIGSEGV: u32 = 11;
+pub const SIGUSR2: u32 = 12;
+pub const SIGPIPE: u32 = 13;
+pub const SIGALRM: u32 = 14;
+pub const SIGTERM: u32 = 15;
+pub const SIGSTKFLT: u32 = 16;
+pub const SIGCHLD: u32 = 17;
+pub const SIGCONT: u32 = 18;
+pub const SIGSTOP: u32 = 19;
+pub const SIGTSTP: u32 = 20;
+pub const SIGTTIN: u32 = 21;
+pub const SIGTTOU: u32 = 22;
+pub const SIGURG: u32 = 23;
+pub const SIGXCPU: u32 = 24;
+pub const SIGXFSZ: u32 = 25;
+pub const SIGVTALRM: u32 = 26;
+pub const SIGPROF: u32 = 27;
+pub const SIGWINCH: u32 = 28;
+pub const SIGIO: u32 = 29;
+pub const SIGPWR: u32 = 30;
+pub const SIGSYS: u32 = 31;
+
+/// Default signal handler.
+pub const SIG_DFL: usize = 0;
+/// Ignore the signal.
+pub const SIG_IGN: usize = 1;
+
+/// The handler takes three arguments (`siginfo_t` and `ucontext_t`).
+pub const SA_SIGINFO: usize = 0x4;
+/// Do not block the signal while its handler is running.
+pub const SA_NODEFER: usize = 0x4000_0000;
+/// The handler returns to `sa_restorer` instead of the signal trampoline.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub const SA_RESTORER: usize = 0x0400_0000;
+/// Reset the handler to the default one after delivering the signal.
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+/// Add the signals to the blocked set, for `rt_sigprocmask`.
+pub const SIG_BLOCK: i32 = 0;
+/// Remove the signals from the blocked set, for `rt_sigprocmask`.
+pub const SIG_UNBLOCK: i32 = 1;
+/// Replace the blocked set, for `rt_sigprocmask`.
+pub const SIG_SETMASK: i32 = 2;
+
+/// A set of signals, bit `n - 1` stands for the signal `n`.
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SignalSet(pub u64);
+
+impl SignalSet {
+    /// Signals that cannot be caught, blocked or ignored.
+    const UNBLOCKABLE: Self = Self(1 << (SIGKILL - 1) | 1 << (SIGSTOP - 1));
+
+    /// Creates an empty set.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Whether the set contains the signal `sig`.
+    pub const fn contains(&self, sig: u32) -> bool {
+        self.0 & (1 << (sig - 1)) != 0
+    }
+
+    /// Adds the signal `sig` to the set.
+    pub fn add(&mut self, sig: u32) {
+        self.0 |= 1 << (sig - 1);
+    }
+
+    /// Removes the signal `sig` from the set.
+    pub fn remove(&mut self, sig: u32) {
+        self.0 &= !(1 << (sig - 1));
+    }
+
+    const fn blockable(self) -> Self {
+        Self(self.0 & !Self::UNBLOCKABLE.0)
+    }
+}
+
+/// The action taken on a signal, in the layout of the kernel
+/// `struct sigaction`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SigAction {
+    /// The handler address, or [`SIG_DFL`] or [`SIG_IGN`].
+    pub handler: usize,
+    /// `SA_*` flags.
+    pub flags: usize,
+    /// The address the handler returns to with [`SA_RESTORER`].
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub restorer: usize,
+    /// Signals blocked while the handler is running.
+    pub mask: SignalSet,
+}
+
+impl SigAction {
+    /// Whether the signal `sig` is discarded on delivery with this action.
+    fn ignores(&self, sig: u32) -> bool {
+        self.handler == SIG_IGN || (self.handler == SIG_DFL && default_ignored(sig))
+    }
+
+    /// Returns `sa_restorer` if the handler should return to it.
+    fn restorer(&self) -> Option<usize> {
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        if self.flags & SA_RESTORER != 0 {
+            return Some(self.restorer);
+        }
+        None
+    }
+}
+
+/// The `siginfo_t` passed to handlers with [`SA_SIGINFO`].
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _pad: [u8; 116],
+}
+
+/// `stack_t`, alternate signal stacks are not supported.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SignalStack {
+    sp: usize,
+    flags: i32,
+    size: usize,
+}
+
+/// The signal actions, which may be shared by processes created with
+/// `CLONE_SIGHAND`.
+type SigActions = SpinNoIrq<[SigAction; SIGNAL_MAX as usize]>;
+
+/// Per-process signal states.
+pub(crate) struct ProcessSignals {
+    actions: SpinNoIrq<Arc<SigActions>>,
+    /// Signals sent to the whole process.
+    pending: AtomicU64,
+}
+
+impl ProcessSignals {
+    pub fn new() -> Self {
+        Self {
+            actions: SpinNoIrq::new(Arc::new(SpinNoIrq::new(
+                [SigAction::default(); SIGNAL_MAX as usize],
+            ))),
+            pending: AtomicU64::new(0),
+        }
+    }
+
+    /// Creates the states of a child process, which shares the actions with
+    /// this process if `share_actions` is set, or gets a copy of them
+    /// otherwise.
+    pub fn fork(&self, share_actions: bool) -> Self {
+        let actions = self.actions();
+        let actions = if share_actions {
+            actions
+        } else {
+            Arc::new(SpinNoIrq::new(*actions.lock()))
+        };
+        Self {
+            actions: SpinNoIrq::new(actions),
+            pending: AtomicU64::new(0),
+        }
+    }
+
+    fn actions(&self) -> Arc<SigActions> {
+        self.actions.lock().clone()
+    }
+
+    /// Resets caught signals to their default actions, on `execve`.
+    ///
+    /// The process stops sharing the actions with other processes.
+    pub fn reset_handlers(&self) {
+        let mut actions = *self.actions().lock();
+        for action in actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+        *self.actions.lock() = Arc::new(SpinNoIrq::new(actions));
+    }
+}
+
+/// Per-thread signal states.
+pub(crate) struct ThreadSignals {
+    /// Signals sent to the thread.
+    pending: AtomicU64,
+    blocked: AtomicU64,
+    /// The context to restore on the next return to user space, set by
+    /// `rt_sigreturn`.
+    saved_context: SpinNoIrq<Option<TrapFrame>>,
+    /// The wait queue the thread is blocked on in [`wait_interruptible`].
+    waiting_on: SpinNoIrq<WaitingOn>,
+}
+
+/// A wait queue pointer, valid while it is set in
+/// [`ThreadSignals::waiting_on`].
+struct WaitingOn(Option<NonNull<WaitQueue>>);
+
+unsafe impl Send for WaitingOn {}
+
+impl ThreadSignals {
+    pub fn new(blocked: SignalSet) -> Self {
+        Self {
+            pending: AtomicU64::new(0),
+            blocked: AtomicU64::new(blocked.0),
+            saved_context: SpinNoIrq::new(None),
+            waiting_on: SpinNoIrq::new(WaitingOn(None)),
+        }
+    }
+
+    /// Wakes up the thread if it is in [`wait_interruptible`], so that it
+    /// notices a new signal.
+    fn interrupt(&self) {
+        if let Some(wq) = self.waiting_on.lock().0 {
+            // Other waiters on the queue recheck their conditions and go
+            // back to sleep.
+            unsafe { wq.as_ref() }.notify_all(false);
+        }
+    }
+
+    pub fn blocked(&self) -> SignalSet {
+        SignalSet(self.blocked.load(Ordering::Acquire))
+    }
+
+    fn set_blocked(&self, set: SignalSet) {
+        self.blocked.store(set.blockable().0, Ordering::Release);
+    }
+}
+
+fn check_signal(sig: u32) -> AxResult {
+    if sig == 0 || sig > SIGNAL_MAX {
+        return ax_err!(InvalidInput, "invalid signal number");
+    }
+    Ok(())
+}
+
+/// Takes a pending signal, bit `sig - 1` is cleared if it was set.
+fn take_pending(pending: &AtomicU64, sig: u32) -> bool {
+    let bit = 1 << (sig - 1);
+    pending.fetch_and(!bit, Ordering::AcqRel) & bit != 0
+}
+
+/// Takes the lowest unblocked pending signal, thread-directed ones first.
+fn dequeue_signal(thread: &Thread, proc: &Process) -> Option<u32> {
+    let blocked = thread.signal.blocked().0;
+    for pending in [&thread.signal.pending, &proc.signal.pending] {
+        loop {
+            let deliverable = pending.load(Ordering::Acquire) & !blocked;
+            if deliverable == 0 {
+                break;
+            }
+            let sig = deliverable.trailing_zeros() + 1;
+            if take_pending(pending, sig) {
+                return Some(sig);
+            }
+        }
+    }
+    None
+}
+
+/// Whether the thread has an unblocked pending signal that is not ignored, or
+/// the process is exiting, either of which interrupts blocking syscalls.
+fn has_pending_signal(thread: &Thread, proc: &Process) -> bool {
+    if is_exiting(thread, proc) {
+        return true;
+    }
+    let mut pending = (thread.signal.pending.load(Ordering::Acquire)
+        | proc.signal.pending.load(Ordering::Acquire))
+        & !thread.signal.blocked().0;
+    if pending == 0 {
+        return false;
+    }
+    let actions = proc.signal.actions();
+    let actions = actions.lock();
+    while pending != 0 {
+        let sig = pending.trailing_zeros() + 1;
+        if !actions[sig as usize - 1].ignores(sig) {
+            return true;
+        }
+        pending &= pending - 1;
+    }
+    false
+}
+
+/// Whether the thread has to exit, since the process is exiting as a whole
+/// or another thread is running `execve`.
+fn is_exiting(thread: &Thread, proc: &Process) -> bool {
+    proc.is_group_exiting() || proc.is_killed_by_exec(thread.tid())
+}
+
+/// Whether the default action of the signal is to ignore it.
+///
+/// Job control is not supported, so stop signals are ignored as well.
+fn default_ignored(sig: u32) -> bool {
+    matches!(
+        sig,
+        SIGCHLD | SIGCONT | SIGURG | SIGWINCH | SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU
+    )
+}
+
+impl Process {
+    /// Sends the signal `sig` to the process, which can be handled by any of
+    /// its threads.
+    pub fn send_signal(&self, sig: u32) {
+        if sig == 0 || self.is_zombie() {
+            return;
+        }
+        self.signal
+            .pending
+            .fetch_or(1 << (sig - 1), Ordering::AcqRel);
+        self.interrupt_threads();
+    }
+
+    /// Wakes up the threads blocked in [`wait_interruptible`], e.g., on a
+    /// signal for the process or when the process is exiting.
+    pub(crate) fn interrupt_threads(&self) {
+        for thread in self.threads() {
+            thread.signal.interrupt();
+        }
+    }
+}
+
+impl Thread {
+    /// Sends the signal `sig` to the thread.
+    pub fn send_signal(&self, sig: u32) {
+        if sig == 0 {
+            return;
+        }
+        self.signal
+            .pending
+            .fetch_or(1 << (sig - 1), Ordering::AcqRel);
+        self.signal.interrupt();
+    }
+}
+
+/// Blocks the current thread on `wq` until `condition` becomes true, or the
+/// `timeout` (if any) elapses.
+///
+/// The wait is interrupted when the thread gets a signal to handle, or the
+/// process is exiting. Returns [`Interrupted`](axerrno::AxError::Interrupted)
+/// in that case, so that the syscall fails with `EINTR` and the signal is
+/// handled on the way back to user space, or
+/// [`TimedOut`](axerrno::AxError::TimedOut) on timeout. Kernel tasks are
+/// never interrupted.
+pub(crate) fn wait_interruptible<F>(
+    wq: &WaitQueue,
+    timeout: Option<Duration>,
+    condition: F,
+) -> AxResult
+where
+    F: Fn() -> bool,
+{
+    wait_signaled(wq, timeout, condition, has_pending_signal)
+}
+
+/// Like [`wait_interruptible`] without a timeout, but the wait is only
+/// interrupted when the thread has to exit, not by signals it can handle.
+pub(crate) fn wait_killable<F>(wq: &WaitQueue, condition: F) -> AxResult
+where
+    F: Fn() -> bool,
+{
+    wait_signaled(wq, None, condition, is_exiting)
+}
+
+fn wait_signaled<F>(
+    wq: &WaitQueue,
+    timeout: Option<Duration>,
+    condition: F,
+    interrupts: fn(&Thread, &Process) -> bool,
+) -> AxResult
+where
+    F: Fn() -> bool,
+{
+    let curr = current();
+    if unsafe { curr.task_ext_ptr() }.is_null() {
+        let timed_out = match timeout {
+            Some(dur) => wq.wait_timeout_until(dur, condition),
+            None => {
+                wq.wait_until(condition);
+                false
+            }
+        };
+        return if timed_out { ax_err!(TimedOut) } else { Ok(()) };
+    }
+
+    let ext = curr.task_ext();
+    let signal = &ext.thread.signal;
+    let interrupted = || interrupts(&ext.thread, &ext.proc);
+    // A signal sent after this either finds the queue here, or is seen by
+    // the first check of the condition.
+    signal.waiting_on.lock().0 = Some(NonNull::from(wq));
+    let wake_up = || condition() || interrupted();
+    let timed_out = match timeout {
+        Some(dur) => wq.wait_timeout_until(dur, wake_up),
+        None => {
+            wq.wait_until(wake_up);
+            false
+        }
+    };
+    signal.waiting_on.lock().0 = None;
+
+    if condition() {
+        Ok(())
+    } else if interrupted() {
+        ax_err!(Interrupted)
+    } else if timed_out {
+        ax_err!(TimedOut)
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the address of the signal trampoline in `uspace`, which is right
+/// below the user stack.
+fn trampoline_addr(uspace: &UserSpace) -> VirtAddr {
+    uspace.stack_top() - crate::USER_STACK_SIZE - PAGE_SIZE_4K
+}
+
+/// Maps the signal trampoline, which calls `rt_sigreturn` when a signal
+/// handler returns.
+pub(crate) fn map_signal_trampoline(uspace: &mut UserSpace) -> AxResult {
+    let addr = trampoline_addr(uspace);
+    uspace.map_alloc(
+        addr,
+        PAGE_SIZE_4K,
+        MappingFlags::READ | MappingFlags::EXECUTE | MappingFlags::USER,
+        true,
+    )?;
+    uspace.write(addr, arch::SIGRETURN_CODE)
+}
+
+/// Examines and changes the action of the signal `sig` of the current
+/// process, returns the old action.
+pub fn sigaction(sig: u32, act: Option<SigAction>) -> AxResult<SigAction> {
+    check_signal(sig)?;
+    let curr = current();
+    let actions = curr.task_ext().proc.signal.actions();
+    let mut actions = actions.lock();
+    let old = actions[sig as usize - 1];
+    if let Some(mut act) = act {
+        if SignalSet::UNBLOCKABLE.contains(sig) {
+            return ax_err!(
+                InvalidInput,
+                "cannot change the action of SIGKILL or SIGSTOP"
+            );
+        }
+        act.mask = act.mask.blockable();
+        actions[sig as usize - 1] = act;
+    }
+    Ok(old)
+}
+
+/// Examines and changes the blocked signals of the current thread, returns
+/// the old set.
+pub fn sigprocmask(how: i32, set: Option<SignalSet>) -> AxResult<SignalSet> {
+    let curr = current();
+    let signal = &curr.task_ext().thread.signal;
+    let old = signal.blocked();
+    if let Some(set) = set {
+        let new = match how {
+            SIG_BLOCK => SignalSet(old.0 | set.0),
+            SIG_UNBLOCK => SignalSet(old.0 & !set.0),
+            SIG_SETMASK => set,
+            _ => return ax_err!(InvalidInput, "invalid sigprocmask operation"),
+        };
+        signal.set_blocked(new);
+    }
+    Ok(old)
+}
+
+/// Returns the pending signals of the current thread, including those sent
+/// to the process.
+pub fn sigpending() -> SignalSet {
+    let curr = current();
+    let ext = curr.task_ext();
+    SignalSet(
+        ext.thread.signal.pending.load(Ordering::Acquire)
+            | ext.proc.signal.pending.load(Ordering::Acquire),
+    )
+}
+
+/// Sends the signal `sig` to the process `pid`.
+///
+/// If `sig` is 0, only checks the existence of the process.
+pub fn send_signal_to_process(pid: Pid, sig: u32) -> AxResult {
+    if sig != 0 {
+        check_signal(sig)?;
+    }
+    match find_process(pid) {
+        Some(proc) if !proc.is_zombie() => {
+            proc.send_signal(sig);
+            Ok(())
+        }
+        _ => ax_err!(NotFound, "no such process"),
+    }
+}
+
+/// Sends the signal `sig` to the thread `tid` of the process `pid`.
+///
+/// If `sig` is 0, only checks the existence of the thread.
+pub fn send_signal_to_thread(pid: Pid, tid: Pid, sig: u32) -> AxResult {
+    if sig != 0 {
+        check_signal(sig)?;
+    }
+    match find_process(pid).and_then(|proc| proc.thread(tid)) {
+        Some(thread) => {
+            thread.send_signal(sig);
+            Ok(())
+        }
+        None => ax_err!(NotFound, "no such thread"),
+    }
+}
+
+/// Sends the signal `sig` to the current thread, which can be neither
+/// blocked nor ignored, e.g., `SIGSEGV` on an unhandled page fault.
+pub fn force_signal(sig: u32) {
+    let curr = current();
+    let ext = curr.task_ext();
+    let mut blocked = ext.thread.signal.blocked();
+    let actions = ext.proc.signal.actions();
+    let mut actions = actions.lock();
+    let action = &mut actions[sig as usize - 1];
+    if blocked.contains(sig) || action.handler == SIG_IGN {
+        blocked.remove(sig);
+        ext.thread.signal.set_blocked(blocked);
+        *action = SigAction::default();
+    }
+    ext.thread.send_signal(sig);
+}
+
+/// Returns from a signal handler, the user context and the blocked signals
+/// saved in the signal frame on the user stack of `tf` are restored.
+pub fn sigreturn(tf: &TrapFrame) -> AxResult {
+    let curr = current();
+    let ext = curr.task_ext();
+    let frame = UserPtr::<arch::SignalFrame>::new(arch::SignalFrame::addr_on_return(tf))
+        .read(&mut ext.aspace.lock())?;
+    let mut context = *tf;
+    frame.restore(&mut context);
+
+    let signal = &ext.thread.signal;
+    signal.set_blocked(frame.sigmask());
+    *signal.saved_context.lock() = Some(context);
+    Ok(())
+}
+
+/// Pushes the signal frame and redirects the user context to the handler.
+///
+/// Returns an error if the frame cannot be written to the user stack.
+fn setup_frame(tf: &mut TrapFrame, sig: u32, action: &SigAction, ext: &TaskExt) -> AxResult {
+    let thread = &ext.thread;
+    let blocked = thread.signal.blocked();
+    let mut info: SigInfo = unsafe { core::mem::zeroed() };
+    info.signo = sig as i32;
+    let frame_addr = arch::SignalFrame::addr_on_delivery(tf);
+    let ret = {
+        let mut aspace = ext.aspace.lock();
+        let ret = action
+            .restorer()
+            .unwrap_or_else(|| trampoline_addr(&aspace).as_usize());
+        let frame = arch::SignalFrame::new(info, tf, blocked, ret);
+        UserPtr::new(frame_addr).write(&mut aspace, frame)?;
+        ret
+    };
+    arch::SignalFrame::enter_handler(tf, frame_addr, sig, action.handler, ret);
+
+    let mut new_blocked = SignalSet(blocked.0 | action.mask.0);
+    if action.flags & SA_NODEFER == 0 {
+        new_blocked.add(sig);
+    }
+    thread.signal.set_blocked(new_blocked);
+    Ok(())
+}
+
+#[register_trap_handler(RETURN_TO_USER)]
+fn handle_signals(tf: &mut TrapFrame) {
+    let curr = current();
+    // Kernel tasks have no signals.
+    if unsafe { curr.task_ext_ptr() }.is_null() {
+        return;
+    }
+    let ext = curr.task_ext();
+    if is_exiting(&ext.thread, &ext.proc) {
+        crate::exit_current(0);
+    }
+    if let Some(context) = ext.thread.signal.saved_context.lock().take() {
+        *tf = context;
+    }
+
+    while let Some(sig) = dequeue_signal(&ext.thread, &ext.proc) {
+        let action = {
+            let actions = ext.proc.signal.actions();
+            let mut actions = actions.lock();
+            let action = actions[sig as usize - 1];
+            if action.flags & SA_RESETHAND != 0 {
+                actions[sig as usize - 1] = SigAction::default();
+            }
+            action
+        };
+        match action.handler {
+            SIG_IGN => {}
+            SIG_DFL if default_ignored(sig) => {}
+            SIG_DFL => {
+                debug!("task {} terminated by signal {}", curr.id_name(), sig);
+                ext.proc.set_group_exit_signal(sig);
+                crate::exit_current(128 + sig as i32);
+            }
+            _ => {
+                if setup_frame(tf, sig, &action, ext).is_err() {
+                    debug!("task {} has a bad signal stack", curr.id_name());
+                    ext.proc.set_group_exit_signal(SIGSEGV);
+                    crate::exit_current(128 + SIGSEGV as i32);
+                }
+                // Deliver one signal at a time, others are delivered after
+                // the handler returns.
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Signal frames of AArch64, in the layout of Linux.
+
+use core::mem::{offset_of, size_of};
+
+use axhal::arch::TrapFrame;
+
+use super::{SigInfo, SignalSet, SignalStack};
+
+/// `mov x8, #139` (`SYS_rt_sigreturn`) and `svc #0`.
+pub(super) const SIGRETURN_CODE: &[u8] = &[0x68, 0x11, 0x80, 0xd2, 0x01, 0x00, 0x00, 0xd4];
+
+/// The condition flags in `PSTATE`, the only bits that user space can change.
+const PSTATE_NZCV: u64 = 0xf000_0000;
+
+/// `struct sigcontext`, FP/SIMD states are not saved.
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+struct MContext {
+    fault_address: u64,
+    regs: [u64; 31],
+    sp: u64,
+    pc: u64,
+    pstate: u64,
+    // `__reserved` is aligned to 16 bytes.
+    _pad: u64,
+    reserved: [u8; 4096],
+}
+
+/// `ucontext_t`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    stack: SignalStack,
+    sigmask: SignalSet,
+    _unused: [u8; 120],
+    mcontext: MContext,
+}
+
+/// The frame pushed onto the user stack when delivering a signal.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct SignalFrame {
+    info: SigInfo,
+    uc: UContext,
+}
+
+impl SignalFrame {
+    /// Creates a frame saving the user context `tf` and the blocked signals.
+    ///
+    /// The return address `ret` is passed in `x30` rather than on the stack.
+    pub fn new(info: SigInfo, tf: &TrapFrame, sigmask: SignalSet, _ret: usize) -> Self {
+        let mut frame: Self = unsafe { core::mem::zeroed() };
+        frame.info = info;
+        frame.uc.sigmask = sigmask;
+        frame.uc.mcontext.regs = tf.r;
+        frame.uc.mcontext.sp = tf.usp;
+        frame.uc.mcontext.pc = tf.elr;
+        frame.uc.mcontext.pstate = tf.spsr;
+        frame
+    }
+
+    /// Returns the address to push the frame at, below the user stack
+    /// pointer of `tf`.
+    pub fn addr_on_delivery(tf: &TrapFrame) -> usize {
+        (tf.usp as usize).wrapping_sub(size_of::<Self>()) & !0xf
+    }
+
+    /// Returns the address of the frame when the handler calls
+    /// `rt_sigreturn`, i.e., the user stack pointer.
+    pub fn addr_on_return(tf: &TrapFrame) -> usize {
+        tf.usp as usize
+    }
+
+    /// Redirects the user context `tf` to `handler`, which returns to `ret`,
+    /// with the frame pushed at `frame_addr`.
+    pub fn enter_handler(
+        tf: &mut TrapFrame,
+        frame_addr: usize,
+        sig: u32,
+        handler: usize,
+        ret: usize,
+    ) {
+        tf.elr = handler as u64;
+        tf.usp = frame_addr as u64;
+        tf.r[30] = ret as u64;
+        tf.r[0] = sig as u64;
+        tf.r[1] = (frame_addr + offset_of!(Self, info)) as u64;
+        tf.r[2] = (frame_addr + offset_of!(Self, uc)) as u64;
+    }
+
+    /// Returns the blocked signals to restore.
+    pub fn sigmask(&self) -> SignalSet {
+        self.uc.sigmask
+    }
+
+    /// Restores the user context saved in the frame to `tf`.
+    ///
+    /// Only the condition flags are taken from the saved `PSTATE`, so that
+    /// the handler cannot return to a privileged mode.
+    pub fn restore(&self, tf: &mut TrapFrame) {
+        let mcontext = &self.uc.mcontext;
+        tf.r = mcontext.regs;
+        tf.usp = mcontext.sp;
+        tf.elr = mcontext.pc;
+        tf.spsr = (tf.spsr & !PSTATE_NZCV) | (mcontext.pstate & PSTATE_NZCV);
+    }
+}
@@ -0,0 +1,93 @@
+//! Signal frames of RISC-V, in the layout of Linux.
+
+use core::mem::{offset_of, size_of};
+
+use axhal::arch::{GeneralRegisters, TrapFrame};
+
+use super::{SigInfo, SignalSet, SignalStack};
+
+/// `li a7, 139` (`SYS_rt_sigreturn`) and `ecall`.
+pub(super) const SIGRETURN_CODE: &[u8] = &[0x93, 0x08, 0xb0, 0x08, 0x73, 0x00, 0x00, 0x00];
+
+/// `mcontext_t`, FP states are not saved.
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+struct MContext {
+    pc: usize,
+    regs: GeneralRegisters,
+    fpstate: [u64; 66],
+}
+
+/// `ucontext_t`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    stack: SignalStack,
+    sigmask: SignalSet,
+    _unused: [u8; 120],
+    mcontext: MContext,
+}
+
+/// The frame pushed onto the user stack when delivering a signal.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct SignalFrame {
+    info: SigInfo,
+    uc: UContext,
+}
+
+impl SignalFrame {
+    /// Creates a frame saving the user context `tf` and the blocked signals.
+    ///
+    /// The return address `ret` is passed in `ra` rather than on the stack.
+    pub fn new(info: SigInfo, tf: &TrapFrame, sigmask: SignalSet, _ret: usize) -> Self {
+        let mut frame: Self = unsafe { core::mem::zeroed() };
+        frame.info = info;
+        frame.uc.sigmask = sigmask;
+        frame.uc.mcontext.pc = tf.sepc;
+        frame.uc.mcontext.regs = tf.regs;
+        frame
+    }
+
+    /// Returns the address to push the frame at, below the user stack
+    /// pointer of `tf`.
+    pub fn addr_on_delivery(tf: &TrapFrame) -> usize {
+        tf.regs.sp.wrapping_sub(size_of::<Self>()) & !0xf
+    }
+
+    /// Returns the address of the frame when the handler calls
+    /// `rt_sigreturn`, i.e., the user stack pointer.
+    pub fn addr_on_return(tf: &TrapFrame) -> usize {
+        tf.regs.sp
+    }
+
+    /// Redirects the user context `tf` to `handler`, which returns to `ret`,
+    /// with the frame pushed at `frame_addr`.
+    pub fn enter_handler(
+        tf: &mut TrapFrame,
+        frame_addr: usize,
+        sig: u32,
+        handler: usize,
+        ret: usize,
+    ) {
+        tf.sepc = handler;
+        tf.regs.sp = frame_addr;
+        tf.regs.ra = ret;
+        tf.regs.a0 = sig as usize;
+        tf.regs.a1 = frame_addr + offset_of!(Self, info);
+        tf.regs.a2 = frame_addr + offset_of!(Self, uc);
+    }
+
+    /// Returns the blocked signals to restore.
+    pub fn sigmask(&self) -> SignalSet {
+        self.uc.sigmask
+    }
+
+    /// Restores the user context saved in the frame to `tf`.
+    pub fn restore(&self, tf: &mut TrapFrame) {
+        tf.regs = self.uc.mcontext.regs;
+        tf.sepc = self.uc.mcontext.pc;
+    }
+}
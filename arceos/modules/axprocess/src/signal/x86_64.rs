@@ -0,0 +1,174 @@
+//! Signal frames of x86_64, in the layout of Linux.
+
+use core::mem::{offset_of, size_of};
+
+use axhal::arch::TrapFrame;
+
+use super::{SigInfo, SignalSet, SignalStack};
+
+/// `mov eax, 15` (`SYS_rt_sigreturn`) and `syscall`.
+pub(super) const SIGRETURN_CODE: &[u8] = &[0xb8, 0x0f, 0x00, 0x00, 0x00, 0x0f, 0x05];
+
+/// The area below the stack pointer that may be used by leaf functions.
+const RED_ZONE_SIZE: usize = 128;
+
+/// The bits in `RFLAGS` that user space can change.
+const RFLAGS_USER: u64 = 0x50dd5;
+
+/// `struct sigcontext`, FP states are not saved.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct MContext {
+    r8: u64,
+    r9: u64,
+    r10: u64,
+    r11: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rdi: u64,
+    rsi: u64,
+    rbp: u64,
+    rbx: u64,
+    rdx: u64,
+    rax: u64,
+    rcx: u64,
+    rsp: u64,
+    rip: u64,
+    rflags: u64,
+    cs: u16,
+    gs: u16,
+    fs: u16,
+    ss: u16,
+    err: u64,
+    trapno: u64,
+    oldmask: u64,
+    cr2: u64,
+    fpstate: u64,
+    _reserved: [u64; 8],
+}
+
+/// `ucontext_t`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    stack: SignalStack,
+    mcontext: MContext,
+    sigmask: SignalSet,
+}
+
+/// The frame pushed onto the user stack when delivering a signal.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct SignalFrame {
+    /// The return address of the handler, popped by its `ret`.
+    ret: usize,
+    uc: UContext,
+    info: SigInfo,
+}
+
+impl SignalFrame {
+    /// Creates a frame saving the user context `tf` and the blocked signals,
+    /// the handler returns to `ret`.
+    pub fn new(info: SigInfo, tf: &TrapFrame, sigmask: SignalSet, ret: usize) -> Self {
+        let mut frame: Self = unsafe { core::mem::zeroed() };
+        frame.ret = ret;
+        frame.info = info;
+        frame.uc.sigmask = sigmask;
+        let mc = &mut frame.uc.mcontext;
+        mc.r8 = tf.r8;
+        mc.r9 = tf.r9;
+        mc.r10 = tf.r10;
+        mc.r11 = tf.r11;
+        mc.r12 = tf.r12;
+        mc.r13 = tf.r13;
+        mc.r14 = tf.r14;
+        mc.r15 = tf.r15;
+        mc.rdi = tf.rdi;
+        mc.rsi = tf.rsi;
+        mc.rbp = tf.rbp;
+        mc.rbx = tf.rbx;
+        mc.rdx = tf.rdx;
+        mc.rax = tf.rax;
+        mc.rcx = tf.rcx;
+        mc.rsp = tf.rsp;
+        mc.rip = tf.rip;
+        mc.rflags = tf.rflags;
+        mc.cs = tf.cs as u16;
+        mc.ss = tf.ss as u16;
+        mc.err = tf.error_code;
+        mc.trapno = tf.vector;
+        frame
+    }
+
+    /// Returns the address to push the frame at, below the red zone of the
+    /// user stack of `tf`.
+    ///
+    /// The address is 8 bytes off 16-byte alignment, as if the handler was
+    /// called by a `call` instruction.
+    pub fn addr_on_delivery(tf: &TrapFrame) -> usize {
+        ((tf.rsp as usize).wrapping_sub(RED_ZONE_SIZE + size_of::<Self>()) & !0xf) - 8
+    }
+
+    /// Returns the address of the frame when the handler calls
+    /// `rt_sigreturn`, which is below the user stack pointer since the return
+    /// address has been popped.
+    pub fn addr_on_return(tf: &TrapFrame) -> usize {
+        (tf.rsp as usize).wrapping_sub(size_of::<usize>())
+    }
+
+    /// Redirects the user context `tf` to `handler`, with the frame pushed at
+    /// `frame_addr`.
+    ///
+    /// The return address is already in the frame, `_ret` is not used.
+    pub fn enter_handler(
+        tf: &mut TrapFrame,
+        frame_addr: usize,
+        sig: u32,
+        handler: usize,
+        _ret: usize,
+    ) {
+        tf.rip = handler as u64;
+        tf.rsp = frame_addr as u64;
+        tf.rdi = sig as u64;
+        tf.rsi = (frame_addr + offset_of!(Self, info)) as u64;
+        tf.rdx = (frame_addr + offset_of!(Self, uc)) as u64;
+        // No vector registers are used for variadic arguments.
+        tf.rax = 0;
+    }
+
+    /// Returns the blocked signals to restore.
+    pub fn sigmask(&self) -> SignalSet {
+        self.uc.sigmask
+    }
+
+    /// Restores the user context saved in the frame to `tf`.
+    ///
+    /// The segment registers are not restored, and only the arithmetic and
+    /// a few control flags are taken from the saved `RFLAGS`, so that the
+    /// handler cannot gain privileges.
+    pub fn restore(&self, tf: &mut TrapFrame) {
+        let mc = &self.uc.mcontext;
+        tf.r8 = mc.r8;
+        tf.r9 = mc.r9;
+        tf.r10 = mc.r10;
+        tf.r11 = mc.r11;
+        tf.r12 = mc.r12;
+        tf.r13 = mc.r13;
+        tf.r14 = mc.r14;
+        tf.r15 = mc.r15;
+        tf.rdi = mc.rdi;
+        tf.rsi = mc.rsi;
+        tf.rbp = mc.rbp;
+        tf.rbx = mc.rbx;
+        tf.rdx = mc.rdx;
+        tf.rax = mc.rax;
+        tf.rcx = mc.rcx;
+        tf.rsp = mc.rsp;
+        tf.rip = mc.rip;
+        tf.rflags = (tf.rflags & !RFLAGS_USER) | (mc.rflags & RFLAGS_USER);
+    }
+}
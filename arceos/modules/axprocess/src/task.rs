@@ -0,0 +1,349 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use arceos_posix_api::{FdTable, FdTableIf};
+use axerrno::{ax_err, AxResult};
+use axhal::arch::{TrapFrame, UspaceContext};
+use axhal::mem::VirtAddr;
+use axmm::UserPtr;
+use axsync::spin::SpinNoIrq;
+use axsync::Mutex;
+use axtask::{current, AxTaskRef, TaskExtRef, TaskInner};
+
+use crate::futex::{futex_wake, FUTEX_BITSET_MATCH_ANY};
+use crate::loader::{load_elf, ElfImage};
+use crate::mm::UserSpace;
+use crate::process::{Pid, Process, Thread};
+use crate::signal::{ProcessSignals, SignalSet, ThreadSignals, SIGCHLD};
+
+bitflags::bitflags! {
+    /// Flags for `clone`.
+    ///
+    /// See <https://man7.org/linux/man-pages/man2/clone.2.html>
+    #[derive(Debug, Clone, Copy)]
+    pub struct CloneFlags: u32 {
+        /// The signal sent to the parent when the child exits.
+        const CSIGNAL = 0xff;
+        /// Share the address space.
+        const CLONE_VM = 0x100;
+        /// Share the filesystem information.
+        const CLONE_FS = 0x200;
+        /// Share the file descriptor table.
+        const CLONE_FILES = 0x400;
+        /// Share the signal handlers.
+        const CLONE_SIGHAND = 0x800;
+        /// Suspend the parent until the child releases the address space.
+        const CLONE_VFORK = 0x4000;
+        /// Use the parent of the caller as the parent of the child.
+        const CLONE_PARENT = 0x8000;
+        /// Create a thread in the same process.
+        const CLONE_THREAD = 0x10000;
+        /// Set the TLS area of the child.
+        const CLONE_SETTLS = 0x80000;
+        /// Store the child TID in the parent's memory.
+        const CLONE_PARENT_SETTID = 0x100000;
+        /// Clear the child TID in the child's memory when the child exits.
+        const CLONE_CHILD_CLEARTID = 0x200000;
+        /// Store the child TID in the child's memory.
+        const CLONE_CHILD_SETTID = 0x1000000;
+    }
+}
+
+/// Task extended data for the monolithic kernel.
+pub struct TaskExt {
+    /// The process the task belongs to.
+    pub proc: Arc<Process>,
+    /// The thread states shared with the process.
+    pub thread: Arc<Thread>,
+    /// The clear thread tid field
+    ///
+    /// See <https://manpages.debian.org/unstable/manpages-dev/set_tid_address.2.en.html#clear_child_tid>
+    ///
+    /// When the thread exits, the kernel clears the word at this address if it is not NULL.
+    clear_child_tid: AtomicU64,
+    /// The user space context.
+    pub uctx: UspaceContext,
+    /// The virtual memory address space.
+    pub aspace: Arc<Mutex<UserSpace>>,
+    /// The file descriptor table, released when the thread exits.
+    fd_table: SpinNoIrq<Arc<FdTable>>,
+}
+
+impl TaskExt {
+    pub const fn new(
+        proc: Arc<Process>,
+        thread: Arc<Thread>,
+        uctx: UspaceContext,
+        aspace: Arc<Mutex<UserSpace>>,
+        fd_table: Arc<FdTable>,
+    ) -> Self {
+        Self {
+            proc,
+            thread,
+            uctx,
+            clear_child_tid: AtomicU64::new(0),
+            aspace,
+            fd_table: SpinNoIrq::new(fd_table),
+        }
+    }
+
+    /// Returns the process ID.
+    pub fn proc_id(&self) -> Pid {
+        self.proc.pid()
+    }
+
+    pub fn clear_child_tid(&self) -> u64 {
+        self.clear_child_tid.load(Ordering::Relaxed)
+    }
+
+    pub fn set_clear_child_tid(&self, clear_child_tid: u64) {
+        self.clear_child_tid
+            .store(clear_child_tid, Ordering::Relaxed);
+    }
+
+    /// Returns the file descriptor table.
+    pub fn fd_table(&self) -> Arc<FdTable> {
+        self.fd_table.lock().clone()
+    }
+}
+
+axtask::def_task_ext!(TaskExt);
+
+struct FdTableIfImpl;
+
+#[crate_interface::impl_interface]
+impl FdTableIf for FdTableIfImpl {
+    fn current_fd_table() -> Option<Arc<FdTable>> {
+        let curr = current();
+        // Kernel tasks use the global table.
+        if unsafe { curr.task_ext_ptr() }.is_null() {
+            return None;
+        }
+        Some(curr.task_ext().fd_table())
+    }
+}
+
+/// Returns the process of the current task.
+///
+/// # Panics
+///
+/// Panics if the current task is not a user task.
+pub fn current_process() -> Arc<Process> {
+    current().task_ext().proc.clone()
+}
+
+fn new_user_task(name: String, set_child_tid: Option<usize>) -> TaskInner {
+    TaskInner::new(
+        move || {
+            let curr = axtask::current();
+            if let Some(tid_ptr) = set_child_tid {
+                // Written in the child's own address space, errors are
+                // ignored like Linux.
+                let tid = curr.id().as_u64() as i32;
+                let _ = UserPtr::new(tid_ptr).write(&mut curr.task_ext().aspace.lock(), tid);
+            }
+            let kstack_top = curr.kernel_stack_top().unwrap();
+            info!(
+                "Enter user space: entry={:#x}, ustack={:#x}, kstack={:#x}",
+                curr.task_ext().uctx.get_ip(),
+                curr.task_ext().uctx.get_sp(),
+                kstack_top,
+            );
+            unsafe { curr.task_ext().uctx.enter_uspace(kstack_top) };
+        },
+        name,
+        crate::KERNEL_STACK_SIZE,
+    )
+}
+
+/// Spawns the first task of a new process.
+///
+/// The new process is a child of the current process if the current task is
+/// a user task, otherwise it has no parent.
+pub fn spawn_user_task(aspace: Arc<Mutex<UserSpace>>, uctx: UspaceContext) -> AxTaskRef {
+    let curr = current();
+    let parent = if unsafe { curr.task_ext_ptr() }.is_null() {
+        None
+    } else {
+        Some(curr.task_ext().proc.clone())
+    };
+
+    let mut task = new_user_task("userboot".into(), None);
+    {
+        let mut aspace = aspace.lock();
+        aspace.add_user();
+        task.ctx_mut().set_page_table_root(aspace.page_table_root());
+    }
+    let pid = task.id().as_u64();
+    let proc = Process::new(pid, parent.as_ref(), SIGCHLD, ProcessSignals::new());
+    let thread = proc.add_thread(pid, ThreadSignals::new(SignalSet::empty()));
+    let fd_table = Arc::new(FdTable::with_stdio());
+    task.init_task_ext(TaskExt::new(proc, thread, uctx, aspace, fd_table));
+    axtask::spawn_task(task)
+}
+
+/// Creates a child of the current task, which returns from the syscall
+/// described by `tf` with 0.
+///
+/// Without [`CLONE_THREAD`](CloneFlags::CLONE_THREAD), the child is the first
+/// thread of a new child process. Without [`CLONE_VM`](CloneFlags::CLONE_VM),
+/// the child gets a copy-on-write duplicate of the address space. Likewise,
+/// the file descriptor table is copied without
+/// [`CLONE_FILES`](CloneFlags::CLONE_FILES), and the signal actions of a new
+/// process are copied without [`CLONE_SIGHAND`](CloneFlags::CLONE_SIGHAND).
+///
+/// Returns the thread ID of the child.
+pub fn clone_task(
+    tf: &TrapFrame,
+    flags: CloneFlags,
+    stack: usize,
+    ptid: usize,
+    tls: usize,
+    ctid: usize,
+) -> AxResult<Pid> {
+    if flags.contains(CloneFlags::CLONE_THREAD) && !flags.contains(CloneFlags::CLONE_SIGHAND) {
+        return ax_err!(InvalidInput, "CLONE_THREAD without CLONE_SIGHAND");
+    }
+    if flags.contains(CloneFlags::CLONE_SIGHAND) && !flags.contains(CloneFlags::CLONE_VM) {
+        return ax_err!(InvalidInput, "CLONE_SIGHAND without CLONE_VM");
+    }
+    let curr = current();
+    let ext = curr.task_ext();
+
+    // The instruction pointer of `tf` is already past the syscall
+    // instruction, the child returns 0 from there.
+    let mut uctx = UspaceContext::from(tf);
+    uctx.set_retval(0);
+    if stack != 0 {
+        uctx.set_sp(stack);
+    }
+    if flags.contains(CloneFlags::CLONE_SETTLS) {
+        uctx.set_tls(tls);
+    }
+
+    // `vfork` is implemented as a copy-on-write fork, so that the parent
+    // does not need to be suspended while the child uses its stack.
+    let aspace = if flags.contains(CloneFlags::CLONE_VM) && !flags.contains(CloneFlags::CLONE_VFORK)
+    {
+        ext.aspace.clone()
+    } else {
+        let child_aspace = ext.aspace.lock().fork()?;
+        Arc::new(Mutex::new(child_aspace))
+    };
+
+    let fd_table = if flags.contains(CloneFlags::CLONE_FILES) {
+        ext.fd_table()
+    } else {
+        Arc::new(ext.fd_table().duplicate())
+    };
+
+    let set_child_tid = flags
+        .contains(CloneFlags::CLONE_CHILD_SETTID)
+        .then_some(ctid);
+    let mut task = new_user_task(curr.name().into(), set_child_tid);
+    task.ctx_mut()
+        .set_page_table_root(aspace.lock().page_table_root());
+    let tid = task.id().as_u64();
+
+    let proc = if flags.contains(CloneFlags::CLONE_THREAD) {
+        ext.proc.clone()
+    } else {
+        let parent = if flags.contains(CloneFlags::CLONE_PARENT) {
+            ext.proc.parent()
+        } else {
+            Some(ext.proc.clone())
+        };
+        let exit_signal = (flags & CloneFlags::CSIGNAL).bits();
+        let signal = ext
+            .proc
+            .signal
+            .fork(flags.contains(CloneFlags::CLONE_SIGHAND));
+        Process::new(tid, parent.as_ref(), exit_signal, signal)
+    };
+    let thread = proc.add_thread(tid, ThreadSignals::new(ext.thread.signal.blocked()));
+    let task_ext = TaskExt::new(proc, thread, uctx, aspace, fd_table);
+    if flags.contains(CloneFlags::CLONE_CHILD_CLEARTID) {
+        task_ext.set_clear_child_tid(ctid as _);
+    }
+    task.init_task_ext(task_ext);
+
+    if flags.contains(CloneFlags::CLONE_PARENT_SETTID) {
+        let _ = UserPtr::new(ptid).write(&mut ext.aspace.lock(), tid as i32);
+    }
+    task.task_ext().aspace.lock().add_user();
+    axtask::spawn_task(task);
+    Ok(tid)
+}
+
+/// Replaces the program of the current process with the ELF file at `path`.
+///
+/// The program is loaded into a new address space, which replaces the old
+/// one only if loading succeeds, so the process keeps running the old
+/// program on errors. The other threads of the process are killed before
+/// that, like Linux. Returns the context to enter the new program, the
+/// caller should release all its resources before entering it since the
+/// kernel stack is reset.
+pub fn exec(path: &str, args: &[String], envs: &[String]) -> AxResult<UspaceContext> {
+    let curr = current();
+    let ext = curr.task_ext();
+
+    let elf = ElfImage::open(path)?;
+    let mut uspace = UserSpace::new()?;
+    let (entry, ustack_top) = load_elf(&mut uspace, &elf, path, args, envs)?;
+
+    ext.proc.dethread(ext.thread.tid())?;
+    let old_uspace = {
+        let mut aspace = ext.aspace.lock();
+        // Other processes created with `CLONE_VM` would lose their memory.
+        if aspace.users() > 1 {
+            return ax_err!(
+                Unsupported,
+                "exec with an address space shared by processes"
+            );
+        }
+        // The point of no return.
+        uspace.add_user();
+        unsafe { axtask::set_current_page_table_root(uspace.page_table_root()) };
+        core::mem::replace(&mut *aspace, uspace)
+    };
+    // The old page table is not in use any more.
+    drop(old_uspace);
+    ext.fd_table().close_on_exec();
+    ext.set_clear_child_tid(0);
+    ext.proc.signal.reset_handlers();
+    Ok(UspaceContext::new(entry, ustack_top))
+}
+
+/// Exits the current thread with `exit_code`.
+///
+/// The process exits when its last thread exits.
+pub fn exit_current(exit_code: i32) -> ! {
+    let curr = current();
+    let ext = curr.task_ext();
+    let clear_tid = ext.clear_child_tid();
+    if clear_tid != 0 {
+        // Wakes up the joiner, see `set_tid_address(2)`.
+        let _ = UserPtr::<i32>::new(clear_tid as usize).write(&mut ext.aspace.lock(), 0);
+        futex_wake(
+            VirtAddr::from(clear_tid as usize),
+            1,
+            FUTEX_BITSET_MATCH_ANY,
+        );
+    }
+    ext.aspace.lock().remove_user();
+    // Closes the files now, the task is released only after being joined.
+    *ext.fd_table.lock() = Arc::new(FdTable::new());
+    ext.proc.exit_thread(ext.thread.tid(), exit_code);
+    axtask::exit(exit_code)
+}
+
+/// Exits all threads of the current process with `exit_code`.
+///
+/// Other threads exit on their next return to user space, those blocked in
+/// syscalls are interrupted. The process exits after all of them have
+/// exited.
+pub fn exit_group_current(exit_code: i32) -> ! {
+    current().task_ext().proc.set_group_exit(exit_code);
+    exit_current(exit_code)
+}
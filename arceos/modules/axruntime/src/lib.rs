@@ -6,13 +6,17 @@
 //! # Cargo Features
 //!
 //! - `alloc`: Enable global memory allocator.
-//! - `paging`: Enable page table manipulation support.
+//! - `paging`: Enable page table manipulation support. With `multitask`,
+//!   kernel stacks are also guarded against overflows.
 //! - `irq`: Enable interrupt handling support.
 //! - `multitask`: Enable multi-threading support.
 //! - `smp`: Enable SMP (symmetric multiprocessing) support.
 //! - `fs`: Enable filesystem support.
 //! - `net`: Enable networking support.
 //! - `display`: Enable graphics support.
+//! - `swap`: Enable swapping to a block device. The swap device is the block
+//!   device after the one used by the filesystem (if `fs` is enabled), so
+//!   both need the `dyn` feature of `axdriver` to probe more than one device.
 //!
 //! All the features are optional and disabled by default.
 
@@ -83,6 +87,27 @@ impl axlog::LogIf for LogIfImpl {
     }
 }
 
+#[cfg(all(feature = "paging", feature = "multitask"))]
+struct KernelStackIfImpl;
+
+#[cfg(all(feature = "paging", feature = "multitask"))]
+#[crate_interface::impl_interface]
+impl axtask::KernelStackIf for KernelStackIfImpl {
+    fn map_kernel_stack(vaddr: axhal::mem::VirtAddr, size: usize) -> bool {
+        use axhal::paging::MappingFlags;
+        axmm::kernel_aspace()
+            .lock()
+            .map_alloc(vaddr, size, MappingFlags::READ | MappingFlags::WRITE, true)
+            .is_ok()
+    }
+
+    fn unmap_kernel_stack(vaddr: axhal::mem::VirtAddr, size: usize) {
+        if let Err(e) = axmm::kernel_aspace().lock().unmap(vaddr, size) {
+            warn!("failed to unmap kernel stack at {:#x}: {:?}", vaddr, e);
+        }
+    }
+}
+
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 static INITED_CPUS: AtomicUsize = AtomicUsize::new(0);
@@ -153,14 +178,25 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     #[cfg(feature = "multitask")]
     axtask::init_scheduler();
 
-    #[cfg(any(feature = "fs", feature = "net", feature = "display"))]
+    #[cfg(any(feature = "fs", feature = "net", feature = "display", feature = "swap"))]
     {
-        #[allow(unused_variables)]
-        let all_devices = axdriver::init_drivers();
+        #[allow(unused_variables, unused_mut)]
+        let mut all_devices = axdriver::init_drivers();
 
-        #[cfg(feature = "fs")]
+        #[cfg(all(feature = "fs", not(feature = "swap")))]
         axfs::init_filesystems(all_devices.block);
 
+        #[cfg(all(feature = "fs", feature = "swap"))]
+        if let Some(dev) = all_devices.block.take_one() {
+            axfs::init_filesystems(axdriver::AxDeviceContainer::from_one(dev));
+        }
+
+        #[cfg(feature = "swap")]
+        match all_devices.block.take_one() {
+            Some(dev) => axmm::swap::init_swap(dev),
+            None => warn!("No block device for swap found"),
+        }
+
         #[cfg(feature = "net")]
         axnet::init_network(all_devices.net);
 
@@ -0,0 +1,76 @@
+//! A barrier to synchronize a group of tasks.
+
+use core::fmt;
+
+use crate::{Condvar, Mutex};
+
+/// A barrier enabling multiple tasks to synchronize the beginning of some
+/// computation, similar to
+/// [`std::sync::Barrier`](https://doc.rust-lang.org/std/sync/struct.Barrier.html).
+pub struct Barrier {
+    lock: Mutex<BarrierState>,
+    cvar: Condvar,
+    num_tasks: usize,
+}
+
+struct BarrierState {
+    count: usize,
+    generation_id: usize,
+}
+
+/// Returned by [`Barrier::wait`] when all tasks in the barrier have
+/// rendezvoused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Returns `true` if this task is the "leader task", i.e., the last one
+    /// arriving at the barrier. Only one task in each rendezvous is.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    /// Creates a new barrier that blocks `n` tasks calling
+    /// [`Barrier::wait`], until they all have called it.
+    pub const fn new(n: usize) -> Self {
+        Self {
+            lock: Mutex::new(BarrierState {
+                count: 0,
+                generation_id: 0,
+            }),
+            cvar: Condvar::new(),
+            num_tasks: n,
+        }
+    }
+
+    /// Blocks the current task until all `n` tasks have rendezvoused here.
+    ///
+    /// The barrier can be reused after that.
+    pub fn wait(&self) -> BarrierWaitResult {
+        let mut state = self.lock.lock();
+        let local_gen = state.generation_id;
+        state.count += 1;
+        if state.count < self.num_tasks {
+            let _state = self
+                .cvar
+                .wait_while(state, |state| local_gen == state.generation_id);
+            BarrierWaitResult(false)
+        } else {
+            state.count = 0;
+            state.generation_id = state.generation_id.wrapping_add(1);
+            drop(state);
+            self.cvar.notify_all();
+            BarrierWaitResult(true)
+        }
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Barrier")
+            .field("num_tasks", &self.num_tasks)
+            .finish_non_exhaustive()
+    }
+}
@@ -3,25 +3,62 @@
 //! Currently supported primitives:
 //!
 //! - [`Mutex`]: A mutual exclusion primitive.
-//! - mod [`spin`]: spinlocks imported from the [`kspin`] crate.
+//! - [`RwLock`]: A reader-writer lock.
+//! - [`Condvar`]: A condition variable working with [`Mutex`].
+//! - [`Semaphore`]: A counting semaphore.
+//! - [`Barrier`]: A barrier to synchronize a group of tasks.
+//! - [`Once`] and [`LazyLock`]: One-time initialization.
+//! - mod [`spin`]: spinlocks imported from the [`kspin`] crate, or wrappers of
+//!   them validated by [`axtask::lockdep`] with the `lockdep` feature.
+//!
+//! All but [`Mutex`] and [`spin`] are only available with the `multitask`
+//! feature.
 //!
 //! # Cargo Features
 //!
 //! - `multitask`: For use in the multi-threaded environments. If the feature is
 //!   not enabled, [`Mutex`] will be an alias of [`spin::SpinNoIrq`]. This
 //!   feature is enabled by default.
+//! - `irq`: Interrupts are enabled. If this feature is enabled, the blocking
+//!   methods with timeouts can be used, such as [`Condvar::wait_timeout`].
+//! - `lockdep`: Validate the order of acquiring [`Mutex`]es and [`spin`]
+//!   locks with [`axtask::lockdep`]. It also enables the `multitask` feature.
 
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
 
+#[cfg(not(feature = "lockdep"))]
 pub use kspin as spin;
 
+#[cfg(feature = "lockdep")]
+pub mod spin;
+
+#[cfg(feature = "multitask")]
+mod barrier;
+#[cfg(feature = "multitask")]
+mod condvar;
 #[cfg(feature = "multitask")]
 mod mutex;
+#[cfg(feature = "multitask")]
+mod once;
+#[cfg(feature = "multitask")]
+mod rwlock;
+#[cfg(feature = "multitask")]
+mod semaphore;
+
+#[cfg(test)]
+mod tests;
 
 #[cfg(feature = "multitask")]
 #[doc(cfg(feature = "multitask"))]
-pub use self::mutex::{Mutex, MutexGuard};
+pub use self::{
+    barrier::{Barrier, BarrierWaitResult},
+    condvar::{Condvar, WaitTimeoutResult},
+    mutex::{Mutex, MutexGuard},
+    once::{LazyLock, Once},
+    rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+    semaphore::Semaphore,
+};
 
 #[cfg(not(feature = "multitask"))]
 #[doc(cfg(not(feature = "multitask")))]
@@ -5,7 +5,13 @@ use core::fmt;
 use core::ops::{Deref, DerefMut};
 use core::sync::atomic::{AtomicU64, Ordering};
 
-use axtask::{current, WaitQueue};
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
+
+#[cfg(feature = "lockdep")]
+use axtask::lockdep::{LockClass, LockKind};
+use axtask::{current, AxTaskRef, WaitQueue};
+use kspin::SpinNoIrq;
 
 /// A mutual exclusion primitive useful for protecting shared data, similar to
 /// [`std::sync::Mutex`](https://doc.rust-lang.org/std/sync/struct.Mutex.html).
@@ -13,9 +19,25 @@ use axtask::{current, WaitQueue};
 /// When the mutex is locked, the current task will block and be put into the
 /// wait queue. When the mutex is unlocked, all tasks waiting on the queue
 /// will be woken up.
+///
+/// A mutex created by [`Mutex::new_pi`] uses priority inheritance: the owner
+/// runs with the priority of the highest-priority task waiting for it until
+/// it unlocks, and the highest-priority task is woken up first. Otherwise the
+/// tasks are woken up in FIFO order. Only the `sched_cfs` scheduler runs
+/// tasks by priorities, see [`axtask::inherit_priority`].
 pub struct Mutex<T: ?Sized> {
     wq: WaitQueue,
     owner_id: AtomicU64,
+    /// Whether priority inheritance is enabled.
+    pi: bool,
+    /// The owner of a mutex with priority inheritance, changed with
+    /// `owner_id` under the lock.
+    pi_owner: SpinNoIrq<Option<AxTaskRef>>,
+    /// Where the mutex was created, which is its lock class. It is `None` for
+    /// a mutex not created by [`Mutex::new`] (e.g., a zeroed one from C), then
+    /// the mutex is a class on its own.
+    #[cfg(feature = "lockdep")]
+    site: Option<&'static Location<'static>>,
     data: UnsafeCell<T>,
 }
 
@@ -34,10 +56,31 @@ unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
 impl<T> Mutex<T> {
     /// Creates a new [`Mutex`] wrapping the supplied data.
     #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(data: T) -> Self {
         Self {
             wq: WaitQueue::new(),
             owner_id: AtomicU64::new(0),
+            pi: false,
+            pi_owner: SpinNoIrq::new(None),
+            #[cfg(feature = "lockdep")]
+            site: Some(Location::caller()),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Creates a new [`Mutex`] with priority inheritance wrapping the
+    /// supplied data.
+    #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new_pi(data: T) -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            owner_id: AtomicU64::new(0),
+            pi: true,
+            pi_owner: SpinNoIrq::new(None),
+            #[cfg(feature = "lockdep")]
+            site: Some(Location::caller()),
             data: UnsafeCell::new(data),
         }
     }
@@ -68,17 +111,29 @@ impl<T: ?Sized> Mutex<T> {
     ///
     /// The returned value may be dereferenced for data access
     /// and the lock will be dropped when the guard falls out of scope.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn lock(&self) -> MutexGuard<T> {
+        #[cfg(feature = "lockdep")]
+        {
+            let at = Location::caller();
+            axtask::lockdep::might_sleep(at);
+            axtask::lockdep::lock_acquire(self.class(), self.key(), LockKind::Sleep, false, at);
+        }
         let current_id = current().id().as_u64();
         loop {
-            // Can fail to lock even if the spinlock is not locked. May be more efficient than `try_lock`
-            // when called in a loop.
-            match self.owner_id.compare_exchange_weak(
-                0,
-                current_id,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
+            let res = if self.pi {
+                self.lock_pi(current_id, true)
+            } else {
+                // Can fail to lock even if the spinlock is not locked. May be more efficient than `try_lock`
+                // when called in a loop.
+                self.owner_id.compare_exchange_weak(
+                    0,
+                    current_id,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+            };
+            match res {
                 Ok(_) => break,
                 Err(owner_id) => {
                     assert_ne!(
@@ -100,15 +155,26 @@ impl<T: ?Sized> Mutex<T> {
 
     /// Try to lock this [`Mutex`], returning a lock guard if successful.
     #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
         let current_id = current().id().as_u64();
         // The reason for using a strong compare_exchange is explained here:
         // https://github.com/Amanieu/parking_lot/pull/207#issuecomment-575869107
-        if self
-            .owner_id
-            .compare_exchange(0, current_id, Ordering::Acquire, Ordering::Relaxed)
-            .is_ok()
-        {
+        let res = if self.pi {
+            self.lock_pi(current_id, false)
+        } else {
+            self.owner_id
+                .compare_exchange(0, current_id, Ordering::Acquire, Ordering::Relaxed)
+        };
+        if res.is_ok() {
+            #[cfg(feature = "lockdep")]
+            axtask::lockdep::lock_acquire(
+                self.class(),
+                self.key(),
+                LockKind::Sleep,
+                true,
+                Location::caller(),
+            );
             Some(MutexGuard {
                 lock: self,
                 data: unsafe { &mut *self.data.get() },
@@ -126,14 +192,63 @@ impl<T: ?Sized> Mutex<T> {
     /// thread. However, this can be useful in some instances for exposing
     /// the lock to FFI that doesn’t know how to deal with RAII.
     pub unsafe fn force_unlock(&self) {
-        let owner_id = self.owner_id.swap(0, Ordering::Release);
+        let (owner_id, pi_owner) = if self.pi {
+            let mut pi_owner = self.pi_owner.lock();
+            (self.owner_id.swap(0, Ordering::Release), pi_owner.take())
+        } else {
+            (self.owner_id.swap(0, Ordering::Release), None)
+        };
         assert_eq!(
             owner_id,
             current().id().as_u64(),
             "{} tried to release mutex it doesn't own",
             current().id_name()
         );
-        self.wq.notify_one(true);
+        #[cfg(feature = "lockdep")]
+        axtask::lockdep::lock_release(self.key());
+        if self.pi {
+            // No more waiters can pass their priorities to it once unlocked.
+            if let Some(owner) = pi_owner {
+                axtask::drop_inherited_priority(&owner, self.key());
+            }
+            self.wq.notify_one_by_priority(true);
+        } else {
+            self.wq.notify_one(true);
+        }
+    }
+
+    /// The key of the mutex to inherit priorities through.
+    fn key(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+
+    #[cfg(feature = "lockdep")]
+    fn class(&self) -> LockClass {
+        match self.site {
+            Some(site) => LockClass::Site(site),
+            None => LockClass::Instance(self.key()),
+        }
+    }
+
+    /// Locks a mutex with priority inheritance if it is unlocked. Otherwise
+    /// returns the ID of the owner, and lets the owner inherit the priority
+    /// of the current task if `inherit`.
+    fn lock_pi(&self, current_id: u64, inherit: bool) -> Result<u64, u64> {
+        let mut pi_owner = self.pi_owner.lock();
+        let res =
+            self.owner_id
+                .compare_exchange(0, current_id, Ordering::Acquire, Ordering::Relaxed);
+        match &res {
+            Ok(_) => *pi_owner = Some(current().as_task_ref().clone()),
+            Err(owner_id) if inherit && *owner_id != current_id => {
+                // The owner drops it after taking itself out under the lock.
+                if let Some(owner) = pi_owner.as_ref() {
+                    axtask::inherit_priority(owner, self.key());
+                }
+            }
+            Err(_) => {}
+        }
+        res
     }
 
     /// Returns a mutable reference to the underlying data.
@@ -167,6 +282,13 @@ impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
     }
 }
 
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    /// Returns the mutex locked by the guard.
+    pub(crate) fn mutex(&self) -> &'a Mutex<T> {
+        self.lock
+    }
+}
+
 impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
     type Target = T;
     #[inline(always)]
@@ -199,21 +321,13 @@ impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
 
 #[cfg(test)]
 mod tests {
+    use crate::tests::{may_interrupt, INIT, SERIAL};
     use crate::Mutex;
     use axtask as thread;
-    use std::sync::Once;
-
-    static INIT: Once = Once::new();
-
-    fn may_interrupt() {
-        // simulate interrupts
-        if rand::random::<u32>() % 3 == 0 {
-            thread::yield_now();
-        }
-    }
 
     #[test]
     fn lots_and_lots() {
+        let _lock = SERIAL.lock();
         INIT.call_once(thread::init_scheduler);
 
         const NUM_TASKS: u32 = 10;
@@ -0,0 +1,245 @@
+//! A sleeping reader-writer lock.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+
+/// The lock state when a writer holds it, otherwise the state is the number
+/// of readers.
+const WRITER: usize = usize::MAX;
+
+/// A reader-writer lock, similar to
+/// [`std::sync::RwLock`](https://doc.rust-lang.org/std/sync/struct.RwLock.html).
+///
+/// Writers are preferred: no more readers can lock it once a writer is
+/// waiting, so writers are not starved by a stream of readers.
+pub struct RwLock<T: ?Sized> {
+    state: AtomicUsize,
+    writers_waiting: AtomicUsize,
+    readers_wq: WaitQueue,
+    writers_wq: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+/// A guard that provides shared data access.
+///
+/// When the guard falls out of scope it will release the read lock.
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the write lock.
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for RwLockReadGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for RwLockWriteGuard<'_, T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new [`RwLock`] wrapping the supplied data.
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            readers_wq: WaitQueue::new(),
+            writers_wq: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes this [`RwLock`] and unwraps the underlying data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    fn can_read(&self) -> bool {
+        self.state.load(Ordering::Relaxed) != WRITER
+            && self.writers_waiting.load(Ordering::Relaxed) == 0
+    }
+
+    fn is_unlocked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) == 0
+    }
+
+    /// Tries to lock this [`RwLock`] with shared read access, returning a
+    /// guard if successful.
+    ///
+    /// It fails if a writer holds the lock or is waiting for it.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        if self.writers_waiting.load(Ordering::Relaxed) != 0 {
+            return None;
+        }
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |n| {
+                (n < WRITER - 1).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| RwLockReadGuard { lock: self })
+    }
+
+    /// Tries to lock this [`RwLock`] with exclusive write access, returning
+    /// a guard if successful.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockWriteGuard { lock: self })
+    }
+
+    /// Locks this [`RwLock`] with shared read access, blocking the current
+    /// task until it can be acquired.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            self.readers_wq.wait_until(|| self.can_read());
+        }
+    }
+
+    /// Locks this [`RwLock`] with exclusive write access, blocking the
+    /// current task until it can be acquired.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        if let Some(guard) = self.try_write() {
+            return guard;
+        }
+        self.writers_waiting.fetch_add(1, Ordering::Relaxed);
+        let guard = loop {
+            if let Some(guard) = self.try_write() {
+                break guard;
+            }
+            self.writers_wq.wait_until(|| self.is_unlocked());
+        };
+        self.writers_waiting.fetch_sub(1, Ordering::Relaxed);
+        guard
+    }
+
+    /// Same as [`RwLock::read`], but returns `None` if the lock cannot be
+    /// acquired in the given duration.
+    #[cfg(feature = "irq")]
+    pub fn read_timeout(&self, dur: core::time::Duration) -> Option<RwLockReadGuard<T>> {
+        let deadline = axhal::time::wall_time() + dur;
+        loop {
+            if let Some(guard) = self.try_read() {
+                return Some(guard);
+            }
+            let now = axhal::time::wall_time();
+            if now >= deadline {
+                return None;
+            }
+            self.readers_wq
+                .wait_timeout_until(deadline - now, || self.can_read());
+        }
+    }
+
+    /// Same as [`RwLock::write`], but returns `None` if the lock cannot be
+    /// acquired in the given duration.
+    #[cfg(feature = "irq")]
+    pub fn write_timeout(&self, dur: core::time::Duration) -> Option<RwLockWriteGuard<T>> {
+        if let Some(guard) = self.try_write() {
+            return Some(guard);
+        }
+        let deadline = axhal::time::wall_time() + dur;
+        self.writers_waiting.fetch_add(1, Ordering::Relaxed);
+        let guard = loop {
+            if let Some(guard) = self.try_write() {
+                break Some(guard);
+            }
+            let now = axhal::time::wall_time();
+            if now >= deadline {
+                break None;
+            }
+            self.writers_wq
+                .wait_timeout_until(deadline - now, || self.is_unlocked());
+        };
+        if self.writers_waiting.fetch_sub(1, Ordering::Relaxed) == 1 && guard.is_none() {
+            // The readers held off by this writer can go on.
+            self.readers_wq.notify_all(true);
+        }
+        guard
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the [`RwLock`] mutably, no actual locking
+    /// needs to take place.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: ?Sized + Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => write!(f, "RwLock {{ data: ")
+                .and_then(|()| (*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "RwLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.lock.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.lock.writers_wq.notify_one(true);
+        }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+        self.lock.writers_wq.notify_one(true);
+        self.lock.readers_wq.notify_all(true);
+    }
+}
@@ -0,0 +1,190 @@
+//! Spinlocks validated by [`axtask::lockdep`].
+//!
+//! They wrap the ones from the [`kspin`] crate with the same interface, and
+//! replace them when the `lockdep` feature is enabled.
+
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+
+use axtask::lockdep::{self, LockClass, LockKind};
+use kernel_guard::{BaseGuard, IrqSave, NoOp, NoPreempt, NoPreemptIrqSave};
+
+/// A guard that a spinlock can be validated with, which tells whether it
+/// disables IRQs.
+pub trait LockdepGuard: BaseGuard {
+    /// The kind of the spinlock using this guard.
+    const KIND: LockKind;
+}
+
+impl LockdepGuard for NoOp {
+    const KIND: LockKind = LockKind::Spin;
+}
+
+impl LockdepGuard for NoPreempt {
+    const KIND: LockKind = LockKind::Spin;
+}
+
+impl LockdepGuard for IrqSave {
+    const KIND: LockKind = LockKind::SpinNoIrq;
+}
+
+impl LockdepGuard for NoPreemptIrqSave {
+    const KIND: LockKind = LockKind::SpinNoIrq;
+}
+
+/// A spinlock with the guard `G`, whose lock class is where it is created.
+pub struct BaseSpinLock<G: BaseGuard, T: ?Sized> {
+    site: &'static Location<'static>,
+    inner: kspin::BaseSpinLock<G, T>,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the lock.
+pub struct BaseSpinLockGuard<'a, G: BaseGuard, T: ?Sized + 'a> {
+    addr: usize,
+    inner: kspin::BaseSpinLockGuard<'a, G, T>,
+}
+
+/// A spin lock that disables kernel preemption while trying to lock, and
+/// re-enables it after unlocking.
+pub type SpinNoPreempt<T> = BaseSpinLock<NoPreempt, T>;
+/// A guard that provides mutable data access for [`SpinNoPreempt`].
+pub type SpinNoPreemptGuard<'a, T> = BaseSpinLockGuard<'a, NoPreempt, T>;
+
+/// A spin lock that disables kernel preemption and local IRQs while trying to
+/// lock, and re-enables it after unlocking.
+pub type SpinNoIrq<T> = BaseSpinLock<NoPreemptIrqSave, T>;
+/// A guard that provides mutable data access for [`SpinNoIrq`].
+pub type SpinNoIrqGuard<'a, T> = BaseSpinLockGuard<'a, NoPreemptIrqSave, T>;
+
+/// A raw spin lock that does nothing while trying to lock.
+pub type SpinRaw<T> = BaseSpinLock<NoOp, T>;
+/// A guard that provides mutable data access for [`SpinRaw`].
+pub type SpinRawGuard<'a, T> = BaseSpinLockGuard<'a, NoOp, T>;
+
+impl<G: BaseGuard, T> BaseSpinLock<G, T> {
+    /// Creates a new [`BaseSpinLock`] wrapping the supplied data.
+    #[inline(always)]
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self {
+            site: Location::caller(),
+            inner: kspin::BaseSpinLock::new(data),
+        }
+    }
+
+    /// Consumes this [`BaseSpinLock`] and unwraps the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<G: LockdepGuard, T: ?Sized> BaseSpinLock<G, T> {
+    fn addr(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+
+    /// Locks the [`BaseSpinLock`] and returns a guard that permits access to
+    /// the inner data.
+    #[inline(always)]
+    #[track_caller]
+    pub fn lock(&self) -> BaseSpinLockGuard<G, T> {
+        let addr = self.addr();
+        let class = LockClass::Site(self.site);
+        lockdep::lock_acquire(class, addr, G::KIND, false, Location::caller());
+        BaseSpinLockGuard {
+            addr,
+            inner: self.inner.lock(),
+        }
+    }
+
+    /// Tries to lock this [`BaseSpinLock`], returning a lock guard if
+    /// successful.
+    #[inline(always)]
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<BaseSpinLockGuard<G, T>> {
+        let inner = self.inner.try_lock()?;
+        let addr = self.addr();
+        let class = LockClass::Site(self.site);
+        lockdep::lock_acquire(class, addr, G::KIND, true, Location::caller());
+        Some(BaseSpinLockGuard { addr, inner })
+    }
+
+    /// Returns `true` if the lock is currently held.
+    ///
+    /// # Safety
+    ///
+    /// This function provides no synchronization guarantees and so its
+    /// result should be considered 'out of date' the instant it is called.
+    #[inline(always)]
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Force unlock this [`BaseSpinLock`].
+    ///
+    /// # Safety
+    ///
+    /// This is *extremely* unsafe if the lock is not held by the current
+    /// thread.
+    #[inline(always)]
+    pub unsafe fn force_unlock(&self) {
+        lockdep::lock_release(self.addr());
+        self.inner.force_unlock()
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the [`BaseSpinLock`] mutably, no actual
+    /// locking needs to take place.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<G: BaseGuard, T: Default> Default for BaseSpinLock<G, T> {
+    #[inline(always)]
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized + fmt::Debug> fmt::Debug for BaseSpinLock<G, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> Deref for BaseSpinLockGuard<'_, G, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> DerefMut for BaseSpinLockGuard<'_, G, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized + fmt::Debug> fmt::Debug for BaseSpinLockGuard<'_, G, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> Drop for BaseSpinLockGuard<'_, G, T> {
+    /// The dropping of the guard will release the lock it was created from.
+    #[inline(always)]
+    fn drop(&mut self) {
+        lockdep::lock_release(self.addr);
+    }
+}
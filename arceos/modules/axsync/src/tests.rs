@@ -0,0 +1,247 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex as StdMutex, Once as StdOnce};
+
+use axtask as thread;
+
+use crate::{Barrier, Condvar, LazyLock, Mutex, Once, RwLock, Semaphore};
+
+pub static INIT: StdOnce = StdOnce::new();
+pub static SERIAL: StdMutex<()> = StdMutex::new(());
+
+pub fn may_interrupt() {
+    // simulate interrupts
+    if rand::random::<u32>() % 3 == 0 {
+        thread::yield_now();
+    }
+}
+
+fn join_all(tasks: Vec<thread::AxTaskRef>) {
+    for task in tasks {
+        assert_eq!(task.join(), Some(0));
+    }
+}
+
+#[test]
+fn test_rwlock() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(thread::init_scheduler);
+
+    const NUM_TASKS: usize = 10;
+    const NUM_ITERS: usize = 1000;
+    static LOCK: RwLock<(usize, usize)> = RwLock::new((0, 0));
+
+    let mut tasks = Vec::new();
+    for _ in 0..NUM_TASKS {
+        tasks.push(thread::spawn(|| {
+            for _ in 0..NUM_ITERS {
+                let mut val = LOCK.write();
+                val.0 += 1;
+                may_interrupt();
+                val.1 += 1;
+            }
+        }));
+        tasks.push(thread::spawn(|| {
+            for _ in 0..NUM_ITERS {
+                let val = LOCK.read();
+                may_interrupt();
+                assert_eq!(val.0, val.1);
+            }
+        }));
+    }
+    join_all(tasks);
+    assert_eq!(*LOCK.read(), (NUM_TASKS * NUM_ITERS, NUM_TASKS * NUM_ITERS));
+}
+
+#[test]
+fn test_condvar() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(thread::init_scheduler);
+
+    const NUM_TASKS: usize = 10;
+    static STARTED: Mutex<usize> = Mutex::new(0);
+    static CVAR: Condvar = Condvar::new();
+
+    let mut tasks = Vec::new();
+    for _ in 0..NUM_TASKS {
+        tasks.push(thread::spawn(|| {
+            may_interrupt();
+            *STARTED.lock() += 1;
+            CVAR.notify_all();
+        }));
+    }
+    let started = CVAR.wait_while(STARTED.lock(), |n| *n < NUM_TASKS);
+    assert_eq!(*started, NUM_TASKS);
+    drop(started);
+    join_all(tasks);
+}
+
+#[test]
+fn test_semaphore() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(thread::init_scheduler);
+
+    const NUM_TASKS: usize = 10;
+    const NUM_PERMITS: usize = 3;
+    static SEM: Semaphore = Semaphore::new(NUM_PERMITS);
+    static HOLDERS: AtomicUsize = AtomicUsize::new(0);
+
+    let mut tasks = Vec::new();
+    for _ in 0..NUM_TASKS {
+        tasks.push(thread::spawn(|| {
+            for _ in 0..100 {
+                SEM.acquire();
+                let holders = HOLDERS.fetch_add(1, Ordering::Relaxed) + 1;
+                assert!(holders <= NUM_PERMITS);
+                may_interrupt();
+                HOLDERS.fetch_sub(1, Ordering::Relaxed);
+                SEM.release();
+            }
+        }));
+    }
+    join_all(tasks);
+    assert_eq!(SEM.available_permits(), NUM_PERMITS);
+}
+
+#[test]
+fn test_barrier() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(thread::init_scheduler);
+
+    const NUM_TASKS: usize = 10;
+    const NUM_ROUNDS: usize = 5;
+    static BARRIER: Barrier = Barrier::new(NUM_TASKS);
+    static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+    static LEADERS: AtomicUsize = AtomicUsize::new(0);
+
+    let mut tasks = Vec::new();
+    for _ in 0..NUM_TASKS {
+        tasks.push(thread::spawn(|| {
+            for round in 0..NUM_ROUNDS {
+                may_interrupt();
+                ARRIVED.fetch_add(1, Ordering::Relaxed);
+                if BARRIER.wait().is_leader() {
+                    LEADERS.fetch_add(1, Ordering::Relaxed);
+                }
+                assert!(ARRIVED.load(Ordering::Relaxed) >= (round + 1) * NUM_TASKS);
+                BARRIER.wait();
+            }
+        }));
+    }
+    join_all(tasks);
+    assert_eq!(LEADERS.load(Ordering::Relaxed), NUM_ROUNDS);
+}
+
+#[test]
+fn test_once() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(thread::init_scheduler);
+
+    const NUM_TASKS: usize = 10;
+    static ONCE: Once = Once::new();
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    static LAZY: LazyLock<usize> = LazyLock::new(|| {
+        may_interrupt();
+        CALLS.fetch_add(1, Ordering::Relaxed) + 42
+    });
+
+    let mut tasks = Vec::new();
+    for _ in 0..NUM_TASKS {
+        tasks.push(thread::spawn(|| {
+            ONCE.call_once(|| {
+                thread::yield_now();
+                CALLS.fetch_add(1, Ordering::Relaxed);
+            });
+            assert!(ONCE.is_completed());
+            assert_eq!(*LAZY, 43);
+        }));
+    }
+    join_all(tasks);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn test_pi_mutex() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(thread::init_scheduler);
+
+    const NUM_TASKS: usize = 10;
+    const NUM_ITERS: usize = 1000;
+    static M: Mutex<usize> = Mutex::new_pi(0);
+
+    let mut tasks = Vec::new();
+    for _ in 0..NUM_TASKS {
+        tasks.push(thread::spawn(|| {
+            for _ in 0..NUM_ITERS {
+                let mut val = M.lock();
+                *val += 1;
+                may_interrupt();
+            }
+        }));
+    }
+    join_all(tasks);
+    assert_eq!(*M.lock(), NUM_TASKS * NUM_ITERS);
+    assert_eq!(thread::current().priority(), 0);
+
+    // The owner runs with the priority of the highest-priority waiter.
+    static PI: Mutex<Vec<isize>> = Mutex::new_pi(Vec::new());
+    let wait_blocked = |task: &thread::AxTaskRef| {
+        while task.wait_reason().is_none() {
+            thread::yield_now();
+        }
+    };
+    let curr = thread::current().as_task_ref().clone();
+    let mut guard = PI.lock();
+    let mut waiters = Vec::new();
+    for prio in [-2, -5] {
+        let task = thread::spawn(move || PI.lock().push(prio));
+        thread::set_base_priority_for_test(&task, prio);
+        wait_blocked(&task);
+        assert_eq!(curr.priority(), prio);
+        waiters.push(task);
+    }
+
+    // Dropped on unlocking, even if the woken waiter does not win the lock.
+    drop(guard);
+    assert_eq!(curr.priority(), 0);
+    guard = PI.lock();
+    assert_eq!(curr.priority(), 0);
+    // The woken waiter passes its priority to the new owner.
+    wait_blocked(&waiters[1]);
+    assert_eq!(curr.priority(), -5);
+    assert_eq!(waiters[0].priority(), -2);
+    drop(guard);
+    assert_eq!(curr.priority(), 0);
+
+    join_all(waiters);
+    assert_eq!(*PI.lock(), [-5, -2]);
+    assert_eq!(curr.priority(), 0);
+}
+
+#[cfg(feature = "lockdep")]
+#[test]
+fn test_lockdep() {
+    use axtask::lockdep::num_reports;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(thread::init_scheduler);
+
+    static A: Mutex<()> = Mutex::new(());
+    static B: Mutex<()> = Mutex::new(());
+    static S: crate::spin::SpinRaw<()> = crate::spin::SpinRaw::new(());
+
+    let reports = num_reports();
+    {
+        let _a = A.lock();
+        let _b = B.lock();
+    }
+    assert_eq!(num_reports(), reports);
+    for _ in 0..2 {
+        let _b = B.lock();
+        let _a = A.lock(); // reported only once
+    }
+    assert_eq!(num_reports(), reports + 1);
+
+    let _s = S.lock();
+    let _a = A.lock(); // sleeping while holding a spinlock
+    assert_eq!(num_reports(), reports + 2);
+}
@@ -0,0 +1,146 @@
+//! Typed syscall arguments and return values.
+
+use core::fmt;
+
+use axerrno::LinuxResult;
+use axhal::arch::TrapFrame;
+use axmm::{UserCStr, UserPtr};
+
+/// Types that can be decoded from a raw syscall argument.
+pub trait SyscallArg: Sized {
+    /// Decodes the argument from the raw register value.
+    fn from_raw(raw: usize) -> Self;
+
+    /// Formats the raw argument for syscall tracing, in hexadecimal by
+    /// default.
+    fn fmt_raw(raw: usize, f: &mut dyn fmt::Write) -> fmt::Result {
+        write!(f, "{:#x}", raw)
+    }
+}
+
+macro_rules! impl_int_arg {
+    ($($t:ty),*) => {
+        $(impl SyscallArg for $t {
+            fn from_raw(raw: usize) -> Self {
+                // Truncating is intended, the upper bits of 32-bit arguments
+                // are undefined.
+                raw as $t
+            }
+
+            fn fmt_raw(raw: usize, f: &mut dyn fmt::Write) -> fmt::Result {
+                write!(f, "{}", Self::from_raw(raw))
+            }
+        })*
+    };
+}
+
+impl_int_arg!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl SyscallArg for bool {
+    fn from_raw(raw: usize) -> Self {
+        raw != 0
+    }
+
+    fn fmt_raw(raw: usize, f: &mut dyn fmt::Write) -> fmt::Result {
+        write!(f, "{}", Self::from_raw(raw))
+    }
+}
+
+impl<T> SyscallArg for *const T {
+    fn from_raw(raw: usize) -> Self {
+        raw as _
+    }
+}
+
+impl<T> SyscallArg for *mut T {
+    fn from_raw(raw: usize) -> Self {
+        raw as _
+    }
+}
+
+impl<T> SyscallArg for UserPtr<T> {
+    fn from_raw(raw: usize) -> Self {
+        UserPtr::new(raw)
+    }
+}
+
+impl SyscallArg for UserCStr {
+    fn from_raw(raw: usize) -> Self {
+        UserCStr::new(raw)
+    }
+}
+
+/// The syscall arguments in a trap frame, decoded one by one in order.
+pub struct SyscallArgs {
+    args: [usize; 6],
+    next: usize,
+}
+
+impl SyscallArgs {
+    /// Reads the raw arguments from the trap frame.
+    pub const fn new(tf: &TrapFrame) -> Self {
+        Self {
+            args: [
+                tf.arg0(),
+                tf.arg1(),
+                tf.arg2(),
+                tf.arg3(),
+                tf.arg4(),
+                tf.arg5(),
+            ],
+            next: 0,
+        }
+    }
+
+    /// Returns the raw arguments.
+    pub const fn raw(&self) -> &[usize; 6] {
+        &self.args
+    }
+
+    /// Decodes the next argument.
+    pub fn decode<T: SyscallArg>(&mut self) -> T {
+        let raw = self.args[self.next];
+        self.next += 1;
+        T::from_raw(raw)
+    }
+}
+
+/// Types that can be returned to user space from a syscall.
+pub trait SyscallRet {
+    /// Converts the value to the raw return value, errors are negated error
+    /// numbers.
+    fn into_raw(self) -> isize;
+}
+
+impl SyscallRet for isize {
+    fn into_raw(self) -> isize {
+        self
+    }
+}
+
+impl SyscallRet for i32 {
+    fn into_raw(self) -> isize {
+        self as _
+    }
+}
+
+impl SyscallRet for usize {
+    fn into_raw(self) -> isize {
+        self as _
+    }
+}
+
+impl SyscallRet for () {
+    fn into_raw(self) -> isize {
+        0
+    }
+}
+
+impl<T: SyscallRet> SyscallRet for LinuxResult<T> {
+    fn into_raw(self) -> isize {
+        match self {
+            Ok(v) => v.into_raw(),
+            Err(e) => -e.code() as isize,
+        }
+    }
+}
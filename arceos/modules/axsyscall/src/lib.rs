@@ -0,0 +1,138 @@
+//! [ArceOS](https://github.com/arceos-org/arceos) syscall dispatching for
+//! monolithic kernels.
+//!
+//! Syscalls are identified by [`Sysno`], which is translated from the syscall
+//! number of the target architecture, so handlers are written once for all
+//! the architectures. Each handler is registered separately with
+//! [`register_syscall!`], which also decodes the arguments from the trap
+//! frame according to the handler signature:
+//!
+//! ```ignore
+//! fn sys_write(fd: i32, buf: *const u8, count: usize) -> isize { ... }
+//! fn sys_clone(tf: &TrapFrame, flags: u32, stack: usize) -> LinuxResult<usize> { ... }
+//!
+//! axsyscall::register_syscall!(Write => sys_write(i32, *const u8, usize));
+//! // The trap frame is passed first if requested with `tf`.
+//! axsyscall::register_syscall!(Clone => sys_clone(tf, u32, usize));
+//! ```
+//!
+//! Syscalls can be traced like `strace` with the [`trace`] module.
+//!
+//! The crate registers the [`SYSCALL`](axhal::trap::SYSCALL) trap handler,
+//! so kernels using it must not register their own.
+
+#![no_std]
+
+#[macro_use]
+extern crate log;
+extern crate alloc;
+
+mod args;
+mod sysno;
+
+pub mod trace;
+
+use core::fmt;
+
+use axerrno::LinuxError;
+use axhal::arch::TrapFrame;
+use axhal::trap::{register_trap_handler, SYSCALL};
+use linkme::distributed_slice;
+
+pub use self::args::{SyscallArg, SyscallArgs, SyscallRet};
+pub use self::sysno::Sysno;
+
+#[doc(hidden)]
+pub use axhal::arch::TrapFrame as __TrapFrame;
+#[doc(hidden)]
+pub use core::fmt as __fmt;
+#[doc(hidden)]
+pub use linkme as __linkme;
+
+/// A syscall handler registered with [`register_syscall!`].
+pub struct SyscallHandler {
+    /// The syscall handled.
+    pub sysno: Sysno,
+    /// The handler function, which decodes the arguments by itself.
+    pub handler: fn(&TrapFrame) -> isize,
+    /// Formats the raw arguments as the handler decodes them.
+    pub fmt_args: fn(&[usize; 6], &mut dyn fmt::Write) -> fmt::Result,
+}
+
+/// The registered syscall handlers.
+#[distributed_slice]
+pub static SYSCALL_HANDLERS: [SyscallHandler];
+
+/// Registers a handler for a syscall.
+///
+/// The syscall is given by the name of the [`Sysno`] variant, followed by the
+/// handler function and its argument types. The arguments are decoded with
+/// [`SyscallArg`] in order, and the return value is converted with
+/// [`SyscallRet`]. If the first argument is `tf`, the handler receives the
+/// trap frame of the syscall as well.
+#[macro_export]
+macro_rules! register_syscall {
+    ($sysno:ident => $handler:ident(tf $(, $ty:ty)* $(,)?)) => {
+        $crate::__register_syscall!($sysno, $($ty),*; |tf: &$crate::__TrapFrame| {
+            let mut _args = $crate::SyscallArgs::new(tf);
+            $crate::SyscallRet::into_raw($handler(tf $(, _args.decode::<$ty>())*))
+        });
+    };
+    ($sysno:ident => $handler:ident($($ty:ty),* $(,)?)) => {
+        $crate::__register_syscall!($sysno, $($ty),*; |tf: &$crate::__TrapFrame| {
+            let mut _args = $crate::SyscallArgs::new(tf);
+            $crate::SyscallRet::into_raw($handler($(_args.decode::<$ty>()),*))
+        });
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __register_syscall {
+    ($sysno:ident, $($ty:ty),*; $handler:expr) => {
+        const _: () = {
+            fn fmt_args(
+                args: &[usize; 6],
+                f: &mut dyn $crate::__fmt::Write,
+            ) -> $crate::__fmt::Result {
+                $crate::trace::fmt_args(&[$(<$ty as $crate::SyscallArg>::fmt_raw),*], args, f)
+            }
+
+            #[$crate::__linkme::distributed_slice($crate::SYSCALL_HANDLERS)]
+            #[linkme(crate = $crate::__linkme)]
+            static HANDLER: $crate::SyscallHandler = $crate::SyscallHandler {
+                sysno: $crate::Sysno::$sysno,
+                handler: $handler,
+                fmt_args,
+            };
+        };
+    };
+}
+
+/// Returns the registered handler of the syscall.
+pub fn syscall_handler(sysno: Sysno) -> Option<&'static SyscallHandler> {
+    let mut iter = SYSCALL_HANDLERS.iter().filter(|h| h.sysno == sysno);
+    let handler = iter.next()?;
+    if iter.next().is_some() {
+        warn!("Multiple handlers for syscall {} registered", sysno.name());
+    }
+    Some(handler)
+}
+
+/// Dispatches the syscall of the number `syscall_num` to its handler, and
+/// traces it if [tracing](trace) is enabled.
+pub fn dispatch_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
+    match Sysno::from_number(syscall_num).and_then(syscall_handler) {
+        Some(handler) if trace::is_enabled() => trace::traced_call(handler, tf),
+        Some(handler) => (handler.handler)(tf),
+        None => {
+            warn!("Unimplemented syscall: {}", syscall_num);
+            -LinuxError::ENOSYS.code() as _
+        }
+    }
+}
+
+#[register_trap_handler(SYSCALL)]
+fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
+    dispatch_syscall(tf, syscall_num)
+}
@@ -0,0 +1,120 @@
+//! Syscall numbers of the supported architectures.
+
+macro_rules! define_sysno {
+    ($($sys:ident => $name:ident = $generic:literal, $x86_64:literal;)*) => {
+        /// Architecture-independent syscall identifiers.
+        #[repr(usize)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub enum Sysno {
+            $(#[doc = concat!("`", stringify!($sys), "`")] $name,)*
+        }
+
+        impl Sysno {
+            /// All the syscalls.
+            pub const ALL: &'static [Sysno] = &[$(Self::$name,)*];
+
+            /// Looks up the syscall by its number on the target architecture.
+            pub const fn from_number(num: usize) -> Option<Self> {
+                match num {
+                    $(_ if num == Self::$name.number() => Some(Self::$name),)*
+                    _ => None,
+                }
+            }
+
+            /// Returns the syscall number on the target architecture.
+            pub const fn number(self) -> usize {
+                match self {
+                    $(Self::$name => {
+                        if cfg!(target_arch = "x86_64") {
+                            $x86_64
+                        } else {
+                            $generic
+                        }
+                    })*
+                }
+            }
+
+            /// Returns the syscall name without the `sys_` prefix.
+            pub const fn name(self) -> &'static str {
+                match self {
+                    $(Self::$name => stringify!($sys),)*
+                }
+            }
+        }
+    };
+}
+
+// The first number is from the generic table (`asm-generic/unistd.h`) shared
+// by riscv64 and aarch64, and the second is from the x86_64 table
+// (`arch/x86/entry/syscalls/syscall_64.tbl`). Syscalls not available on all
+// the architectures (e.g., `open` and `fork` on x86_64) are not listed.
+define_sysno! {
+    getcwd => Getcwd = 17, 79;
+    dup => Dup = 23, 32;
+    dup3 => Dup3 = 24, 292;
+    fcntl => Fcntl = 25, 72;
+    ioctl => Ioctl = 29, 16;
+    mkdirat => Mkdirat = 34, 258;
+    unlinkat => Unlinkat = 35, 263;
+    ftruncate => Ftruncate = 46, 77;
+    openat => Openat = 56, 257;
+    close => Close = 57, 3;
+    pipe2 => Pipe2 = 59, 293;
+    getdents64 => Getdents64 = 61, 217;
+    lseek => Lseek = 62, 8;
+    read => Read = 63, 0;
+    write => Write = 64, 1;
+    readv => Readv = 65, 19;
+    writev => Writev = 66, 20;
+    readlinkat => Readlinkat = 78, 267;
+    newfstatat => Newfstatat = 79, 262;
+    fstat => Fstat = 80, 5;
+    exit => Exit = 93, 60;
+    exit_group => ExitGroup = 94, 231;
+    set_tid_address => SetTidAddress = 96, 218;
+    futex => Futex = 98, 202;
+    set_robust_list => SetRobustList = 99, 273;
+    get_robust_list => GetRobustList = 100, 274;
+    nanosleep => Nanosleep = 101, 35;
+    clock_gettime => ClockGettime = 113, 228;
+    sched_setaffinity => SchedSetaffinity = 122, 203;
+    sched_getaffinity => SchedGetaffinity = 123, 204;
+    sched_yield => SchedYield = 124, 24;
+    kill => Kill = 129, 62;
+    tkill => Tkill = 130, 200;
+    tgkill => Tgkill = 131, 234;
+    sigaltstack => Sigaltstack = 132, 131;
+    rt_sigaction => RtSigaction = 134, 13;
+    rt_sigprocmask => RtSigprocmask = 135, 14;
+    rt_sigpending => RtSigpending = 136, 127;
+    rt_sigreturn => RtSigreturn = 139, 15;
+    times => Times = 153, 100;
+    uname => Uname = 160, 63;
+    getrusage => Getrusage = 165, 98;
+    gettimeofday => Gettimeofday = 169, 96;
+    getpid => Getpid = 172, 39;
+    getppid => Getppid = 173, 110;
+    getuid => Getuid = 174, 102;
+    geteuid => Geteuid = 175, 107;
+    getgid => Getgid = 176, 104;
+    getegid => Getegid = 177, 108;
+    gettid => Gettid = 178, 186;
+    shmget => Shmget = 194, 29;
+    shmctl => Shmctl = 195, 31;
+    shmat => Shmat = 196, 30;
+    shmdt => Shmdt = 197, 67;
+    brk => Brk = 214, 12;
+    munmap => Munmap = 215, 11;
+    mremap => Mremap = 216, 25;
+    clone => Clone = 220, 56;
+    execve => Execve = 221, 59;
+    mmap => Mmap = 222, 9;
+    mprotect => Mprotect = 226, 10;
+    msync => Msync = 227, 26;
+    wait4 => Wait4 = 260, 61;
+    prlimit64 => Prlimit64 = 261, 302;
+    sched_setattr => SchedSetattr = 274, 314;
+    sched_getattr => SchedGetattr = 275, 315;
+    getrandom => Getrandom = 278, 318;
+    memfd_create => MemfdCreate = 279, 319;
+}
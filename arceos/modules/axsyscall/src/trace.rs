@@ -0,0 +1,233 @@
+//! `strace`-like syscall tracing.
+//!
+//! When tracing is enabled, each dispatched syscall that passes the task and
+//! syscall filters is recorded with the calling task, the decoded arguments,
+//! the return value and the time it took. The latest [`MAX_RECORDS`] records
+//! are kept, which can be dumped to the log or to a file (e.g.,
+//! `/proc/strace`). Optionally, each record is also logged once the syscall
+//! returns.
+//!
+//! `exit` and `exit_group` never return, so they are recorded before being
+//! handled, without a return value. `execve` is recorded only if it fails.
+
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use axerrno::LinuxError;
+use axhal::arch::TrapFrame;
+use axhal::time::{monotonic_time, TimeValue};
+use axsync::spin::SpinNoIrq;
+
+use crate::{SyscallHandler, Sysno};
+
+/// The maximum number of records kept, older records are dropped first.
+pub const MAX_RECORDS: usize = 4096;
+
+/// The largest error number, return values in `-MAX_ERRNO..0` are errors.
+const MAX_ERRNO: isize = 4095;
+
+/// A traced syscall.
+#[derive(Clone)]
+pub struct SyscallRecord {
+    /// The ID of the calling task.
+    pub tid: u64,
+    /// The syscall.
+    pub sysno: Sysno,
+    /// The raw arguments.
+    pub args: [usize; 6],
+    /// The raw return value, `None` if the syscall does not return.
+    pub ret: Option<isize>,
+    /// The monotonic time when the syscall was made.
+    pub start: TimeValue,
+    /// How long the syscall took.
+    pub duration: TimeValue,
+    fmt_args: fn(&[usize; 6], &mut dyn fmt::Write) -> fmt::Result,
+}
+
+impl fmt::Display for SyscallRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:>5}.{:06}] {:<4} {}(",
+            self.start.as_secs(),
+            self.start.subsec_micros(),
+            self.tid,
+            self.sysno.name()
+        )?;
+        (self.fmt_args)(&self.args, f)?;
+        f.write_str(") = ")?;
+        match self.ret {
+            None => f.write_str("?"),
+            Some(ret) if (-MAX_ERRNO..0).contains(&ret) => {
+                match LinuxError::try_from(-ret as i32) {
+                    Ok(e) => write!(f, "-1 {:?} ({})", e, e.as_str()),
+                    Err(_) => write!(f, "{}", ret),
+                }
+            }
+            Some(ret) => write!(f, "{}", ret),
+        }?;
+        write!(
+            f,
+            " <{}.{:06}>",
+            self.duration.as_secs(),
+            self.duration.subsec_micros()
+        )
+    }
+}
+
+struct TraceConfig {
+    log: bool,
+    tasks: Option<BTreeSet<u64>>,
+    syscalls: Option<BTreeSet<Sysno>>,
+}
+
+impl TraceConfig {
+    fn matches(&self, tid: u64, sysno: Sysno) -> bool {
+        self.tasks.as_ref().map_or(true, |t| t.contains(&tid))
+            && self.syscalls.as_ref().map_or(true, |s| s.contains(&sysno))
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static CONFIG: SpinNoIrq<TraceConfig> = SpinNoIrq::new(TraceConfig {
+    log: false,
+    tasks: None,
+    syscalls: None,
+});
+
+static RECORDS: SpinNoIrq<VecDeque<SyscallRecord>> = SpinNoIrq::new(VecDeque::new());
+
+/// Starts tracing syscalls.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Release);
+}
+
+/// Stops tracing syscalls, the records are kept.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Release);
+}
+
+/// Whether syscalls are being traced.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Sets whether to log each record when the syscall returns.
+pub fn set_log(log: bool) {
+    CONFIG.lock().log = log;
+}
+
+/// Only traces the syscalls made by the given tasks, or by all tasks if
+/// `None`.
+pub fn set_task_filter(tids: Option<&[u64]>) {
+    CONFIG.lock().tasks = tids.map(|tids| tids.iter().copied().collect());
+}
+
+/// Only traces the given syscalls, or all syscalls if `None`.
+pub fn set_syscall_filter(syscalls: Option<&[Sysno]>) {
+    CONFIG.lock().syscalls = syscalls.map(|s| s.iter().copied().collect());
+}
+
+/// Returns the records from the oldest to the latest.
+pub fn records() -> Vec<SyscallRecord> {
+    RECORDS.lock().iter().cloned().collect()
+}
+
+/// Returns the records of the task from the oldest to the latest.
+pub fn task_records(tid: u64) -> Vec<SyscallRecord> {
+    RECORDS
+        .lock()
+        .iter()
+        .filter(|r| r.tid == tid)
+        .cloned()
+        .collect()
+}
+
+/// Drops all the records.
+pub fn clear() {
+    RECORDS.lock().clear();
+}
+
+/// Writes all the records, one per line.
+pub fn dump(f: &mut dyn fmt::Write) -> fmt::Result {
+    for record in records() {
+        writeln!(f, "{}", record)?;
+    }
+    Ok(())
+}
+
+/// Writes all the records to the log.
+pub fn dump_to_log() {
+    for record in records() {
+        info!("{}", record);
+    }
+}
+
+/// Writes all the records to the file at `path`, which is created or
+/// truncated.
+#[cfg(feature = "fs")]
+pub fn dump_to_file(path: &str) -> axerrno::AxResult {
+    let mut buf = alloc::string::String::new();
+    dump(&mut buf).map_err(|_| axerrno::AxError::NoMemory)?;
+    axfs::api::write(path, buf)
+}
+
+fn push_record(record: SyscallRecord, log: bool) {
+    if log {
+        info!("{}", record);
+    }
+    let mut records = RECORDS.lock();
+    if records.len() == MAX_RECORDS {
+        records.pop_front();
+    }
+    records.push_back(record);
+}
+
+/// Calls the syscall handler and records the syscall.
+pub(crate) fn traced_call(handler: &SyscallHandler, tf: &TrapFrame) -> isize {
+    let tid = axtask::current().id().as_u64();
+    let log = {
+        let config = CONFIG.lock();
+        if !config.matches(tid, handler.sysno) {
+            return (handler.handler)(tf);
+        }
+        config.log
+    };
+    let mut record = SyscallRecord {
+        tid,
+        sysno: handler.sysno,
+        args: *crate::SyscallArgs::new(tf).raw(),
+        ret: None,
+        start: monotonic_time(),
+        duration: TimeValue::ZERO,
+        fmt_args: handler.fmt_args,
+    };
+    if matches!(handler.sysno, Sysno::Exit | Sysno::ExitGroup) {
+        push_record(record, log);
+        return (handler.handler)(tf);
+    }
+    let ret = (handler.handler)(tf);
+    record.ret = Some(ret);
+    record.duration = monotonic_time().saturating_sub(record.start);
+    push_record(record, log);
+    ret
+}
+
+/// Formats the raw arguments with the formatting functions of their types.
+#[doc(hidden)]
+pub fn fmt_args(
+    fmts: &[fn(usize, &mut dyn fmt::Write) -> fmt::Result],
+    args: &[usize; 6],
+    f: &mut dyn fmt::Write,
+) -> fmt::Result {
+    for (i, (fmt, &raw)) in fmts.iter().zip(args).enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        fmt(raw, f)?;
+    }
+    Ok(())
+}
@@ -2,30 +2,51 @@
 
 use alloc::{string::String, sync::Arc};
 
-pub(crate) use crate::run_queue::{AxRunQueue, RUN_QUEUE};
+pub(crate) use crate::run_queue::{current_run_queue, AxRunQueue};
 
 #[doc(cfg(feature = "multitask"))]
-pub use crate::task::{CurrentTask, TaskId, TaskInner};
+pub use crate::cpumask::CpuMask;
+#[doc(cfg(feature = "multitask"))]
+pub use crate::stat::{tasks, total_stat, TaskStat, WaitReason};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::task::{CurrentTask, TaskId, TaskInner, TaskState};
 #[doc(cfg(feature = "multitask"))]
 pub use crate::task_ext::{TaskExtMut, TaskExtRef};
 #[doc(cfg(feature = "multitask"))]
-pub use crate::wait_queue::WaitQueue;
+pub use crate::wait_queue::{Interrupted, WaitQueue};
+
+#[cfg(feature = "paging")]
+pub use crate::kstack::KernelStackIf;
+#[cfg(feature = "sched_edf")]
+pub use crate::sched_edf::EdfParams;
 
 /// The reference type of a task.
 pub type AxTaskRef = Arc<AxTask>;
 
+/// The exit code of the tasks exited by [`kill`].
+pub const KILLED_EXIT_CODE: i32 = i32::MIN;
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "sched_rr")] {
         const MAX_TIME_SLICE: usize = 5;
         pub(crate) type AxTask = scheduler::RRTask<TaskInner, MAX_TIME_SLICE>;
-        pub(crate) type Scheduler = scheduler::RRScheduler<TaskInner, MAX_TIME_SLICE>;
+        pub(crate) type BestEffortScheduler = scheduler::RRScheduler<TaskInner, MAX_TIME_SLICE>;
     } else if #[cfg(feature = "sched_cfs")] {
         pub(crate) type AxTask = scheduler::CFSTask<TaskInner>;
-        pub(crate) type Scheduler = scheduler::CFScheduler<TaskInner>;
+        pub(crate) type BestEffortScheduler = scheduler::CFScheduler<TaskInner>;
     } else {
         // If no scheduler features are set, use FIFO as the default.
         pub(crate) type AxTask = scheduler::FifoTask<TaskInner>;
-        pub(crate) type Scheduler = scheduler::FifoScheduler<TaskInner>;
+        pub(crate) type BestEffortScheduler = scheduler::FifoScheduler<TaskInner>;
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "sched_edf")] {
+        // Real-time tasks run before the others.
+        pub(crate) type Scheduler = crate::sched_edf::EdfScheduler;
+    } else {
+        pub(crate) type Scheduler = BestEffortScheduler;
     }
 }
 
@@ -85,14 +106,18 @@ pub fn init_scheduler_secondary() {
 #[cfg(feature = "irq")]
 #[doc(cfg(feature = "irq"))]
 pub fn on_timer_tick() {
+    #[cfg(feature = "lockdep")]
+    crate::lockdep::set_irqs_online();
     crate::timers::check_events();
-    RUN_QUEUE.lock().scheduler_timer_tick();
+    #[cfg(feature = "paging")]
+    crate::kstack::flush_stale_stacks();
+    current_run_queue().scheduler_timer_tick();
 }
 
 /// Adds the given task to the run queue, returns the task reference.
 pub fn spawn_task(task: TaskInner) -> AxTaskRef {
     let task_ref = task.into_arc();
-    RUN_QUEUE.lock().add_task(task_ref.clone());
+    current_run_queue().add_task(task_ref.clone());
     task_ref
 }
 
@@ -129,18 +154,126 @@ where
 ///
 /// [CFS]: https://en.wikipedia.org/wiki/Completely_Fair_Scheduler
 pub fn set_priority(prio: isize) -> bool {
-    RUN_QUEUE.lock().set_current_priority(prio)
+    current_run_queue().set_current_priority(prio)
+}
+
+/// Makes the task `owner`, which holds the lock `key`, inherit the priority
+/// of the current task, as the current task is going to wait for the lock
+/// (see [`TaskInner::priority`]).
+///
+/// It lasts until [`drop_inherited_priority`] is called with the same lock,
+/// usually when the owner releases it. The priority is not passed on if the
+/// owner is waiting for another lock.
+///
+/// Only the `sched_cfs` scheduler runs the owner with the inherited
+/// priority. The other schedulers ignore priorities, and only the wakeup
+/// order of [`WaitQueue::notify_one_by_priority`] follows them.
+pub fn inherit_priority(owner: &AxTaskRef, key: usize) {
+    let prio = current().priority();
+    current_run_queue().inherit_priority(owner, key, prio);
+}
+
+/// Drops the priority inherited by `task` through the lock `key`, see
+/// [`inherit_priority`].
+pub fn drop_inherited_priority(task: &AxTaskRef, key: usize) {
+    current_run_queue().drop_inherited_priority(task, key);
+}
+
+/// Sets the priority of `task` without passing it to the scheduler, so that
+/// priority inheritance can be tested with any scheduler.
+#[cfg(feature = "test")]
+#[doc(hidden)]
+pub fn set_base_priority_for_test(task: &AxTaskRef, prio: isize) {
+    task.set_base_priority(prio);
+}
+
+/// Sets the CPUs the current task is allowed to run on.
+///
+/// The current task is moved to one of them at once if the current CPU is not
+/// in `cpumask`. Returns `false` if `cpumask` is empty, or the task is in the
+/// real-time class, which pins it to the CPU it is admitted on.
+pub fn set_current_affinity(cpumask: CpuMask) -> bool {
+    let curr = current();
+    #[cfg(feature = "sched_edf")]
+    if curr.edf_state().lock().is_some() {
+        return false;
+    }
+    if !curr.set_affinity(cpumask) {
+        return false;
+    }
+    if !cpumask.get(axhal::cpu::this_cpu_id()) {
+        yield_now();
+    }
+    true
+}
+
+/// Moves the current task into the real-time class with the given parameters,
+/// or back to the best-effort class if `params` is `None`.
+///
+/// The task runs for at most `params.runtime` in each period, and yielding
+/// ends its run in the current period. It is pinned to the CPU it is admitted
+/// on, and moved there at once. Returns `false` if the parameters are
+/// invalid, or no CPU the task is allowed to run on has enough bandwidth
+/// left.
+#[cfg(feature = "sched_edf")]
+pub fn set_current_edf(params: Option<EdfParams>) -> bool {
+    let curr = current();
+    if !crate::sched_edf::set_task_params(curr.as_task_ref(), params) {
+        return false;
+    }
+    if !curr.cpumask().get(axhal::cpu::this_cpu_id()) {
+        yield_now();
+    }
+    true
+}
+
+/// Changes the page table root of the current task, and switches to the new
+/// page table at once, e.g., when the task replaces its address space.
+///
+/// # Safety
+///
+/// The new page table must map the kernel in the same way as the old one.
+#[cfg(feature = "uspace")]
+pub unsafe fn set_current_page_table_root(root: axhal::mem::PhysAddr) {
+    let _guard = kernel_guard::NoPreempt::new();
+    (*current().ctx_mut_ptr()).set_page_table_root(root);
+    axhal::arch::write_page_table_root(root);
+}
+
+/// Requests the given task to exit.
+///
+/// The task is woken up if it is in a killable wait of [`WaitQueue`], which
+/// returns [`Interrupted`]. It exits with [`KILLED_EXIT_CODE`] only when it
+/// calls [`exit_if_killed`], which it should do where it holds no locks, as
+/// destructors of the values on its stack are not run. A task never calling
+/// it is never stopped.
+///
+/// Returns `false` if the task is an idle task, or has already exited.
+pub fn kill(task: &AxTaskRef) -> bool {
+    if task.is_idle() || task.state() == TaskState::Exited {
+        return false;
+    }
+    current_run_queue().kill_task(task.clone());
+    true
+}
+
+/// Exits the current task if it has been [`kill`]ed.
+pub fn exit_if_killed() {
+    if current().is_kill_pending() {
+        exit(KILLED_EXIT_CODE);
+    }
 }
 
 /// Current task gives up the CPU time voluntarily, and switches to another
 /// ready task.
 pub fn yield_now() {
-    RUN_QUEUE.lock().yield_current();
+    current_run_queue().yield_current();
 }
 
 /// Current task is going to sleep for the given duration.
 ///
 /// If the feature `irq` is not enabled, it uses busy-wait instead.
+#[cfg_attr(feature = "lockdep", track_caller)]
 pub fn sleep(dur: core::time::Duration) {
     sleep_until(axhal::time::wall_time() + dur);
 }
@@ -148,26 +281,36 @@ pub fn sleep(dur: core::time::Duration) {
 /// Current task is going to sleep, it will be woken up at the given deadline.
 ///
 /// If the feature `irq` is not enabled, it uses busy-wait instead.
+#[cfg_attr(feature = "lockdep", track_caller)]
 pub fn sleep_until(deadline: axhal::time::TimeValue) {
+    #[cfg(feature = "lockdep")]
+    crate::lockdep::might_sleep(core::panic::Location::caller());
     #[cfg(feature = "irq")]
-    RUN_QUEUE.lock().sleep_until(deadline);
+    current_run_queue().sleep_until(deadline);
     #[cfg(not(feature = "irq"))]
     axhal::time::busy_wait_until(deadline);
 }
 
 /// Exits the current task.
 pub fn exit(exit_code: i32) -> ! {
-    RUN_QUEUE.lock().exit_current(exit_code)
+    current_run_queue().exit_current(exit_code)
 }
 
 /// The idle task routine.
 ///
-/// It runs an infinite loop that keeps calling [`yield_now()`].
+/// It runs an infinite loop that keeps calling [`yield_now()`], and waits for
+/// IRQs in between. On SMP, the tasks sent by other CPUs or left to be stolen
+/// are picked up when the next timer tick wakes this CPU up. Without IRQs, it
+/// keeps polling for them instead.
 pub fn run_idle() -> ! {
     loop {
         yield_now();
-        debug!("idle task: waiting for IRQs...");
         #[cfg(feature = "irq")]
-        axhal::arch::wait_for_irqs();
+        {
+            debug!("idle task: waiting for IRQs...");
+            axhal::arch::wait_for_irqs();
+        }
+        #[cfg(not(feature = "irq"))]
+        core::hint::spin_loop();
     }
 }
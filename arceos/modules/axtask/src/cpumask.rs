@@ -0,0 +1,93 @@
+use core::fmt;
+
+use axconfig::SMP;
+
+const _: () = assert!(SMP <= 64, "at most 64 CPUs are supported");
+
+/// A set of CPUs, e.g., the CPUs a task is allowed to run on.
+///
+/// CPU IDs not less than [`axconfig::SMP`] are never in the set.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CpuMask(u64);
+
+impl CpuMask {
+    const ALL_BITS: u64 = u64::MAX >> (64 - SMP);
+
+    /// Creates an empty set.
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    /// Creates a set of all the CPUs.
+    pub const fn full() -> Self {
+        Self(Self::ALL_BITS)
+    }
+
+    /// Creates a set of the single CPU `cpu_id`, which is empty if there is
+    /// no such CPU.
+    pub const fn one(cpu_id: usize) -> Self {
+        if cpu_id < SMP {
+            Self(1 << cpu_id)
+        } else {
+            Self(0)
+        }
+    }
+
+    /// Creates a set from a bitmask, where bit `i` stands for CPU `i`.
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits & Self::ALL_BITS)
+    }
+
+    /// Returns the bitmask of the set.
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Whether the CPU `cpu_id` is in the set.
+    pub const fn get(&self, cpu_id: usize) -> bool {
+        cpu_id < SMP && self.0 & (1 << cpu_id) != 0
+    }
+
+    /// Adds the CPU `cpu_id` to the set, or removes it if `value` is false.
+    pub fn set(&mut self, cpu_id: usize, value: bool) {
+        if cpu_id < SMP {
+            if value {
+                self.0 |= 1 << cpu_id;
+            } else {
+                self.0 &= !(1 << cpu_id);
+            }
+        }
+    }
+
+    /// Whether the set is empty.
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the smallest CPU ID in the set.
+    pub const fn first(&self) -> Option<usize> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.0.trailing_zeros() as usize)
+        }
+    }
+
+    /// Returns an iterator over the CPU IDs in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> {
+        let bits = self.0;
+        (0..SMP).filter(move |&i| bits & (1 << i) != 0)
+    }
+}
+
+impl Default for CpuMask {
+    fn default() -> Self {
+        Self::full()
+    }
+}
+
+impl fmt::Debug for CpuMask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,153 @@
+//! Kernel stacks with guard pages.
+//!
+//! Each stack is mapped at the top of a slot in
+//! [`kernel_stack_region`](axhal::mem::kernel_stack_region), whose lower half
+//! is never mapped. Overflowing the stack faults in the guard pages, and the
+//! overflowing task is reported before the kernel panics.
+//!
+//! Unmapping a stack only flushes the TLB of the current CPU. On SMP, a freed
+//! slot is quarantined until every CPU has flushed its TLB in
+//! [`flush_stale_stacks`] on the timer tick, so that no CPU can reach the old
+//! frames through a stale entry once the slot is reused.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axconfig::SMP;
+
+use axhal::mem::{
+    kernel_stack_region, MemoryAddr, VirtAddr, KERNEL_STACK_REGION_SIZE, KERNEL_STACK_SLOT_SIZE,
+};
+use axhal::trap::{register_trap_handler, STACK_OVERFLOW};
+use kspin::SpinNoIrq;
+
+/// The interface to map kernel stacks in the kernel address space.
+#[crate_interface::def_interface]
+pub trait KernelStackIf {
+    /// Allocates `size` bytes of physical frames and maps them at `vaddr` in
+    /// the kernel address space, readable and writable. Returns `false` on
+    /// failure.
+    fn map_kernel_stack(vaddr: VirtAddr, size: usize) -> bool;
+
+    /// Unmaps `[vaddr, vaddr + size)` from the kernel address space, and
+    /// frees the frames allocated by
+    /// [`map_kernel_stack`](Self::map_kernel_stack).
+    fn unmap_kernel_stack(vaddr: VirtAddr, size: usize);
+}
+
+/// The largest stack that can be guarded, the rest of the slot is the guard.
+pub(crate) const MAX_GUARDED_STACK_SIZE: usize = KERNEL_STACK_SLOT_SIZE / 2;
+
+const NUM_SLOTS: usize = KERNEL_STACK_REGION_SIZE / KERNEL_STACK_SLOT_SIZE;
+
+static NEXT_SLOT: AtomicUsize = AtomicUsize::new(0);
+static FREE_SLOTS: SpinNoIrq<Vec<usize>> = SpinNoIrq::new(Vec::new());
+
+/// Slots freed on SMP, with the flush generation each one waits for.
+static QUARANTINED_SLOTS: SpinNoIrq<Vec<(usize, usize)>> = SpinNoIrq::new(Vec::new());
+/// Bumped each time a slot is quarantined.
+static FLUSH_GEN: AtomicUsize = AtomicUsize::new(0);
+/// The flush generation each CPU has caught up with.
+static FLUSHED_GEN: [AtomicUsize; SMP] = [const { AtomicUsize::new(0) }; SMP];
+
+/// Flushes the TLB of the current CPU if any slot has been quarantined since
+/// the last flush.
+pub(crate) fn flush_stale_stacks() {
+    if SMP == 1 {
+        return;
+    }
+    let gen = FLUSH_GEN.load(Ordering::Acquire);
+    let flushed = &FLUSHED_GEN[axhal::cpu::this_cpu_id()];
+    if flushed.load(Ordering::Relaxed) < gen {
+        axhal::arch::flush_tlb(None);
+        flushed.store(gen, Ordering::Release);
+    }
+}
+
+/// Moves the quarantined slots flushed by all CPUs to the free list.
+fn release_quarantined() {
+    let flushed = FLUSHED_GEN
+        .iter()
+        .map(|gen| gen.load(Ordering::Acquire))
+        .min()
+        .unwrap_or(0);
+    let mut quarantined = QUARANTINED_SLOTS.lock();
+    if quarantined.iter().all(|&(_, gen)| gen > flushed) {
+        return;
+    }
+    let mut free = FREE_SLOTS.lock();
+    quarantined.retain(|&(slot, gen)| {
+        if gen <= flushed {
+            free.push(slot);
+        }
+        gen > flushed
+    });
+}
+
+fn alloc_slot() -> Option<usize> {
+    if SMP > 1 {
+        release_quarantined();
+    }
+    if let Some(slot) = FREE_SLOTS.lock().pop() {
+        return Some(slot);
+    }
+    NEXT_SLOT
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+            (next < NUM_SLOTS).then_some(next + 1)
+        })
+        .ok()
+}
+
+/// Returns the top of the stack mapped in `slot`.
+pub(crate) fn slot_top(slot: usize) -> VirtAddr {
+    kernel_stack_region().start + (slot + 1) * KERNEL_STACK_SLOT_SIZE
+}
+
+/// Maps a new stack with `size` bytes to the top of a free slot. Returns the
+/// slot, or `None` if all slots are in use or it cannot be mapped.
+pub(crate) fn map_stack(size: usize) -> Option<usize> {
+    assert!(size <= MAX_GUARDED_STACK_SIZE && size.is_aligned_4k());
+    let slot = alloc_slot()?;
+    if crate_interface::call_interface!(KernelStackIf::map_kernel_stack(
+        slot_top(slot) - size,
+        size
+    )) {
+        Some(slot)
+    } else {
+        FREE_SLOTS.lock().push(slot);
+        None
+    }
+}
+
+/// Unmaps the stack with `size` bytes in `slot`, and frees the slot.
+///
+/// On SMP, the slot is reused only after all CPUs have flushed their TLBs.
+pub(crate) fn unmap_stack(slot: usize, size: usize) {
+    crate_interface::call_interface!(KernelStackIf::unmap_kernel_stack(
+        slot_top(slot) - size,
+        size
+    ));
+    if SMP == 1 {
+        FREE_SLOTS.lock().push(slot);
+    } else {
+        // Taken under the lock so that generations are pushed in order.
+        let mut quarantined = QUARANTINED_SLOTS.lock();
+        let gen = FLUSH_GEN.fetch_add(1, Ordering::AcqRel) + 1;
+        quarantined.push((slot, gen));
+    }
+}
+
+#[register_trap_handler(STACK_OVERFLOW)]
+fn report_stack_overflow(vaddr: VirtAddr) {
+    let slot = (vaddr - kernel_stack_region().start) / KERNEL_STACK_SLOT_SIZE;
+    match crate::current_may_uninit() {
+        Some(curr) if curr.kstack_slot() == Some(slot) => {
+            error!("{} overflowed its kernel stack", curr.id_name());
+        }
+        Some(curr) => error!(
+            "{} faulted in the guard pages of another kernel stack",
+            curr.id_name()
+        ),
+        None => error!("Kernel stack overflow before tasks are initialized"),
+    }
+}
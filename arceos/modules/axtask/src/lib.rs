@@ -13,6 +13,15 @@
 //!    APIs can be used, such as [`sleep`], [`sleep_until`], and
 //!    [`WaitQueue::wait_timeout`].
 //! - `preempt`: Enable preemptive scheduling.
+//! - `uspace`: Account the time tasks spend in user space apart from the
+//!   time in the kernel (see [`TaskStat`]). It also enables the `paging`
+//!   feature.
+//! - `paging`: Allocate kernel stacks in a dedicated virtual region with
+//!   guard pages below them, so that stack overflows are detected. The kernel
+//!   address space is managed elsewhere, so [`KernelStackIf`] must be
+//!   implemented to map the stacks.
+//! - `lockdep`: Validate the order of lock acquisitions and report possible
+//!   deadlocks, see [`lockdep`]. It also enables the `multitask` feature.
 //! - `sched_fifo`: Use the [FIFO cooperative scheduler][1]. It also enables the
 //!   `multitask` feature if it is enabled. This feature is enabled by default,
 //!   and it can be overriden by other scheduler features.
@@ -20,6 +29,9 @@
 //!   the `multitask` and `preempt` features if it is enabled.
 //! - `sched_cfs`: Use the [Completely Fair Scheduler][3]. It also enables the
 //!   the `multitask` and `preempt` features if it is enabled.
+//! - `sched_edf`: Add an earliest-deadline-first real-time class, which runs
+//!   before the scheduler above (see [`set_current_edf`]). It also enables
+//!   the `multitask` and `preempt` features if it is enabled.
 //!
 //! [1]: scheduler::FifoScheduler
 //! [2]: scheduler::RRScheduler
@@ -42,7 +54,9 @@ cfg_if::cfg_if! {
         extern crate log;
         extern crate alloc;
 
+        mod cpumask;
         mod run_queue;
+        mod stat;
         mod task;
         mod task_ext;
         mod api;
@@ -51,6 +65,15 @@ cfg_if::cfg_if! {
         #[cfg(feature = "irq")]
         mod timers;
 
+        #[cfg(feature = "paging")]
+        mod kstack;
+
+        #[cfg(feature = "sched_edf")]
+        mod sched_edf;
+
+        #[cfg(feature = "lockdep")]
+        pub mod lockdep;
+
         #[doc(cfg(feature = "multitask"))]
         pub use self::api::*;
         pub use self::api::{sleep, sleep_until, yield_now};
@@ -0,0 +1,364 @@
+//! A runtime lock validator.
+//!
+//! It records the order in which lock classes are acquired by each task, and
+//! reports through the log:
+//!
+//! - cycles in the order, i.e., possible deadlocks, with the full chain of
+//!   dependencies that closes the cycle;
+//! - recursive locking of the same lock;
+//! - locks held with IRQs enabled that are also acquired in IRQ handlers;
+//! - sleeping in IRQ handlers, with IRQs or preemption disabled, or while
+//!   holding a spinlock.
+//!
+//! Each problem is reported once. Only the locks reporting their acquisition
+//! and release here are tracked, i.e., `axsync::Mutex` and the spinlocks in
+//! `axsync::spin`, which the modules above `axsync` (`axmm`, `axdma`,
+//! `axprocess`, `axsyscall` and `axstd`) use instead of [`kspin`]. The
+//! modules below it, including this one, use [`kspin`] directly and are not
+//! validated. Guards of `kernel_guard` are covered by the checks of sleeping
+//! with IRQs or preemption disabled.
+
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::vec::Vec;
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+
+use crate::CurrentTask;
+
+/// The class of a lock.
+///
+/// The locks of a class are assumed to play the same role, so the order
+/// between two locks is validated between their classes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockClass {
+    /// The locks created at the given place of the source code.
+    Site(&'static Location<'static>),
+    /// A single lock at the given address.
+    Instance(usize),
+}
+
+impl fmt::Display for LockClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Site(loc) => write!(f, "lock created at {}", loc),
+            Self::Instance(addr) => write!(f, "lock@{:#x}", addr),
+        }
+    }
+}
+
+/// The kind of a lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    /// A spinlock leaving IRQs as they are while held.
+    Spin,
+    /// A spinlock disabling IRQs while held.
+    SpinNoIrq,
+    /// A lock which may sleep while acquiring.
+    Sleep,
+}
+
+/// A lock held by a task.
+pub(crate) struct HeldLock {
+    class: LockClass,
+    addr: usize,
+    kind: LockKind,
+    acquired_at: &'static Location<'static>,
+}
+
+/// The locks held by a task, in acquisition order.
+pub(crate) type HeldLocks = SpinNoIrq<Vec<HeldLock>>;
+
+/// A dependency from a lock class to another one, acquired while holding
+/// the former.
+struct Dependency {
+    prev_at: &'static Location<'static>,
+    next_at: &'static Location<'static>,
+}
+
+/// Where a lock class was first used in an IRQ handler, and first held with
+/// IRQs enabled.
+#[derive(Default)]
+struct IrqUsage {
+    in_irq: Option<&'static Location<'static>>,
+    irqs_enabled: Option<&'static Location<'static>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Problem {
+    Cycle,
+    Recursive,
+    IrqUnsafe,
+    Sleep,
+}
+
+struct LockGraph {
+    deps: BTreeMap<LockClass, BTreeMap<LockClass, Dependency>>,
+    irq_usage: BTreeMap<LockClass, IrqUsage>,
+    reported: BTreeSet<(Problem, LockClass, LockClass)>,
+}
+
+static GRAPH: SpinNoIrq<LockGraph> = SpinNoIrq::new(LockGraph {
+    deps: BTreeMap::new(),
+    irq_usage: BTreeMap::new(),
+    reported: BTreeSet::new(),
+});
+
+static NUM_REPORTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether IRQs have been enabled after booting, sleeping with IRQs
+/// disabled is expected before.
+static IRQS_ONLINE: AtomicBool = AtomicBool::new(false);
+
+fn in_irq() -> bool {
+    #[cfg(feature = "irq")]
+    return axhal::irq::in_irq();
+    #[cfg(not(feature = "irq"))]
+    return false;
+}
+
+fn report_held(curr: &CurrentTask, held: &[HeldLock]) {
+    if held.is_empty() {
+        error!("{} holds no locks.", curr.id_name());
+        return;
+    }
+    error!("{} holds {} lock(s):", curr.id_name(), held.len());
+    for (i, h) in held.iter().enumerate() {
+        error!(
+            "  #{}: {} ({:#x}), acquired at {}",
+            i, h.class, h.addr, h.acquired_at
+        );
+    }
+}
+
+impl LockGraph {
+    /// Marks the problem as reported, returns `false` if it already was.
+    fn report(&mut self, problem: Problem, a: LockClass, b: LockClass) -> bool {
+        if !self.reported.insert((problem, a, b)) {
+            return false;
+        }
+        NUM_REPORTS.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Finds the chain of dependencies from `from` to `to`.
+    fn find_chain(&self, from: LockClass, to: LockClass) -> Option<Vec<(LockClass, LockClass)>> {
+        let mut parents = BTreeMap::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(class) = queue.pop_front() {
+            if class == to {
+                let mut chain = Vec::new();
+                let mut next = to;
+                while next != from {
+                    let prev = parents[&next];
+                    chain.push((prev, next));
+                    next = prev;
+                }
+                chain.reverse();
+                return Some(chain);
+            }
+            for &next in self.deps.get(&class).into_iter().flat_map(|d| d.keys()) {
+                if next != from && !parents.contains_key(&next) {
+                    parents.insert(next, class);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// Records that `class` is acquired at `at` while holding `prev`, or
+    /// reports the cycle it closes.
+    fn add_dependency(
+        &mut self,
+        curr: &CurrentTask,
+        held: &[HeldLock],
+        prev: &HeldLock,
+        class: LockClass,
+        at: &'static Location<'static>,
+    ) {
+        if self
+            .deps
+            .get(&prev.class)
+            .is_some_and(|d| d.contains_key(&class))
+        {
+            return;
+        }
+        let Some(chain) = self.find_chain(class, prev.class) else {
+            self.deps.entry(prev.class).or_default().insert(
+                class,
+                Dependency {
+                    prev_at: prev.acquired_at,
+                    next_at: at,
+                },
+            );
+            return;
+        };
+        if !self.report(Problem::Cycle, prev.class, class) {
+            return;
+        }
+        error!("lockdep: possible circular locking dependency detected");
+        error!(
+            "{} is trying to acquire {} at {}",
+            curr.id_name(),
+            class,
+            at
+        );
+        report_held(curr, held);
+        error!("but the reverse order was seen before:");
+        for (a, b) in chain {
+            let dep = &self.deps[&a][&b];
+            error!(
+                "  {} acquired at {}, while holding {} acquired at {}",
+                b, dep.next_at, a, dep.prev_at
+            );
+        }
+    }
+
+    /// Records how `class` is used with IRQs, or reports that it may be
+    /// interrupted by an IRQ handler acquiring it.
+    fn check_irq_usage(
+        &mut self,
+        curr: &CurrentTask,
+        held: &[HeldLock],
+        class: LockClass,
+        kind: LockKind,
+        at: &'static Location<'static>,
+        (in_irq, irqs_enabled): (bool, bool),
+    ) {
+        let usage = self.irq_usage.entry(class).or_default();
+        let (in_irq_at, irqs_enabled_at) = if in_irq {
+            usage.in_irq.get_or_insert(at);
+            match usage.irqs_enabled {
+                Some(enabled_at) => (at, enabled_at),
+                None => return,
+            }
+        } else if kind != LockKind::SpinNoIrq && irqs_enabled {
+            usage.irqs_enabled.get_or_insert(at);
+            match usage.in_irq {
+                Some(irq_at) => (irq_at, at),
+                None => return,
+            }
+        } else {
+            return;
+        };
+        if !self.report(Problem::IrqUnsafe, class, class) {
+            return;
+        }
+        error!("lockdep: IRQ-unsafe lock usage detected");
+        error!(
+            "{} is acquired in IRQ context at {}, and held with IRQs enabled at {}",
+            class, in_irq_at, irqs_enabled_at
+        );
+        report_held(curr, held);
+    }
+}
+
+/// Records that the lock at `addr` of the given class and kind is being
+/// acquired at `at` by the current task, and validates it.
+///
+/// It should be called before blocking for the lock, so that a deadlock is
+/// reported before it happens. A successful try-lock can not deadlock, so
+/// its order is not validated, but it is recorded as held.
+pub fn lock_acquire(
+    class: LockClass,
+    addr: usize,
+    kind: LockKind,
+    trylock: bool,
+    at: &'static Location<'static>,
+) {
+    let Some(curr) = CurrentTask::try_get() else {
+        return;
+    };
+    // Before disabling IRQs by locking below.
+    let irq_state = (in_irq(), axhal::arch::irqs_enabled());
+    let mut held = curr.held_locks().lock();
+    let mut graph = GRAPH.lock();
+    if !trylock {
+        if let Some(h) = held.iter().find(|h| h.addr == addr) {
+            if graph.report(Problem::Recursive, h.class, class) {
+                error!("lockdep: recursive locking detected");
+                error!(
+                    "{} is trying to acquire {} at {}",
+                    curr.id_name(),
+                    class,
+                    at
+                );
+                report_held(&curr, &held);
+            }
+        }
+    }
+    graph.check_irq_usage(&curr, &held, class, kind, at, irq_state);
+    if !trylock {
+        for prev in held.iter().filter(|h| h.class != class) {
+            graph.add_dependency(&curr, &held, prev, class, at);
+        }
+    }
+    held.push(HeldLock {
+        class,
+        addr,
+        kind,
+        acquired_at: at,
+    });
+}
+
+/// Records that the lock at `addr` is released by the current task.
+///
+/// Locks can be released in any order.
+pub fn lock_release(addr: usize) {
+    let Some(curr) = CurrentTask::try_get() else {
+        return;
+    };
+    let mut held = curr.held_locks().lock();
+    if let Some(i) = held.iter().rposition(|h| h.addr == addr) {
+        held.remove(i);
+    }
+}
+
+/// Validates that the current task can sleep at `at`.
+///
+/// It reports if called in an IRQ handler, with IRQs or preemption disabled,
+/// or while holding a spinlock.
+pub fn might_sleep(at: &'static Location<'static>) {
+    let Some(curr) = CurrentTask::try_get() else {
+        return;
+    };
+    // Before disabling IRQs and preemption by locking below.
+    let irqs_disabled = IRQS_ONLINE.load(Ordering::Relaxed) && !axhal::arch::irqs_enabled();
+    #[cfg(feature = "preempt")]
+    let preempt_disabled = !curr.can_preempt(0);
+    #[cfg(not(feature = "preempt"))]
+    let preempt_disabled = false;
+
+    let held = curr.held_locks().lock();
+    let reason = if in_irq() {
+        "in IRQ context"
+    } else if held.iter().any(|h| h.kind != LockKind::Sleep) {
+        "while holding a spinlock"
+    } else if irqs_disabled {
+        "with IRQs disabled"
+    } else if preempt_disabled {
+        "with preemption disabled"
+    } else {
+        return;
+    };
+    let site = LockClass::Site(at);
+    if !GRAPH.lock().report(Problem::Sleep, site, site) {
+        return;
+    }
+    error!("lockdep: sleeping {} at {}", reason, at);
+    report_held(&curr, &held);
+}
+
+/// Returns the number of problems reported.
+pub fn num_reports() -> usize {
+    NUM_REPORTS.load(Ordering::Relaxed)
+}
+
+/// Notes that IRQs have been enabled after booting.
+#[cfg(feature = "irq")]
+pub(crate) fn set_irqs_online() {
+    IRQS_ONLINE.store(true, Ordering::Relaxed);
+}
@@ -1,16 +1,39 @@
-use alloc::collections::VecDeque;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
-use kspin::SpinNoIrq;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use axconfig::SMP;
+use axhal::cpu::this_cpu_id;
+use kernel_guard::NoPreemptIrqSave;
+use kspin::{SpinNoIrq, SpinRaw, SpinRawGuard};
 use lazyinit::LazyInit;
 use scheduler::BaseScheduler;
 
 use crate::task::{CurrentTask, TaskState};
-use crate::{AxTaskRef, Scheduler, TaskInner, WaitQueue};
+use crate::{AxTaskRef, CpuMask, Scheduler, TaskInner, WaitQueue, WaitReason};
+
+/// The run queue of each CPU.
+///
+/// A CPU only runs tasks from its own queue, and steals tasks from the others
+/// when it has nothing to run. The queue is locked across context switches,
+/// and unlocked by the task switched to, see [`CurrentRunQueueRef`].
+///
+/// The locks do not disable IRQs themselves, as the queue of the current CPU
+/// is only locked by [`current_run_queue`] with IRQs disabled, and the others
+/// are only tried from there.
+static RUN_QUEUES: [LazyInit<SpinRaw<AxRunQueue>>; SMP] = [const { LazyInit::new() }; SMP];
 
-// TODO: per-CPU
-pub(crate) static RUN_QUEUE: LazyInit<SpinNoIrq<AxRunQueue>> = LazyInit::new();
+/// Ready tasks sent to each CPU by the others, which are moved into its run
+/// queue when it reschedules next time.
+static REMOTE_TASKS: [SpinNoIrq<VecDeque<AxTaskRef>>; SMP] =
+    [const { SpinNoIrq::new(VecDeque::new()) }; SMP];
+
+/// The task last switched out on each CPU, and whether it should be sent to
+/// another CPU, handled in [`finish_task_switch`] by the task switched to.
+static PREV_TASKS: [SpinNoIrq<Option<(AxTaskRef, bool)>>; SMP] =
+    [const { SpinNoIrq::new(None) }; SMP];
 
-// TODO: per-CPU
 static EXITED_TASKS: SpinNoIrq<VecDeque<AxTaskRef>> = SpinNoIrq::new(VecDeque::new());
 
 static WAIT_FOR_EXIT: WaitQueue = WaitQueue::new();
@@ -18,33 +41,265 @@ static WAIT_FOR_EXIT: WaitQueue = WaitQueue::new();
 #[percpu::def_percpu]
 static IDLE_TASK: LazyInit<AxTaskRef> = LazyInit::new();
 
+/// Locks the run queue of the current CPU.
+pub(crate) fn current_run_queue() -> CurrentRunQueueRef {
+    // The task stays on this CPU once preemption is disabled.
+    let irq_guard = NoPreemptIrqSave::new();
+    CurrentRunQueueRef {
+        rq: Some(RUN_QUEUES[this_cpu_id()].lock()),
+        _irq_guard: irq_guard,
+    }
+}
+
+/// Selects a CPU from `cpumask` to run a task, in a round-robin way.
+fn select_cpu(cpumask: CpuMask) -> usize {
+    static NEXT_CPU: AtomicUsize = AtomicUsize::new(0);
+    let start = NEXT_CPU.fetch_add(1, Ordering::Relaxed);
+    (0..SMP)
+        .map(|i| (start + i) % SMP)
+        .find(|&cpu_id| cpumask.get(cpu_id))
+        .expect("empty CPU mask")
+}
+
+/// Sends the ready `task` to the CPU `cpu_id`, whose run queue may be locked
+/// by others.
+fn send_task(cpu_id: usize, task: AxTaskRef) {
+    debug!("task send: {} to CPU {}", task.id_name(), cpu_id);
+    REMOTE_TASKS[cpu_id].lock().push_back(task);
+}
+
+/// Finishes a context switch on the current CPU, called by the task switched
+/// to.
+///
+/// The previous task is switched out completely now, so other CPUs can run
+/// it. The run queue it locked on this CPU is handed over across the switch,
+/// and released here.
+pub(crate) fn finish_task_switch() {
+    let cpu_id = this_cpu_id();
+    let prev = PREV_TASKS[cpu_id].lock().take();
+    if let Some((prev, send)) = prev {
+        prev.set_on_cpu(false);
+        if send {
+            send_task(select_cpu(prev.cpumask()), prev);
+        }
+    }
+    // Safety: the lock was taken on this CPU by the previous task, whose
+    // guard has been forgotten in `CurrentRunQueueRef::switch_to`.
+    unsafe { RUN_QUEUES[cpu_id].force_unlock() };
+}
+
+/// The run queue of the current CPU, locked with IRQs and preemption
+/// disabled.
+///
+/// The lock is held across context switches, and released on the same CPU
+/// by the task switched to in [`finish_task_switch`]. So once the task
+/// switches out, the guard no longer holds any run queue, and it only
+/// restores IRQs and preemption when dropped, maybe on another CPU.
+pub(crate) struct CurrentRunQueueRef {
+    rq: Option<SpinRawGuard<'static, AxRunQueue>>,
+    _irq_guard: NoPreemptIrqSave,
+}
+
+impl Deref for CurrentRunQueueRef {
+    type Target = AxRunQueue;
+
+    fn deref(&self) -> &AxRunQueue {
+        self.rq
+            .as_ref()
+            .expect("run queue released by a context switch")
+    }
+}
+
+impl DerefMut for CurrentRunQueueRef {
+    fn deref_mut(&mut self) -> &mut AxRunQueue {
+        self.rq
+            .as_mut()
+            .expect("run queue released by a context switch")
+    }
+}
+
 pub(crate) struct AxRunQueue {
+    cpu_id: usize,
     scheduler: Scheduler,
+    /// The tasks in the scheduler that are allowed on other CPUs as well,
+    /// keyed by their IDs, which can be stolen without disturbing the order
+    /// of the rest.
+    migratable: BTreeMap<u64, AxTaskRef>,
 }
 
 impl AxRunQueue {
-    pub fn new() -> SpinNoIrq<Self> {
-        let gc_task = TaskInner::new(gc_entry, "gc".into(), axconfig::TASK_STACK_SIZE).into_arc();
-        let mut scheduler = Scheduler::new();
-        scheduler.add_task(gc_task);
-        SpinNoIrq::new(Self { scheduler })
+    pub fn new(cpu_id: usize) -> SpinRaw<Self> {
+        SpinRaw::new(Self {
+            cpu_id,
+            scheduler: Scheduler::new(),
+            migratable: BTreeMap::new(),
+        })
     }
 
     pub fn add_task(&mut self, task: AxTaskRef) {
         debug!("task spawn: {}", task.id_name());
         assert!(task.is_ready());
-        self.scheduler.add_task(task);
+        self.enqueue(task);
     }
 
     #[cfg(feature = "irq")]
     pub fn scheduler_timer_tick(&mut self) {
         let curr = crate::current();
+        self.update_priority(curr.as_task_ref());
         if !curr.is_idle() && self.scheduler.task_tick(curr.as_task_ref()) {
             #[cfg(feature = "preempt")]
             curr.set_preempt_pending(true);
         }
     }
 
+    pub fn set_current_priority(&mut self, prio: isize) -> bool {
+        let curr = crate::current();
+        if !self.scheduler.set_priority(curr.as_task_ref(), prio) {
+            return false;
+        }
+        curr.set_base_priority(prio);
+        // Keep the inherited priority if it is higher.
+        self.apply_priority(curr.as_task_ref());
+        true
+    }
+
+    pub fn inherit_priority(&mut self, task: &AxTaskRef, key: usize, prio: isize) {
+        task.inherit_priority(key, prio);
+        self.apply_priority(task);
+    }
+
+    pub fn drop_inherited_priority(&mut self, task: &AxTaskRef, key: usize) {
+        if task.drop_inherited_priority(key) {
+            self.apply_priority(task);
+        }
+    }
+
+    pub fn unblock_task(&mut self, task: AxTaskRef, resched: bool) {
+        debug!("task unblock: {}", task.id_name());
+        // The task may be woken up by several CPUs at the same time.
+        if task.transition_state(TaskState::Blocked, TaskState::Ready) {
+            self.enqueue(task); // TODO: priority
+            if resched {
+                #[cfg(feature = "preempt")]
+                crate::current().set_preempt_pending(true);
+            }
+        }
+    }
+
+    /// Requests `task` to exit, and wakes it up if it is blocked in a
+    /// killable wait.
+    pub fn kill_task(&mut self, task: AxTaskRef) {
+        debug!("task kill: {}", task.id_name());
+        task.set_kill_pending();
+        // Pairs with the fence in `resched_blocked`, so either the task finds
+        // itself killed, or it is found blocked here.
+        fence(Ordering::SeqCst);
+        if task.is_killable() {
+            self.unblock_task(task, false);
+        }
+    }
+}
+
+impl AxRunQueue {
+    /// Puts the ready `task` into this run queue if it is allowed to run on
+    /// this CPU, or sends it to another CPU otherwise.
+    fn enqueue(&mut self, task: AxTaskRef) {
+        let cpumask = task.cpumask();
+        if cpumask.get(self.cpu_id) {
+            self.sched_add(task);
+        } else {
+            send_task(select_cpu(cpumask), task);
+        }
+    }
+
+    /// Passes the priority of `task` to the scheduler holding it.
+    ///
+    /// It is done at once if `task` is the current task. Otherwise `task` may
+    /// be in the run queue of another CPU, which passes the priority when it
+    /// puts `task` into its scheduler, picks it or ticks it next time.
+    fn apply_priority(&mut self, task: &AxTaskRef) {
+        task.set_priority_changed();
+        if crate::current().ptr_eq(task) {
+            self.update_priority(task);
+        }
+    }
+
+    /// Passes the priority of `task`, which is held by this run queue, to the
+    /// scheduler if it has changed.
+    fn update_priority(&mut self, task: &AxTaskRef) {
+        if task.take_priority_changed() && !task.is_idle() {
+            self.scheduler.set_priority(task, task.priority());
+        }
+    }
+
+    /// Moves the tasks sent by other CPUs into this run queue.
+    fn receive_remote_tasks(&mut self) {
+        let tasks = core::mem::take(&mut *REMOTE_TASKS[self.cpu_id].lock());
+        for task in tasks {
+            self.sched_add(task);
+        }
+    }
+
+    /// Records `task`, which is being put into the scheduler, if other CPUs
+    /// may steal it.
+    fn track_migratable(&mut self, task: &AxTaskRef) {
+        if SMP > 1 && task.cpumask().bits() & !CpuMask::one(self.cpu_id).bits() != 0 {
+            self.migratable.insert(task.id().as_u64(), task.clone());
+        }
+    }
+
+    fn sched_add(&mut self, task: AxTaskRef) {
+        self.update_priority(&task);
+        self.track_migratable(&task);
+        self.scheduler.add_task(task);
+    }
+
+    fn sched_put_prev(&mut self, task: AxTaskRef, preempt: bool) {
+        self.update_priority(&task);
+        self.track_migratable(&task);
+        self.scheduler.put_prev_task(task, preempt);
+    }
+
+    fn sched_pick(&mut self) -> Option<AxTaskRef> {
+        let task = self.scheduler.pick_next_task()?;
+        self.migratable.remove(&task.id().as_u64());
+        self.update_priority(&task);
+        Some(task)
+    }
+
+    /// Takes a ready task allowed on this CPU from the run queue of another
+    /// CPU.
+    fn steal_task(&mut self) -> Option<AxTaskRef> {
+        for i in 1..SMP {
+            let cpu_id = (self.cpu_id + i) % SMP;
+            // Never wait for another run queue with this one locked, or two
+            // CPUs stealing from each other deadlock.
+            let Some(mut rq) = RUN_QUEUES[cpu_id].get().and_then(|rq| rq.try_lock()) else {
+                continue;
+            };
+            let Some(id) = rq
+                .migratable
+                .iter()
+                .find(|(_, task)| task.cpumask().get(self.cpu_id))
+                .map(|(&id, _)| id)
+            else {
+                continue;
+            };
+            let task = rq.migratable.remove(&id).unwrap();
+            // Only the stolen task leaves the queue, the others keep their
+            // places.
+            let task = rq
+                .scheduler
+                .remove_task(&task)
+                .expect("migratable task not in the run queue");
+            debug!("task steal: {} from CPU {}", task.id_name(), cpu_id);
+            return Some(task);
+        }
+        None
+    }
+}
+
+impl CurrentRunQueueRef {
     pub fn yield_current(&mut self) {
         let curr = crate::current();
         trace!("task yield: {}", curr.id_name());
@@ -52,19 +307,13 @@ impl AxRunQueue {
         self.resched(false);
     }
 
-    pub fn set_current_priority(&mut self, prio: isize) -> bool {
-        self.scheduler
-            .set_priority(crate::current().as_task_ref(), prio)
-    }
-
     #[cfg(feature = "preempt")]
     pub fn preempt_resched(&mut self) {
         let curr = crate::current();
         assert!(curr.is_running());
 
         // When we get the mutable reference of the run queue, we must
-        // have held the `SpinNoIrq` lock with both IRQs and preemption
-        // disabled. So we need to set `current_disable_count` to 1 in
+        // have held the lock with both IRQs and preemption disabled. So we need to set `current_disable_count` to 1 in
         // `can_preempt()` to obtain the preemption permission before
         //  locking the run queue.
         let can_preempt = curr.can_preempt(1);
@@ -91,6 +340,8 @@ impl AxRunQueue {
             axhal::misc::terminate();
         } else {
             curr.set_state(TaskState::Exited);
+            #[cfg(feature = "sched_edf")]
+            crate::sched_edf::set_task_params(curr.as_task_ref(), None);
             curr.notify_exit(exit_code, self);
             EXITED_TASKS.lock().push_back(curr.clone());
             WAIT_FOR_EXIT.notify_one_locked(false, self);
@@ -99,7 +350,10 @@ impl AxRunQueue {
         unreachable!("task exited!");
     }
 
-    pub fn block_current<F>(&mut self, wait_queue_push: F)
+    /// Blocks the current task. If `killable`, it is woken up by
+    /// [`kill_task`](AxRunQueue::kill_task), and does not block at all if it
+    /// has been killed already.
+    pub fn block_current<F>(&mut self, reason: WaitReason, killable: bool, wait_queue_push: F)
     where
         F: FnOnce(AxTaskRef),
     {
@@ -112,21 +366,11 @@ impl AxRunQueue {
         #[cfg(feature = "preempt")]
         assert!(curr.can_preempt(1));
 
+        curr.set_wait_reason(reason);
+        curr.set_killable(killable);
         curr.set_state(TaskState::Blocked);
         wait_queue_push(curr.clone());
-        self.resched(false);
-    }
-
-    pub fn unblock_task(&mut self, task: AxTaskRef, resched: bool) {
-        debug!("task unblock: {}", task.id_name());
-        if task.is_blocked() {
-            task.set_state(TaskState::Ready);
-            self.scheduler.add_task(task); // TODO: priority
-            if resched {
-                #[cfg(feature = "preempt")]
-                crate::current().set_preempt_pending(true);
-            }
-        }
+        self.resched_blocked(&curr);
     }
 
     #[cfg(feature = "irq")]
@@ -138,44 +382,103 @@ impl AxRunQueue {
 
         let now = axhal::time::wall_time();
         if now < deadline {
-            crate::timers::set_alarm_wakeup(deadline, curr.clone());
+            // Block first, as the alarm may go off on another CPU at once.
+            curr.set_wait_reason(WaitReason::Sleep);
+            curr.set_killable(false);
             curr.set_state(TaskState::Blocked);
-            self.resched(false);
+            crate::timers::set_alarm_wakeup(deadline, curr.clone());
+            self.resched_blocked(&curr);
+            if curr.in_timer_list() {
+                // Woken up by `kill` that raced with a killable wait before.
+                crate::timers::cancel_alarm(curr.as_task_ref());
+            }
         }
     }
-}
 
-impl AxRunQueue {
+    /// Switches out the current task, which has just been blocked, unless it
+    /// is in a killable wait and has been killed.
+    fn resched_blocked(&mut self, curr: &CurrentTask) {
+        fence(Ordering::SeqCst);
+        if curr.is_killable()
+            && curr.is_kill_pending()
+            && curr.transition_state(TaskState::Blocked, TaskState::Running)
+        {
+            debug!("task killed before blocking: {}", curr.id_name());
+            return;
+        }
+        self.resched(false);
+    }
+
     /// Common reschedule subroutine. If `preempt`, keep current task's time
     /// slice, otherwise reset it.
     fn resched(&mut self, preempt: bool) {
         let prev = crate::current();
+        let mut send_prev = false;
         if prev.is_running() {
             prev.set_state(TaskState::Ready);
             if !prev.is_idle() {
-                self.scheduler.put_prev_task(prev.clone(), preempt);
+                if prev.cpumask().get(self.cpu_id) {
+                    self.sched_put_prev(prev.clone(), preempt);
+                } else {
+                    // The affinity has changed, send it away once it is
+                    // switched out.
+                    send_prev = true;
+                }
             }
+        } else {
+            #[cfg(feature = "sched_edf")]
+            self.scheduler.charge_stopped(prev.as_task_ref());
         }
-        let next = self.scheduler.pick_next_task().unwrap_or_else(|| unsafe {
-            // Safety: IRQs must be disabled at this time.
-            IDLE_TASK.current_ref_raw().get_unchecked().clone()
-        });
-        self.switch_to(prev, next);
+        self.receive_remote_tasks();
+        let next = loop {
+            let Some(task) = self.sched_pick().or_else(|| self.steal_task()) else {
+                break unsafe {
+                    // Safety: IRQs must be disabled at this time.
+                    IDLE_TASK.current_ref_raw().get_unchecked().clone()
+                };
+            };
+            if task.cpumask().get(self.cpu_id) {
+                break task;
+            }
+            // The affinity has changed while it was waiting.
+            send_task(select_cpu(task.cpumask()), task);
+        };
+        self.switch_to(prev, next, send_prev);
     }
 
-    fn switch_to(&mut self, prev_task: CurrentTask, next_task: AxTaskRef) {
+    /// Switches from the current task to `next_task`, the run queue can no
+    /// longer be used when it returns.
+    fn switch_to(&mut self, prev_task: CurrentTask, next_task: AxTaskRef, send_prev: bool) {
         trace!(
             "context switch: {} -> {}",
             prev_task.id_name(),
             next_task.id_name()
         );
-        #[cfg(feature = "preempt")]
-        next_task.set_preempt_pending(false);
-        next_task.set_state(TaskState::Running);
         if prev_task.ptr_eq(&next_task) {
+            #[cfg(feature = "preempt")]
+            next_task.set_preempt_pending(false);
+            next_task.set_state(TaskState::Running);
             return;
         }
 
+        // Yielding or being preempted leaves the previous task ready.
+        let now = axhal::time::monotonic_time_nanos();
+        prev_task.acct().switch_out(now, !prev_task.is_ready());
+        next_task.acct().switch_in(now);
+
+        // The next task may be woken up before it is switched out completely
+        // on another CPU, wait for it before touching its state.
+        while next_task.on_cpu() {
+            core::hint::spin_loop();
+        }
+        #[cfg(feature = "preempt")]
+        next_task.set_preempt_pending(false);
+        next_task.set_state(TaskState::Running);
+        next_task.set_on_cpu(true);
+        *PREV_TASKS[self.cpu_id].lock() = Some((prev_task.clone(), send_prev));
+        // The lock is released by the next task in `finish_task_switch`.
+        core::mem::forget(self.rq.take());
+
         unsafe {
             let prev_ctx_ptr = prev_task.ctx_mut_ptr();
             let next_ctx_ptr = next_task.ctx_mut_ptr();
@@ -188,6 +491,9 @@ impl AxRunQueue {
             CurrentTask::set_current(prev_task, next_task);
             (*prev_ctx_ptr).switch_to(&*next_ctx_ptr);
         }
+
+        // Switched back, maybe on another CPU.
+        finish_task_switch();
     }
 }
 
@@ -214,6 +520,8 @@ fn gc_entry() {
 }
 
 pub(crate) fn init() {
+    let cpu_id = this_cpu_id();
+
     // Create the `idle` task (not current task).
     const IDLE_TASK_STACK_SIZE: usize = 4096;
     let idle_task = TaskInner::new(|| crate::run_idle(), "idle".into(), IDLE_TASK_STACK_SIZE);
@@ -226,10 +534,14 @@ pub(crate) fn init() {
     main_task.set_state(TaskState::Running);
     unsafe { CurrentTask::init_current(main_task) };
 
-    RUN_QUEUE.init_once(AxRunQueue::new());
+    RUN_QUEUES[cpu_id].init_once(AxRunQueue::new(cpu_id));
+    let gc_task = TaskInner::new(gc_entry, "gc".into(), axconfig::TASK_STACK_SIZE).into_arc();
+    RUN_QUEUES[cpu_id].lock().add_task(gc_task);
 }
 
 pub(crate) fn init_secondary() {
+    let cpu_id = this_cpu_id();
+
     // Put the subsequent execution into the `idle` task.
     let idle_task = TaskInner::new_init("idle".into()).into_arc();
     idle_task.set_state(TaskState::Running);
@@ -237,4 +549,6 @@ pub(crate) fn init_secondary() {
         i.init_once(idle_task.clone());
     });
     unsafe { CurrentTask::init_current(idle_task) }
+
+    RUN_QUEUES[cpu_id].init_once(AxRunQueue::new(cpu_id));
 }
@@ -0,0 +1,357 @@
+//! Earliest-deadline-first (EDF) real-time scheduling.
+//!
+//! A task joins the real-time class by declaring its [`EdfParams`] with
+//! [`set_current_edf`](crate::set_current_edf). Real-time tasks always run
+//! before the other tasks, which are scheduled by the best-effort scheduler
+//! selected by the other `sched_*` features.
+//!
+//! The budget of a task is enforced like a constant bandwidth server: the
+//! task is throttled once it has run for `runtime` in the current period, or
+//! when it yields, and is given a new budget and deadline when the next
+//! period begins. The runtime is accounted at timer ticks and context
+//! switches, so overruns are bounded by a tick.
+//!
+//! Admission control is done per CPU: a task is admitted on a CPU it is
+//! allowed to run on with enough bandwidth left, and is pinned to that CPU
+//! while it stays in the real-time class. Tasks are never migrated by the
+//! load balancer, so the bandwidth reserved on each CPU is what it really
+//! runs.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use axhal::cpu::this_cpu_id;
+use axhal::time::{monotonic_time, TimeValue};
+use scheduler::BaseScheduler;
+
+use crate::{AxTaskRef, BestEffortScheduler, CpuMask};
+
+/// Bandwidths are fixed-point numbers with this many fractional bits, where
+/// 1.0 means a whole CPU.
+const BW_SHIFT: u32 = 20;
+
+/// The bandwidth real-time tasks may reserve on each CPU, 95% of it, so that
+/// the other tasks are never starved.
+const MAX_CPU_BANDWIDTH: u64 = (1 << BW_SHIFT) * 95 / 100;
+
+/// The bandwidth reserved on each CPU by the real-time tasks pinned to it.
+static CPU_BANDWIDTH: [AtomicU64; axconfig::SMP] = [const { AtomicU64::new(0) }; axconfig::SMP];
+
+/// The real-time parameters of a task, like `sched_attr` of Linux
+/// `SCHED_DEADLINE`.
+///
+/// In every `period`, the task may run for `runtime`, which should be done
+/// within `deadline` since the period begins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EdfParams {
+    /// The CPU time the task may use in each period.
+    pub runtime: Duration,
+    /// The deadline relative to the beginning of each period.
+    pub deadline: Duration,
+    /// The length of each period.
+    pub period: Duration,
+}
+
+impl EdfParams {
+    /// Whether `0 < runtime <= deadline <= period`.
+    pub fn is_valid(&self) -> bool {
+        !self.runtime.is_zero() && self.runtime <= self.deadline && self.deadline <= self.period
+    }
+
+    /// The CPU bandwidth needed to meet the deadlines, i.e.,
+    /// `runtime / deadline`.
+    fn bandwidth(&self) -> u64 {
+        ((self.runtime.as_nanos() << BW_SHIFT) / self.deadline.as_nanos()) as u64
+    }
+}
+
+/// The scheduling state of a real-time task.
+pub(crate) struct EdfState {
+    params: EdfParams,
+    /// When the current period began.
+    release: TimeValue,
+    /// The absolute deadline of the current period.
+    deadline: TimeValue,
+    /// The runtime left in the current period.
+    budget: Duration,
+    /// When the runtime was accounted last time.
+    exec_start: TimeValue,
+    /// Whether the task has blocked since it ran last time, so that it is
+    /// waking up when enqueued next time, rather than being moved.
+    blocked: bool,
+    /// The CPU the task is admitted on and pinned to.
+    cpu: usize,
+    /// The CPUs the task was allowed to run on before it was admitted, which
+    /// are restored when it leaves the real-time class.
+    cpumask: CpuMask,
+}
+
+impl EdfState {
+    fn new(params: EdfParams, now: TimeValue, cpu: usize, cpumask: CpuMask) -> Self {
+        Self {
+            params,
+            release: now,
+            deadline: now + params.deadline,
+            budget: params.runtime,
+            exec_start: now,
+            blocked: false,
+            cpu,
+            cpumask,
+        }
+    }
+
+    /// Starts a new period at `release` with a full budget.
+    fn renew(&mut self, release: TimeValue) {
+        self.release = release;
+        self.deadline = release + self.params.deadline;
+        self.budget = self.params.runtime;
+    }
+
+    /// Accounts the runtime since the last time.
+    fn charge(&mut self, now: TimeValue) {
+        self.budget = self
+            .budget
+            .saturating_sub(now.saturating_sub(self.exec_start));
+        self.exec_start = now;
+    }
+
+    /// Updates the state of a task waking up at `now`.
+    ///
+    /// If the rest of the budget cannot be used before the deadline without
+    /// exceeding the reserved bandwidth, a new period starts now, so a task
+    /// sleeping for long cannot take more than its share.
+    fn wake_up(&mut self, now: TimeValue) {
+        let overflow = self.budget.as_nanos() * self.params.deadline.as_nanos()
+            > self.deadline.saturating_sub(now).as_nanos() * self.params.runtime.as_nanos();
+        if now >= self.deadline || overflow {
+            self.renew(now);
+        }
+    }
+
+    fn next_period(&self) -> TimeValue {
+        self.release + self.params.period
+    }
+}
+
+/// Sets the real-time parameters of `task`, which must not be in any run
+/// queue, e.g., the current task.
+///
+/// The task is pinned to the CPU it is admitted on, which is the one it was
+/// admitted on before or the current CPU if possible. It leaves the
+/// real-time class and gets its old affinity back if `params` is `None`.
+/// Returns `false` if the parameters are invalid, or no CPU the task is
+/// allowed to run on has enough bandwidth left.
+pub(crate) fn set_task_params(task: &AxTaskRef, params: Option<EdfParams>) -> bool {
+    let mut state = task.edf_state().lock();
+    let old = state.as_ref().map(|s| (s.cpu, s.params.bandwidth()));
+    let cpumask = state.as_ref().map_or_else(|| task.cpumask(), |s| s.cpumask);
+    let Some(params) = params else {
+        if let Some((cpu, bw)) = old {
+            CPU_BANDWIDTH[cpu].fetch_sub(bw, Ordering::AcqRel);
+            task.set_affinity(cpumask);
+        }
+        *state = None;
+        return true;
+    };
+    if !params.is_valid() {
+        return false;
+    }
+
+    let new_bw = params.bandwidth();
+    let preferred = old.map_or_else(this_cpu_id, |(cpu, _)| cpu);
+    let reserve = |cpu: usize| {
+        // The old reservation is replaced if it is on the same CPU.
+        let old_bw = old.filter(|&(c, _)| c == cpu).map_or(0, |(_, bw)| bw);
+        CPU_BANDWIDTH[cpu]
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |total| {
+                let total = total - old_bw + new_bw;
+                (total <= MAX_CPU_BANDWIDTH).then_some(total)
+            })
+            .is_ok()
+    };
+    let admitted = core::iter::once(preferred)
+        .filter(|&cpu| cpumask.get(cpu))
+        .chain(cpumask.iter().filter(|&cpu| cpu != preferred))
+        .find(|&cpu| reserve(cpu));
+    let Some(cpu) = admitted else {
+        debug!("EDF admission denied: {} {:?}", task.id_name(), params);
+        return false;
+    };
+    if let Some((old_cpu, old_bw)) = old.filter(|&(c, _)| c != cpu) {
+        CPU_BANDWIDTH[old_cpu].fetch_sub(old_bw, Ordering::AcqRel);
+    }
+    task.set_affinity(CpuMask::one(cpu));
+    *state = Some(EdfState::new(params, monotonic_time(), cpu, cpumask));
+    true
+}
+
+/// A scheduler running real-time tasks by EDF, and the other tasks by the
+/// best-effort scheduler.
+pub(crate) struct EdfScheduler {
+    /// The ready real-time tasks, ordered by their deadlines, then IDs.
+    ready: BTreeMap<(TimeValue, u64), AxTaskRef>,
+    /// The real-time tasks out of budget, waiting for their next periods.
+    throttled: Vec<AxTaskRef>,
+    best_effort: BestEffortScheduler,
+}
+
+impl EdfScheduler {
+    pub fn new() -> Self {
+        Self {
+            ready: BTreeMap::new(),
+            throttled: Vec::new(),
+            best_effort: BestEffortScheduler::new(),
+        }
+    }
+
+    pub fn scheduler_name() -> String {
+        alloc::format!("EDF + {}", BestEffortScheduler::scheduler_name())
+    }
+
+    /// Accounts the runtime of `task`, which has just stopped running
+    /// without being put back, e.g., blocked.
+    pub fn charge_stopped(&mut self, task: &AxTaskRef) {
+        if let Some(state) = task.edf_state().lock().as_mut() {
+            state.charge(monotonic_time());
+            state.blocked = true;
+        }
+    }
+
+    fn enqueue_rt(&mut self, task: AxTaskRef, state: &EdfState) {
+        if state.budget.is_zero() {
+            trace!("EDF throttle: {}", task.id_name());
+            self.throttled.push(task);
+        } else {
+            self.ready
+                .insert((state.deadline, task.id().as_u64()), task);
+        }
+    }
+
+    /// Gives new budgets to the throttled tasks whose next periods have
+    /// begun.
+    fn replenish(&mut self, now: TimeValue) {
+        let mut i = 0;
+        while i < self.throttled.len() {
+            let key = {
+                let task = &self.throttled[i];
+                let mut state = task.edf_state().lock();
+                let state = state.as_mut().unwrap();
+                let next = state.next_period();
+                if next > now {
+                    None
+                } else {
+                    // Do not catch up with the periods missed.
+                    let missed = next + state.params.period <= now;
+                    state.renew(if missed { now } else { next });
+                    Some((state.deadline, task.id().as_u64()))
+                }
+            };
+            match key {
+                Some(key) => {
+                    let task = self.throttled.swap_remove(i);
+                    self.ready.insert(key, task);
+                }
+                None => i += 1,
+            }
+        }
+    }
+}
+
+impl BaseScheduler for EdfScheduler {
+    type SchedItem = AxTaskRef;
+
+    fn init(&mut self) {
+        self.best_effort.init();
+    }
+
+    fn add_task(&mut self, task: AxTaskRef) {
+        let mut state = task.edf_state().lock();
+        match state.as_mut() {
+            Some(s) => {
+                // Only a real wakeup may start a new period, not a task
+                // sent from another CPU or put back after its parameters
+                // change.
+                if core::mem::take(&mut s.blocked) {
+                    s.wake_up(monotonic_time());
+                }
+                self.enqueue_rt(task.clone(), s);
+            }
+            None => {
+                drop(state);
+                self.best_effort.add_task(task);
+            }
+        }
+    }
+
+    fn remove_task(&mut self, task: &AxTaskRef) -> Option<AxTaskRef> {
+        let deadline = task.edf_state().lock().as_ref().map(|s| s.deadline);
+        match deadline {
+            Some(deadline) => self
+                .ready
+                .remove(&(deadline, task.id().as_u64()))
+                .or_else(|| {
+                    let i = self.throttled.iter().position(|t| Arc::ptr_eq(t, task))?;
+                    Some(self.throttled.swap_remove(i))
+                }),
+            None => self.best_effort.remove_task(task),
+        }
+    }
+
+    fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+        let now = monotonic_time();
+        self.replenish(now);
+        match self.ready.pop_first() {
+            Some((_, task)) => {
+                task.edf_state().lock().as_mut().unwrap().exec_start = now;
+                Some(task)
+            }
+            None => self.best_effort.pick_next_task(),
+        }
+    }
+
+    fn put_prev_task(&mut self, prev: AxTaskRef, preempt: bool) {
+        let mut state = prev.edf_state().lock();
+        match state.as_mut() {
+            Some(s) => {
+                s.charge(monotonic_time());
+                if !preempt {
+                    // Yielding finishes the work of this period.
+                    s.budget = Duration::ZERO;
+                }
+                self.enqueue_rt(prev.clone(), s);
+            }
+            None => {
+                drop(state);
+                self.best_effort.put_prev_task(prev, preempt);
+            }
+        }
+    }
+
+    fn task_tick(&mut self, current: &AxTaskRef) -> bool {
+        let now = monotonic_time();
+        self.replenish(now);
+        let earliest = self
+            .ready
+            .first_key_value()
+            .map(|((deadline, _), _)| *deadline);
+        let mut state = current.edf_state().lock();
+        match state.as_mut() {
+            Some(s) => {
+                s.charge(now);
+                s.budget.is_zero() || earliest.is_some_and(|d| d < s.deadline)
+            }
+            None => {
+                drop(state);
+                earliest.is_some() || self.best_effort.task_tick(current)
+            }
+        }
+    }
+
+    fn set_priority(&mut self, task: &AxTaskRef, prio: isize) -> bool {
+        if task.edf_state().lock().is_some() {
+            return false;
+        }
+        self.best_effort.set_priority(task, prio)
+    }
+}
@@ -0,0 +1,198 @@
+//! Task accounting and the list of all tasks.
+
+use alloc::{collections::BTreeMap, sync::Weak, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use axhal::time::monotonic_time_nanos;
+use kspin::SpinNoIrq;
+
+use crate::{AxTask, AxTaskRef, TaskInner};
+
+/// Why a task is blocked.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitReason {
+    /// Sleeping until a deadline, see [`sleep_until`](crate::sleep_until).
+    Sleep = 1,
+    /// Waiting in a [`WaitQueue`](crate::WaitQueue).
+    WaitQueue = 2,
+    /// Waiting in a [`WaitQueue`](crate::WaitQueue) with a timeout.
+    WaitQueueTimeout = 3,
+}
+
+impl WaitReason {
+    pub(crate) fn from_u8(reason: u8) -> Option<Self> {
+        match reason {
+            1 => Some(Self::Sleep),
+            2 => Some(Self::WaitQueue),
+            3 => Some(Self::WaitQueueTimeout),
+            _ => None,
+        }
+    }
+}
+
+/// The CPU time and context switches of a task, or of several tasks summed
+/// up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TaskStat {
+    /// The time spent in user space.
+    pub utime: Duration,
+    /// The time spent in the kernel.
+    pub stime: Duration,
+    /// The number of context switches because the task blocked or exited.
+    pub nvcsw: u64,
+    /// The number of context switches because the task was preempted or
+    /// yielded.
+    pub nivcsw: u64,
+}
+
+impl TaskStat {
+    fn add(&mut self, other: &Self) {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.nvcsw += other.nvcsw;
+        self.nivcsw += other.nivcsw;
+    }
+}
+
+/// The counters behind [`TaskStat`], only updated by the CPU running the
+/// task.
+pub(crate) struct TaskAcct {
+    utime_ns: AtomicU64,
+    stime_ns: AtomicU64,
+    nvcsw: AtomicU64,
+    nivcsw: AtomicU64,
+    /// When the CPU time was accounted last time.
+    last_ns: AtomicU64,
+    start_ns: u64,
+}
+
+impl TaskAcct {
+    pub fn new() -> Self {
+        let now = monotonic_time_nanos();
+        Self {
+            utime_ns: AtomicU64::new(0),
+            stime_ns: AtomicU64::new(0),
+            nvcsw: AtomicU64::new(0),
+            nivcsw: AtomicU64::new(0),
+            last_ns: AtomicU64::new(now),
+            start_ns: now,
+        }
+    }
+
+    /// Accounts the time since the last accounting as user time if `user`, or
+    /// as system time otherwise.
+    pub fn charge(&self, user: bool, now: u64) {
+        let delta = now.saturating_sub(self.last_ns.swap(now, Ordering::Relaxed));
+        let time = if user { &self.utime_ns } else { &self.stime_ns };
+        time.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    /// Called when the task is switched out of the CPU.
+    pub fn switch_out(&self, now: u64, voluntary: bool) {
+        self.charge(false, now);
+        let count = if voluntary { &self.nvcsw } else { &self.nivcsw };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Called when the task is switched to, the time before is not charged.
+    pub fn switch_in(&self, now: u64) {
+        self.last_ns.store(now, Ordering::Relaxed);
+    }
+
+    pub fn start_time(&self) -> Duration {
+        Duration::from_nanos(self.start_ns)
+    }
+
+    /// Returns the statistics, with the system time not accounted yet if the
+    /// task is running on the current CPU.
+    pub fn stat(&self, running_here: bool) -> TaskStat {
+        let mut stime_ns = self.stime_ns.load(Ordering::Relaxed);
+        if running_here {
+            stime_ns += monotonic_time_nanos().saturating_sub(self.last_ns.load(Ordering::Relaxed));
+        }
+        TaskStat {
+            utime: Duration::from_nanos(self.utime_ns.load(Ordering::Relaxed)),
+            stime: Duration::from_nanos(stime_ns),
+            nvcsw: self.nvcsw.load(Ordering::Relaxed),
+            nivcsw: self.nivcsw.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct TaskList {
+    tasks: BTreeMap<u64, Weak<AxTask>>,
+    /// The statistics of the tasks dropped.
+    exited: TaskStat,
+}
+
+static TASK_LIST: SpinNoIrq<TaskList> = SpinNoIrq::new(TaskList {
+    tasks: BTreeMap::new(),
+    exited: TaskStat {
+        utime: Duration::ZERO,
+        stime: Duration::ZERO,
+        nvcsw: 0,
+        nivcsw: 0,
+    },
+});
+
+pub(crate) fn register_task(task: &AxTaskRef) {
+    TASK_LIST
+        .lock()
+        .tasks
+        .insert(task.id().as_u64(), AxTaskRef::downgrade(task));
+}
+
+pub(crate) fn unregister_task(task: &TaskInner) {
+    let mut list = TASK_LIST.lock();
+    list.tasks.remove(&task.id().as_u64());
+    list.exited.add(&task.stat());
+}
+
+/// Returns the live tasks, in the order of their IDs.
+///
+/// The tasks are collected when called, so the ones spawned afterwards are
+/// not included.
+pub fn tasks() -> impl Iterator<Item = AxTaskRef> {
+    let tasks: Vec<_> = TASK_LIST
+        .lock()
+        .tasks
+        .values()
+        .filter_map(Weak::upgrade)
+        .collect();
+    tasks.into_iter()
+}
+
+/// Returns the statistics of all the tasks, live or exited, summed up.
+pub fn total_stat() -> TaskStat {
+    let (mut total, live) = {
+        let list = TASK_LIST.lock();
+        let live: Vec<_> = list.tasks.values().filter_map(Weak::upgrade).collect();
+        (list.exited, live)
+    };
+    // The tasks may be dropped here, so do it with the list unlocked.
+    for task in live {
+        total.add(&task.stat());
+    }
+    total
+}
+
+#[cfg(feature = "uspace")]
+mod uspace {
+    use axhal::arch::TrapFrame;
+    use axhal::time::monotonic_time_nanos;
+    use axhal::trap::{register_trap_handler, ENTER_FROM_USER, RETURN_TO_USER};
+
+    #[register_trap_handler(ENTER_FROM_USER)]
+    fn account_user_time(_tf: &TrapFrame) {
+        crate::current().acct().charge(true, monotonic_time_nanos());
+    }
+
+    #[register_trap_handler(RETURN_TO_USER)]
+    fn account_system_time(_tf: &mut TrapFrame) {
+        crate::current()
+            .acct()
+            .charge(false, monotonic_time_nanos());
+    }
+}
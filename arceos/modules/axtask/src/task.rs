@@ -1,6 +1,6 @@
-use alloc::{boxed::Box, string::String, sync::Arc};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc};
 use core::ops::Deref;
-use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicIsize, AtomicU64, AtomicU8, Ordering};
 use core::{alloc::Layout, cell::UnsafeCell, fmt, ptr::NonNull};
 
 #[cfg(feature = "preempt")]
@@ -12,8 +12,9 @@ use axhal::tls::TlsArea;
 use axhal::arch::TaskContext;
 use memory_addr::{align_up_4k, VirtAddr};
 
+use crate::stat::{TaskAcct, TaskStat, WaitReason};
 use crate::task_ext::AxTaskExt;
-use crate::{AxRunQueue, AxTask, AxTaskRef, WaitQueue};
+use crate::{AxRunQueue, AxTask, AxTaskRef, CpuMask, WaitQueue};
 
 /// A unique identifier for a thread.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -22,10 +23,14 @@ pub struct TaskId(u64);
 /// The possible states of a task.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub(crate) enum TaskState {
+pub enum TaskState {
+    /// Running on a CPU.
     Running = 1,
+    /// Waiting in a run queue to run.
     Ready = 2,
+    /// Waiting for an event, see [`TaskInner::wait_reason`].
     Blocked = 3,
+    /// Exited, but not dropped yet.
     Exited = 4,
 }
 
@@ -38,10 +43,28 @@ pub struct TaskInner {
 
     entry: Option<*mut dyn FnOnce()>,
     state: AtomicU8,
+    wait_reason: AtomicU8,
+    /// Whether the task is running on a CPU, or has not been switched out
+    /// completely yet.
+    on_cpu: AtomicBool,
+    /// The CPUs the task is allowed to run on.
+    cpumask: AtomicU64,
+    /// The priority set by [`crate::set_priority`].
+    priority: AtomicIsize,
+    /// The priorities inherited from the tasks waiting for the locks held by
+    /// this task, keyed by the lock addresses.
+    inherited_priorities: kspin::SpinNoIrq<BTreeMap<usize, isize>>,
+    /// Whether the priority has changed and is not passed to the scheduler
+    /// holding the task yet.
+    priority_changed: AtomicBool,
 
     in_wait_queue: AtomicBool,
     #[cfg(feature = "irq")]
     in_timer_list: AtomicBool,
+    /// Whether the task is requested to exit, see [`crate::kill`].
+    kill_pending: AtomicBool,
+    /// Whether the task is blocked in a wait that [`crate::kill`] interrupts.
+    killable: AtomicBool,
 
     #[cfg(feature = "preempt")]
     need_resched: AtomicBool,
@@ -50,6 +73,12 @@ pub struct TaskInner {
 
     exit_code: AtomicI32,
     wait_for_exit: WaitQueue,
+    acct: TaskAcct,
+
+    #[cfg(feature = "sched_edf")]
+    edf_state: kspin::SpinNoIrq<Option<crate::sched_edf::EdfState>>,
+    #[cfg(feature = "lockdep")]
+    held_locks: crate::lockdep::HeldLocks,
 
     kstack: Option<TaskStack>,
     ctx: UnsafeCell<TaskContext>,
@@ -135,6 +164,71 @@ impl TaskInner {
         Some(self.exit_code.load(Ordering::Acquire))
     }
 
+    /// Gets the state of the task.
+    #[inline]
+    pub fn state(&self) -> TaskState {
+        self.state.load(Ordering::Acquire).into()
+    }
+
+    /// Returns why the task is blocked, or `None` if it is not.
+    pub fn wait_reason(&self) -> Option<WaitReason> {
+        if self.is_blocked() {
+            WaitReason::from_u8(self.wait_reason.load(Ordering::Acquire))
+        } else {
+            None
+        }
+    }
+
+    /// Whether the task has been killed and should exit at the next
+    /// cancellation point, see [`crate::kill`].
+    pub fn is_kill_pending(&self) -> bool {
+        self.kill_pending.load(Ordering::SeqCst)
+    }
+
+    /// Returns the CPU time and context switches of the task.
+    pub fn stat(&self) -> TaskStat {
+        let running_here = crate::current_may_uninit()
+            .is_some_and(|curr| core::ptr::eq::<TaskInner>(&*curr, self));
+        self.acct.stat(running_here)
+    }
+
+    /// Returns the time since boot when the task was created.
+    pub fn start_time(&self) -> axhal::time::TimeValue {
+        self.acct.start_time()
+    }
+
+    /// Returns the priority of the task, the smaller the higher.
+    ///
+    /// It is the highest one of the priority set by
+    /// [`set_priority`](crate::set_priority) and the ones inherited (see
+    /// [`inherit_priority`](crate::inherit_priority)).
+    pub fn priority(&self) -> isize {
+        let base = self.priority.load(Ordering::Acquire);
+        self.inherited_priorities
+            .lock()
+            .values()
+            .fold(base, |prio, &p| prio.min(p))
+    }
+
+    /// Returns the CPUs the task is allowed to run on.
+    pub fn cpumask(&self) -> CpuMask {
+        CpuMask::from_bits(self.cpumask.load(Ordering::Acquire))
+    }
+
+    /// Sets the CPUs the task is allowed to run on.
+    ///
+    /// It takes effect the next time the task is scheduled, so a running task
+    /// may stay on a CPU not in `cpumask` until it yields (see
+    /// [`set_current_affinity`](crate::set_current_affinity)). Returns `false`
+    /// if `cpumask` is empty.
+    pub fn set_affinity(&self, cpumask: CpuMask) -> bool {
+        if cpumask.is_empty() {
+            return false;
+        }
+        self.cpumask.store(cpumask.bits(), Ordering::Release);
+        true
+    }
+
     /// Returns the pointer to the user-defined task extended data.
     ///
     /// # Safety
@@ -171,15 +265,28 @@ impl TaskInner {
             is_init: false,
             entry: None,
             state: AtomicU8::new(TaskState::Ready as u8),
+            wait_reason: AtomicU8::new(0),
+            on_cpu: AtomicBool::new(false),
+            cpumask: AtomicU64::new(CpuMask::full().bits()),
+            priority: AtomicIsize::new(0),
+            inherited_priorities: kspin::SpinNoIrq::new(BTreeMap::new()),
+            priority_changed: AtomicBool::new(false),
             in_wait_queue: AtomicBool::new(false),
             #[cfg(feature = "irq")]
             in_timer_list: AtomicBool::new(false),
+            kill_pending: AtomicBool::new(false),
+            killable: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
             need_resched: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
             preempt_disable_count: AtomicUsize::new(0),
             exit_code: AtomicI32::new(0),
             wait_for_exit: WaitQueue::new(),
+            acct: TaskAcct::new(),
+            #[cfg(feature = "sched_edf")]
+            edf_state: kspin::SpinNoIrq::new(None),
+            #[cfg(feature = "lockdep")]
+            held_locks: crate::lockdep::HeldLocks::new(alloc::vec::Vec::new()),
             kstack: None,
             ctx: UnsafeCell::new(TaskContext::new()),
             task_ext: AxTaskExt::empty(),
@@ -206,17 +313,62 @@ impl TaskInner {
     }
 
     pub(crate) fn into_arc(self) -> AxTaskRef {
-        Arc::new(AxTask::new(self))
+        let task = Arc::new(AxTask::new(self));
+        crate::stat::register_task(&task);
+        task
     }
 
     #[inline]
-    pub(crate) fn state(&self) -> TaskState {
-        self.state.load(Ordering::Acquire).into()
+    pub(crate) fn set_state(&self, state: TaskState) {
+        self.state.store(state as u8, Ordering::Release)
     }
 
+    /// Sets the state to `to` if it is `from`, returns whether it is set.
     #[inline]
-    pub(crate) fn set_state(&self, state: TaskState) {
-        self.state.store(state as u8, Ordering::Release)
+    pub(crate) fn transition_state(&self, from: TaskState, to: TaskState) -> bool {
+        self.state
+            .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    #[inline]
+    pub(crate) fn set_base_priority(&self, prio: isize) {
+        self.priority.store(prio, Ordering::Release);
+    }
+
+    /// Inherits `prio` through the lock `key`, keeping the higher one if it
+    /// has been inherited through the same lock.
+    pub(crate) fn inherit_priority(&self, key: usize, prio: isize) {
+        let mut inherited = self.inherited_priorities.lock();
+        let p = inherited.entry(key).or_insert(prio);
+        *p = (*p).min(prio);
+    }
+
+    /// Drops the priority inherited through the lock `key`, returns whether
+    /// there is one.
+    pub(crate) fn drop_inherited_priority(&self, key: usize) -> bool {
+        self.inherited_priorities.lock().remove(&key).is_some()
+    }
+
+    #[inline]
+    pub(crate) fn set_priority_changed(&self) {
+        self.priority_changed.store(true, Ordering::Release);
+    }
+
+    /// Returns whether the priority has changed since the last call.
+    #[inline]
+    pub(crate) fn take_priority_changed(&self) -> bool {
+        self.priority_changed.swap(false, Ordering::AcqRel)
+    }
+
+    #[inline]
+    pub(crate) fn set_wait_reason(&self, reason: WaitReason) {
+        self.wait_reason.store(reason as u8, Ordering::Release)
+    }
+
+    #[inline]
+    pub(crate) fn acct(&self) -> &TaskAcct {
+        &self.acct
     }
 
     #[inline]
@@ -244,6 +396,16 @@ impl TaskInner {
         self.is_idle
     }
 
+    #[inline]
+    pub(crate) fn on_cpu(&self) -> bool {
+        self.on_cpu.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub(crate) fn set_on_cpu(&self, on_cpu: bool) {
+        self.on_cpu.store(on_cpu, Ordering::Release);
+    }
+
     #[inline]
     pub(crate) fn in_wait_queue(&self) -> bool {
         self.in_wait_queue.load(Ordering::Acquire)
@@ -266,6 +428,21 @@ impl TaskInner {
         self.in_timer_list.store(in_timer_list, Ordering::Release);
     }
 
+    #[inline]
+    pub(crate) fn set_kill_pending(&self) {
+        self.kill_pending.store(true, Ordering::SeqCst);
+    }
+
+    #[inline]
+    pub(crate) fn is_killable(&self) -> bool {
+        self.killable.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub(crate) fn set_killable(&self, killable: bool) {
+        self.killable.store(killable, Ordering::SeqCst);
+    }
+
     #[inline]
     #[cfg(feature = "preempt")]
     pub(crate) fn set_preempt_pending(&self, pending: bool) {
@@ -297,13 +474,28 @@ impl TaskInner {
     fn current_check_preempt_pending() {
         let curr = crate::current();
         if curr.need_resched.load(Ordering::Acquire) && curr.can_preempt(0) {
-            let mut rq = crate::RUN_QUEUE.lock();
+            let mut rq = crate::current_run_queue();
             if curr.need_resched.load(Ordering::Acquire) {
                 rq.preempt_resched();
             }
         }
     }
 
+    /// Returns the real-time scheduling state, which is `None` if the task is
+    /// not in the real-time class.
+    #[inline]
+    #[cfg(feature = "sched_edf")]
+    pub(crate) fn edf_state(&self) -> &kspin::SpinNoIrq<Option<crate::sched_edf::EdfState>> {
+        &self.edf_state
+    }
+
+    /// Returns the locks held by the task, in acquisition order.
+    #[inline]
+    #[cfg(feature = "lockdep")]
+    pub(crate) fn held_locks(&self) -> &crate::lockdep::HeldLocks {
+        &self.held_locks
+    }
+
     pub(crate) fn notify_exit(&self, exit_code: i32, rq: &mut AxRunQueue) {
         self.exit_code.store(exit_code, Ordering::Release);
         self.wait_for_exit.notify_all_locked(false, rq);
@@ -322,11 +514,15 @@ impl TaskInner {
 
     /// Returns the top address of the kernel stack.
     #[inline]
-    pub const fn kernel_stack_top(&self) -> Option<VirtAddr> {
-        match &self.kstack {
-            Some(s) => Some(s.top()),
-            None => None,
-        }
+    pub fn kernel_stack_top(&self) -> Option<VirtAddr> {
+        self.kstack.as_ref().map(|s| s.top())
+    }
+
+    /// Returns the slot in the kernel stack region of the kernel stack, if it
+    /// is guarded.
+    #[cfg(feature = "paging")]
+    pub(crate) fn kstack_slot(&self) -> Option<usize> {
+        self.kstack.as_ref().and_then(|s| s.slot())
     }
 }
 
@@ -343,31 +539,66 @@ impl fmt::Debug for TaskInner {
 impl Drop for TaskInner {
     fn drop(&mut self) {
         debug!("task drop: {}", self.id_name());
+        crate::stat::unregister_task(self);
     }
 }
 
-struct TaskStack {
-    ptr: NonNull<u8>,
-    layout: Layout,
+enum TaskStack {
+    /// A stack allocated from the heap, without guard pages.
+    Heap { ptr: NonNull<u8>, layout: Layout },
+    /// A stack of `size` bytes mapped to the top of a slot in the kernel
+    /// stack region, whose frames are allocated by [`KernelStackIf`].
+    ///
+    /// [`KernelStackIf`]: crate::KernelStackIf
+    #[cfg(feature = "paging")]
+    Guarded { slot: usize, size: usize },
 }
 
 impl TaskStack {
     pub fn alloc(size: usize) -> Self {
+        #[cfg(feature = "paging")]
+        if size <= crate::kstack::MAX_GUARDED_STACK_SIZE {
+            if let Some(slot) = crate::kstack::map_stack(size) {
+                return Self::Guarded { slot, size };
+            }
+            warn!("failed to map a guarded kernel stack of size {:#x}", size);
+        }
+
         let layout = Layout::from_size_align(size, 16).unwrap();
-        Self {
+        Self::Heap {
             ptr: NonNull::new(unsafe { alloc::alloc::alloc(layout) }).unwrap(),
             layout,
         }
     }
 
-    pub const fn top(&self) -> VirtAddr {
-        unsafe { core::mem::transmute(self.ptr.as_ptr().add(self.layout.size())) }
+    pub fn top(&self) -> VirtAddr {
+        match *self {
+            Self::Heap { ptr, layout } => unsafe {
+                core::mem::transmute(ptr.as_ptr().add(layout.size()))
+            },
+            #[cfg(feature = "paging")]
+            Self::Guarded { slot, .. } => crate::kstack::slot_top(slot),
+        }
+    }
+
+    /// Returns the slot in the kernel stack region the stack is mapped to,
+    /// if it is guarded.
+    #[cfg(feature = "paging")]
+    pub fn slot(&self) -> Option<usize> {
+        match *self {
+            Self::Guarded { slot, .. } => Some(slot),
+            Self::Heap { .. } => None,
+        }
     }
 }
 
 impl Drop for TaskStack {
     fn drop(&mut self) {
-        unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+        match *self {
+            Self::Heap { ptr, layout } => unsafe { alloc::alloc::dealloc(ptr.as_ptr(), layout) },
+            #[cfg(feature = "paging")]
+            Self::Guarded { slot, size } => crate::kstack::unmap_stack(slot, size),
+        }
     }
 }
 
@@ -407,6 +638,7 @@ impl CurrentTask {
 
     pub(crate) unsafe fn init_current(init_task: AxTaskRef) {
         assert!(init_task.is_init());
+        init_task.set_on_cpu(true);
         #[cfg(feature = "tls")]
         axhal::arch::write_thread_pointer(init_task.tls.tls_ptr() as usize);
         let ptr = Arc::into_raw(init_task);
@@ -429,8 +661,7 @@ impl Deref for CurrentTask {
 }
 
 extern "C" fn task_entry() -> ! {
-    // release the lock that was implicitly held across the reschedule
-    unsafe { crate::RUN_QUEUE.force_unlock() };
+    crate::run_queue::finish_task_switch();
     #[cfg(feature = "irq")]
     axhal::arch::enable_irqs();
     let task = crate::current();
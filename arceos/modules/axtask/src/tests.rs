@@ -1,7 +1,7 @@
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Mutex, Once};
 
-use crate::{api as axtask, current, WaitQueue};
+use crate::{api as axtask, current, Interrupted, WaitQueue};
 
 static INIT: Once = Once::new();
 static SERIAL: Mutex<()> = Mutex::new(());
@@ -128,3 +128,100 @@ fn test_task_join() {
         assert_eq!(tasks[i].join(), Some(i as _));
     }
 }
+
+#[test]
+fn test_task_kill() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static WQ: WaitQueue = WaitQueue::new();
+    static STARTED: AtomicUsize = AtomicUsize::new(0);
+    static READY: AtomicBool = AtomicBool::new(false);
+    static RETURNED: AtomicBool = AtomicBool::new(false);
+
+    let waiter = axtask::spawn(|| {
+        STARTED.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(WQ.wait_until_killable(|| false), Err(Interrupted));
+        axtask::exit_if_killed();
+        unreachable!("killed task did not exit");
+    });
+    let spinner = axtask::spawn(|| {
+        STARTED.fetch_add(1, Ordering::Relaxed);
+        loop {
+            axtask::yield_now();
+            axtask::exit_if_killed();
+        }
+    });
+    // Not interrupted, it only exits after the wait, e.g., unlocking.
+    let sleeper = axtask::spawn(|| {
+        STARTED.fetch_add(1, Ordering::Relaxed);
+        WQ.wait_until(|| READY.load(Ordering::Acquire));
+        RETURNED.store(true, Ordering::Release);
+        axtask::exit_if_killed();
+        unreachable!("killed task did not exit");
+    });
+
+    while STARTED.load(Ordering::Relaxed) < 3 {
+        axtask::yield_now();
+    }
+    assert!(axtask::kill(&waiter));
+    assert!(axtask::kill(&spinner));
+    assert!(axtask::kill(&sleeper));
+    assert_eq!(waiter.join(), Some(axtask::KILLED_EXIT_CODE));
+    assert_eq!(spinner.join(), Some(axtask::KILLED_EXIT_CODE));
+    assert!(!axtask::kill(&waiter));
+
+    for _ in 0..10 {
+        axtask::yield_now();
+    }
+    assert!(!RETURNED.load(Ordering::Acquire));
+    READY.store(true, Ordering::Release);
+    WQ.notify_all(false);
+    assert_eq!(sleeper.join(), Some(axtask::KILLED_EXIT_CODE));
+    assert!(RETURNED.load(Ordering::Acquire));
+}
+
+#[cfg(feature = "sched_edf")]
+#[test]
+fn test_edf_admission() {
+    use crate::{CpuMask, EdfParams};
+    use core::time::Duration;
+
+    fn edf_params(runtime_ms: u64, deadline_ms: u64) -> EdfParams {
+        EdfParams {
+            runtime: Duration::from_millis(runtime_ms),
+            deadline: Duration::from_millis(deadline_ms),
+            period: Duration::from_millis(deadline_ms),
+        }
+    }
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    assert!(!edf_params(0, 10).is_valid());
+    assert!(!edf_params(20, 10).is_valid());
+    assert!(!axtask::set_current_edf(Some(edf_params(20, 10))));
+
+    let cpumask = current().cpumask();
+    // Half of the CPU, the task is pinned to it.
+    assert!(axtask::set_current_edf(Some(edf_params(5, 10))));
+    assert_eq!(current().cpumask(), CpuMask::one(0));
+    assert!(!axtask::set_current_affinity(CpuMask::full()));
+
+    // Another task may take at most 45% more of the same CPU.
+    let task = axtask::spawn(|| {
+        assert!(!axtask::set_current_edf(Some(edf_params(5, 10))));
+        assert!(axtask::set_current_edf(Some(edf_params(4, 10))));
+        // Exits in the real-time class, which releases the bandwidth.
+    });
+    assert_eq!(task.join(), Some(0));
+
+    // The old reservation is replaced, and kept if the new one fails.
+    assert!(axtask::set_current_edf(Some(edf_params(9, 10))));
+    assert!(!axtask::set_current_edf(Some(edf_params(10, 10))));
+    assert!(!axtask::set_current_affinity(CpuMask::full()));
+
+    assert!(axtask::set_current_edf(None));
+    assert_eq!(current().cpumask(), cpumask);
+    assert!(axtask::set_current_affinity(cpumask));
+}
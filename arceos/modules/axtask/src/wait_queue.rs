@@ -2,10 +2,21 @@ use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use kspin::SpinRaw;
 
-use crate::{AxRunQueue, AxTaskRef, CurrentTask, RUN_QUEUE};
+use crate::{current_run_queue, AxRunQueue, AxTaskRef, CurrentTask, WaitReason};
+
+/// The error returned by the killable waits of [`WaitQueue`], when the task
+/// has been [`kill`](crate::kill)ed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupted;
 
 /// A queue to store sleeping tasks.
 ///
+/// The `*_killable` methods return [`Interrupted`] once the task is
+/// [`kill`](crate::kill)ed, which the caller should pass up to a point where
+/// no lock is held, e.g., the return of a syscall, and exit there with
+/// [`exit_if_killed`](crate::exit_if_killed). The other methods are not
+/// interrupted.
+///
 /// # Examples
 ///
 /// ```
@@ -27,7 +38,17 @@ use crate::{AxRunQueue, AxTaskRef, CurrentTask, RUN_QUEUE};
 /// assert_eq!(VALUE.load(Ordering::Relaxed), 1);
 /// ```
 pub struct WaitQueue {
-    queue: SpinRaw<VecDeque<AxTaskRef>>, // we already disabled IRQs when lock the `RUN_QUEUE`
+    queue: SpinRaw<VecDeque<AxTaskRef>>, // we already disabled IRQs when lock the run queue
+}
+
+/// Returns [`Interrupted`] if the wait is killable and the current task has
+/// been killed.
+fn check_killed(killable: bool) -> Result<(), Interrupted> {
+    if killable && crate::current().is_kill_pending() {
+        Err(Interrupted)
+    } else {
+        Ok(())
+    }
 }
 
 impl WaitQueue {
@@ -50,7 +71,7 @@ impl WaitQueue {
         // the event from another queue.
         if curr.in_wait_queue() {
             // wake up by timer (timeout).
-            // `RUN_QUEUE` is not locked here, so disable IRQs.
+            // The run queue is not locked here, so disable IRQs.
             let _guard = kernel_guard::IrqSave::new();
             self.queue.lock().retain(|t| !curr.ptr_eq(t));
             curr.set_in_wait_queue(false);
@@ -64,12 +85,28 @@ impl WaitQueue {
 
     /// Blocks the current task and put it into the wait queue, until other task
     /// notifies it.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn wait(&self) {
-        RUN_QUEUE.lock().block_current(|task| {
+        let _ = self.wait_inner(false);
+    }
+
+    /// Like [`wait`](Self::wait), but returns [`Interrupted`] if the task is
+    /// killed.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn wait_killable(&self) -> Result<(), Interrupted> {
+        self.wait_inner(true)
+    }
+
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    fn wait_inner(&self, killable: bool) -> Result<(), Interrupted> {
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::might_sleep(core::panic::Location::caller());
+        current_run_queue().block_current(WaitReason::WaitQueue, killable, |task| {
             task.set_in_wait_queue(true);
             self.queue.lock().push_back(task)
         });
         self.cancel_events(crate::current());
+        check_killed(killable)
     }
 
     /// Blocks the current task and put it into the wait queue, until the given
@@ -77,27 +114,79 @@ impl WaitQueue {
     ///
     /// Note that even other tasks notify this task, it will not wake up until
     /// the condition becomes true.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn wait_until<F>(&self, condition: F)
     where
         F: Fn() -> bool,
     {
+        let _ = self.wait_until_inner(false, condition);
+    }
+
+    /// Like [`wait_until`](Self::wait_until), but returns [`Interrupted`] if
+    /// the task is killed before the condition becomes true.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn wait_until_killable<F>(&self, condition: F) -> Result<(), Interrupted>
+    where
+        F: Fn() -> bool,
+    {
+        self.wait_until_inner(true, condition)
+    }
+
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    fn wait_until_inner<F>(&self, killable: bool, condition: F) -> Result<(), Interrupted>
+    where
+        F: Fn() -> bool,
+    {
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::might_sleep(core::panic::Location::caller());
+        let mut result = Ok(());
         loop {
-            let mut rq = RUN_QUEUE.lock();
+            let mut rq = current_run_queue();
+            // Check the condition with the wait queue locked, so a task
+            // notifying from another CPU after changing it must find us.
+            let mut wq = self.queue.lock();
             if condition() {
                 break;
             }
-            rq.block_current(|task| {
+            result = check_killed(killable);
+            if result.is_err() {
+                break;
+            }
+            // Release the wait queue in the closure, before switching out.
+            rq.block_current(WaitReason::WaitQueue, killable, move |task| {
                 task.set_in_wait_queue(true);
-                self.queue.lock().push_back(task);
+                wq.push_back(task);
             });
         }
         self.cancel_events(crate::current());
+        result
     }
 
     /// Blocks the current task and put it into the wait queue, until other tasks
     /// notify it, or the given duration has elapsed.
     #[cfg(feature = "irq")]
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn wait_timeout(&self, dur: core::time::Duration) -> bool {
+        self.wait_timeout_inner(false, dur).unwrap_or(false)
+    }
+
+    /// Like [`wait_timeout`](Self::wait_timeout), but returns [`Interrupted`]
+    /// if the task is killed.
+    #[cfg(feature = "irq")]
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn wait_timeout_killable(&self, dur: core::time::Duration) -> Result<bool, Interrupted> {
+        self.wait_timeout_inner(true, dur)
+    }
+
+    #[cfg(feature = "irq")]
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    fn wait_timeout_inner(
+        &self,
+        killable: bool,
+        dur: core::time::Duration,
+    ) -> Result<bool, Interrupted> {
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::might_sleep(core::panic::Location::caller());
         let curr = crate::current();
         let deadline = axhal::time::wall_time() + dur;
         debug!(
@@ -105,15 +194,18 @@ impl WaitQueue {
             curr.id_name(),
             deadline
         );
-        crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
-        RUN_QUEUE.lock().block_current(|task| {
+        current_run_queue().block_current(WaitReason::WaitQueueTimeout, killable, |task| {
+            // Set the alarm after blocking, or it may go off on another CPU
+            // before and be missed.
+            crate::timers::set_alarm_wakeup(deadline, task.clone());
             task.set_in_wait_queue(true);
             self.queue.lock().push_back(task)
         });
         let timeout = curr.in_wait_queue(); // still in the wait queue, must have timed out
         self.cancel_events(curr);
-        timeout
+        check_killed(killable)?;
+        Ok(timeout)
     }
 
     /// Blocks the current task and put it into the wait queue, until the given
@@ -122,10 +214,44 @@ impl WaitQueue {
     /// Note that even other tasks notify this task, it will not wake up until
     /// the above conditions are met.
     #[cfg(feature = "irq")]
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub fn wait_timeout_until<F>(&self, dur: core::time::Duration, condition: F) -> bool
     where
         F: Fn() -> bool,
     {
+        self.wait_timeout_until_inner(false, dur, condition)
+            .unwrap_or(false)
+    }
+
+    /// Like [`wait_timeout_until`](Self::wait_timeout_until), but returns
+    /// [`Interrupted`] if the task is killed before the condition becomes
+    /// true.
+    #[cfg(feature = "irq")]
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub fn wait_timeout_until_killable<F>(
+        &self,
+        dur: core::time::Duration,
+        condition: F,
+    ) -> Result<bool, Interrupted>
+    where
+        F: Fn() -> bool,
+    {
+        self.wait_timeout_until_inner(true, dur, condition)
+    }
+
+    #[cfg(feature = "irq")]
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    fn wait_timeout_until_inner<F>(
+        &self,
+        killable: bool,
+        dur: core::time::Duration,
+        condition: F,
+    ) -> Result<bool, Interrupted>
+    where
+        F: Fn() -> bool,
+    {
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::might_sleep(core::panic::Location::caller());
         let curr = crate::current();
         let deadline = axhal::time::wall_time() + dur;
         debug!(
@@ -133,22 +259,29 @@ impl WaitQueue {
             curr.id_name(),
             deadline
         );
-        crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
-        let mut timeout = true;
+        let mut result = Ok(true);
         while axhal::time::wall_time() < deadline {
-            let mut rq = RUN_QUEUE.lock();
+            let mut rq = current_run_queue();
+            let mut wq = self.queue.lock();
             if condition() {
-                timeout = false;
+                result = Ok(false);
+                break;
+            }
+            if let Err(err) = check_killed(killable) {
+                result = Err(err);
                 break;
             }
-            rq.block_current(|task| {
+            rq.block_current(WaitReason::WaitQueueTimeout, killable, move |task| {
+                if !task.in_timer_list() {
+                    crate::timers::set_alarm_wakeup(deadline, task.clone());
+                }
                 task.set_in_wait_queue(true);
-                self.queue.lock().push_back(task);
+                wq.push_back(task);
             });
         }
         self.cancel_events(curr);
-        timeout
+        result
     }
 
     /// Wakes up one task in the wait queue, usually the first one.
@@ -156,7 +289,7 @@ impl WaitQueue {
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_one(&self, resched: bool) -> bool {
-        let mut rq = RUN_QUEUE.lock();
+        let mut rq = current_run_queue();
         if !self.queue.lock().is_empty() {
             self.notify_one_locked(resched, &mut rq)
         } else {
@@ -164,20 +297,42 @@ impl WaitQueue {
         }
     }
 
+    /// Wakes up the task with the highest [priority](crate::TaskInner::priority)
+    /// in the wait queue, or the first one of them if there are several.
+    ///
+    /// If `resched` is true, the current task will be preempted when the
+    /// preemption is enabled.
+    pub fn notify_one_by_priority(&self, resched: bool) -> bool {
+        let mut rq = current_run_queue();
+        let mut wq = self.queue.lock();
+        let index = wq
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, t)| t.priority())
+            .map(|(i, _)| i);
+        if let Some(task) = index.and_then(|i| wq.remove(i)) {
+            task.set_in_wait_queue(false);
+            rq.unblock_task(task, resched);
+            true
+        } else {
+            false
+        }
+    }
+
     /// Wakes all tasks in the wait queue.
     ///
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_all(&self, resched: bool) {
         loop {
-            let mut rq = RUN_QUEUE.lock();
+            let mut rq = current_run_queue();
             if let Some(task) = self.queue.lock().pop_front() {
                 task.set_in_wait_queue(false);
                 rq.unblock_task(task, resched);
             } else {
                 break;
             }
-            drop(rq); // we must unlock `RUN_QUEUE` after unlocking `self.queue`.
+            drop(rq); // we must unlock the run queue after unlocking `self.queue`.
         }
     }
 
@@ -186,7 +341,7 @@ impl WaitQueue {
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_task(&mut self, resched: bool, task: &AxTaskRef) -> bool {
-        let mut rq = RUN_QUEUE.lock();
+        let mut rq = current_run_queue();
         let mut wq = self.queue.lock();
         if let Some(index) = wq.iter().position(|t| Arc::ptr_eq(t, task)) {
             task.set_in_wait_queue(false);
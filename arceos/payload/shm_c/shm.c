@@ -0,0 +1,84 @@
+#define _GNU_SOURCE
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+#include <sys/ipc.h>
+#include <sys/mman.h>
+#include <sys/shm.h>
+#include <sys/wait.h>
+
+#define SIZE 4096
+
+void child_write(char *buf, const char *content)
+{
+    pid_t pid = fork();
+    if (pid < 0) {
+        printf("Fork error!\n");
+        exit(-1);
+    }
+    if (pid == 0) {
+        strcpy(buf, content);
+        exit(0);
+    }
+    waitpid(pid, NULL, 0);
+}
+
+void test_sysv_shm(void)
+{
+    int shmid;
+    char *addr;
+
+    shmid = shmget(IPC_PRIVATE, SIZE, IPC_CREAT | 0600);
+    if (shmid < 0) {
+        printf("Shmget error!\n");
+        exit(-1);
+    }
+    addr = shmat(shmid, NULL, 0);
+    if (addr == (void *)-1) {
+        printf("Shmat error!\n");
+        exit(-1);
+    }
+    child_write(addr, "hello from shm!");
+    printf("Read back shm: %s\n", addr);
+    shmdt(addr);
+    shmctl(shmid, IPC_RMID, NULL);
+}
+
+void test_memfd(void)
+{
+    int fd;
+    char *addr;
+    char buf[32] = {0};
+
+    fd = memfd_create("test", MFD_CLOEXEC);
+    if (fd < 0) {
+        printf("Memfd_create error!\n");
+        exit(-1);
+    }
+    if (ftruncate(fd, SIZE) < 0) {
+        printf("Ftruncate error!\n");
+        exit(-1);
+    }
+    addr = mmap(NULL, SIZE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    if (addr == MAP_FAILED) {
+        printf("Map memfd error!\n");
+        exit(-1);
+    }
+    child_write(addr, "hello from memfd!");
+    read(fd, buf, sizeof(buf) - 1);
+    printf("Read back memfd: %s\n", buf);
+    munmap(addr, SIZE);
+    close(fd);
+}
+
+int main()
+{
+    printf("Shm ...\n");
+
+    test_sysv_shm();
+    test_memfd();
+
+    printf("Shm ok!\n");
+    return 0;
+}
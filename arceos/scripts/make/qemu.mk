@@ -33,6 +33,10 @@ qemu_args-$(BLK) += \
   -device virtio-blk-$(vdev-suffix),drive=disk0 \
   -drive id=disk0,if=none,format=raw,file=$(DISK_IMG)
 
+qemu_args-$(SWAP) += \
+  -device virtio-blk-$(vdev-suffix),drive=swap0 \
+  -drive id=swap0,if=none,format=raw,file=$(SWAP_IMG)
+
 qemu_args-$(NET) += \
   -device virtio-net-$(vdev-suffix),netdev=net0
 
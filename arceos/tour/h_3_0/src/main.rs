@@ -22,7 +22,7 @@ const PHY_MEM_SIZE: usize = 0x100_0000;
 const KERNEL_BASE: usize = 0x8020_0000;
 
 use axmm::AddrSpace;
-use axhal::paging::MappingFlags;
+use axhal::paging::{MappingFlags, PageSize};
 
 #[no_mangle]
 fn main() {
@@ -36,7 +36,7 @@ fn main() {
 
     // Physical memory region. Full access flags.
     let mapping_flags = MappingFlags::from_bits(0xf).unwrap();
-    aspace.map_alloc(PHY_MEM_START.into(), PHY_MEM_SIZE, mapping_flags, true).unwrap();
+    aspace.map_huge(PHY_MEM_START.into(), PHY_MEM_SIZE, mapping_flags, PageSize::Size2M, true).unwrap();
 
     // Load corresponding images for VM.
     info!("VM created success, loading images...");
@@ -18,7 +18,7 @@ use riscv_vcpu::RISCVVCpu;
 use riscv_vcpu::AxVCpuExitReason::NestedPageFault;
 
 use axmm::AddrSpace;
-use axhal::paging::MappingFlags;
+use axhal::paging::{MappingFlags, PageSize};
 use vmdev::VmDevGroup;
 
 const VM_ASPACE_BASE: usize = 0x0;
@@ -39,7 +39,7 @@ fn main() {
 
     // Physical memory region. Full access flags.
     let mapping_flags = MappingFlags::from_bits(0xf).unwrap();
-    aspace.map_alloc(PHY_MEM_START.into(), PHY_MEM_SIZE, mapping_flags, true).unwrap();
+    aspace.map_huge(PHY_MEM_START.into(), PHY_MEM_SIZE, mapping_flags, PageSize::Size2M, true).unwrap();
 
     // Load corresponding images for VM.
     info!("VM created success, loading images...");
@@ -1,23 +1,7 @@
-#![allow(dead_code)]
+use axsyscall::register_syscall;
 
-use axhal::arch::TrapFrame;
-use axhal::trap::{register_trap_handler, SYSCALL};
-use axerrno::LinuxError;
+register_syscall!(Exit => sys_exit(i32));
 
-const SYS_EXIT: usize = 93;
-
-#[register_trap_handler(SYSCALL)]
-fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
-    ax_println!("handle_syscall ...");
-    let ret = match syscall_num {
-        SYS_EXIT => {
-            ax_println!("[SYS_EXIT]: process is exiting ..");
-            axtask::exit(tf.arg0() as _)
-        },
-        _ => {
-            ax_println!("Unimplemented syscall: {}", syscall_num);
-            -LinuxError::ENOSYS.code() as _
-        }
-    };
-    ret
+fn sys_exit(code: i32) -> isize {
+    axtask::exit(code)
 }
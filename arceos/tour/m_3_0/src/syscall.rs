@@ -1,53 +1,63 @@
-#![allow(dead_code)]
-
 use core::ffi::c_void;
-use axhal::arch::TrapFrame;
-use axhal::trap::{register_trap_handler, SYSCALL};
 use axerrno::LinuxError;
 use axtask::current;
 use axtask::TaskExtRef;
+use axmm::UserSlice;
 use arceos_posix_api as api;
+use axsyscall::register_syscall;
 
-const SYS_IOCTL: usize = 29;
-const SYS_WRITEV: usize = 66;
-const SYS_EXIT: usize = 93;
-const SYS_EXIT_GROUP: usize = 94;
-const SYS_SET_TID_ADDRESS: usize = 96;
+register_syscall!(Ioctl => sys_ioctl(i32, usize, *mut c_void));
+register_syscall!(SetTidAddress => sys_set_tid_address(*const i32));
+register_syscall!(Writev => sys_writev(i32, *const api::ctypes::iovec, i32));
+register_syscall!(ExitGroup => sys_exit(i32));
+register_syscall!(Exit => sys_exit(i32));
 
-#[register_trap_handler(SYSCALL)]
-fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
-    ax_println!("handle_syscall [{}] ...", syscall_num);
-    let ret = match syscall_num {
-         SYS_IOCTL => sys_ioctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
-        SYS_SET_TID_ADDRESS => sys_set_tid_address(tf.arg0() as _),
-        SYS_WRITEV => sys_writev(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
-        SYS_EXIT_GROUP => {
-            ax_println!("[SYS_EXIT_GROUP]: system is exiting ..");
-            axtask::exit(tf.arg0() as _)
-        },
-        SYS_EXIT => {
-            ax_println!("[SYS_EXIT]: system is exiting ..");
-            axtask::exit(tf.arg0() as _)
-        },
-        _ => {
-            ax_println!("Unimplemented syscall: {}", syscall_num);
-            -LinuxError::ENOSYS.code() as _
-        }
-    };
-    ret
+fn sys_exit(code: i32) -> isize {
+    axtask::exit(code)
 }
 
+/// Writes the user buffers described by the `iocnt` iovecs at `iov`, which
+/// are copied in first since user memory is not accessible directly.
 fn sys_writev(fd: i32, iov: *const api::ctypes::iovec, iocnt: i32) -> isize {
-    unsafe { api::sys_writev(fd, iov, iocnt) }
+    if !(0..=1024).contains(&iocnt) {
+        return -LinuxError::EINVAL.code() as _;
+    }
+    let curr = current();
+    let mut aspace = curr.task_ext().aspace.lock();
+    let iovs = match UserSlice::<api::ctypes::iovec>::new(iov as usize, iocnt as usize)
+        .read_to_vec(&mut aspace)
+    {
+        Ok(iovs) => iovs,
+        Err(e) => return -LinuxError::from(e).code() as _,
+    };
+    let mut ret = 0;
+    for iov in iovs {
+        let buf = match UserSlice::<u8>::new(iov.iov_base as usize, iov.iov_len as usize)
+            .read_to_vec(&mut aspace)
+        {
+            Ok(buf) => buf,
+            Err(e) if ret == 0 => return -LinuxError::from(e).code() as _,
+            Err(_) => break,
+        };
+        let n = api::sys_write(fd, buf.as_ptr() as _, buf.len());
+        if n < 0 {
+            return if ret == 0 { n } else { ret };
+        }
+        ret += n;
+        if (n as usize) < buf.len() {
+            break;
+        }
+    }
+    ret
 }
 
-pub(crate) fn sys_set_tid_address(tid_ptd: *const i32) -> isize {
+fn sys_set_tid_address(tid_ptd: *const i32) -> isize {
     let curr = current();
     curr.task_ext().set_clear_child_tid(tid_ptd as _);
     curr.id().as_u64() as isize
 }
 
+/// Terminal control is not supported, and is ignored.
 fn sys_ioctl(_fd: i32, _op: usize, _argp: *mut c_void) -> i32 {
-    ax_println!("Unimplemented syscall: SYS_IOCTL");
     0
 }
@@ -1,71 +1,111 @@
-#![allow(dead_code)]
-
 use core::ffi::{c_void, c_char, c_int};
-use axhal::arch::TrapFrame;
-use axhal::trap::{register_trap_handler, SYSCALL};
 use axerrno::LinuxError;
 use axtask::current;
 use axtask::TaskExtRef;
+use axmm::{UserCStr, UserSlice};
 use arceos_posix_api as api;
-
-const SYS_IOCTL: usize = 29;
-const SYS_OPENAT: usize = 56;
-const SYS_CLOSE: usize = 57;
-const SYS_READ: usize = 63;
-const SYS_WRITE: usize = 64;
-const SYS_WRITEV: usize = 66;
-const SYS_EXIT: usize = 93;
-const SYS_EXIT_GROUP: usize = 94;
-const SYS_SET_TID_ADDRESS: usize = 96;
+use axsyscall::register_syscall;
 
 const AT_FDCWD: i32 = -100;
+const PATH_MAX: usize = 4096;
+const IO_BUF_SIZE: usize = 0x1_0000;
 
-#[register_trap_handler(SYSCALL)]
-fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
-    ax_println!("handle_syscall [{}] ...", syscall_num);
-    let ret = match syscall_num {
-         SYS_IOCTL => sys_ioctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
-        SYS_SET_TID_ADDRESS => sys_set_tid_address(tf.arg0() as _),
-        SYS_OPENAT => sys_openat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _, tf.arg3() as _),
-        SYS_CLOSE => sys_close(tf.arg0() as _),
-        SYS_READ => sys_read(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
-        SYS_WRITE => sys_write(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
-        SYS_WRITEV => sys_writev(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
-        SYS_EXIT_GROUP => {
-            ax_println!("[SYS_EXIT_GROUP]: system is exiting ..");
-            axtask::exit(tf.arg0() as _)
-        },
-        SYS_EXIT => {
-            ax_println!("[SYS_EXIT]: system is exiting ..");
-            axtask::exit(tf.arg0() as _)
-        },
-        _ => {
-            ax_println!("Unimplemented syscall: {}", syscall_num);
-            -LinuxError::ENOSYS.code() as _
-        }
-    };
-    ret
+register_syscall!(Ioctl => sys_ioctl(i32, usize, *mut c_void));
+register_syscall!(SetTidAddress => sys_set_tid_address(*const i32));
+register_syscall!(Openat => sys_openat(c_int, *const c_char, c_int, api::ctypes::mode_t));
+register_syscall!(Close => sys_close(i32));
+register_syscall!(Read => sys_read(i32, *mut c_void, usize));
+register_syscall!(Write => sys_write(i32, *const c_void, usize));
+register_syscall!(Writev => sys_writev(i32, *const api::ctypes::iovec, i32));
+register_syscall!(ExitGroup => sys_exit(i32));
+register_syscall!(Exit => sys_exit(i32));
+
+fn sys_exit(code: i32) -> isize {
+    axtask::exit(code)
 }
 
 fn sys_openat(dfd: c_int, fname: *const c_char, flags: c_int, mode: api::ctypes::mode_t) -> isize {
-    assert_eq!(dfd, AT_FDCWD);
-    api::sys_open(fname, flags, mode) as isize
+    let curr = current();
+    let path = match UserCStr::new(fname as usize).read(&mut curr.task_ext().aspace.lock(), PATH_MAX - 1) {
+        Ok(path) => path,
+        Err(e) => return -LinuxError::from(e).code() as _,
+    };
+    // Directory file descriptors are not supported, they only matter for
+    // relative paths.
+    if dfd != AT_FDCWD && !path.starts_with('/') {
+        return -LinuxError::ENOSYS.code() as _;
+    }
+    // Appends the NUL terminator for the C API.
+    let mut path = path.into_bytes();
+    path.push(0);
+    api::sys_open(path.as_ptr() as _, flags, mode) as isize
 }
 
 fn sys_close(fd: i32) -> isize {
     api::sys_close(fd) as isize
 }
 
+/// Reads into a kernel buffer first, and copies the data out to the user
+/// buffer, since user memory is not accessible directly. Short reads are
+/// allowed, so the kernel buffer is bounded.
 fn sys_read(fd: i32, buf: *mut c_void, count: usize) -> isize {
-    api::sys_read(fd, buf, count)
+    let mut kbuf = alloc::vec![0u8; count.min(IO_BUF_SIZE)];
+    let n = api::sys_read(fd, kbuf.as_mut_ptr() as _, kbuf.len());
+    if n <= 0 {
+        return n;
+    }
+    let curr = current();
+    match UserSlice::new(buf as usize, count)
+        .copy_from_slice(&mut curr.task_ext().aspace.lock(), &kbuf[..n as usize])
+    {
+        Ok(()) => n,
+        Err(e) => -LinuxError::from(e).code() as _,
+    }
 }
 
+/// Copies the user buffer in first, since user memory is not accessible
+/// directly.
 fn sys_write(fd: i32, buf: *const c_void, count: usize) -> isize {
-    api::sys_write(fd, buf, count)
+    let curr = current();
+    match UserSlice::<u8>::new(buf as usize, count).read_to_vec(&mut curr.task_ext().aspace.lock()) {
+        Ok(kbuf) => api::sys_write(fd, kbuf.as_ptr() as _, count),
+        Err(e) => -LinuxError::from(e).code() as _,
+    }
 }
 
+/// Writes the user buffers described by the `iocnt` iovecs at `iov`, which
+/// are copied in first since user memory is not accessible directly.
 fn sys_writev(fd: i32, iov: *const api::ctypes::iovec, iocnt: i32) -> isize {
-    unsafe { api::sys_writev(fd, iov, iocnt) }
+    if !(0..=1024).contains(&iocnt) {
+        return -LinuxError::EINVAL.code() as _;
+    }
+    let curr = current();
+    let mut aspace = curr.task_ext().aspace.lock();
+    let iovs = match UserSlice::<api::ctypes::iovec>::new(iov as usize, iocnt as usize)
+        .read_to_vec(&mut aspace)
+    {
+        Ok(iovs) => iovs,
+        Err(e) => return -LinuxError::from(e).code() as _,
+    };
+    let mut ret = 0;
+    for iov in iovs {
+        let buf = match UserSlice::<u8>::new(iov.iov_base as usize, iov.iov_len as usize)
+            .read_to_vec(&mut aspace)
+        {
+            Ok(buf) => buf,
+            Err(e) if ret == 0 => return -LinuxError::from(e).code() as _,
+            Err(_) => break,
+        };
+        let n = api::sys_write(fd, buf.as_ptr() as _, buf.len());
+        if n < 0 {
+            return if ret == 0 { n } else { ret };
+        }
+        ret += n;
+        if (n as usize) < buf.len() {
+            break;
+        }
+    }
+    ret
 }
 
 fn sys_set_tid_address(tid_ptd: *const i32) -> isize {
@@ -74,7 +114,7 @@ fn sys_set_tid_address(tid_ptd: *const i32) -> isize {
     curr.id().as_u64() as isize
 }
 
+/// Terminal control is not supported, and is ignored.
 fn sys_ioctl(_fd: i32, _op: usize, _argp: *mut c_void) -> i32 {
-    ax_println!("Ignore SYS_IOCTL");
     0
 }
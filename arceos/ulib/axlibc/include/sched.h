@@ -2,6 +2,7 @@
 #define _SCHED_H
 
 #include <stddef.h>
+#include <sys/types.h>
 
 typedef struct cpu_set_t {
     unsigned long __bits[128 / sizeof(long)];
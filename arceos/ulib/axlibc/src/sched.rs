@@ -0,0 +1,15 @@
+use core::ffi::c_int;
+
+use arceos_posix_api::sys_sched_setaffinity;
+
+use crate::{ctypes, utils::e};
+
+/// Set the CPUs a thread is allowed to run on.
+#[no_mangle]
+pub unsafe extern "C" fn sched_setaffinity(
+    pid: c_int,
+    cpusetsize: usize,
+    mask: *const ctypes::cpu_set_t,
+) -> c_int {
+    e(sys_sched_setaffinity(pid, cpusetsize, mask))
+}
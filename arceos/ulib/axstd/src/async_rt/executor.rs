@@ -0,0 +1,362 @@
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::task::Wake;
+use alloc::{boxed::Box, vec::Vec};
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+
+use arceos_api::modules::axsync::spin::SpinNoIrq;
+use arceos_api::task::{
+    ax_current_task_id, ax_wait_queue_wait, ax_wait_queue_wake, AxWaitQueueHandle,
+};
+use arceos_api::time::{ax_wall_time, AxTimeValue};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// How often the network interfaces are polled while futures wait for I/O,
+/// in case no other task polls them.
+const IO_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The executors of the threads running [`block_on`], by thread ID.
+static EXECUTORS: SpinNoIrq<BTreeMap<u64, Arc<Executor>>> = SpinNoIrq::new(BTreeMap::new());
+
+/// The executor running the futures of a thread.
+pub(super) struct Executor {
+    /// The spawned futures to poll.
+    ready: SpinNoIrq<VecDeque<Arc<Job>>>,
+    /// Whether the future passed to [`block_on`] needs to be polled.
+    main_woken: AtomicBool,
+    /// Set when woken, the thread sleeps in `wq` until then.
+    notified: AtomicBool,
+    wq: AxWaitQueueHandle,
+    /// The futures waiting for network I/O.
+    io_waiters: SpinNoIrq<Vec<Waker>>,
+    /// Set when polling the network interfaces may have changed the socket
+    /// states, so the futures waiting for I/O should retry.
+    io_ready: AtomicBool,
+    /// Sets `io_ready` and wakes up the thread, registered to the network
+    /// stack.
+    io_waker: Waker,
+    /// The futures waiting for deadlines, by the deadlines and timer IDs.
+    timers: SpinNoIrq<BTreeMap<(AxTimeValue, u64), Waker>>,
+    next_timer_id: AtomicU64,
+}
+
+/// A spawned future.
+struct Job {
+    /// It is taken out while being polled.
+    future: SpinNoIrq<Option<BoxFuture>>,
+    /// Whether it is in the run queue.
+    queued: AtomicBool,
+    executor: Weak<Executor>,
+}
+
+/// The waker of the future passed to [`block_on`].
+struct MainWaker(Weak<Executor>);
+
+/// The waker woken by the network stack, see [`Executor::io_waker`].
+struct IoWaker(Weak<Executor>);
+
+impl Executor {
+    fn new(this: Weak<Self>) -> Self {
+        Self {
+            ready: SpinNoIrq::new(VecDeque::new()),
+            main_woken: AtomicBool::new(true),
+            notified: AtomicBool::new(false),
+            wq: AxWaitQueueHandle::new(),
+            io_waiters: SpinNoIrq::new(Vec::new()),
+            io_ready: AtomicBool::new(false),
+            io_waker: Waker::from(Arc::new(IoWaker(this))),
+            timers: SpinNoIrq::new(BTreeMap::new()),
+            next_timer_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the executor of the current thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current thread is not running [`block_on`].
+    pub(super) fn current() -> Arc<Self> {
+        EXECUTORS
+            .lock()
+            .get(&ax_current_task_id())
+            .cloned()
+            .expect("not running in `block_on()`")
+    }
+
+    fn notify(&self) {
+        self.notified.store(true, Ordering::Release);
+        ax_wait_queue_wake(&self.wq, 1);
+    }
+
+    fn has_work(&self) -> bool {
+        self.main_woken.load(Ordering::Acquire) || !self.ready.lock().is_empty()
+    }
+
+    /// Wakes up the waker when the network stack is polled next time.
+    pub(super) fn wait_io(&self, waker: &Waker) {
+        self.io_waiters.lock().push(waker.clone());
+    }
+
+    /// Wakes up the waker at the deadline, returns the timer ID.
+    pub(super) fn add_timer(&self, deadline: AxTimeValue, waker: &Waker) -> u64 {
+        let id = self.next_timer_id.fetch_add(1, Ordering::Relaxed);
+        self.timers.lock().insert((deadline, id), waker.clone());
+        id
+    }
+
+    /// Updates the waker of the timer, returns `false` if it has fired.
+    pub(super) fn update_timer(&self, deadline: AxTimeValue, id: u64, waker: &Waker) -> bool {
+        match self.timers.lock().get_mut(&(deadline, id)) {
+            Some(old) => {
+                old.clone_from(waker);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(super) fn cancel_timer(&self, deadline: AxTimeValue, id: u64) {
+        self.timers.lock().remove(&(deadline, id));
+    }
+
+    /// Polls the spawned futures ready now.
+    fn run_ready(&self) {
+        let jobs = core::mem::take(&mut *self.ready.lock());
+        for job in jobs {
+            job.queued.store(false, Ordering::Release);
+            let Some(mut future) = job.future.lock().take() else {
+                continue;
+            };
+            let waker = Waker::from(job.clone());
+            if future
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_pending()
+            {
+                *job.future.lock() = Some(future);
+            }
+        }
+    }
+
+    fn fire_timers(&self) {
+        let now = ax_wall_time();
+        let expired = {
+            let mut timers = self.timers.lock();
+            let later = timers.split_off(&(now, u64::MAX));
+            core::mem::replace(&mut *timers, later)
+        };
+        expired.into_values().for_each(Waker::wake);
+    }
+
+    /// Wakes up the futures waiting for I/O if the socket states may have
+    /// changed, returns whether there are still futures waiting.
+    fn poll_io(&self) -> bool {
+        if self.io_waiters.lock().is_empty() {
+            return false;
+        }
+        #[cfg(feature = "net")]
+        {
+            // Registered first, so no change by other tasks is missed.
+            arceos_api::net::ax_register_poll_waker(&self.io_waker);
+            arceos_api::net::ax_poll_interfaces().ok();
+        }
+        if self.io_ready.swap(false, Ordering::AcqRel) {
+            let io_waiters = core::mem::take(&mut *self.io_waiters.lock());
+            io_waiters.into_iter().for_each(Waker::wake);
+        }
+        !self.io_waiters.lock().is_empty()
+    }
+
+    /// Waits until some future is woken up.
+    ///
+    /// The thread sleeps until the next timer fires, and polls the network
+    /// interfaces at least every [`IO_POLL_INTERVAL`] while futures wait for
+    /// I/O. It is woken up earlier if other tasks poll the interfaces.
+    fn park(&self) {
+        self.notified.store(false, Ordering::Release);
+        self.fire_timers();
+        let waiting_io = self.poll_io();
+        if self.has_work() {
+            return;
+        }
+
+        let next_deadline = self
+            .timers
+            .lock()
+            .keys()
+            .next()
+            .map(|&(deadline, _)| deadline);
+        let timeout = next_deadline
+            .map(|deadline| deadline.saturating_sub(ax_wall_time()))
+            .into_iter()
+            .chain(waiting_io.then_some(IO_POLL_INTERVAL))
+            .min();
+        if cfg!(not(feature = "irq")) && timeout.is_some() {
+            // Cannot sleep with a timeout.
+            crate::thread::yield_now();
+        } else if ax_wait_queue_wait(&self.wq, || self.notified.load(Ordering::Acquire), timeout)
+            && waiting_io
+        {
+            // Retry the I/O every interval anyway, in case some change is
+            // not reported by polling the interfaces.
+            self.io_ready.store(true, Ordering::Release);
+        }
+    }
+}
+
+impl Wake for Job {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let Some(executor) = self.executor.upgrade() else {
+            return; // `block_on` has returned
+        };
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            executor.ready.lock().push_back(self.clone());
+        }
+        executor.notify();
+    }
+}
+
+impl Wake for IoWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Some(executor) = self.0.upgrade() {
+            executor.io_ready.store(true, Ordering::Release);
+            executor.notify();
+        }
+    }
+}
+
+impl Wake for MainWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Some(executor) = self.0.upgrade() {
+            executor.main_woken.store(true, Ordering::Release);
+            executor.notify();
+        }
+    }
+}
+
+/// Runs the future to completion on the current thread, together with the
+/// futures spawned by [`spawn`] in the meantime.
+///
+/// The spawned futures not completed are dropped when it returns.
+///
+/// # Panics
+///
+/// Panics if called in a future run by it.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let executor = Arc::new_cyclic(|this| Executor::new(this.clone()));
+    let thread_id = ax_current_task_id();
+    let old = EXECUTORS.lock().insert(thread_id, executor.clone());
+    assert!(old.is_none(), "`block_on()` cannot be nested");
+
+    let waker = Waker::from(Arc::new(MainWaker(Arc::downgrade(&executor))));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    let output = loop {
+        if executor.main_woken.swap(false, Ordering::AcqRel) {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                break output;
+            }
+        }
+        executor.run_ready();
+        executor.park();
+    };
+    EXECUTORS.lock().remove(&thread_id);
+    output
+}
+
+/// The state shared by a spawned future and its [`JoinHandle`].
+struct JoinState<T> {
+    output: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// An owned permission to await the output of a spawned future.
+///
+/// The future goes on running if it is dropped.
+pub struct JoinHandle<T> {
+    state: Arc<SpinNoIrq<JoinState<T>>>,
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock();
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Spawns a future to run concurrently on the current thread, returns a
+/// [`JoinHandle`] to await its output.
+///
+/// # Panics
+///
+/// Panics if the current thread is not running [`block_on`].
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let executor = Executor::current();
+    let state = Arc::new(SpinNoIrq::new(JoinState {
+        output: None,
+        waker: None,
+    }));
+    let job_state = state.clone();
+    let job = Arc::new(Job {
+        future: SpinNoIrq::new(Some(Box::pin(async move {
+            let output = future.await;
+            let waker = {
+                let mut state = job_state.lock();
+                state.output = Some(output);
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }))),
+        queued: AtomicBool::new(true),
+        executor: Arc::downgrade(&executor),
+    });
+    executor.ready.lock().push_back(job);
+    JoinHandle { state }
+}
+
+/// Yields to the other futures of the current thread.
+pub async fn yield_now() {
+    let mut yielded = false;
+    core::future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
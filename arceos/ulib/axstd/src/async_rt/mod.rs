@@ -0,0 +1,38 @@
+//! A cooperative async runtime.
+//!
+//! [`block_on`] runs a future and the ones [`spawn`]ed by it on the current
+//! thread, so that many connections can be served by one thread with the
+//! async TCP/UDP sockets in mod [`net`] and the timers in mod [`time`].
+//!
+//! Wakers put the futures back to the run queue of the thread, and notify it
+//! through a wait queue if it is sleeping. The futures waiting for I/O are
+//! retried when polling the network interfaces may have changed the socket
+//! states, by any task. As the interfaces are not polled in IRQs, a thread
+//! with such futures also polls them itself periodically while sleeping.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::async_rt::{self, net::TcpListener};
+//!
+//! async_rt::block_on(async {
+//!     let listener = TcpListener::bind("0.0.0.0:5555").unwrap();
+//!     loop {
+//!         let (stream, _) = listener.accept().await.unwrap();
+//!         async_rt::spawn(async move {
+//!             let mut buf = [0; 1024];
+//!             while let Ok(n @ 1..) = stream.read(&mut buf).await {
+//!                 stream.write_all(&buf[..n]).await.unwrap();
+//!             }
+//!         });
+//!     }
+//! });
+//! ```
+
+mod executor;
+
+#[cfg(feature = "net")]
+pub mod net;
+pub mod time;
+
+pub use self::executor::{block_on, spawn, yield_now, JoinHandle};
@@ -0,0 +1,227 @@
+//! Async TCP/UDP sockets.
+//!
+//! They are nonblocking sockets, whose operations are retried each time the
+//! network interfaces are polled, until they do not return
+//! [`WouldBlock`](AxError::WouldBlock).
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use arceos_api::net::{self as api, AxTcpSocketHandle, AxUdpSocketHandle};
+use axerrno::{ax_err_type, AxError};
+
+use super::executor::Executor;
+use crate::io;
+use crate::net::{each_addr, SocketAddr, ToSocketAddrs};
+
+/// Runs the nonblocking operation until it does not return `WouldBlock`.
+async fn io_loop<T>(mut f: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    poll_fn(|cx| {
+        api::ax_poll_interfaces()?;
+        match f() {
+            Err(AxError::WouldBlock) => {
+                Executor::current().wait_io(cx.waker());
+                Poll::Pending
+            }
+            res => Poll::Ready(res),
+        }
+    })
+    .await
+}
+
+/// An async TCP stream between a local and a remote socket.
+pub struct TcpStream(AxTcpSocketHandle);
+
+/// An async TCP socket server, listening for connections.
+pub struct TcpListener(AxTcpSocketHandle);
+
+impl TcpStream {
+    fn new(socket: AxTcpSocketHandle) -> io::Result<Self> {
+        api::ax_tcp_set_nonblocking(&socket, true)?;
+        Ok(Self(socket))
+    }
+
+    async fn connect_one(addr: SocketAddr) -> io::Result<Self> {
+        let stream = Self::new(api::ax_tcp_socket())?;
+        match api::ax_tcp_connect(&stream.0, addr) {
+            Err(AxError::WouldBlock) => {}
+            res => return res.map(|_| stream),
+        }
+        io_loop(|| match api::ax_tcp_poll(&stream.0)?.writable {
+            true => Ok(()),
+            false => Err(AxError::WouldBlock),
+        })
+        .await?;
+        // The socket is closed if the connection failed.
+        match api::ax_tcp_peer_addr(&stream.0) {
+            Ok(_) => Ok(stream),
+            Err(_) => Err(ax_err_type!(ConnectionRefused, "socket connect() failed")),
+        }
+    }
+
+    /// Opens a TCP connection to a remote host.
+    ///
+    /// If `addr` yields multiple addresses, `connect` will be attempted with
+    /// each of the addresses until a connection is successful. If none of
+    /// the addresses result in a successful connection, the error returned from
+    /// the last connection attempt (the last address) is returned.
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs()? {
+            match Self::connect_one(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err
+            .unwrap_or_else(|| ax_err_type!(InvalidInput, "could not resolve to any addresses")))
+    }
+
+    /// Returns the socket address of the local half of this TCP connection.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        api::ax_tcp_socket_addr(&self.0)
+    }
+
+    /// Returns the socket address of the remote peer of this TCP connection.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        api::ax_tcp_peer_addr(&self.0)
+    }
+
+    /// Shuts down the connection.
+    pub fn shutdown(&self) -> io::Result<()> {
+        api::ax_tcp_shutdown(&self.0)
+    }
+
+    /// Receives some data into the buffer, returns the number of bytes read.
+    ///
+    /// It returns `Ok(0)` if the remote peer has closed the connection.
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        io_loop(|| api::ax_tcp_recv(&self.0, buf)).await
+    }
+
+    /// Sends some data from the buffer, returns the number of bytes written.
+    pub async fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        io_loop(|| api::ax_tcp_send(&self.0, buf)).await
+    }
+
+    /// Sends the entire buffer.
+    pub async fn write_all(&self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf).await? {
+                0 => return Err(ax_err_type!(WriteZero, "failed to write whole buffer")),
+                n => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TcpListener {
+    /// Creates a new `TcpListener` which will be bound to the specified
+    /// address.
+    ///
+    /// If `addr` yields multiple addresses, `bind` will be attempted with
+    /// each of the addresses until one succeeds and returns the listener. If
+    /// none of the addresses succeed in creating a listener, the error returned
+    /// from the last attempt (the last address) is returned.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
+        each_addr(addr, |addr: io::Result<&SocketAddr>| {
+            let addr = addr?;
+            let backlog = 128;
+            let socket = api::ax_tcp_socket();
+            api::ax_tcp_set_nonblocking(&socket, true)?;
+            api::ax_tcp_bind(&socket, *addr)?;
+            api::ax_tcp_listen(&socket, backlog)?;
+            Ok(TcpListener(socket))
+        })
+    }
+
+    /// Returns the local socket address of this listener.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        api::ax_tcp_socket_addr(&self.0)
+    }
+
+    /// Accepts a new incoming connection from this listener.
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let (socket, addr) = io_loop(|| api::ax_tcp_accept(&self.0)).await?;
+        Ok((TcpStream::new(socket)?, addr))
+    }
+}
+
+/// An async UDP socket.
+pub struct UdpSocket(AxUdpSocketHandle);
+
+impl UdpSocket {
+    /// Creates a UDP socket from the given address.
+    ///
+    /// If `addr` yields multiple addresses, `bind` will be attempted with
+    /// each of the addresses until one succeeds and returns the socket. If none
+    /// of the addresses succeed in creating a socket, the error returned from
+    /// the last attempt (the last address) is returned.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
+        each_addr(addr, |addr: io::Result<&SocketAddr>| {
+            let addr = addr?;
+            let socket = api::ax_udp_socket();
+            api::ax_udp_set_nonblocking(&socket, true)?;
+            api::ax_udp_bind(&socket, *addr)?;
+            Ok(UdpSocket(socket))
+        })
+    }
+
+    /// Returns the socket address that this socket was created from.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        api::ax_udp_socket_addr(&self.0)
+    }
+
+    /// Returns the socket address of the remote peer this socket was connected to.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        api::ax_udp_peer_addr(&self.0)
+    }
+
+    /// Connects this UDP socket to a remote address, allowing the `send` and
+    /// `recv` methods to be used.
+    ///
+    /// If `addr` yields multiple addresses, `connect` will be attempted with
+    /// each of the addresses until one succeeds.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        each_addr(addr, |addr: io::Result<&SocketAddr>| {
+            let addr = addr?;
+            api::ax_udp_connect(&self.0, *addr)
+        })
+    }
+
+    /// Receives a single datagram message on the socket. On success, returns
+    /// the number of bytes read and the origin.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        io_loop(|| api::ax_udp_recv_from(&self.0, buf)).await
+    }
+
+    /// Receives a single datagram message on the socket, without removing it from
+    /// the queue. On success, returns the number of bytes read and the origin.
+    pub async fn peek_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        io_loop(|| api::ax_udp_peek_from(&self.0, buf)).await
+    }
+
+    /// Sends data on the socket to the given address. On success, returns the
+    /// number of bytes written.
+    ///
+    /// Only the first address yielded by `addr` is used.
+    pub async fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> io::Result<usize> {
+        match addr.to_socket_addrs()?.next() {
+            Some(addr) => io_loop(|| api::ax_udp_send_to(&self.0, buf, addr)).await,
+            None => Err(ax_err_type!(InvalidInput, "no addresses to send data to")),
+        }
+    }
+
+    /// Receives a single datagram message on the socket from the remote
+    /// address to which it is connected.
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        io_loop(|| api::ax_udp_recv(&self.0, buf)).await
+    }
+
+    /// Sends data on the socket to the remote address to which it is
+    /// connected.
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        io_loop(|| api::ax_udp_send(&self.0, buf)).await
+    }
+}
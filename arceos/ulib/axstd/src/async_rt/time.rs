@@ -0,0 +1,109 @@
+//! Timer futures.
+
+extern crate alloc;
+
+use alloc::sync::{Arc, Weak};
+use core::fmt;
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use arceos_api::time::{ax_wall_time, AxTimeValue};
+
+use super::executor::Executor;
+
+/// A future completing at a deadline, returned by [`sleep`] and
+/// [`sleep_until`].
+///
+/// The thread running it sleeps until the deadline if there are no other
+/// futures to run, with a timeout set on its wait queue.
+pub struct Sleep {
+    deadline: AxTimeValue,
+    /// The executor and ID of the timer, after it is set.
+    timer: Option<(Weak<Executor>, u64)>,
+}
+
+/// Waits until the given duration has elapsed.
+pub fn sleep(dur: Duration) -> Sleep {
+    sleep_until(ax_wall_time() + dur)
+}
+
+/// Waits until the given deadline.
+pub fn sleep_until(deadline: AxTimeValue) -> Sleep {
+    Sleep {
+        deadline,
+        timer: None,
+    }
+}
+
+impl Sleep {
+    /// Returns the deadline of this future.
+    pub fn deadline(&self) -> AxTimeValue {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if ax_wall_time() >= self.deadline {
+            return Poll::Ready(());
+        }
+        let deadline = self.deadline;
+        if let Some((executor, id)) = &self.timer {
+            if let Some(executor) = executor.upgrade() {
+                if executor.update_timer(deadline, *id, cx.waker()) {
+                    return Poll::Pending;
+                }
+            }
+        }
+        let executor = Executor::current();
+        let id = executor.add_timer(deadline, cx.waker());
+        self.timer = Some((Arc::downgrade(&executor), id));
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some((executor, id)) = self.timer.take() {
+            if let Some(executor) = executor.upgrade() {
+                executor.cancel_timer(self.deadline, id);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Sleep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sleep")
+            .field("deadline", &self.deadline)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The error returned by [`timeout`] when the future does not complete in
+/// time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("deadline has elapsed")
+    }
+}
+
+/// Runs the future until it completes, or the given duration has elapsed.
+pub async fn timeout<F: Future>(dur: Duration, future: F) -> Result<F::Output, Elapsed> {
+    let mut future = pin!(future);
+    let mut sleep = sleep(dur);
+    core::future::poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut sleep).poll(cx).map(|()| Err(Elapsed))
+    })
+    .await
+}
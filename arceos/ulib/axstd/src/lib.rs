@@ -71,9 +71,14 @@ pub mod sync;
 pub mod thread;
 pub mod time;
 
+#[cfg(feature = "multitask")]
+pub mod async_rt;
 #[cfg(feature = "fs")]
 pub mod fs;
 #[cfg(feature = "net")]
 pub mod net;
 #[cfg(feature = "alloc")]
 pub mod collections;
+
+#[cfg(test)]
+mod tests;
@@ -8,11 +8,15 @@ pub use core::sync::atomic;
 pub use alloc::sync::{Arc, Weak};
 
 #[cfg(feature = "multitask")]
-mod mutex;
+#[doc(cfg(feature = "multitask"))]
+pub mod mpsc;
 
 #[cfg(feature = "multitask")]
 #[doc(cfg(feature = "multitask"))]
-pub use self::mutex::{Mutex, MutexGuard};
+pub use arceos_api::modules::axsync::{
+    Barrier, BarrierWaitResult, Condvar, LazyLock, Mutex, MutexGuard, Once, RwLock,
+    RwLockReadGuard, RwLockWriteGuard, Semaphore, WaitTimeoutResult,
+};
 
 #[cfg(not(feature = "multitask"))]
 #[doc(cfg(not(feature = "multitask")))]
@@ -0,0 +1,528 @@
+//! Multi-producer, single-consumer FIFO queue communication primitives.
+//!
+//! Same as [`std::sync::mpsc`], it provides the [`channel`] function for
+//! asynchronous (unbounded) channels and the [`sync_channel`] function for
+//! synchronous (bounded) ones.
+//!
+//! The blocked senders and receivers sleep in wait queues until they can go
+//! on, instead of spinning.
+//!
+//! [`std::sync::mpsc`]: https://doc.rust-lang.org/std/sync/mpsc/index.html
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(feature = "irq")]
+use core::time::Duration;
+
+use arceos_api::modules::axsync::spin::SpinNoIrq;
+use arceos_api::task::{ax_wait_queue_wait, ax_wait_queue_wake, AxWaitQueueHandle};
+
+/// The state shared by the two halves of a channel.
+struct Channel<T> {
+    queue: SpinNoIrq<VecDeque<T>>,
+    /// The maximum number of buffered messages, `None` for unbounded. A
+    /// bound of 0 buffers one message until it is received.
+    bound: Option<usize>,
+    /// The number of buffered messages, to check without locking the queue.
+    len: AtomicUsize,
+    /// The number of messages received so far.
+    received: AtomicUsize,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+    receiver_waiting: AtomicBool,
+    recv_wq: AxWaitQueueHandle,
+    send_wq: AxWaitQueueHandle,
+}
+
+impl<T> Channel<T> {
+    fn new(bound: Option<usize>) -> Self {
+        Self {
+            queue: SpinNoIrq::new(VecDeque::new()),
+            bound,
+            len: AtomicUsize::new(0),
+            received: AtomicUsize::new(0),
+            senders: AtomicUsize::new(1),
+            receiver_alive: AtomicBool::new(true),
+            receiver_waiting: AtomicBool::new(false),
+            recv_wq: AxWaitQueueHandle::new(),
+            send_wq: AxWaitQueueHandle::new(),
+        }
+    }
+
+    fn is_disconnected(&self) -> bool {
+        !self.receiver_alive.load(Ordering::Acquire)
+    }
+
+    fn is_full(&self) -> bool {
+        self.bound
+            .is_some_and(|bound| self.len.load(Ordering::Acquire) >= bound.max(1))
+    }
+
+    /// Buffers the message if there is space, returns its ticket: it has
+    /// been received when the number of received messages reaches it.
+    fn try_push(&self, t: T) -> Result<usize, TrySendError<T>> {
+        let mut queue = self.queue.lock();
+        if self.is_disconnected() {
+            return Err(TrySendError::Disconnected(t));
+        }
+        if self.is_full() {
+            return Err(TrySendError::Full(t));
+        }
+        queue.push_back(t);
+        let ticket = self.received.load(Ordering::Acquire) + queue.len();
+        self.len.store(queue.len(), Ordering::Release);
+        drop(queue);
+        ax_wait_queue_wake(&self.recv_wq, 1);
+        Ok(ticket)
+    }
+
+    /// Blocks until the space for the message is available, then buffers it.
+    /// With a bound of 0, it also blocks until the message is received.
+    fn send(&self, mut t: T) -> Result<(), SendError<T>> {
+        let ticket = loop {
+            match self.try_push(t) {
+                Ok(ticket) => break ticket,
+                Err(TrySendError::Disconnected(t)) => return Err(SendError(t)),
+                Err(TrySendError::Full(back)) => t = back,
+            }
+            ax_wait_queue_wait(
+                &self.send_wq,
+                || !self.is_full() || self.is_disconnected(),
+                None,
+            );
+        };
+        if self.bound == Some(0) {
+            ax_wait_queue_wait(
+                &self.send_wq,
+                || self.received.load(Ordering::Acquire) >= ticket || self.is_disconnected(),
+                None,
+            );
+            if self.received.load(Ordering::Acquire) < ticket {
+                // Disconnected before receiving it, so it is still the only
+                // one buffered.
+                let mut queue = self.queue.lock();
+                if let Some(t) = queue.pop_back() {
+                    self.len.store(queue.len(), Ordering::Release);
+                    return Err(SendError(t));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut queue = self.queue.lock();
+        match queue.pop_front() {
+            Some(t) => {
+                self.len.store(queue.len(), Ordering::Release);
+                self.received.fetch_add(1, Ordering::AcqRel);
+                drop(queue);
+                // Rendezvous senders are waiting for different messages.
+                let count = if self.bound == Some(0) { u32::MAX } else { 1 };
+                ax_wait_queue_wake(&self.send_wq, count);
+                Ok(t)
+            }
+            None if self.senders.load(Ordering::Acquire) == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Blocks until a message is buffered or all senders are gone, or the
+    /// timeout has elapsed. Returns whether it has timed out.
+    fn wait_recv(&self, timeout: Option<core::time::Duration>) -> bool {
+        // Lets rendezvous senders buffer messages with `try_send`.
+        self.receiver_waiting.store(true, Ordering::Release);
+        let timed_out = ax_wait_queue_wait(
+            &self.recv_wq,
+            || self.len.load(Ordering::Acquire) > 0 || self.senders.load(Ordering::Acquire) == 0,
+            timeout,
+        );
+        self.receiver_waiting.store(false, Ordering::Release);
+        timed_out
+    }
+}
+
+/// The sending-half of an asynchronous channel created by [`channel`].
+///
+/// Messages can be sent through it with [`Sender::send`], which never
+/// blocks. It can be cloned to send to the same channel multiple times.
+pub struct Sender<T> {
+    inner: Arc<Channel<T>>,
+}
+
+/// The sending-half of a synchronous channel created by [`sync_channel`].
+///
+/// Messages can be sent through it with [`SyncSender::send`], which blocks
+/// if there is no space in the buffer. It can be cloned to send to the same
+/// channel multiple times.
+pub struct SyncSender<T> {
+    inner: Arc<Channel<T>>,
+}
+
+/// The receiving half of a channel created by [`channel`] or
+/// [`sync_channel`].
+///
+/// Messages sent to the channel can be retrieved with [`Receiver::recv`].
+pub struct Receiver<T> {
+    inner: Arc<Channel<T>>,
+}
+
+/// Creates a new asynchronous channel, returning the sender/receiver
+/// halves.
+///
+/// All data sent on the [`Sender`] will become available on the
+/// [`Receiver`] in the same order as it was sent, and no send will block
+/// the calling thread (this channel has an "infinite buffer").
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Channel::new(None));
+    (
+        Sender {
+            inner: inner.clone(),
+        },
+        Receiver { inner },
+    )
+}
+
+/// Creates a new synchronous, bounded channel, returning the sender/receiver
+/// halves.
+///
+/// The channel buffers up to `bound` messages, and [`SyncSender::send`]
+/// blocks when the buffer is full. If `bound` is 0, the channel becomes a
+/// "rendezvous" channel where each send blocks until the message is
+/// received.
+pub fn sync_channel<T>(bound: usize) -> (SyncSender<T>, Receiver<T>) {
+    let inner = Arc::new(Channel::new(Some(bound)));
+    (
+        SyncSender {
+            inner: inner.clone(),
+        },
+        Receiver { inner },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Sends a value on this channel.
+    ///
+    /// It fails and returns the value back only if the receiver has been
+    /// dropped.
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.inner.send(t)
+    }
+}
+
+impl<T> SyncSender<T> {
+    /// Sends a value on this channel, blocking the current thread until
+    /// there is space in the buffer (or until it is received, if the bound
+    /// is 0).
+    ///
+    /// It fails and returns the value back if the receiver has been dropped.
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.inner.send(t)
+    }
+
+    /// Tries to send a value on this channel without blocking.
+    ///
+    /// It fails if the buffer is full, or the bound is 0 and the receiver
+    /// is not waiting for a message.
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        let inner = &self.inner;
+        if inner.bound == Some(0) && !inner.receiver_waiting.load(Ordering::Acquire) {
+            if inner.is_disconnected() {
+                return Err(TrySendError::Disconnected(t));
+            }
+            return Err(TrySendError::Full(t));
+        }
+        inner.try_push(t).map(|_| ())
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Tries to receive a message without blocking.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_recv()
+    }
+
+    /// Receives a message, blocking the current thread until there is one.
+    ///
+    /// It fails if there are no messages and all senders have been dropped.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        loop {
+            match self.inner.try_recv() {
+                Ok(t) => return Ok(t),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {
+                    self.inner.wait_recv(None);
+                }
+            }
+        }
+    }
+
+    /// Receives a message, blocking the current thread until there is one,
+    /// or the given duration has elapsed.
+    #[cfg(feature = "irq")]
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = arceos_api::time::ax_wall_time() + timeout;
+        loop {
+            match self.inner.try_recv() {
+                Ok(t) => return Ok(t),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            let now = arceos_api::time::ax_wall_time();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            self.inner.wait_recv(Some(deadline - now));
+        }
+    }
+
+    /// Returns an iterator blocking to receive messages, until all senders
+    /// have been dropped.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    /// Returns an iterator receiving the messages available without
+    /// blocking.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.inner.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Clone for SyncSender<T> {
+    fn clone(&self) -> Self {
+        self.inner.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+fn drop_sender<T>(inner: &Channel<T>) {
+    if inner.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+        ax_wait_queue_wake(&inner.recv_wq, u32::MAX);
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        drop_sender(&self.inner);
+    }
+}
+
+impl<T> Drop for SyncSender<T> {
+    fn drop(&mut self) {
+        drop_sender(&self.inner);
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // Lock the queue to order it with the senders buffering messages.
+        let queue = self.inner.queue.lock();
+        self.inner.receiver_alive.store(false, Ordering::Release);
+        drop(queue);
+        ax_wait_queue_wake(&self.inner.send_wq, u32::MAX);
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for SyncSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SyncSender").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+/// An iterator over messages on a [`Receiver`], created by
+/// [`Receiver::iter`].
+#[derive(Debug)]
+pub struct Iter<'a, T: 'a> {
+    rx: &'a Receiver<T>,
+}
+
+/// An iterator that attempts to yield all pending values for a
+/// [`Receiver`], created by [`Receiver::try_iter`].
+#[derive(Debug)]
+pub struct TryIter<'a, T: 'a> {
+    rx: &'a Receiver<T>,
+}
+
+/// An owning iterator over messages on a [`Receiver`], created by
+/// [`Receiver::into_iter`].
+#[derive(Debug)]
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+/// An error returned from [`Sender::send`] or [`SyncSender::send`], when the
+/// receiver has been dropped. It contains the value failed to send.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct SendError<T>(pub T);
+
+/// An error returned from [`Receiver::recv`], when all senders have been
+/// dropped.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct RecvError;
+
+/// An error returned from [`Receiver::try_recv`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TryRecvError {
+    /// The channel is currently empty, but the senders are still alive.
+    Empty,
+    /// All senders have been dropped, and there are no more messages.
+    Disconnected,
+}
+
+/// An error returned from [`Receiver::recv_timeout`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RecvTimeoutError {
+    /// No message arrived in the given duration, but the senders are still
+    /// alive.
+    Timeout,
+    /// All senders have been dropped, and there are no more messages.
+    Disconnected,
+}
+
+/// An error returned from [`SyncSender::try_send`]. It contains the value
+/// failed to send.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum TrySendError<T> {
+    /// The buffer is full, or the bound is 0 and the receiver is not
+    /// waiting.
+    Full(T),
+    /// The receiver has been dropped.
+    Disconnected(T),
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("sending on a closed channel")
+    }
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Full(..) => f.write_str("Full(..)"),
+            Self::Disconnected(..) => f.write_str("Disconnected(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Full(..) => f.write_str("sending on a full channel"),
+            Self::Disconnected(..) => f.write_str("sending on a closed channel"),
+        }
+    }
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("receiving on a closed channel")
+    }
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("receiving on an empty channel"),
+            Self::Disconnected => f.write_str("receiving on a closed channel"),
+        }
+    }
+}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Timeout => f.write_str("timed out waiting on channel"),
+            Self::Disconnected => f.write_str("channel is empty and sending half is closed"),
+        }
+    }
+}
+
+impl<T> From<SendError<T>> for TrySendError<T> {
+    fn from(err: SendError<T>) -> Self {
+        Self::Disconnected(err.0)
+    }
+}
+
+impl From<RecvError> for TryRecvError {
+    fn from(_: RecvError) -> Self {
+        Self::Disconnected
+    }
+}
+
+impl From<RecvError> for RecvTimeoutError {
+    fn from(_: RecvError) -> Self {
+        Self::Disconnected
+    }
+}
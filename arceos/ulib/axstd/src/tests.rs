@@ -0,0 +1,110 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use std::sync::{Mutex as StdMutex, Once as StdOnce};
+
+use arceos_api::modules::axtask;
+
+use crate::async_rt::{self, time::Elapsed};
+use crate::sync::mpsc::{self, RecvError, RecvTimeoutError, TrySendError};
+use crate::thread;
+
+static INIT: StdOnce = StdOnce::new();
+static SERIAL: StdMutex<()> = StdMutex::new(());
+
+#[test]
+fn test_mpsc_bounded_send_blocks() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static SENT: AtomicBool = AtomicBool::new(false);
+
+    let (tx, rx) = mpsc::sync_channel(1);
+    tx.send(1).unwrap();
+    assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+
+    let sender = thread::spawn(move || {
+        tx.send(2).unwrap(); // blocks until the first message is received
+        SENT.store(true, Ordering::Release);
+    });
+    for _ in 0..10 {
+        thread::yield_now();
+    }
+    assert!(!SENT.load(Ordering::Acquire));
+
+    assert_eq!(rx.recv(), Ok(1));
+    sender.join().unwrap();
+    assert!(SENT.load(Ordering::Acquire));
+    assert_eq!(rx.recv(), Ok(2));
+    assert_eq!(rx.recv(), Err(RecvError)); // the sender has been dropped
+}
+
+#[test]
+fn test_mpsc_rendezvous() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let (tx, rx) = mpsc::sync_channel(0);
+    // No receiver is waiting.
+    assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+
+    let sender = thread::spawn(move || {
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+    });
+    assert_eq!(rx.iter().collect::<Vec<_>>(), [0, 1, 2]);
+    sender.join().unwrap();
+}
+
+#[test]
+fn test_mpsc_recv_timeout() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let (tx, rx) = mpsc::channel();
+    // The clock does not advance on the host, so only zero timeouts elapse.
+    assert_eq!(
+        rx.recv_timeout(Duration::ZERO),
+        Err(RecvTimeoutError::Timeout)
+    );
+
+    let sender = thread::spawn(move || {
+        thread::yield_now();
+        tx.send(1).unwrap();
+    });
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(1));
+    sender.join().unwrap();
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(1)),
+        Err(RecvTimeoutError::Disconnected)
+    );
+}
+
+#[test]
+fn test_block_on_timer() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let output = async_rt::block_on(async {
+        let task = async_rt::spawn(async {
+            for _ in 0..3 {
+                async_rt::yield_now().await;
+            }
+            42
+        });
+        // Sets a timer, which is cancelled when the task completes first.
+        let res = async_rt::time::timeout(Duration::from_secs(1), task).await;
+        assert_eq!(res, Ok(42));
+
+        // The clock does not advance on the host, so only zero timeouts
+        // elapse.
+        let res = async_rt::time::timeout(Duration::ZERO, core::future::pending::<()>()).await;
+        assert_eq!(res, Err(Elapsed));
+        async_rt::time::sleep(Duration::ZERO).await;
+        7
+    });
+    assert_eq!(output, 7);
+
+    // The thread can run another executor after the last one returned.
+    assert_eq!(async_rt::block_on(async { 8 }), 8);
+}